@@ -0,0 +1,70 @@
+//! Compiles the GLSL shaders of the runner's own pipelines, the sprites and the like, to SPIR-V in
+//! `OUT_DIR`, where the modules include them from.  The shaders you're working on are rust-gpu
+//! crates built by the runner at run time, these only change along with the runner.
+//!
+//! shaderc links the Vulkan SDK's copy when `VULKAN_SDK` or `SHADERC_LIB_DIR` is set, and builds
+//! it from source otherwise, which needs cmake, python and a C++ compiler.
+//!
+//! iOS builds embed the shader crate's SPIR-V instead of building it at run time, and get the
+//! `sky_shader_asset` cfg once it has been copied to `assets/`, see `load_embedded_shaders`.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// Relative to `src`, each is written to `OUT_DIR` as `<file name>.spv`.
+const SHADERS: &[&str] = &[
+    "sprite/sprite.vert",
+    "sprite/sprite.frag",
+    "debug_draw/debug_draw.vert",
+    "debug_draw/debug_draw.frag",
+    "grid/grid.vert",
+    "grid/grid.frag",
+    "egui_painter/egui.vert",
+    "egui_painter/egui.frag",
+];
+
+/// Built from the shader crate on the host, embedded on iOS.
+const SKY_SHADER_ASSET: &str = "assets/sky_shader.spv";
+
+fn main() {
+    if env::var("CARGO_CFG_TARGET_OS").map_or(false, |os| os == "ios") {
+        println!("cargo:rerun-if-changed={}", SKY_SHADER_ASSET);
+        if Path::new(SKY_SHADER_ASSET).is_file() {
+            println!("cargo:rustc-cfg=sky_shader_asset");
+        }
+    }
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let mut compiler = shaderc::Compiler::new().expect("failed to initialize shaderc");
+    let mut options = shaderc::CompileOptions::new().unwrap();
+    options.set_target_env(
+        shaderc::TargetEnv::Vulkan,
+        shaderc::EnvVersion::Vulkan1_0 as u32,
+    );
+    options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+
+    for shader in SHADERS {
+        let path = Path::new("src").join(shader);
+        println!("cargo:rerun-if-changed={}", path.display());
+        let kind = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("vert") => shaderc::ShaderKind::Vertex,
+            Some("frag") => shaderc::ShaderKind::Fragment,
+            _ => panic!("{}: unknown shader stage", path.display()),
+        };
+        let source = fs::read_to_string(&path)
+            .unwrap_or_else(|err| panic!("failed to read {}: {}", path.display(), err));
+        let artifact = compiler
+            .compile_into_spirv(
+                &source,
+                kind,
+                &path.to_string_lossy(),
+                "main",
+                Some(&options),
+            )
+            .unwrap_or_else(|err| panic!("{}", err));
+        let file_name = format!("{}.spv", path.file_name().unwrap().to_string_lossy());
+        fs::write(out_dir.join(file_name), artifact.as_binary_u8()).unwrap();
+    }
+}
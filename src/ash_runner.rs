@@ -6,30 +6,110 @@ use ash::{
 };
 
 use winit::{
-    event::{Event, VirtualKeyCode, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
+    dpi::PhysicalPosition,
+    event::{ElementState, Event, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent},
+    event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget},
+    monitor::MonitorHandle,
+    window::{Fullscreen, Window, WindowBuilder},
 };
 
-use serde::Deserialize;
+use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
+
+use rayon::prelude::*;
+
+use serde::{Deserialize, Serialize};
 
 use std::{
     borrow::Cow,
-    collections::HashMap,
+    cell::RefCell,
+    collections::{hash_map::DefaultHasher, HashMap},
     default::Default,
     ffi::{CStr, CString},
     fs::File,
+    hash::{Hash, Hasher},
     ops::Drop,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
-    sync::atomic::{AtomicBool, Ordering},
+    sync::{
+        mpsc::{self, Receiver, Sender, TryRecvError},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use structopt::StructOpt;
 
+use crate::calibrated_timestamps;
+use crate::camera::{Camera, CameraMode};
+use crate::capture;
+use crate::cli::{self, Cli, ShaderBuildArgs, Subcommand};
+use crate::clock::ShaderClock;
+use crate::compile_report::CompileReport;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+use crate::compiler::ShaderCompiler;
+use crate::debug_draw::{DebugDraw, DebugDrawRenderer};
+use crate::device_requirements::DeviceRequirements;
+use crate::export::{self, Exporter};
+use crate::fly_camera::{self, FlyCamera};
+use crate::frame_graph::FrameGraph;
+use crate::frame_ring::FrameRing;
+use crate::frame_timing::{FrameTimer, FrameTiming};
+#[cfg(windows)]
+use crate::fullscreen_exclusive::{self, FullScreenExclusive};
+use crate::golden;
+use crate::grid::Grid;
+use crate::help::HelpOverlay;
+use crate::keymap::{Action, Keymap};
+use crate::latency::LatencyMarkers;
+use crate::layout_cache::LayoutCache;
+use crate::orbit_camera::OrbitCamera;
+use crate::particles::{ParticleDescription, ParticleSystem};
+use crate::pass_times::PassTimesOverlay;
+use crate::passes::{Passes, SceneDescription};
+use crate::perf_counters::{self, PerfCounters};
+use crate::picking::{Pick, PickTarget};
+use crate::pipeline_library::{
+    self, PhysicalDeviceGraphicsPipelineLibraryFeatures, PipelineLibraries,
+};
+use crate::pipeline_registry::{BlendMode, PipelineConfig, PipelineHandle, PipelineRegistry};
+use crate::plugin::Plugin;
+use crate::portability::{self, PhysicalDevicePortabilitySubsetFeatures};
+use crate::profiling;
+use crate::readback;
+use crate::reflect;
+use crate::render_pass::{self, AttachmentImages, DepthConvention, RenderPassConfig};
+use crate::sampler_cache::{SamplerCache, SamplerDesc};
+use crate::scene::SceneWatcher;
+#[cfg(feature = "rhai")]
+use crate::script::ScriptPlugin;
+use crate::settings::Settings;
+use crate::shader_drop::{self, ShaderOffer};
+use crate::subgroup::SubgroupSupport;
+use crate::synchronization2::{
+    self, PhysicalDeviceSynchronization2Features, PipelineStageFlags2, SemaphoreSubmit, Submit,
+    Synchronization2,
+};
+use crate::watermark::Watermark;
+#[cfg(feature = "openxr")]
+use crate::xr;
+
+/// Pushed as raw bytes, so laid out in declaration order like the shaders' struct.
+#[repr(C)]
 #[derive(Copy, Clone)]
 pub struct ShaderConstants {
     pub width: u32,
     pub height: u32,
+    pub scale_factor: f32,
+    /// `RenderCtx::shader_params`.
+    pub params: [f32; 8],
+    /// Seconds since the first frame, see `ShaderClock`.
+    pub time: f32,
+    pub frame: u32,
+    /// Differs between runs unless `--deterministic`.
+    pub seed: u32,
+    /// From world space to clip space: `RenderCtx::view_projection` in the window, each eye's pose
+    /// and field of view in an XR headset.
+    pub view_projection: [[f32; 4]; 4],
 }
 
 #[derive(Debug, StructOpt)]
@@ -38,131 +118,1434 @@ pub struct Options {
     /// Use Vulkan debug layer (requires Vulkan SDK installed)
     #[structopt(short, long)]
     debug_layer: bool,
+
+    /// Turn on more of the validation layer's checks, as comma separated names: gpu-assisted
+    /// checks shader accesses on the GPU, sync finds missing or wrong barriers, best-practices
+    /// warns about slow API use.  Implies --debug-layer
+    #[structopt(long)]
+    validation: Option<ValidationFeatures>,
+
+    /// RON file binding the hotkeys to other keys, e.g. "{ toggle_vsync: F2 }"
+    #[structopt(long, parse(from_os_str))]
+    keymap: Option<PathBuf>,
+
+    /// RON file the window geometry, present mode and enabled pipelines are saved to on exit and
+    /// restored from at startup
+    #[structopt(long, parse(from_os_str), default_value = "ash-runner.ron")]
+    settings: PathBuf,
+
+    /// Neither restore nor save the settings
+    #[structopt(long)]
+    no_settings: bool,
+
+    /// Present mode to start with: mailbox, immediate, fifo or fifo-relaxed.  Unsupported modes fall
+    /// back along mailbox, immediate, fifo, printing the mode chosen instead; the default is
+    /// mailbox, or the mode saved in the settings
+    #[structopt(long, parse(try_from_str = parse_present_mode))]
+    present_mode: Option<vk::PresentModeKHR>,
+
+    /// Burn the time, the shaders drawn and the shader crate's git revision into captures
+    #[structopt(long)]
+    watermark: bool,
+
+    /// Limit the frame rate by sleeping after present
+    #[structopt(long, parse(try_from_str = parse_max_fps))]
+    max_fps: Option<f64>,
+
+    /// Show the graph of CPU and GPU frame times from the start, otherwise toggled with F3
+    #[structopt(long)]
+    frame_graph: bool,
+
+    /// Show the GPU time of each render pass from the start, otherwise toggled with F7, and print
+    /// it every few seconds once --passes adds passes to the window's
+    #[structopt(long)]
+    pass_times: bool,
+
+    /// Write the CPU time, GPU time, latency where the device has calibrated timestamps, and acquire
+    /// and present waits of every frame to this CSV file
+    #[structopt(long, parse(from_os_str))]
+    timing_csv: Option<PathBuf>,
+
+    /// Sample these hardware performance counters with VK_KHR_performance_query, as comma
+    /// separated names, for the window's render pass and each --passes pass, printing their
+    /// averages every few seconds; "list" prints the device's counters
+    #[structopt(long)]
+    perf_counters: Option<String>,
+
+    /// Use reverse-Z depth, mapping near to 1 and far to 0 for better precision at a distance:
+    /// plugins clear depth to 0 and test with GREATER_OR_EQUAL, see `DepthConvention`
+    #[structopt(long)]
+    reverse_z: bool,
+
+    /// Advance the shader time by a fixed step per frame and use a fixed random seed, so two runs
+    /// render the same frames
+    #[structopt(long)]
+    deterministic: bool,
+
+    /// Wait for the GPU after each present so the next frame starts from fresh input, and print
+    /// the measured latency every few seconds
+    #[structopt(long)]
+    low_latency: bool,
+
+    /// Make the window background transparent, compositing with the shader's alpha output
+    #[structopt(long)]
+    transparent: bool,
+
+    /// Keep the window above all other windows
+    #[structopt(long)]
+    always_on_top: bool,
+
+    /// Hide the window from the taskbar/dock, where the platform allows it
+    #[structopt(long)]
+    skip_taskbar: bool,
+
+    /// Initial window position in physical pixels, as "x,y"
+    #[structopt(long, parse(try_from_str = parse_position))]
+    position: Option<PhysicalPosition<i32>>,
+
+    /// Start in borderless fullscreen
+    #[structopt(long)]
+    fullscreen: bool,
+
+    /// Start fullscreen, taking exclusive control of the monitor with VK_EXT_full_screen_exclusive
+    /// while the window has focus, for the lowest presentation latency
+    #[cfg(windows)]
+    #[structopt(long)]
+    exclusive_fullscreen: bool,
+
+    /// Index of the monitor to place the window on, see --list-monitors
+    #[structopt(long)]
+    monitor: Option<usize>,
+
+    /// Print the available monitors and exit
+    #[structopt(long)]
+    list_monitors: bool,
+
+    /// Copy every frame into an image with exportable memory, and hand it to the processes
+    /// connecting to this Unix socket path, or on Windows this local TCP address, see src/export.rs
+    #[structopt(long)]
+    export_memory: Option<String>,
+
+    /// Swapchain color space: srgb, display-p3 or hdr10 (BT.2020 with the PQ transfer function)
+    #[structopt(long, default_value = "srgb")]
+    color_space: ColorSpace,
+
+    /// Bits per swapchain color channel: 8, 10 (A2B10G10R10 UNORM) or 16f (RGBA16 float), falling
+    /// back to 8 where the surface lacks the format.  Deeper formats reduce banding in smooth
+    /// gradients, but aren't sRGB encoded on write, so in the sRGB color space they need
+    /// --shader-output encoded
+    #[structopt(long, default_value = "8")]
+    surface_depth: SurfaceDepth,
+
+    /// How shader output is treated: "linear" is encoded to sRGB on write, "encoded" is written
+    /// as-is because the shader already applied the transfer function
+    #[structopt(long, default_value = "linear")]
+    pub(crate) shader_output: ShaderOutput,
+
+    /// Let a running shader build finish when another is requested, then build once more, instead
+    /// of cancelling it
+    #[structopt(long)]
+    no_cancel_builds: bool,
+
+    /// Number of swapchain images to ask for, clamped to what the surface supports.  Fewer images
+    /// lower latency, more smooth over uneven frame times; the default is the minimum plus one
+    #[structopt(long)]
+    image_count: Option<u32>,
+
+    /// Keep this aspect ratio, as "w:h", letterboxing the window around it
+    #[structopt(long, parse(try_from_str = parse_aspect_ratio), conflicts_with = "resolution")]
+    aspect_ratio: Option<f32>,
+
+    /// Render as if the window had this size, as "wxh", letterboxed to its aspect ratio
+    #[structopt(long, parse(try_from_str = parse_resolution))]
+    pub(crate) resolution: Option<vk::Extent2D>,
+
+    /// Leave +Y pointing down in clip space as Vulkan defines it, instead of flipping the viewport
+    /// so it points up as in OpenGL, for shaders written for that convention
+    #[structopt(long)]
+    y_down: bool,
+
+    /// Draw a ground grid and the axes, seen through the camera that dragging with the mouse
+    /// orbits
+    #[structopt(long)]
+    grid: bool,
+
+    /// Speed of the fly camera, toggled with F6, in world units per second
+    #[structopt(long, default_value = "3")]
+    fly_speed: f32,
+
+    /// Compare two pipelines side by side, as "left,right" pipeline names
+    #[structopt(long)]
+    split_screen: Option<String>,
+
+    /// Color the window is cleared to before drawing, as "r,g,b" or "r,g,b,a" in linear 0-1
+    /// values, or "none" to keep the previous frame and accumulate into it
+    #[structopt(long)]
+    pub(crate) clear_color: Option<ClearColor>,
+
+    #[structopt(flatten)]
+    pub(crate) shader_build: ShaderBuildArgs,
+
+    /// Run this rhai script every frame, reloading it when it changes (requires the rhai feature)
+    #[cfg(feature = "rhai")]
+    #[structopt(long, parse(from_os_str))]
+    script: Option<PathBuf>,
+
+    /// Json file with the pipelines to draw and the clear color, applied again whenever it changes
+    #[structopt(long, parse(from_os_str))]
+    pub(crate) scene: Option<PathBuf>,
+
+    /// RON file describing offscreen targets and the passes drawing into them and the window
+    #[structopt(long, parse(from_os_str))]
+    passes: Option<PathBuf>,
+
+    /// RON file describing particles simulated by a compute shader and drawn from its storage
+    /// buffer
+    #[structopt(long, parse(from_os_str))]
+    particles: Option<PathBuf>,
+
+    /// Link reloaded pipelines from precompiled parts with VK_EXT_graphics_pipeline_library, if the
+    /// device supports it
+    #[structopt(long)]
+    pipeline_library: bool,
+
+    /// Also render in stereo to an OpenXR headset (requires the openxr feature)
+    #[structopt(long)]
+    xr: bool,
+
+    /// Render on a CPU Vulkan implementation such as lavapipe or SwiftShader if one is installed,
+    /// for machines without a GPU like CI runners
+    #[structopt(long)]
+    prefer_software: bool,
 }
 
-// This is not an ideal solution, but it's simple and doesn't require an async runtime.
-static NEEDS_REBUILD: AtomicBool = AtomicBool::new(false);
-static IS_COMPILING: AtomicBool = AtomicBool::new(false);
-static mut NEW_SHADERS: Vec<SpirvShader> = Vec::<SpirvShader>::new();
+fn parse_position(s: &str) -> Result<PhysicalPosition<i32>, String> {
+    let mut coords = s.splitn(2, ',').map(|coord| coord.trim().parse::<i32>());
+    match (coords.next(), coords.next()) {
+        (Some(Ok(x)), Some(Ok(y))) => Ok(PhysicalPosition::new(x, y)),
+        _ => Err(format!("expected \"x,y\", got \"{}\"", s)),
+    }
+}
+
+fn parse_aspect_ratio(s: &str) -> Result<f32, String> {
+    let mut terms = s.splitn(2, ':').map(|term| term.trim().parse::<f32>());
+    match (terms.next(), terms.next()) {
+        (Some(Ok(w)), Some(Ok(h))) if w > 0.0 && h > 0.0 => Ok(w / h),
+        _ => Err(format!("expected \"w:h\", got \"{}\"", s)),
+    }
+}
+
+/// The present modes tried in order when the one asked for is unsupported: without vsync and
+/// without tearing, then without vsync, then FIFO, which every surface supports.
+pub const PRESENT_MODE_PREFERENCE: [vk::PresentModeKHR; 3] = [
+    vk::PresentModeKHR::MAILBOX,
+    vk::PresentModeKHR::IMMEDIATE,
+    vk::PresentModeKHR::FIFO,
+];
+
+/// Present modes named like `vk::PresentModeKHR`'s constants, in any case.
+pub(crate) fn parse_present_mode(s: &str) -> Result<vk::PresentModeKHR, String> {
+    match s.trim().to_ascii_uppercase().replace('-', "_").as_str() {
+        "IMMEDIATE" => Ok(vk::PresentModeKHR::IMMEDIATE),
+        "MAILBOX" => Ok(vk::PresentModeKHR::MAILBOX),
+        "FIFO" => Ok(vk::PresentModeKHR::FIFO),
+        "FIFO_RELAXED" => Ok(vk::PresentModeKHR::FIFO_RELAXED),
+        _ => Err(format!(
+            "expected immediate, mailbox, fifo or fifo-relaxed, got \"{}\"",
+            s
+        )),
+    }
+}
+
+fn parse_max_fps(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(max_fps) if max_fps.is_finite() && max_fps > 0.0 => Ok(max_fps),
+        _ => Err(format!("expected a positive number, got \"{}\"", s)),
+    }
+}
+
+fn parse_resolution(s: &str) -> Result<vk::Extent2D, String> {
+    let mut terms = s.splitn(2, 'x').map(|term| term.trim().parse::<u32>());
+    match (terms.next(), terms.next()) {
+        (Some(Ok(width)), Some(Ok(height))) if width > 0 && height > 0 => {
+            Ok(vk::Extent2D { width, height })
+        }
+        _ => Err(format!("expected \"wxh\", got \"{}\"", s)),
+    }
+}
+
+impl Options {
+    /// Checks the combinations of options their attributes can't express.
+    pub(crate) fn check(&self) -> Result<(), String> {
+        if self.surface_depth != SurfaceDepth::Eight
+            && self.color_space == ColorSpace::Srgb
+            && self.shader_output == ShaderOutput::Linear
+        {
+            return Err(
+                "--surface-depth 10 and 16f aren't sRGB encoded on write, so in the srgb \
+                        color space they need --shader-output encoded"
+                    .into(),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the composition of resolution dependent shaders when the window is resized, by drawing
+/// into the largest centered box of a fixed aspect ratio.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Letterbox {
+    AspectRatio(f32),
+    /// Also reports this size to the shaders instead of the box's.
+    Resolution(vk::Extent2D),
+}
+
+impl Letterbox {
+    fn aspect_ratio(self) -> f32 {
+        match self {
+            Letterbox::AspectRatio(aspect_ratio) => aspect_ratio,
+            Letterbox::Resolution(resolution) => resolution.width as f32 / resolution.height as f32,
+        }
+    }
+
+    /// The box within `extent`.
+    pub fn rect(self, extent: vk::Extent2D) -> vk::Rect2D {
+        let aspect_ratio = self.aspect_ratio();
+        let box_extent = if extent.width as f32 > extent.height as f32 * aspect_ratio {
+            vk::Extent2D {
+                width: ((extent.height as f32 * aspect_ratio).round() as u32).max(1),
+                height: extent.height,
+            }
+        } else {
+            vk::Extent2D {
+                width: extent.width,
+                height: ((extent.width as f32 / aspect_ratio).round() as u32).max(1),
+            }
+        };
+        vk::Rect2D {
+            offset: vk::Offset2D {
+                x: ((extent.width - box_extent.width) / 2) as i32,
+                y: ((extent.height - box_extent.height) / 2) as i32,
+            },
+            extent: box_extent,
+        }
+    }
+}
+
+/// The surface color spaces that can be requested with `--color-space`.  Everything but sRGB needs
+/// `VK_EXT_swapchain_colorspace` and a display that supports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,
+    DisplayP3,
+    Hdr10,
+}
+
+impl ColorSpace {
+    pub fn to_vk(self) -> vk::ColorSpaceKHR {
+        match self {
+            ColorSpace::Srgb => vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            ColorSpace::DisplayP3 => vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT,
+            ColorSpace::Hdr10 => vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+        }
+    }
+}
+
+impl std::str::FromStr for ColorSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "srgb" => Ok(ColorSpace::Srgb),
+            "display-p3" => Ok(ColorSpace::DisplayP3),
+            "hdr10" => Ok(ColorSpace::Hdr10),
+            _ => Err(format!(
+                "unknown color space \"{}\", expected srgb, display-p3 or hdr10",
+                s
+            )),
+        }
+    }
+}
+
+/// The swapchain formats `--surface-depth` asks for, before falling back to 8 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SurfaceDepth {
+    Eight,
+    Ten,
+    HalfFloat,
+}
+
+impl SurfaceDepth {
+    fn formats(self) -> &'static [vk::Format] {
+        match self {
+            SurfaceDepth::Eight => &[],
+            SurfaceDepth::Ten => &[
+                vk::Format::A2B10G10R10_UNORM_PACK32,
+                vk::Format::A2R10G10B10_UNORM_PACK32,
+            ],
+            SurfaceDepth::HalfFloat => &[vk::Format::R16G16B16A16_SFLOAT],
+        }
+    }
+}
+
+impl std::str::FromStr for SurfaceDepth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(SurfaceDepth::Eight),
+            "10" => Ok(SurfaceDepth::Ten),
+            "16f" => Ok(SurfaceDepth::HalfFloat),
+            _ => Err(format!(
+                "unknown surface depth \"{}\", expected 8, 10 or 16f",
+                s
+            )),
+        }
+    }
+}
+
+/// The validation layer's optional checks `--validation` turns on with `VK_EXT_validation_features`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationFeatures(Vec<vk::ValidationFeatureEnableEXT>);
+
+impl ValidationFeatures {
+    /// `VK_VALIDATION_FEATURE_ENABLE_SYNCHRONIZATION_VALIDATION_EXT`, newer than ash's headers.
+    const SYNCHRONIZATION: vk::ValidationFeatureEnableEXT =
+        vk::ValidationFeatureEnableEXT::from_raw(4);
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn contains(&self, feature: vk::ValidationFeatureEnableEXT) -> bool {
+        self.0.contains(&feature)
+    }
+}
+
+impl std::str::FromStr for ValidationFeatures {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|name| match name.trim() {
+                "gpu-assisted" => Ok(vk::ValidationFeatureEnableEXT::GPU_ASSISTED),
+                "sync" => Ok(Self::SYNCHRONIZATION),
+                "best-practices" => Ok(vk::ValidationFeatureEnableEXT::BEST_PRACTICES),
+                name => Err(format!(
+                    "unknown validation feature \"{}\", expected gpu-assisted, sync or best-practices",
+                    name
+                )),
+            })
+            .collect::<Result<_, _>>()
+            .map(ValidationFeatures)
+    }
+}
+
+/// Whether the fragment shader outputs linear values or ones that are already sRGB encoded.  Encoded
+/// output is rendered through UNORM views so the hardware doesn't encode it a second time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaderOutput {
+    Linear,
+    Encoded,
+}
+
+impl std::str::FromStr for ShaderOutput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(ShaderOutput::Linear),
+            "encoded" => Ok(ShaderOutput::Encoded),
+            _ => Err(format!(
+                "unknown shader output \"{}\", expected linear or encoded",
+                s
+            )),
+        }
+    }
+}
+
+/// What each frame starts from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClearColor {
+    Color([f32; 4]),
+    /// Loads the previous contents of the swapchain image instead of clearing it.
+    DontClear,
+}
+
+impl std::str::FromStr for ClearColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "none" {
+            return Ok(ClearColor::DontClear);
+        }
+        let components = s
+            .split(',')
+            .map(|component| component.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>();
+        match components.as_ref().map(|components| components.as_slice()) {
+            Ok(&[r, g, b]) => Ok(ClearColor::Color([r, g, b, 1.0])),
+            Ok(&[r, g, b, a]) => Ok(ClearColor::Color([r, g, b, a])),
+            _ => Err(format!(
+                "expected \"r,g,b\", \"r,g,b,a\" or \"none\", got \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+const SRGB_FORMATS: [vk::Format; 5] = [
+    vk::Format::R8G8B8_SRGB,
+    vk::Format::B8G8R8_SRGB,
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::B8G8R8A8_SRGB,
+    vk::Format::A8B8G8R8_SRGB_PACK32,
+];
+
+/// The UNORM format with the same layout as one of `SRGB_FORMATS`.
+fn unorm_format(format: vk::Format) -> Option<vk::Format> {
+    match format {
+        vk::Format::R8G8B8_SRGB => Some(vk::Format::R8G8B8_UNORM),
+        vk::Format::B8G8R8_SRGB => Some(vk::Format::B8G8R8_UNORM),
+        vk::Format::R8G8B8A8_SRGB => Some(vk::Format::R8G8B8A8_UNORM),
+        vk::Format::B8G8R8A8_SRGB => Some(vk::Format::B8G8R8A8_UNORM),
+        vk::Format::A8B8G8R8_SRGB_PACK32 => Some(vk::Format::A8B8G8R8_UNORM_PACK32),
+        _ => None,
+    }
+}
 
 pub fn main() {
-    let options = Options::from_args();
-    let shaders = compile_shaders();
+    main_with_plugins(Vec::new());
+}
+
+/// Runs the runner with extra passes and per-frame hooks.
+pub fn main_with_plugins(plugins: Vec<Box<dyn Plugin>>) {
+    match Cli::parse().command {
+        // the defaults, also for Android and iOS, which launch without arguments
+        None => run(Options::from_iter(&["run"]), plugins, None),
+        Some(Subcommand::Run(options)) => run(options, plugins, None),
+        Some(Subcommand::Bench(options)) => run(
+            options.run,
+            plugins,
+            Some(Bench::new(options.warmup, options.frames)),
+        ),
+        Some(Subcommand::Compile(options)) => cli::compile(&options),
+        Some(Subcommand::Validate(options)) => cli::validate(&options),
+        Some(Subcommand::Info(options)) => cli::print_info(&options),
+        Some(Subcommand::Test(options)) => golden::run_tests(&options),
+    }
+}
+
+/// The pipeline drawn without a `--scene`.
+pub fn default_entry_points() -> (VertexShaderEntryPoint, FragmentShaderEntryPoint) {
+    (
+        VertexShaderEntryPoint {
+            module: "sky_shader".into(),
+            entry_point: "main_vs".into(),
+        },
+        FragmentShaderEntryPoint {
+            module: "sky_shader".into(),
+            entry_point: "main_fs".into(),
+        },
+    )
+}
 
+/// Opens the window and renders until it is closed, or until the benchmark is done.
+fn run(options: Options, plugins: Vec<Box<dyn Plugin>>, bench: Option<Bench>) {
     // runtime setup
-    let event_loop = EventLoop::<CompilerEvent>::with_user_event();
-    let window = winit::window::WindowBuilder::new()
-        .with_title("Rust GPU - ash")
-        .with_inner_size(winit::dpi::LogicalSize::new(
-            f64::from(1280),
-            f64::from(720),
-        ))
-        .build(&event_loop)
-        .unwrap();
-    let mut ctx = RenderBase::new(window, &options).into_ctx();
+    let event_loop = EventLoop::<UserEvent>::with_user_event();
+    if options.list_monitors {
+        print_monitors(&event_loop);
+        return;
+    }
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    let shader_build = options.shader_build.build_options();
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "android")] {
+            let shaders = load_shader_assets();
+            // the surface can only be created once the activity has handed us a native window
+            while ndk_glue::native_window().is_none() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        } else if #[cfg(target_os = "ios")] {
+            let shaders = load_embedded_shaders();
+        } else {
+            let shaders = compile_shaders(&shader_build);
+        }
+    }
+    // benchmarks start from the same state every time
+    let persist_settings = !options.no_settings && bench.is_none();
+    let mut settings = if persist_settings {
+        Settings::load(&options.settings).unwrap_or_else(|err| {
+            eprintln!("failed to load {}: {}", options.settings.display(), err);
+            Settings::default()
+        })
+    } else {
+        Settings::default()
+    };
+    let window = Arc::new(create_window(&options, &settings, &event_loop));
+    let settings_window = window.clone();
+    let mut requirements = DeviceRequirements::new();
+    for plugin in plugins.iter() {
+        plugin.device_requirements(&mut requirements);
+    }
+    // for `SamplerDesc::anisotropy`
+    requirements.request_feature("samplerAnisotropy", |features| {
+        &mut features.sampler_anisotropy
+    });
+    if options.perf_counters.is_some() {
+        perf_counters::device_requirements(&mut requirements);
+    }
+    let gpu_assisted = options.validation.as_ref().map_or(false, |validation| {
+        validation.contains(vk::ValidationFeatureEnableEXT::GPU_ASSISTED)
+    });
+    if gpu_assisted {
+        // the instrumented shaders write their findings to a buffer
+        requirements
+            .request_feature("vertexPipelineStoresAndAtomics", |features| {
+                &mut features.vertex_pipeline_stores_and_atomics
+            })
+            .request_feature("fragmentStoresAndAtomics", |features| {
+                &mut features.fragment_stores_and_atomics
+            });
+    }
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "openxr")] {
+            let (mut ctx, xr_session) = if options.xr {
+                let (xr_instance, system, base) = xr::create_base(window, &options);
+                let ctx = base.into_ctx();
+                let xr_session = xr::XrSession::new(xr_instance, system, &ctx);
+                (ctx, Some(xr_session))
+            } else {
+                let base = RenderBase::with_requirements(window, &options, requirements);
+                (base.into_ctx(), None)
+            };
+        } else {
+            assert!(!options.xr, "--xr requires building with the openxr feature");
+            let mut ctx = RenderBase::with_requirements(window, &options, requirements).into_ctx();
+        }
+    }
+    // benchmarks measure how fast frames can go
+    let frame_limiter = match bench {
+        Some(_) => None,
+        None => options.max_fps.map(FrameLimiter::new),
+    };
+    if options.low_latency {
+        ctx.latency_markers = Some(LatencyMarkers::new());
+    }
+    if options.reverse_z {
+        ctx.depth_convention = DepthConvention::Reverse;
+    }
+    if options.deterministic {
+        ctx.clock = ShaderClock::new(true);
+    }
+    if let Some(path) = &options.keymap {
+        ctx.keymap = Keymap::load(path)
+            .unwrap_or_else(|err| panic!("Failed to load {}: {}", path.display(), err));
+    }
+    if let Some(names) = &options.perf_counters {
+        ctx.perf_counters = PerfCounters::new(&ctx.base, names);
+    }
+    ctx.frame_timer.print_pass_times = options.pass_times;
+    if let Some(path) = &options.timing_csv {
+        ctx.frame_timer
+            .write_csv(path)
+            .unwrap_or_else(|err| panic!("Failed to create {}: {}", path.display(), err));
+    }
+    if let Some(clear_color) = options.clear_color {
+        ctx.set_clear_color(clear_color);
+    }
+    let letterbox = match (options.resolution, options.aspect_ratio) {
+        (Some(resolution), _) => Some(Letterbox::Resolution(resolution)),
+        (None, Some(aspect_ratio)) => Some(Letterbox::AspectRatio(aspect_ratio)),
+        (None, None) => None,
+    };
+    ctx.set_letterbox(letterbox);
+    if options.y_down {
+        ctx.set_flip_y(false);
+    }
 
     // Create shader module and pipelines
     for SpirvShader { name, spirv } in shaders {
         ctx.insert_shader_module(name, spirv);
     }
-    ctx.build_pipelines(
-        vk::PipelineCache::null(),
-        vec![(
-            VertexShaderEntryPoint {
-                module: "sky_shader".into(),
-                entry_point: "main_vs".into(),
-            },
-            FragmentShaderEntryPoint {
-                module: "sky_shader".into(),
-                entry_point: "main_fs".into(),
-            },
-        )],
+    match &options.scene {
+        Some(path) => ctx.add_plugin(Box::new(SceneWatcher::new(path.clone()))),
+        None => {
+            let (vertex, fragment) = default_entry_points();
+            ctx.pipelines.insert("sky", vertex, fragment);
+        }
+    }
+    for plugin in plugins {
+        ctx.add_plugin(plugin);
+    }
+    if let Some(path) = &options.passes {
+        let description = SceneDescription::load(path)
+            .unwrap_or_else(|err| panic!("Failed to load {}: {}", path.display(), err));
+        ctx.add_plugin(Box::new(Passes::new(description)));
+    }
+    if let Some(path) = &options.particles {
+        let description = ParticleDescription::load(path)
+            .unwrap_or_else(|err| panic!("Failed to load {}: {}", path.display(), err));
+        ctx.add_plugin(Box::new(ParticleSystem::new(description)));
+    }
+    #[cfg(feature = "rhai")]
+    if let Some(path) = &options.script {
+        ctx.add_plugin(Box::new(ScriptPlugin::new(path.clone())));
+    }
+    if options.grid {
+        ctx.add_plugin(Box::new(Grid::default()));
+    }
+    ctx.add_plugin(Box::new(OrbitCamera::default()));
+    ctx.add_plugin(Box::new(FlyCamera::new(options.fly_speed)));
+    ctx.add_plugin(Box::new(DebugDrawRenderer::default()));
+    #[cfg(feature = "puffin")]
+    ctx.add_plugin(Box::new(profiling::PuffinToggle::default()));
+    ctx.add_plugin(Box::new(PassTimesOverlay::new(options.pass_times)));
+    ctx.add_plugin(Box::new(HelpOverlay::default()));
+    // last, so it draws over everything else
+    ctx.add_plugin(Box::new(FrameGraph::new(options.frame_graph)));
+    ctx.rebuild_pipelines(vk::PipelineCache::null());
+    if let Some(names) = &options.split_screen {
+        let mut handles = names
+            .splitn(2, ',')
+            .map(|name| ctx.pipelines.handle(name.trim()));
+        match (handles.next(), handles.next()) {
+            (Some(Some(left)), Some(Some(right))) => ctx.split_screen = Some((left, right)),
+            _ => eprintln!(
+                "--split-screen expects two pipeline names, got \"{}\"",
+                names
+            ),
+        }
+    }
+
+    settings.apply(&mut ctx);
+    if let Some(present_mode) = options.present_mode {
+        ctx.set_present_mode(present_mode);
+    }
+    println!("present mode: {:?}", ctx.present_mode);
+    println!("subgroups: {}", ctx.subgroup);
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    let mut compiler = ShaderCompiler::new(
+        event_loop.create_proxy(),
+        !options.no_cancel_builds,
+        shader_build,
     );
+    let keymap = ctx.keymap.clone();
+    // mirrors `ctx.camera_mode`, to route the movement keys
+    let mut flying = false;
+    let (sender, messages) = mpsc::channel();
+    let proxy = event_loop.create_proxy();
+    let render_thread = RenderThread {
+        ctx,
+        frame_limiter,
+        bench,
+        shader_offer: None,
+        watermark: options.watermark,
+        #[cfg(feature = "openxr")]
+        xr_session,
+    };
+    let mut render_thread = Some(std::thread::spawn(move || {
+        let ctx_settings = render_thread.run(messages);
+        // nothing happens if the event loop is already exiting
+        proxy.send_event(UserEvent::RenderThreadExited).ok();
+        ctx_settings
+    }));
 
-    event_loop.run(move |event, _window_target, control_flow| match event {
-        Event::RedrawEventsCleared { .. } => {
-            if !IS_COMPILING.load(Ordering::SeqCst) && NEEDS_REBUILD.load(Ordering::SeqCst) {
-                // if a recompile isn't in progress, this is the only thread.
-                unsafe {
-                    for SpirvShader { name, spirv } in NEW_SHADERS.drain(..) {
-                        ctx.insert_shader_module(name, spirv);
+    event_loop.run(move |event, _window_target, control_flow| {
+        *control_flow = ControlFlow::Wait;
+        let message = match event {
+            Event::Suspended => {
+                let (done, wait) = mpsc::channel();
+                sender.send(RenderMessage::Suspend(done)).ok();
+                // the native window may be destroyed once this returns, the surface has to go first
+                wait.recv().ok();
+                return;
+            }
+            Event::Resumed => RenderMessage::Resume,
+            Event::UserEvent(UserEvent::RenderThreadExited) => RenderMessage::Exit,
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            Event::UserEvent(UserEvent::Compiler { build_id, event }) => {
+                if !compiler.finished(build_id) {
+                    return;
+                }
+                match event {
+                    CompilerEvent::Complete(shaders, report) => {
+                        report.print();
+                        if compiler.take_switched() {
+                            RenderMessage::ShaderSet(shaders)
+                        } else {
+                            RenderMessage::Shaders(shaders)
+                        }
+                    }
+                    CompilerEvent::Failed(report) => {
+                        report.print();
+                        eprintln!("shader build failed, keeping the previous shaders");
+                        return;
                     }
                 }
-                ctx.rebuild_pipelines(vk::PipelineCache::null());
-                NEEDS_REBUILD.store(false, Ordering::SeqCst);
-            }
-            ctx.render();
-        }
-        Event::WindowEvent { event, .. } => match event {
-            WindowEvent::KeyboardInput { input, .. } => match input.virtual_keycode {
-                Some(VirtualKeyCode::Escape) => *control_flow = ControlFlow::Exit,
-                Some(VirtualKeyCode::F5) => {
-                    // cannot start multiple recompiles at once, cannot cancel either
-                    if !IS_COMPILING.compare_and_swap(false, true, Ordering::SeqCst) {
-                        std::thread::spawn(|| {
-                            unsafe {
-                                NEW_SHADERS = compile_shaders();
-                            }
-                            NEEDS_REBUILD.store(true, Ordering::SeqCst);
-                            IS_COMPILING.store(false, Ordering::SeqCst);
-                        });
+            }
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::KeyboardInput { input, .. } => {
+                    let key = match input.virtual_keycode {
+                        Some(key) => key,
+                        None => return,
+                    };
+                    let pressed = input.state == ElementState::Pressed;
+                    let action = keymap.action(key);
+                    if action == Some(Action::ToggleFlyCamera) && pressed {
+                        flying = !flying;
+                    }
+                    match action {
+                        // the fly camera steers with them, whatever they are bound to
+                        _ if flying && fly_camera::MOVEMENT_KEYS.contains(&key) => {
+                            RenderMessage::Key(key, pressed)
+                        }
+                        Some(Action::ShowPrevious) => RenderMessage::ShowPrevious(pressed),
+                        // plugins get their keys
+                        Some(Action::ToggleHelp)
+                        | Some(Action::ToggleFlyCamera)
+                        | Some(Action::ToggleFrameGraph)
+                        | Some(Action::TogglePassTimes)
+                        | Some(Action::TogglePuffin)
+                        | None => RenderMessage::Key(key, pressed),
+                        // the rest act on presses
+                        Some(_) if !pressed => return,
+                        Some(Action::Exit) => RenderMessage::Exit,
+                        Some(Action::RebuildShaders) => {
+                            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                            compiler.request();
+                            return;
+                        }
+                        Some(Action::ToggleVsync) => RenderMessage::ToggleVsync,
+                        Some(Action::ToggleWireframe) => RenderMessage::ToggleWireframe,
+                        Some(Action::Capture) => {
+                            let timestamp = SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap()
+                                .as_secs();
+                            let path = format!("capture-{}.exr", timestamp);
+                            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                            let crate_dir = Some(compiler.crate_dir().to_owned());
+                            #[cfg(any(target_os = "android", target_os = "ios"))]
+                            let crate_dir = None;
+                            RenderMessage::Capture(PathBuf::from(path), crate_dir)
+                        }
+                        Some(Action::TogglePause) => RenderMessage::TogglePause,
+                        Some(Action::StepTime) => RenderMessage::StepTime,
+                        Some(Action::SlowerTime) => RenderMessage::ScaleTimeSpeed(0.5),
+                        Some(Action::FasterTime) => RenderMessage::ScaleTimeSpeed(2.0),
+                        Some(Action::BindDroppedShader) => RenderMessage::BindDroppedShader,
+                    }
+                }
+                WindowEvent::MouseInput { state, button, .. } => {
+                    RenderMessage::MouseButton(button, state == ElementState::Pressed)
+                }
+                WindowEvent::CursorMoved { position, .. } => {
+                    RenderMessage::CursorMoved([position.x, position.y])
+                }
+                WindowEvent::MouseWheel { delta, .. } => RenderMessage::MouseWheel(match delta {
+                    MouseScrollDelta::LineDelta(_, lines) => lines,
+                    MouseScrollDelta::PixelDelta(position) => {
+                        (position.y / PIXELS_PER_SCROLL_LINE) as f32
+                    }
+                }),
+                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                WindowEvent::DroppedFile(path) if path.join("Cargo.toml").is_file() => {
+                    compiler.switch_crate(path);
+                    return;
+                }
+                WindowEvent::DroppedFile(path) => {
+                    if path
+                        .extension()
+                        .map_or(true, |extension| extension != "spv")
+                    {
+                        eprintln!(
+                            "can't load {}, only .spv files and shader crates",
+                            path.display()
+                        );
+                        return;
                     }
-                    *control_flow = ControlFlow::Wait;
+                    RenderMessage::DroppedSpirv(path)
                 }
-                _ => *control_flow = ControlFlow::Wait,
+                WindowEvent::Resized(_) => RenderMessage::Resized,
+                WindowEvent::Focused(focused) => RenderMessage::Focused(focused),
+                WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                    RenderMessage::ScaleFactorChanged(scale_factor)
+                }
+                WindowEvent::CloseRequested => RenderMessage::Exit,
+                _ => return,
             },
-            WindowEvent::Resized(_) => {
+            _ => return,
+        };
+        let exit = matches!(message, RenderMessage::Exit);
+        sender.send(message).ok();
+        if exit {
+            // let the render thread tear down the device before the process exits
+            if let Some(render_thread) = render_thread.take() {
+                let ctx_settings = render_thread.join().unwrap();
+                if persist_settings {
+                    settings.present_mode = ctx_settings.present_mode;
+                    settings.enabled_pipelines = ctx_settings.enabled_pipelines;
+                    settings.update_from_window(&settings_window);
+                    if let Err(err) = settings.save(&options.settings) {
+                        eprintln!("failed to save {}: {}", options.settings.display(), err);
+                    }
+                }
+            }
+            *control_flow = ControlFlow::Exit;
+        }
+    });
+}
+
+/// Events sent to the event loop from other threads.
+#[derive(Debug)]
+pub enum UserEvent {
+    /// The render thread stopped on its own, e.g. because the XR runtime ended the session.
+    RenderThreadExited,
+    Compiler {
+        build_id: u64,
+        event: CompilerEvent,
+    },
+}
+
+/// Sent from the event loop to the render thread, which owns the `RenderCtx`.
+enum RenderMessage {
+    Resized,
+    ScaleFactorChanged(f64),
+    ToggleVsync,
+    ToggleWireframe,
+    ShowPrevious(bool),
+    TogglePause,
+    StepTime,
+    /// Multiplies the time's speed.
+    ScaleTimeSpeed(f32),
+    Focused(bool),
+    /// To this path, with the shader crate's directory for the watermark.
+    Capture(PathBuf, Option<PathBuf>),
+    /// Any key the runner doesn't use itself, for the plugins.
+    Key(VirtualKeyCode, bool),
+    MouseButton(MouseButton, bool),
+    /// In physical pixels from the top left of the window.
+    CursorMoved([f64; 2]),
+    /// In lines.
+    MouseWheel(f32),
+    Shaders(Vec<SpirvShader>),
+    /// The first shaders of a newly switched to shader crate.
+    ShaderSet(Vec<SpirvShader>),
+    /// A `.spv` file dropped onto the window.
+    DroppedSpirv(PathBuf),
+    BindDroppedShader,
+    /// The surface has to be destroyed before answering, as the window goes away afterwards.
+    Suspend(Sender<()>),
+    Resume,
+    Exit,
+}
+
+/// Renders frames as fast as the present mode and frame limiter allow, so that the event loop
+/// blocking, e.g. while a window is dragged on Windows, doesn't stall rendering.
+struct RenderThread {
+    ctx: RenderCtx,
+    frame_limiter: Option<FrameLimiter>,
+    bench: Option<Bench>,
+    /// The last dropped module, until it is bound.
+    shader_offer: Option<ShaderOffer>,
+    /// Burns a `Watermark` into captures.
+    watermark: bool,
+    #[cfg(feature = "openxr")]
+    xr_session: Option<xr::XrSession>,
+}
+
+impl RenderThread {
+    /// Returns the settings to remember from the `RenderCtx`, which is destroyed along with it.
+    fn run(mut self, messages: Receiver<RenderMessage>) -> Settings {
+        loop {
+            let message = if self.ctx.minimized || self.ctx.suspended {
+                // nothing to present to, sleep until the window is restored
+                messages.recv().map_err(|_| TryRecvError::Disconnected)
+            } else {
+                messages.try_recv()
+            };
+            match message {
+                Ok(RenderMessage::Exit) | Err(TryRecvError::Disconnected) => break,
+                Ok(message) => {
+                    self.handle(message);
+                    continue;
+                }
+                Err(TryRecvError::Empty) => {}
+            }
+
+            self.ctx.render();
+            #[cfg(feature = "openxr")]
+            if self.xr_session.is_some() {
+                // `XrSession::frame` blocks for the runtime's frame pacing, so handle what came in
+                // while rendering the window first
+                if !self.handle_pending(&messages) {
+                    break;
+                }
+                let xr_session = self.xr_session.as_mut().unwrap();
+                if !xr_session.frame(&self.ctx) {
+                    break;
+                }
+            }
+            if let Some(bench) = self.bench.as_mut() {
+                if bench.frame() {
+                    bench.report();
+                    break;
+                }
+            }
+            if let Some(frame_limiter) = self.frame_limiter.as_mut() {
+                frame_limiter.wait();
+            }
+        }
+        // the session uses the device, so it goes first
+        #[cfg(feature = "openxr")]
+        if let Some(mut xr_session) = self.xr_session.take() {
+            xr_session.destroy(&self.ctx.base.device);
+        }
+        let mut settings = Settings::default();
+        settings.update_from_ctx(&self.ctx);
+        settings
+    }
+
+    /// Handles the messages waiting in the channel.  Returns false on exit.
+    #[cfg(feature = "openxr")]
+    fn handle_pending(&mut self, messages: &Receiver<RenderMessage>) -> bool {
+        loop {
+            match messages.try_recv() {
+                Ok(RenderMessage::Exit) | Err(TryRecvError::Disconnected) => return false,
+                Ok(message) => self.handle(message),
+                Err(TryRecvError::Empty) => return true,
+            }
+        }
+    }
+
+    fn handle(&mut self, message: RenderMessage) {
+        let ctx = &mut self.ctx;
+        match message {
+            RenderMessage::Resized => ctx.recreate_swapchain(),
+            RenderMessage::ScaleFactorChanged(scale_factor) => {
+                ctx.scale_factor = scale_factor;
                 ctx.recreate_swapchain();
             }
-            WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-            _ => *control_flow = ControlFlow::Wait,
-        },
-        _ => *control_flow = ControlFlow::Wait,
+            RenderMessage::ToggleVsync => {
+                ctx.toggle_vsync();
+                println!("present mode: {:?}", ctx.present_mode);
+            }
+            RenderMessage::ToggleWireframe => ctx.toggle_wireframe(),
+            RenderMessage::ShowPrevious(show) => ctx.show_previous = show,
+            RenderMessage::TogglePause => {
+                let paused = !ctx.clock.is_paused();
+                ctx.clock.set_paused(paused);
+                let state = if paused { "paused" } else { "resumed" };
+                println!("time {} at {:.3} s", state, ctx.clock.time());
+            }
+            RenderMessage::StepTime => {
+                if ctx.clock.is_paused() {
+                    ctx.clock.step();
+                    println!("time stepped to frame {}", ctx.clock.frame.wrapping_add(1));
+                }
+            }
+            RenderMessage::ScaleTimeSpeed(factor) => {
+                let speed = ctx.clock.speed() * factor;
+                ctx.clock.set_speed(speed);
+                println!("time speed {}x", ctx.clock.speed());
+            }
+            RenderMessage::Focused(focused) => ctx.set_focused(focused),
+            RenderMessage::Capture(path, crate_dir) => {
+                if ctx.minimized || ctx.suspended {
+                    return;
+                }
+                let watermark = if self.watermark {
+                    Some(Watermark::new(ctx, crate_dir.as_deref()))
+                } else {
+                    None
+                };
+                match capture::capture_exr(ctx, &path, watermark.as_ref()) {
+                    Ok(()) => println!("saved {}", path.display()),
+                    Err(err) => eprintln!("failed to write {}: {}", path.display(), err),
+                }
+            }
+            RenderMessage::Key(key, pressed) => {
+                ctx.run_plugins(|plugin, ctx| plugin.key(ctx, key, pressed))
+            }
+            RenderMessage::MouseButton(button, pressed) => {
+                ctx.run_plugins(|plugin, ctx| plugin.mouse_button(ctx, button, pressed))
+            }
+            RenderMessage::CursorMoved(position) => {
+                ctx.cursor_position = Some(position);
+                ctx.run_plugins(|plugin, ctx| plugin.cursor_moved(ctx, position))
+            }
+            RenderMessage::MouseWheel(delta) => {
+                ctx.run_plugins(|plugin, ctx| plugin.mouse_wheel(ctx, delta))
+            }
+            RenderMessage::Shaders(shaders) => {
+                profiling::message("shaders reloaded");
+                let mut changed = false;
+                for SpirvShader { name, spirv } in shaders {
+                    changed |= ctx.insert_shader_module(name, spirv);
+                }
+                // saving an unrelated file still triggers a build, don't hitch on its output
+                if changed {
+                    ctx.rebuild_pipelines(vk::PipelineCache::null());
+                }
+            }
+            RenderMessage::ShaderSet(shaders) => {
+                profiling::message("shader crate switched");
+                shader_drop::replace_shader_set(ctx, shaders);
+            }
+            RenderMessage::DroppedSpirv(path) => {
+                self.shader_offer = shader_drop::load(ctx, &path);
+            }
+            RenderMessage::BindDroppedShader => {
+                if let Some(offer) = self.shader_offer.take() {
+                    offer.bind(ctx);
+                }
+            }
+            RenderMessage::Suspend(done) => {
+                ctx.suspend();
+                done.send(()).ok();
+            }
+            RenderMessage::Resume => {
+                if ctx.suspended {
+                    ctx.resume();
+                }
+            }
+            RenderMessage::Exit => {}
+        }
+    }
+}
+
+pub fn print_monitors<T>(event_loop: &EventLoopWindowTarget<T>) {
+    for (index, monitor) in event_loop.available_monitors().enumerate() {
+        let size = monitor.size();
+        let position = monitor.position();
+        println!(
+            "{}: {} {}x{} at {},{} (scale {})",
+            index,
+            monitor.name().unwrap_or_else(|| "<unnamed>".into()),
+            size.width,
+            size.height,
+            position.x,
+            position.y,
+            monitor.scale_factor(),
+        );
+    }
+}
+
+/// Creates the runner window with the window-level settings from `options` applied.
+/// Restores the geometry in `settings` where `options` leave it open.
+pub fn create_window<T>(
+    options: &Options,
+    settings: &Settings,
+    event_loop: &EventLoopWindowTarget<T>,
+) -> Window {
+    let monitor: Option<MonitorHandle> = options.monitor.map(|index| {
+        event_loop
+            .available_monitors()
+            .nth(index)
+            .unwrap_or_else(|| {
+                panic!(
+                    "No monitor with index {}, see --list-monitors for the available ones",
+                    index
+                )
+            })
+    });
+    // when not fullscreen, an explicit monitor just moves the window onto it
+    #[cfg(windows)]
+    let fullscreen = options.fullscreen || options.exclusive_fullscreen;
+    #[cfg(not(windows))]
+    let fullscreen = options.fullscreen;
+    // an explicit monitor places the window instead of the saved geometry
+    let fullscreen = fullscreen || (settings.fullscreen && monitor.is_none());
+    let position = options.position.or_else(|| match &monitor {
+        _ if fullscreen => None,
+        Some(monitor) => Some(monitor.position()),
+        None => settings.position.map(|[x, y]| PhysicalPosition::new(x, y)),
     });
+    let fullscreen = if fullscreen {
+        Some(Fullscreen::Borderless(monitor))
+    } else {
+        None
+    };
+
+    let builder = WindowBuilder::new().with_title("Rust GPU - ash");
+    let builder = match settings.size {
+        Some([width, height]) => {
+            builder.with_inner_size(winit::dpi::PhysicalSize::new(width, height))
+        }
+        None => builder.with_inner_size(winit::dpi::LogicalSize::new(
+            f64::from(1280),
+            f64::from(720),
+        )),
+    };
+    let builder = builder
+        .with_transparent(options.transparent)
+        .with_always_on_top(options.always_on_top)
+        .with_fullscreen(fullscreen)
+        // position the window before showing it, so it doesn't flash up somewhere else first
+        .with_visible(position.is_none());
+    let builder = if options.skip_taskbar {
+        with_skip_taskbar(builder)
+    } else {
+        builder
+    };
+    let window = builder.build(event_loop).unwrap();
+    if let Some(position) = position {
+        window.set_outer_position(position);
+        window.set_visible(true);
+    }
+    window
+}
+
+fn with_skip_taskbar(builder: WindowBuilder) -> WindowBuilder {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "macos")] {
+            use winit::platform::macos::{ActivationPolicy, WindowBuilderExtMacOS};
+            builder.with_activation_policy(ActivationPolicy::Accessory)
+        } else if #[cfg(any(
+            target_os = "linux",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "openbsd"
+        ))] {
+            // utility windows are left out of the taskbar by most X11 window managers
+            use winit::platform::unix::{WindowBuilderExtUnix, XWindowType};
+            builder.with_x11_window_type(vec![XWindowType::Utility])
+        } else {
+            println!("--skip-taskbar is not supported on this platform");
+            builder
+        }
+    }
+}
+
+/// Builds the shader crate, or loads the .spv files of the last build if its sources haven't changed
+/// since, possibly in an earlier run.
+pub fn compile_shaders(build: &ShaderBuildOptions) -> Vec<SpirvShader> {
+    build_shaders(build).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Like `compile_shaders`, but returns an error if the build fails, with the diagnostics already
+/// printed.
+pub fn build_shaders(build: &ShaderBuildOptions) -> Result<Vec<SpirvShader>, String> {
+    let source_hash = shader_source_hash(build);
+    if let Some(shaders) = cached_shaders(build, source_hash) {
+        println!("shader sources unchanged, using the cached build");
+        return Ok(shaders);
+    }
+    let cargo_out = shader_build_command(build)
+        .output()
+        .map_err(|err| format!("cargo failed to execute build: {}", err))?;
+    let report = CompileReport::parse(&cargo_out.stdout);
+    report.print();
+    if !cargo_out.status.success() || !report.success {
+        return Err("shader build failed".into());
+    }
+    if report.artifacts.is_empty() {
+        return Err("shader build produced no SPIR-V".into());
+    }
+    Ok(read_shader_artifacts(build, &report, source_hash))
+}
+
+const SHADER_CRATE_DIR: &str = "shaders";
+/// Prefix of the file recording which sources the last successful build was made from, in its
+/// target directory.
+const SHADER_BUILD_CACHE: &str = "ash-runner-build";
+
+/// Where the shader crate is without `--shader-crate`: next to the runner's own crate when run from
+/// its source tree, otherwise next to the executable, but never relative to the working directory.
+pub fn default_shader_crate_dir() -> PathBuf {
+    let source_tree = Path::new(env!("CARGO_MANIFEST_DIR")).join(SHADER_CRATE_DIR);
+    if source_tree.is_dir() {
+        return source_tree;
+    }
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join(SHADER_CRATE_DIR)))
+        .unwrap_or_else(|| PathBuf::from(SHADER_CRATE_DIR))
+}
+
+#[derive(Serialize, Deserialize)]
+struct ShaderBuildCache {
+    source_hash: u64,
+    artifacts: Vec<PathBuf>,
+}
+
+impl ShaderBuildCache {
+    fn load(build: &ShaderBuildOptions) -> Option<Self> {
+        let file = File::open(build.cache_path()).ok()?;
+        serde_json::from_reader(std::io::BufReader::new(file)).ok()
+    }
+}
+
+/// Hashes the build options and the paths and contents of every file in the shader crate, except
+/// its build output.  The hash isn't stable across std versions, which at worst costs one unneeded
+/// build.
+pub(crate) fn shader_source_hash(build: &ShaderBuildOptions) -> u64 {
+    fn hash_dir(dir: &Path, target_dir: &Path, hasher: &mut DefaultHasher) {
+        let mut entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .collect::<Vec<_>>(),
+            Err(_) => return,
+        };
+        // directory order is up to the file system
+        entries.sort();
+        for path in entries {
+            if path.is_dir() {
+                if path != target_dir && path.file_name().map_or(false, |name| name != "target") {
+                    hash_dir(&path, target_dir, hasher);
+                }
+            } else if let Ok(contents) = std::fs::read(&path) {
+                path.hash(hasher);
+                contents.hash(hasher);
+            }
+        }
+    }
+
+    let mut hasher = DefaultHasher::new();
+    build.hash(&mut hasher);
+    hash_dir(&build.crate_dir, &build.target_dir(), &mut hasher);
+    hasher.finish()
+}
+
+/// Whether the last successful build was made from sources with this hash.
+pub(crate) fn is_build_current(build: &ShaderBuildOptions, source_hash: u64) -> bool {
+    ShaderBuildCache::load(build).map_or(false, |cache| cache.source_hash == source_hash)
+}
+
+/// The shaders of the last successful build, if it was made from sources with this hash and its
+/// .spv files are still there.
+fn cached_shaders(build: &ShaderBuildOptions, source_hash: u64) -> Option<Vec<SpirvShader>> {
+    let cache = ShaderBuildCache::load(build)?;
+    if cache.source_hash != source_hash || !cache.artifacts.iter().all(|path| path.is_file()) {
+        return None;
+    }
+    Some(load_shaders(cache.artifacts))
+}
+
+/// How the shader crate is built.  Part of the source hash, since it changes the build output.
+#[derive(Debug, Clone, Hash)]
+pub struct ShaderBuildOptions {
+    /// The directory with the shader crate's Cargo.toml.
+    pub crate_dir: PathBuf,
+    /// Cargo's target directory, `target` in the crate directory if `None`.
+    pub target_dir: Option<PathBuf>,
+    /// Cargo features to enable in the shader crate.
+    pub features: Vec<String>,
+    /// Builds without `--release`, keeping debug info and variable names in the SPIR-V.
+    pub debug: bool,
+    /// Passed to cargo after the runner's own arguments.
+    pub cargo_args: Vec<String>,
+}
+
+impl Default for ShaderBuildOptions {
+    fn default() -> Self {
+        Self {
+            crate_dir: default_shader_crate_dir(),
+            target_dir: None,
+            features: Vec::new(),
+            debug: false,
+            cargo_args: Vec::new(),
+        }
+    }
+}
+
+impl ShaderBuildOptions {
+    pub fn manifest_path(&self) -> PathBuf {
+        self.crate_dir.join("Cargo.toml")
+    }
+
+    pub fn target_dir(&self) -> PathBuf {
+        match &self.target_dir {
+            Some(target_dir) => target_dir.clone(),
+            None => self.crate_dir.join("target"),
+        }
+    }
+
+    /// Named after the crate directory, so crates sharing a target directory keep separate caches.
+    fn cache_path(&self) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        self.crate_dir.hash(&mut hasher);
+        self.target_dir().join(format!(
+            "{}-{:016x}.json",
+            SHADER_BUILD_CACHE,
+            hasher.finish()
+        ))
+    }
 }
 
-pub fn compile_shaders() -> Vec<SpirvShader> {
+/// The cargo invocation that builds the shader crate, printing its artifacts and diagnostics as json
+/// to stdout.
+pub(crate) fn shader_build_command(build: &ShaderBuildOptions) -> Command {
     // Check if/what needs rebuild
     // (cargo might just handle this on its own? ignore for now)
 
-    let spirv_codegen_backend = String::from("codegen_backend=rustc_codegen_spirv.dll");
-    let rustflags = format!("-Z {} -Z symbol-mangling-version=v0", spirv_codegen_backend);
-    let manifest_path = "shaders\\Cargo.toml";
-    let target_dir = "shaders\\target";
+    let spirv_codegen_backend = format!(
+        "codegen_backend={}rustc_codegen_spirv{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    );
+    let mut rustflags = format!("-Z {} -Z symbol-mangling-version=v0", spirv_codegen_backend);
+    if build.debug {
+        rustflags.push_str(" -C debuginfo=2");
+    }
 
     // run a cargo process with spirv codegen
-    let cargo_out = Command::new("cargo")
-        .args(&["build", "--release"])
+    let mut command = Command::new("cargo");
+    command.arg("build");
+    if !build.debug {
+        command.arg("--release");
+    }
+    command
         .arg("--target-dir")
-        .arg(target_dir)
+        .arg(build.target_dir())
         .arg("--manifest-path")
-        .arg(manifest_path)
+        .arg(build.manifest_path())
         .args(&["--target", "spirv-unknown-unknown"])
-        .args(&["--message-format", "json-render-diagnostics"])
+        .args(&["--message-format", "json"])
         .args(&["-Z", "build-std=core"])
         .env("RUSTFLAGS", rustflags)
-        .stderr(Stdio::inherit())
-        .output()
-        .expect("cargo failed to execute build");
+        .stderr(Stdio::inherit());
+    if !build.features.is_empty() {
+        command.arg("--features").arg(build.features.join(","));
+    }
+    if build.debug {
+        // makes rustc_codegen_spirv emit OpName for locals
+        command.env("NAME_VARIABLES", "1");
+    }
+    command.args(&build.cargo_args);
+    command
+}
 
-    // parse the json output from cargo to get the artifact paths
-    let spv_paths: Vec<PathBuf> = String::from_utf8(cargo_out.stdout)
-        .unwrap()
-        .lines()
-        .filter_map(|line| match serde_json::from_str::<SpirvArtifacts>(line) {
-            Ok(line) => Some(line),
-            Err(_) => None,
-        })
-        .filter(|line| line.reason == "compiler-artifact")
-        .last()
-        .expect("No output artifacts")
-        .filenames
-        .expect("No artifact filenemaes")
-        .into_iter()
-        .filter(|filename| filename.ends_with(".spv"))
-        .map(Into::into)
-        .collect();
+/// Loads the SPIR-V modules of a successful shader build, and records them as the build of the
+/// sources with `source_hash`.
+pub(crate) fn read_shader_artifacts(
+    build: &ShaderBuildOptions,
+    report: &CompileReport,
+    source_hash: u64,
+) -> Vec<SpirvShader> {
+    assert!(!report.artifacts.is_empty(), "No output artifacts");
+    let spv_paths = report.artifacts.clone();
 
+    let cache = ShaderBuildCache {
+        source_hash,
+        artifacts: spv_paths.clone(),
+    };
+    let cache_path = build.cache_path();
+    if let Err(err) = File::create(&cache_path)
+        .map_err(serde_json::Error::io)
+        .and_then(|file| serde_json::to_writer(file, &cache))
+    {
+        eprintln!("failed to write {}: {}", cache_path.display(), err);
+    }
+
+    load_shaders(spv_paths)
+}
+
+fn load_shaders(spv_paths: Vec<PathBuf>) -> Vec<SpirvShader> {
     // load the spirv data into memory
     let mut artifacts = Vec::<SpirvShader>::with_capacity(spv_paths.len());
     for path in spv_paths {
@@ -178,31 +1561,305 @@ pub fn compile_shaders() -> Vec<SpirvShader> {
     artifacts
 }
 
-#[derive(Deserialize)]
-struct SpirvArtifacts {
-    reason: String,
-    filenames: Option<Vec<String>>,
+/// Frame times for `bench`, measured between consecutive frames once the warmup frames are done.
+pub struct Bench {
+    warmup: u32,
+    frames: u32,
+    last_frame: Option<Instant>,
+    frame_times: Vec<Duration>,
+}
+
+impl Bench {
+    /// Panics unless `frames` is positive, `bench --frames` is checked when parsed.
+    pub fn new(warmup: u32, frames: u32) -> Self {
+        assert!(frames > 0, "bench needs at least one frame");
+        Self {
+            warmup,
+            frames,
+            last_frame: None,
+            frame_times: Vec::with_capacity(frames as usize),
+        }
+    }
+
+    /// Call after each frame.  Returns whether all frames have been measured.
+    pub fn frame(&mut self) -> bool {
+        let now = Instant::now();
+        if self.warmup > 0 {
+            self.warmup -= 1;
+        } else if let Some(last_frame) = self.last_frame {
+            self.frame_times.push(now - last_frame);
+        }
+        self.last_frame = Some(now);
+        self.frame_times.len() >= self.frames as usize
+    }
+
+    pub fn report(&self) {
+        let mut frame_times = self
+            .frame_times
+            .iter()
+            .map(|frame_time| frame_time.as_secs_f64() * 1000.0)
+            .collect::<Vec<_>>();
+        if frame_times.is_empty() {
+            return;
+        }
+        frame_times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let average = frame_times.iter().sum::<f64>() / frame_times.len() as f64;
+        let p99 = frame_times[(frame_times.len() - 1) * 99 / 100];
+        println!(
+            "{} frames: average {:.3} ms ({:.1} fps), min {:.3} ms, max {:.3} ms, p99 {:.3} ms",
+            frame_times.len(),
+            average,
+            1000.0 / average,
+            frame_times[0],
+            frame_times[frame_times.len() - 1],
+            p99,
+        );
+    }
+}
+
+/// Sleeps after present until the next frame is due.  `thread::sleep` tends to oversleep by up to
+/// a scheduler tick, so the last bit of the wait is spent yielding instead.
+pub struct FrameLimiter {
+    frame_time: Duration,
+    next_frame: Instant,
+}
+
+impl FrameLimiter {
+    const SPIN_THRESHOLD: Duration = Duration::from_millis(2);
+
+    /// Panics unless `max_fps` is positive, `--max-fps` is checked when parsed.
+    pub fn new(max_fps: f64) -> Self {
+        assert!(max_fps > 0.0, "max fps must be positive");
+        let frame_time = Duration::from_secs_f64(1.0 / max_fps);
+        Self {
+            frame_time,
+            next_frame: Instant::now() + frame_time,
+        }
+    }
+
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        if now >= self.next_frame {
+            // running behind, don't try to catch up with a burst of frames
+            self.next_frame = now + self.frame_time;
+            return;
+        }
+        let remaining = self.next_frame - now;
+        if remaining > Self::SPIN_THRESHOLD {
+            std::thread::sleep(remaining - Self::SPIN_THRESHOLD);
+        }
+        while Instant::now() < self.next_frame {
+            std::thread::yield_now();
+        }
+        self.next_frame += self.frame_time;
+    }
+}
+
+/// Loads the precompiled `.spv` files packaged in the APK's assets, as there is no cargo on-device
+/// to compile the shader crate with.
+#[cfg(target_os = "android")]
+pub fn load_shader_assets() -> Vec<SpirvShader> {
+    let asset_manager = ndk_glue::native_activity().asset_manager();
+    asset_manager
+        .open_dir(&CString::new("").unwrap())
+        .expect("Unable to open the asset directory")
+        .filter_map(|filename| {
+            let name = filename.to_str().ok()?.strip_suffix(".spv")?.to_owned();
+            let mut asset = asset_manager.open(&filename)?;
+            let spirv = read_spv(&mut asset).unwrap();
+            Some(SpirvShader { name, spirv })
+        })
+        .collect()
 }
 
+/// The shaders embedded into the binary at build time, for iOS where apps can't run cargo.  Build
+/// the shader crate on the host and copy the resulting `.spv` files into `assets/` (the same
+/// directory that is packaged on Android) before building the app.
+#[cfg(all(target_os = "ios", sky_shader_asset))]
+pub fn load_embedded_shaders() -> Vec<SpirvShader> {
+    vec![SpirvShader::from_bytes(
+        "sky_shader",
+        include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/assets/sky_shader.spv"
+        )),
+    )]
+}
+
+#[cfg(all(target_os = "ios", not(sky_shader_asset)))]
+compile_error!(
+    "iOS builds embed assets/sky_shader.spv: build the shader crate on the host, which running \
+     the runner there does, and copy the sky_shader.spv it produces into assets/"
+);
+
 #[derive(Debug)]
 pub struct SpirvShader {
     pub name: String,
     pub spirv: Vec<u32>,
 }
 
+impl SpirvShader {
+    /// Parses a SPIR-V binary, e.g. one embedded with `include_bytes!`.
+    pub fn from_bytes(name: impl Into<String>, bytes: &[u8]) -> Self {
+        let spirv = read_spv(&mut std::io::Cursor::new(bytes)).expect("Invalid SPIR-V");
+        Self {
+            name: name.into(),
+            spirv,
+        }
+    }
+}
+
 #[non_exhaustive]
 #[derive(Debug)]
 pub enum CompilerEvent {
-    Complete(Vec<SpirvShader>),
+    Complete(Vec<SpirvShader>, CompileReport),
+    Failed(CompileReport),
+}
+
+/// Anything the renderer can create a surface for.  Implemented for winit windows; implement it for
+/// other windowing frameworks to embed the renderer in them.
+///
+/// raw-window-handle 0.3 carries the display connection inside the window handle (e.g. Xlib's
+/// `display`), so there is no separate display handle to provide.
+pub trait RenderWindow: HasRawWindowHandle + Send + Sync {
+    /// Size of the drawable area in physical pixels.
+    fn inner_size(&self) -> vk::Extent2D;
+
+    /// Ratio between physical and logical pixels.
+    fn scale_factor(&self) -> f64 {
+        1.0
+    }
+
+    /// The monitor the window is on, for exclusive fullscreen.
+    #[cfg(windows)]
+    fn hmonitor(&self) -> Option<vk::HMONITOR> {
+        None
+    }
+
+    /// Whether the surface is created with `VK_EXT_headless_surface` instead of from the window
+    /// handle, which is then never asked for.
+    fn headless(&self) -> bool {
+        false
+    }
+}
+
+/// Stands in for a window on machines without a display, e.g. CI runners.  Its surface comes from
+/// `VK_EXT_headless_surface`, which lavapipe and SwiftShader have.
+pub struct HeadlessWindow {
+    pub extent: vk::Extent2D,
+}
+
+impl RenderWindow for HeadlessWindow {
+    fn inner_size(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    fn headless(&self) -> bool {
+        true
+    }
+}
+
+unsafe impl HasRawWindowHandle for HeadlessWindow {
+    fn raw_window_handle(&self) -> RawWindowHandle {
+        unreachable!("a headless window has no window handle")
+    }
+}
+
+impl RenderWindow for Window {
+    fn inner_size(&self) -> vk::Extent2D {
+        let size = Window::inner_size(self);
+        vk::Extent2D {
+            width: size.width,
+            height: size.height,
+        }
+    }
+
+    fn scale_factor(&self) -> f64 {
+        Window::scale_factor(self)
+    }
+
+    #[cfg(windows)]
+    fn hmonitor(&self) -> Option<vk::HMONITOR> {
+        use winit::platform::windows::MonitorHandleExtWindows;
+        self.current_monitor().map(|monitor| monitor.hmonitor())
+    }
+}
+
+/// Lets a `dyn RenderWindow` be passed where ash-window expects a `dyn HasRawWindowHandle`.
+struct RawHandle(RawWindowHandle);
+
+unsafe impl HasRawWindowHandle for RawHandle {
+    fn raw_window_handle(&self) -> RawWindowHandle {
+        self.0
+    }
+}
+
+/// The instance extensions needed to create a surface for `window`.
+fn surface_extensions(window: &dyn RenderWindow) -> Vec<&'static CStr> {
+    if window.headless() {
+        vec![khr::Surface::name(), vk::ExtHeadlessSurfaceFn::name()]
+    } else {
+        ash_window::enumerate_required_extensions(&RawHandle(window.raw_window_handle())).unwrap()
+    }
+}
+
+/// Creates a surface for `window`, which the instance was created with `surface_extensions` of.
+unsafe fn create_surface(
+    entry: &Entry,
+    instance: &ash::Instance,
+    window: &dyn RenderWindow,
+) -> vk::SurfaceKHR {
+    if window.headless() {
+        let fns = vk::ExtHeadlessSurfaceFn::load(|name| {
+            std::mem::transmute(entry.get_instance_proc_addr(instance.handle(), name.as_ptr()))
+        });
+        let mut surface = vk::SurfaceKHR::null();
+        let result = fns.create_headless_surface_ext(
+            instance.handle(),
+            &vk::HeadlessSurfaceCreateInfoEXT::default(),
+            std::ptr::null(),
+            &mut surface,
+        );
+        assert_eq!(
+            result,
+            vk::Result::SUCCESS,
+            "Headless surface creation error"
+        );
+        surface
+    } else {
+        ash_window::create_surface(
+            entry,
+            instance,
+            &RawHandle(window.raw_window_handle()),
+            None,
+        )
+        .unwrap()
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub type Entry = ash_molten::MoltenEntry;
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+pub type Entry = ash::Entry;
+
+/// Vulkan objects owned by a host application, see `RenderBase::from_external`.
+pub struct ExternalDevice {
+    pub entry: Entry,
+    /// Must have the surface extensions for the window enabled.
+    pub instance: ash::Instance,
+    pub pdevice: vk::PhysicalDevice,
+    /// Must have `VK_KHR_swapchain` enabled.
+    pub device: ash::Device,
+    pub queue_family_index: u32,
+    /// Used for both rendering and presenting, so it must support graphics and presenting to the
+    /// window.
+    pub queue: vk::Queue,
 }
 
 pub struct RenderBase {
-    pub window: winit::window::Window,
+    pub window: Arc<dyn RenderWindow>,
 
-    #[cfg(target_os = "macos")]
-    pub entry: ash_molten::MoltenEntry,
-    #[cfg(not(target_os = "macos"))]
-    pub entry: ash::Entry,
+    pub entry: Entry,
 
     pub instance: ash::Instance,
     pub device: ash::Device,
@@ -218,22 +1875,83 @@ pub struct RenderBase {
     pub surface: vk::SurfaceKHR,
     pub surface_loader: khr::Surface,
     pub surface_format: vk::SurfaceFormatKHR,
+    /// The format the swapchain images are viewed and rendered as, which render passes and pipelines
+    /// must match.  Differs from `surface_format` for encoded shader output on sRGB surfaces.
+    pub view_format: vk::Format,
+
+    pub transparent: bool,
+    /// The instance and device belong to the host application and are not destroyed on drop, see
+    /// `from_external` and `from_handed_over`.
+    pub external: bool,
+    /// Where importers of the exported frames connect, see `Exporter`.
+    pub export_memory: Option<String>,
+    /// Pipelines are linked from `VK_EXT_graphics_pipeline_library` libraries.
+    pub pipeline_library: bool,
+    /// `fillModeNonSolid` is enabled, which wireframe rendering needs.
+    pub fill_mode_non_solid: bool,
+    /// Swapchain images to ask for, before clamping to the surface capabilities.  The minimum plus
+    /// one if `None`.
+    pub image_count: Option<u32>,
+    /// The core features enabled on the device, including the supported ones of the
+    /// `DeviceRequirements`.  Unknown, so all false, for external devices.
+    pub enabled_features: vk::PhysicalDeviceFeatures,
+    /// The extensions enabled on the device.  Unknown, so empty, for external devices.
+    pub enabled_extensions: Vec<CString>,
+    /// What the device can do if it only implements the Vulkan portability subset, like MoltenVK.
+    pub portability_subset: Option<PhysicalDevicePortabilitySubsetFeatures>,
+    /// Swapchains are created for exclusive fullscreen, acquired while the window has focus.
+    #[cfg(windows)]
+    pub full_screen_exclusive: Option<FullScreenExclusive>,
 }
 
 impl RenderBase {
-    pub fn new(window: winit::window::Window, options: &Options) -> Self {
+    pub fn new<W: RenderWindow + 'static>(window: Arc<W>, options: &Options) -> Self {
+        Self::with_requirements(window, options, DeviceRequirements::new())
+    }
+
+    /// Picks a device with the required features and extensions of `requirements`, enables them
+    /// and the supported optional ones, and chains its feature structs.  Panics listing what each
+    /// device lacks if none has them all.
+    pub fn with_requirements<W: RenderWindow + 'static>(
+        window: Arc<W>,
+        options: &Options,
+        mut requirements: DeviceRequirements,
+    ) -> Self {
         cfg_if::cfg_if! {
-            if #[cfg(target_os = "macos")] {
+            if #[cfg(any(target_os = "macos", target_os = "ios"))] {
+                // ash-window backs the surface with a CAMetalLayer for MoltenVK to render into
                 let entry = ash_molten::MoltenEntry::load().unwrap();
             } else {
                 let entry = ash::Entry::new().unwrap();
             }
         }
 
+        #[cfg(windows)]
+        let exclusive_fullscreen = options.exclusive_fullscreen
+            && entry
+                .enumerate_instance_extension_properties()
+                .unwrap()
+                .iter()
+                .any(|ext| unsafe {
+                    CStr::from_ptr(ext.extension_name.as_ptr())
+                        == fullscreen_exclusive::instance_extension_name()
+                });
+
+        let validation_features = options.validation.clone().unwrap_or_default();
+        let debug_layer = options.debug_layer || !validation_features.is_empty();
+        // for the debug labels captures show, even without the layer, which provides it otherwise
+        let debug_utils = debug_layer
+            || entry
+                .enumerate_instance_extension_properties()
+                .unwrap()
+                .iter()
+                .any(|ext| unsafe {
+                    CStr::from_ptr(ext.extension_name.as_ptr()) == ext::DebugUtils::name()
+                });
         let instance: ash::Instance = {
             let app_name = CString::new("VulkanTriangle").unwrap();
 
-            let layer_names = if options.debug_layer {
+            let layer_names = if debug_layer {
                 vec![CString::new("VK_LAYER_KHRONOS_validation").unwrap()]
             } else {
                 vec![]
@@ -243,14 +1961,37 @@ impl RenderBase {
                 .map(|raw_name| raw_name.as_ptr())
                 .collect();
 
-            let mut extension_names_raw = ash_window::enumerate_required_extensions(&window)
-                .unwrap()
+            let mut extension_names_raw = surface_extensions(&*window)
                 .iter()
                 .map(|ext| ext.as_ptr())
                 .collect::<Vec<_>>();
-            if options.debug_layer {
+            if debug_utils {
                 extension_names_raw.push(ext::DebugUtils::name().as_ptr());
             }
+            // provided by the validation layer
+            if !validation_features.is_empty() {
+                extension_names_raw.push(vk::ExtValidationFeaturesFn::name().as_ptr());
+            }
+            if options.color_space != ColorSpace::Srgb {
+                let colorspace_extension = vk::ExtSwapchainColorspaceFn::name();
+                let supported = entry
+                    .enumerate_instance_extension_properties()
+                    .unwrap()
+                    .iter()
+                    .any(|ext| unsafe {
+                        CStr::from_ptr(ext.extension_name.as_ptr()) == colorspace_extension
+                    });
+                assert!(
+                    supported,
+                    "{:?} output needs {:?}, which the Vulkan implementation doesn't have",
+                    options.color_space, colorspace_extension
+                );
+                extension_names_raw.push(colorspace_extension.as_ptr());
+            }
+            #[cfg(windows)]
+            if exclusive_fullscreen {
+                extension_names_raw.push(fullscreen_exclusive::instance_extension_name().as_ptr());
+            }
 
             let appinfo = vk::ApplicationInfo::builder()
                 .application_name(&app_name)
@@ -259,10 +2000,16 @@ impl RenderBase {
                 .engine_version(0)
                 .api_version(vk::make_version(1, 1, 0));
 
-            let instance_create_info = vk::InstanceCreateInfo::builder()
+            let mut validation_features_info = vk::ValidationFeaturesEXT::builder()
+                .enabled_validation_features(&validation_features.0);
+            let mut instance_create_info = vk::InstanceCreateInfo::builder()
                 .application_info(&appinfo)
                 .enabled_layer_names(&layers_names_raw)
                 .enabled_extension_names(&extension_names_raw);
+            if !validation_features.is_empty() {
+                instance_create_info =
+                    instance_create_info.push_next(&mut validation_features_info);
+            }
 
             unsafe {
                 entry
@@ -271,12 +2018,15 @@ impl RenderBase {
             }
         };
 
-        let surface =
-            unsafe { ash_window::create_surface(&entry, &instance, &window, None).unwrap() };
+        let surface = unsafe { create_surface(&entry, &instance, &*window) };
 
-        let (debug_utils_loader, debug_call_back) = if options.debug_layer {
-            let debug_utils_loader = ext::DebugUtils::new(&entry, &instance);
-            let debug_call_back = {
+        let debug_utils_loader = if debug_utils {
+            Some(ext::DebugUtils::new(&entry, &instance))
+        } else {
+            None
+        };
+        let debug_call_back = match &debug_utils_loader {
+            Some(debug_utils_loader) if debug_layer => {
                 let debug_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
                     .message_severity(
                         vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
@@ -287,25 +2037,24 @@ impl RenderBase {
                     .pfn_user_callback(Some(vulkan_debug_callback));
 
                 unsafe {
-                    debug_utils_loader
-                        .create_debug_utils_messenger(&debug_info, None)
-                        .unwrap()
+                    Some(
+                        debug_utils_loader
+                            .create_debug_utils_messenger(&debug_info, None)
+                            .unwrap(),
+                    )
                 }
-            };
-
-            (Some(debug_utils_loader), Some(debug_call_back))
-        } else {
-            (None, None)
+            }
+            _ => None,
         };
 
         let surface_loader = khr::Surface::new(&entry, &instance);
 
-        let (pdevice, queue_family_index) = unsafe {
+        let suitable = unsafe {
             instance
                 .enumerate_physical_devices()
                 .expect("Physical device error")
                 .iter()
-                .find_map(|pdevice| {
+                .filter_map(|pdevice| {
                     instance
                         .get_physical_device_queue_family_properties(*pdevice)
                         .iter()
@@ -326,55 +2075,177 @@ impl RenderBase {
                             }
                         })
                 })
-                .expect("Couldn't find suitable device.")
+                .collect::<Vec<_>>()
+        };
+        let (suitable, lacking): (Vec<_>, Vec<_>) = suitable
+            .into_iter()
+            .map(|(pdevice, queue_family_index)| {
+                let missing = requirements.missing(&instance, pdevice);
+                (pdevice, queue_family_index, missing)
+            })
+            .partition(|(_, _, missing)| missing.is_empty());
+        if suitable.is_empty() && !lacking.is_empty() {
+            let devices = lacking
+                .iter()
+                .map(|(pdevice, _, missing)| {
+                    let properties = unsafe { instance.get_physical_device_properties(*pdevice) };
+                    let name = unsafe { CStr::from_ptr(properties.device_name.as_ptr()) };
+                    format!("\n  {}: {}", name.to_string_lossy(), missing.join(", "))
+                })
+                .collect::<String>();
+            panic!(
+                "No device has the required features and extensions, missing:{}",
+                devices
+            );
+        }
+        let suitable = suitable
+            .into_iter()
+            .map(|(pdevice, queue_family_index, _)| (pdevice, queue_family_index))
+            .collect::<Vec<_>>();
+        let software = |&(pdevice, _): &(vk::PhysicalDevice, u32)| {
+            unsafe { instance.get_physical_device_properties(pdevice) }.device_type
+                == vk::PhysicalDeviceType::CPU
+        };
+        let (pdevice, queue_family_index) = if options.prefer_software {
+            let device = suitable.iter().copied().find(software);
+            if device.is_none() {
+                println!("no software Vulkan implementation found, using a hardware device");
+            }
+            device.or_else(|| suitable.first().copied())
+        } else {
+            suitable.first().copied()
+        }
+        .expect("Couldn't find suitable device.");
+
+        let surface_format = Self::choose_surface_format(
+            &surface_loader,
+            pdevice,
+            surface,
+            options.color_space,
+            options.surface_depth,
+            options.shader_output,
+        );
+        let view_format = Self::view_format(surface_format, options.shader_output);
+
+        let fill_mode_non_solid = unsafe { instance.get_physical_device_features(pdevice) }
+            .fill_mode_non_solid
+            == vk::TRUE;
+        let pipeline_library = options.pipeline_library && {
+            let supported = pipeline_library::is_supported(&instance, pdevice);
+            if !supported {
+                println!("pipeline libraries aren't supported, falling back to full pipelines");
+            }
+            supported
         };
+        let portability_subset = portability::query(&instance, pdevice);
+        #[cfg(windows)]
+        let hmonitor = window.hmonitor().filter(|_| {
+            exclusive_fullscreen && fullscreen_exclusive::is_supported(&instance, pdevice)
+        });
+        #[cfg(windows)]
+        if options.exclusive_fullscreen && hmonitor.is_none() {
+            println!("exclusive fullscreen isn't supported, staying borderless");
+        }
 
-        let device: ash::Device = {
-            let device_extension_names_raw = [khr::Swapchain::name().as_ptr()];
-            let features = vk::PhysicalDeviceFeatures {
+        let (device, enabled_features, enabled_extensions) = {
+            let mut enabled_extensions = vec![khr::Swapchain::name().to_owned()];
+            if options.export_memory.is_some() {
+                for name in export::extension_names().iter() {
+                    enabled_extensions.push((*name).to_owned());
+                }
+            }
+            if view_format != surface_format.format {
+                let supported = unsafe {
+                    instance
+                        .enumerate_device_extension_properties(pdevice)
+                        .unwrap()
+                        .iter()
+                        .any(|ext| {
+                            CStr::from_ptr(ext.extension_name.as_ptr())
+                                == vk::KhrSwapchainMutableFormatFn::name()
+                        })
+                };
+                assert!(
+                    supported,
+                    "The surface only offers sRGB formats and the device can't create UNORM views \
+                     of them for encoded shader output"
+                );
+                enabled_extensions.push(vk::KhrSwapchainMutableFormatFn::name().to_owned());
+                enabled_extensions.push(vk::KhrImageFormatListFn::name().to_owned());
+            }
+            if pipeline_library {
+                for name in pipeline_library::extension_names().iter() {
+                    enabled_extensions.push((*name).to_owned());
+                }
+            }
+            if calibrated_timestamps::is_supported(&entry, &instance, pdevice) {
+                enabled_extensions.push(calibrated_timestamps::extension_name().to_owned());
+            }
+            let synchronization2 = synchronization2::is_supported(&instance, pdevice);
+            if synchronization2 {
+                enabled_extensions.push(synchronization2::extension_name().to_owned());
+            }
+            #[cfg(windows)]
+            if hmonitor.is_some() {
+                enabled_extensions.push(fullscreen_exclusive::extension_name().to_owned());
+            }
+            let mut features = vk::PhysicalDeviceFeatures {
                 shader_clip_distance: 1,
+                fill_mode_non_solid: fill_mode_non_solid as vk::Bool32,
                 ..Default::default()
             };
+            // a portability implementation is only well defined with the subset enabled
+            if portability_subset.is_some() {
+                enabled_extensions.push(portability::extension_name().to_owned());
+            }
+            requirements.enable(&instance, pdevice, &mut features, &mut enabled_extensions);
+            let device_extension_names_raw = enabled_extensions
+                .iter()
+                .map(|name| name.as_ptr())
+                .collect::<Vec<_>>();
+            let mut library_features = PhysicalDeviceGraphicsPipelineLibraryFeatures::enabled();
+            let mut synchronization2_features = PhysicalDeviceSynchronization2Features::enabled();
+            let mut subset_features = portability_subset.unwrap_or_default();
+            // the exported semaphore is a timeline semaphore
+            let mut timeline_features =
+                vk::PhysicalDeviceTimelineSemaphoreFeatures::builder().timeline_semaphore(true);
             let priorities = [1.0];
             let queue_info = [vk::DeviceQueueCreateInfo::builder()
                 .queue_family_index(queue_family_index)
                 .queue_priorities(&priorities)
                 .build()];
-            let device_create_info = vk::DeviceCreateInfo::builder()
+            let mut device_create_info = vk::DeviceCreateInfo::builder()
                 .queue_create_infos(&queue_info)
                 .enabled_extension_names(&device_extension_names_raw)
                 .enabled_features(&features);
-            unsafe {
+            if pipeline_library {
+                device_create_info = device_create_info.push_next(&mut library_features);
+            }
+            if synchronization2 {
+                device_create_info = device_create_info.push_next(&mut synchronization2_features);
+            }
+            if portability_subset.is_some() {
+                device_create_info = device_create_info.push_next(&mut subset_features);
+            }
+            if options.export_memory.is_some() {
+                device_create_info = device_create_info.push_next(&mut timeline_features);
+            }
+            requirements.chain(&mut device_create_info);
+            let device = unsafe {
                 instance
                     .create_device(pdevice, &device_create_info, None)
                     .unwrap()
-            }
+            };
+            (device, features, enabled_extensions)
         };
 
         let swapchain_loader = khr::Swapchain::new(&instance, &device);
+        #[cfg(windows)]
+        let full_screen_exclusive =
+            hmonitor.map(|hmonitor| FullScreenExclusive::new(&instance, &device, hmonitor));
 
         let present_queue = unsafe { device.get_device_queue(queue_family_index as u32, 0) };
 
-        let surface_format = {
-            let acceptable_formats = {
-                [
-                    vk::Format::R8G8B8_SRGB,
-                    vk::Format::B8G8R8_SRGB,
-                    vk::Format::R8G8B8A8_SRGB,
-                    vk::Format::B8G8R8A8_SRGB,
-                    vk::Format::A8B8G8R8_SRGB_PACK32,
-                ]
-            };
-            unsafe {
-                *surface_loader
-                    .get_physical_device_surface_formats(pdevice, surface)
-                    .unwrap()
-                    .iter()
-                    .find(|sfmt| acceptable_formats.contains(&sfmt.format))
-                    .expect("Unable to find suitable surface format.")
-            }
-        };
-
         RenderBase {
             entry,
             instance,
@@ -384,11 +2255,188 @@ impl RenderBase {
             window,
             surface_loader,
             surface_format,
+            view_format,
             present_queue,
             swapchain_loader,
             surface,
             debug_call_back,
             debug_utils_loader,
+            transparent: options.transparent,
+            external: false,
+            export_memory: options.export_memory.clone(),
+            pipeline_library,
+            fill_mode_non_solid,
+            image_count: options.image_count,
+            enabled_features,
+            enabled_extensions,
+            portability_subset,
+            #[cfg(windows)]
+            full_screen_exclusive,
+        }
+    }
+
+    /// Builds on a Vulkan instance and device owned by the host application, so the shader hot
+    /// reloading can be dropped into an existing engine.  Only the surface and swapchain are
+    /// created here, the instance and device are left alive on drop.
+    pub fn from_external<W: RenderWindow + 'static>(
+        window: Arc<W>,
+        external: ExternalDevice,
+        options: &Options,
+    ) -> Self {
+        let ExternalDevice {
+            entry,
+            instance,
+            pdevice,
+            device,
+            queue_family_index,
+            queue,
+        } = external;
+
+        let surface = unsafe { create_surface(&entry, &instance, &*window) };
+        let surface_loader = khr::Surface::new(&entry, &instance);
+        let supports_present = unsafe {
+            surface_loader
+                .get_physical_device_surface_support(pdevice, queue_family_index, surface)
+                .unwrap()
+        };
+        assert!(
+            supports_present,
+            "The external queue family can't present to the window"
+        );
+
+        let swapchain_loader = khr::Swapchain::new(&instance, &device);
+        // encoded output of an sRGB-only surface needs the host to have enabled
+        // VK_KHR_swapchain_mutable_format on the device
+        let surface_format = Self::choose_surface_format(
+            &surface_loader,
+            pdevice,
+            surface,
+            options.color_space,
+            options.surface_depth,
+            options.shader_output,
+        );
+        let view_format = Self::view_format(surface_format, options.shader_output);
+        // the host must have enabled the subset on its device already
+        let portability_subset = portability::query(&instance, pdevice);
+
+        RenderBase {
+            entry,
+            instance,
+            device,
+            queue_family_index,
+            pdevice,
+            window,
+            surface_loader,
+            surface_format,
+            view_format,
+            present_queue: queue,
+            swapchain_loader,
+            surface,
+            debug_call_back: None,
+            debug_utils_loader: None,
+            transparent: options.transparent,
+            external: true,
+            export_memory: options.export_memory.clone(),
+            // the host would have to enable the extensions and features on its device
+            pipeline_library: false,
+            fill_mode_non_solid: false,
+            image_count: options.image_count,
+            enabled_features: vk::PhysicalDeviceFeatures::default(),
+            enabled_extensions: Vec::new(),
+            portability_subset,
+            #[cfg(windows)]
+            full_screen_exclusive: None,
+        }
+    }
+
+    /// Like `from_external`, but for an instance and device created elsewhere and handed over,
+    /// such as by the OpenXR runtime, which are destroyed on drop like the runner's own.
+    pub fn from_handed_over<W: RenderWindow + 'static>(
+        window: Arc<W>,
+        device: ExternalDevice,
+        options: &Options,
+    ) -> Self {
+        let mut base = Self::from_external(window, device, options);
+        base.external = false;
+        base
+    }
+
+    fn choose_surface_format(
+        surface_loader: &khr::Surface,
+        pdevice: vk::PhysicalDevice,
+        surface: vk::SurfaceKHR,
+        color_space: ColorSpace,
+        surface_depth: SurfaceDepth,
+        shader_output: ShaderOutput,
+    ) -> vk::SurfaceFormatKHR {
+        assert!(
+            surface_depth == SurfaceDepth::Eight
+                || color_space != ColorSpace::Srgb
+                || shader_output == ShaderOutput::Encoded,
+            "--surface-depth {:?} isn't sRGB encoded on write, so it needs --shader-output encoded \
+             in the sRGB color space",
+            surface_depth
+        );
+        let surface_formats = unsafe {
+            surface_loader
+                .get_physical_device_surface_formats(pdevice, surface)
+                .unwrap()
+        };
+        let find = |acceptable_formats: &[vk::Format]| {
+            surface_formats
+                .iter()
+                .find(|sfmt| {
+                    sfmt.color_space == color_space.to_vk()
+                        && acceptable_formats.contains(&sfmt.format)
+                })
+                .copied()
+        };
+        let deeper = find(surface_depth.formats());
+        if deeper.is_none() && surface_depth != SurfaceDepth::Eight {
+            println!(
+                "the surface has no {:?} format for {:?}, falling back to 8 bits",
+                surface_depth, color_space
+            );
+        }
+        let surface_format = match (color_space, shader_output) {
+            _ if deeper.is_some() => deeper,
+            (ColorSpace::Srgb, ShaderOutput::Linear) => find(&SRGB_FORMATS),
+            // a UNORM swapchain avoids needing UNORM views of sRGB images
+            (ColorSpace::Srgb, ShaderOutput::Encoded) => {
+                let unorm_formats = SRGB_FORMATS
+                    .iter()
+                    .filter_map(|&format| unorm_format(format))
+                    .collect::<Vec<_>>();
+                find(&unorm_formats).or_else(|| find(&SRGB_FORMATS))
+            }
+            // wide gamut and HDR color spaces take whatever format the driver pairs with them,
+            // usually 10 bit UNORM or 16 bit float
+            _ => surface_formats
+                .iter()
+                .find(|sfmt| sfmt.color_space == color_space.to_vk())
+                .copied(),
+        };
+        surface_format.unwrap_or_else(|| {
+            panic!(
+                "Unable to find a surface format for {:?}, supported color spaces: {:?}",
+                color_space,
+                surface_formats
+                    .iter()
+                    .map(|sfmt| sfmt.color_space)
+                    .collect::<Vec<_>>()
+            )
+        })
+    }
+
+    fn view_format(
+        surface_format: vk::SurfaceFormatKHR,
+        shader_output: ShaderOutput,
+    ) -> vk::Format {
+        match shader_output {
+            ShaderOutput::Linear => surface_format.format,
+            ShaderOutput::Encoded => {
+                unorm_format(surface_format.format).unwrap_or(surface_format.format)
+            }
         }
     }
 
@@ -399,15 +2447,60 @@ impl RenderBase {
                 .unwrap()
         };
         match surface_capabilities.current_extent.width {
-            std::u32::MAX => {
-                let window_inner = self.window.inner_size();
-                vk::Extent2D {
-                    width: window_inner.width,
-                    height: window_inner.height,
+            std::u32::MAX => self.window.inner_size(),
+            _ => surface_capabilities.current_extent,
+        }
+    }
+
+    /// Replaces the options of `desc` the device can't do with the closest ones it can, so they
+    /// aren't undefined behavior, and returns what was replaced.
+    pub fn restrict_pipeline(&self, desc: &mut PipelineDescriptor) -> Vec<&'static str> {
+        let mut replaced = Vec::new();
+        if desc.rasterization.line_width != 1.0 && self.enabled_features.wide_lines == vk::FALSE {
+            desc.rasterization.line_width = 1.0;
+            replaced.push("wide lines, drawing them 1 pixel wide");
+        }
+        let subset = match &self.portability_subset {
+            Some(subset) => subset,
+            None => return replaced,
+        };
+        if subset.triangle_fans == vk::FALSE
+            && desc.input_assembly.topology == vk::PrimitiveTopology::TRIANGLE_FAN
+        {
+            desc.input_assembly.topology = vk::PrimitiveTopology::TRIANGLE_LIST;
+            replaced.push("triangle fans, drawing a triangle list");
+        }
+        if subset.point_polygons == vk::FALSE
+            && desc.rasterization.polygon_mode == vk::PolygonMode::POINT
+        {
+            desc.rasterization.polygon_mode = vk::PolygonMode::FILL;
+            replaced.push("point polygons, filling them");
+        }
+        if subset.constant_alpha_color_blend_factors == vk::FALSE {
+            let mut constant_alpha = false;
+            for attachment in desc.color_blend_attachments.iter_mut() {
+                let mut factors = [
+                    &mut attachment.src_color_blend_factor,
+                    &mut attachment.dst_color_blend_factor,
+                ];
+                for factor in factors.iter_mut() {
+                    match **factor {
+                        vk::BlendFactor::CONSTANT_ALPHA => {
+                            **factor = vk::BlendFactor::CONSTANT_COLOR
+                        }
+                        vk::BlendFactor::ONE_MINUS_CONSTANT_ALPHA => {
+                            **factor = vk::BlendFactor::ONE_MINUS_CONSTANT_COLOR
+                        }
+                        _ => continue,
+                    }
+                    constant_alpha = true;
                 }
             }
-            _ => surface_capabilities.current_extent,
+            if constant_alpha {
+                replaced.push("constant alpha color blend factors, using the constant color");
+            }
         }
+        replaced
     }
 
     pub fn into_ctx(self) -> RenderCtx {
@@ -422,9 +2515,51 @@ impl RenderBase {
         }
     }
 
-    pub fn create_swapchain(&self) -> vk::SwapchainKHR {
+    pub fn present_modes(&self) -> Vec<vk::PresentModeKHR> {
+        unsafe {
+            self.surface_loader
+                .get_physical_device_surface_present_modes(self.pdevice, self.surface)
+                .unwrap()
+        }
+    }
+
+    /// Returns `preferred` if the surface supports it, otherwise the first supported mode after it
+    /// in `PRESENT_MODE_PREFERENCE`, printing what was chosen instead.  Modes outside the chain,
+    /// like FIFO_RELAXED, fall back to FIFO, which every surface supports.
+    pub fn choose_present_mode(&self, preferred: vk::PresentModeKHR) -> vk::PresentModeKHR {
+        let supported = self.present_modes();
+        if supported.contains(&preferred) {
+            return preferred;
+        }
+        let fallbacks = match PRESENT_MODE_PREFERENCE
+            .iter()
+            .position(|&present_mode| present_mode == preferred)
+        {
+            Some(position) => &PRESENT_MODE_PREFERENCE[position + 1..],
+            None => &PRESENT_MODE_PREFERENCE[PRESENT_MODE_PREFERENCE.len() - 1..],
+        };
+        let chosen = fallbacks
+            .iter()
+            .copied()
+            .find(|present_mode| supported.contains(present_mode))
+            .unwrap_or(vk::PresentModeKHR::FIFO);
+        println!(
+            "present mode {:?} is unsupported, using {:?} (the surface supports {:?})",
+            preferred, chosen, supported
+        );
+        chosen
+    }
+
+    pub fn create_swapchain(
+        &self,
+        present_mode: vk::PresentModeKHR,
+        extent: vk::Extent2D,
+    ) -> vk::SwapchainKHR {
         let surface_capabilities = self.surface_capabilities();
-        let mut desired_image_count = surface_capabilities.min_image_count + 1;
+        let mut desired_image_count = self
+            .image_count
+            .unwrap_or(surface_capabilities.min_image_count + 1)
+            .max(surface_capabilities.min_image_count);
         if surface_capabilities.max_image_count > 0
             && desired_image_count > surface_capabilities.max_image_count
         {
@@ -438,28 +2573,42 @@ impl RenderBase {
         } else {
             surface_capabilities.current_transform
         };
-        let present_mode = unsafe {
-            self.surface_loader
-                .get_physical_device_surface_present_modes(self.pdevice, self.surface)
-                .unwrap()
-                .iter()
-                .cloned()
-                .find(|&mode| mode == vk::PresentModeKHR::MAILBOX)
-                .unwrap_or(vk::PresentModeKHR::FIFO)
-        };
-        let swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
+        let composite_alpha = self.composite_alpha(&surface_capabilities);
+        // transfer source allows copying presented frames out, e.g. for exporting
+        let image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | (surface_capabilities.supported_usage_flags & vk::ImageUsageFlags::TRANSFER_SRC);
+        let view_formats = [self.surface_format.format, self.view_format];
+        let mut format_list = vk::ImageFormatListCreateInfo::builder().view_formats(&view_formats);
+        #[cfg(windows)]
+        let mut exclusive_info = vk::SurfaceFullScreenExclusiveInfoEXT::builder()
+            .full_screen_exclusive(vk::FullScreenExclusiveEXT::APPLICATION_CONTROLLED);
+        #[cfg(windows)]
+        let mut exclusive_win32_info = vk::SurfaceFullScreenExclusiveWin32InfoEXT::builder();
+        let mut swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
             .surface(self.surface)
             .min_image_count(desired_image_count)
             .image_color_space(self.surface_format.color_space)
             .image_format(self.surface_format.format)
-            .image_extent(self.surface_resolution())
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .image_extent(extent)
+            .image_usage(image_usage)
             .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
             .pre_transform(pre_transform)
-            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+            .composite_alpha(composite_alpha)
             .present_mode(present_mode)
             .clipped(true)
             .image_array_layers(1);
+        if self.view_format != self.surface_format.format {
+            swapchain_create_info = swapchain_create_info
+                .flags(vk::SwapchainCreateFlagsKHR::MUTABLE_FORMAT)
+                .push_next(&mut format_list);
+        }
+        #[cfg(windows)]
+        if let Some(full_screen_exclusive) = &self.full_screen_exclusive {
+            exclusive_win32_info = exclusive_win32_info.hmonitor(full_screen_exclusive.hmonitor);
+            swapchain_create_info = swapchain_create_info
+                .push_next(&mut exclusive_info)
+                .push_next(&mut exclusive_win32_info);
+        }
         unsafe {
             self.swapchain_loader
                 .create_swapchain(&swapchain_create_info, None)
@@ -467,16 +2616,68 @@ impl RenderBase {
         }
     }
 
-    pub fn create_image_views(&self, swapchain: vk::SwapchainKHR) -> Vec<vk::ImageView> {
+    /// Picks an alpha compositing mode.  Opaque unless the window is transparent and the surface
+    /// supports blending with what is behind it.
+    pub fn composite_alpha(
+        &self,
+        surface_capabilities: &vk::SurfaceCapabilitiesKHR,
+    ) -> vk::CompositeAlphaFlagsKHR {
+        let supported = surface_capabilities.supported_composite_alpha;
+        if self.transparent {
+            for &mode in &[
+                vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+                vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+                vk::CompositeAlphaFlagsKHR::INHERIT,
+            ] {
+                if supported.contains(mode) {
+                    return mode;
+                }
+            }
+        }
+        if supported.contains(vk::CompositeAlphaFlagsKHR::OPAQUE) {
+            vk::CompositeAlphaFlagsKHR::OPAQUE
+        } else {
+            // surfaces support at least one mode, take the lowest
+            vk::CompositeAlphaFlagsKHR::from_raw(
+                supported.as_raw() & supported.as_raw().wrapping_neg(),
+            )
+        }
+    }
+
+    pub fn find_memory_type(
+        &self,
+        memory_type_bits: u32,
+        flags: vk::MemoryPropertyFlags,
+    ) -> Option<u32> {
+        let memory_properties = unsafe {
+            self.instance
+                .get_physical_device_memory_properties(self.pdevice)
+        };
+        memory_properties.memory_types[..memory_properties.memory_type_count as usize]
+            .iter()
+            .enumerate()
+            .position(|(index, memory_type)| {
+                memory_type_bits & (1 << index) != 0 && memory_type.property_flags.contains(flags)
+            })
+            .map(|index| index as u32)
+    }
+
+    pub fn swapchain_images(&self, swapchain: vk::SwapchainKHR) -> Vec<vk::Image> {
         unsafe {
             self.swapchain_loader
                 .get_swapchain_images(swapchain)
                 .unwrap()
+        }
+    }
+
+    pub fn create_image_views(&self, images: &[vk::Image]) -> Vec<vk::ImageView> {
+        unsafe {
+            images
                 .iter()
                 .map(|&image| {
                     let create_view_info = vk::ImageViewCreateInfo::builder()
                         .view_type(vk::ImageViewType::TYPE_2D)
-                        .format(self.surface_format.format)
+                        .format(self.view_format)
                         .components(vk::ComponentMapping {
                             r: vk::ComponentSwizzle::R,
                             g: vk::ComponentSwizzle::G,
@@ -499,24 +2700,28 @@ impl RenderBase {
         }
     }
 
+    /// One framebuffer per image view, each also holding the `extra_views` of the render pass'
+    /// additional attachments.
     pub fn create_framebuffers(
         &self,
         image_views: &[vk::ImageView],
+        extra_views: &[vk::ImageView],
         render_pass: vk::RenderPass,
+        extent: vk::Extent2D,
     ) -> Vec<vk::Framebuffer> {
         image_views
             .iter()
             .map(|&present_image_view| {
-                let framebuffer_attachments = [present_image_view];
-                let surface_resolution = self.surface_resolution();
+                let mut framebuffer_attachments = vec![present_image_view];
+                framebuffer_attachments.extend_from_slice(extra_views);
                 unsafe {
                     self.device
                         .create_framebuffer(
                             &vk::FramebufferCreateInfo::builder()
                                 .render_pass(render_pass)
                                 .attachments(&framebuffer_attachments)
-                                .width(surface_resolution.width)
-                                .height(surface_resolution.height)
+                                .width(extent.width)
+                                .height(extent.height)
                                 .layers(1),
                             None,
                         )
@@ -526,40 +2731,12 @@ impl RenderBase {
             .collect()
     }
 
-    pub fn create_render_pass(&self) -> vk::RenderPass {
-        let renderpass_attachments = [vk::AttachmentDescription {
-            format: self.surface_format.format,
-            samples: vk::SampleCountFlags::TYPE_1,
-            load_op: vk::AttachmentLoadOp::CLEAR,
-            store_op: vk::AttachmentStoreOp::STORE,
-            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
-            ..Default::default()
-        }];
-        let color_attachment_refs = [vk::AttachmentReference {
-            attachment: 0,
-            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-        }];
-        let dependencies = [vk::SubpassDependency {
-            src_subpass: vk::SUBPASS_EXTERNAL,
-            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-            dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
-                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
-            dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-            ..Default::default()
-        }];
-        let subpasses = [vk::SubpassDescription::builder()
-            .color_attachments(&color_attachment_refs)
-            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-            .build()];
-        let renderpass_create_info = vk::RenderPassCreateInfo::builder()
-            .attachments(&renderpass_attachments)
-            .subpasses(&subpasses)
-            .dependencies(&dependencies);
-        unsafe {
-            self.device
-                .create_render_pass(&renderpass_create_info, None)
-                .unwrap()
-        }
+    pub fn create_render_pass(&self, config: &RenderPassConfig) -> vk::RenderPass {
+        config.create_render_pass(
+            &self.device,
+            self.view_format,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        )
     }
 
     pub fn create_render_sync(&self) -> RenderSync {
@@ -574,14 +2751,18 @@ impl RenderBase {
 impl Drop for RenderBase {
     fn drop(&mut self) {
         unsafe {
-            self.device.destroy_device(None);
+            if !self.external {
+                self.device.destroy_device(None);
+            }
             self.surface_loader.destroy_surface(self.surface, None);
             if let Some((debug_utils, call_back)) =
                 Option::zip(self.debug_utils_loader.take(), self.debug_call_back.take())
             {
                 debug_utils.destroy_debug_utils_messenger(call_back, None);
             }
-            self.instance.destroy_instance(None);
+            if !self.external {
+                self.instance.destroy_instance(None);
+            }
         }
     }
 }
@@ -678,15 +2859,88 @@ pub struct RenderCtx {
     pub sync: RenderSync,
 
     pub swapchain: vk::SwapchainKHR,
+    pub present_mode: vk::PresentModeKHR,
+    /// Size of the swapchain images in physical pixels.
+    pub extent: vk::Extent2D,
+    pub scale_factor: f64,
+    /// Set while the surface has a zero-sized extent, in which case there is no swapchain to render
+    /// to.
+    pub minimized: bool,
+    /// Set between `suspend` and `resume`, while there is no surface at all.
+    pub suspended: bool,
+    /// The window has keyboard focus.  Change with `set_focused`.
+    pub focused: bool,
+    pub images: Vec<vk::Image>,
     pub image_views: Vec<vk::ImageView>,
     pub render_pass: vk::RenderPass,
+    /// Change with `set_render_pass_config`.
+    pub render_pass_config: RenderPassConfig,
+    /// Change with `set_clear_color`, which keeps the render pass' load op in sync.
+    pub clear_color: ClearColor,
+    pub attachment_images: AttachmentImages,
     pub framebuffers: Vec<vk::Framebuffer>,
     pub commands: RenderCommandPool,
+    pub exporter: Option<Exporter>,
+    pub pipeline_libraries: Option<PipelineLibraries>,
     pub viewports: Box<[vk::Viewport]>,
     pub scissors: Box<[vk::Rect2D]>,
-    pub pipelines: Vec<Pipeline>,
+    /// Change with `set_letterbox`, which updates the viewports and scissors.
+    pub letterbox: Option<Letterbox>,
+    /// Change with `set_flip_y`, which updates the viewports.
+    pub flip_y: bool,
+    pub pipelines: PipelineRegistry,
+    /// Draws the first pipeline on the left half and the second on the right half, instead of all
+    /// enabled pipelines.  Both cover the whole render area, so their halves line up.
+    pub split_screen: Option<(PipelineHandle, PipelineHandle)>,
+    /// Draws the pipelines from before the last rebuild, to compare a reload against.
+    pub show_previous: bool,
+    /// Pipelines are built with `PolygonMode::LINE`.
+    pub wireframe: bool,
+    /// Set with `--reverse-z`, for plugins to clear and test their depth attachments with, and to
+    /// build their projections.
+    pub depth_convention: DepthConvention,
     pub shader_modules: HashMap<String, vk::ShaderModule>,
-    pub shader_set: Vec<(VertexShaderEntryPoint, FragmentShaderEntryPoint)>,
+    /// Hashes of the SPIR-V each module in `shader_modules` was created from.
+    pub shader_hashes: HashMap<String, u64>,
+    /// Push constant block sizes of the entry points in each module, from reflection.
+    pub push_constant_sizes: HashMap<String, HashMap<String, u32>>,
+    /// Names of the entry points in each module, from reflection.
+    pub entry_points: HashMap<String, Vec<String>>,
+    /// Free for plugins and scripts to pass values to the shaders, after the size and scale factor
+    /// in the push constants.
+    pub shader_params: [f32; 8],
+    /// Change with `ShaderClock::new(true)` for deterministic rendering.
+    pub clock: ShaderClock,
+    /// The hotkeys, for plugins to look up theirs.
+    pub keymap: Keymap,
+    /// Add with `add_plugin`.  Each hook runs with the plugin taken out of this list.
+    pub plugins: Vec<Box<dyn Plugin>>,
+    /// Set for `--low-latency`, which waits for each frame to complete after presenting it.
+    pub latency_markers: Option<LatencyMarkers>,
+    pub frame_timer: FrameTimer,
+    /// Submits and barriers, through `VK_KHR_synchronization2` where the device has it.
+    pub synchronization2: Synchronization2,
+    /// What shader modules are checked against when inserted.
+    pub subgroup: SubgroupSupport,
+    /// For data that changes every frame, reclaimed as the frames using it finish.
+    pub frame_ring: FrameRing,
+    /// Lines for plugins to add in `Plugin::update`, drawn over this frame.
+    pub debug_draw: DebugDraw,
+    /// What the debug lines and the grid are seen through, moved by the camera controllers.
+    pub camera: Camera,
+    pub camera_mode: CameraMode,
+    /// In physical pixels from the window's top left corner, `None` until the cursor moves over the
+    /// window.
+    pub cursor_position: Option<[f64; 2]>,
+    /// Read back after the next frame, see `request_pick`.
+    pick_requests: Vec<PickTarget>,
+    /// Set for `--perf-counters`.  Plugins rendering passes of their own sample them with
+    /// `PerfCounters::begin` and `end`.
+    pub perf_counters: Option<PerfCounters>,
+    /// Get layouts from `descriptor_set_layout` and `pipeline_layout`, which share identical ones.
+    layout_cache: RefCell<LayoutCache>,
+    /// Get samplers from `sampler`.
+    sampler_cache: RefCell<SamplerCache>,
 
     pub compiler_thread: Option<bool>,
 }
@@ -695,101 +2949,399 @@ impl RenderCtx {
     pub fn from_base(base: RenderBase) -> Self {
         let sync = RenderSync::new(&base);
 
-        let swapchain = base.create_swapchain();
-        let image_views = base.create_image_views(swapchain);
-        let render_pass = base.create_render_pass();
-        let framebuffers = base.create_framebuffers(&image_views, render_pass);
+        let present_mode = base.choose_present_mode(vk::PresentModeKHR::MAILBOX);
+        let extent = base.surface_resolution();
+        let scale_factor = base.window.scale_factor();
+        let swapchain = base.create_swapchain(present_mode, extent);
+        let images = base.swapchain_images(swapchain);
+        let image_views = base.create_image_views(&images);
+        let render_pass_config = RenderPassConfig::default();
+        let render_pass = base.create_render_pass(&render_pass_config);
+        let clear_color = ClearColor::Color(if base.transparent {
+            [0.0, 0.0, 0.0, 0.0]
+        } else {
+            [0.0, 0.0, 1.0, 0.0]
+        });
+        let framebuffers = base.create_framebuffers(&image_views, &[], render_pass, extent);
         let commands = RenderCommandPool::new(&base);
-        let (viewports, scissors) = {
-            let surface_resolution = base.surface_resolution();
-            (
-                Box::new([vk::Viewport {
-                    x: 0.0,
-                    y: surface_resolution.height as f32,
-                    width: surface_resolution.width as f32,
-                    height: -(surface_resolution.height as f32),
-                    min_depth: 0.0,
-                    max_depth: 1.0,
-                }]),
-                Box::new([vk::Rect2D {
-                    offset: vk::Offset2D { x: 0, y: 0 },
-                    extent: surface_resolution,
-                }]),
-            )
+        let frame_timer = FrameTimer::new(&base);
+        let synchronization2 = Synchronization2::new(&base);
+        let subgroup = SubgroupSupport::new(&base.instance, base.pdevice);
+        let frame_ring = FrameRing::new(&base, FRAME_RING_SIZE);
+        let (viewports, scissors) = Self::create_viewports_scissors(extent, true);
+        let exporter = Self::create_exporter(&base, extent);
+        let pipeline_libraries = if base.pipeline_library {
+            Some(PipelineLibraries::new(&base.device))
+        } else {
+            None
         };
 
         RenderCtx {
             sync,
             base,
             swapchain,
+            present_mode,
+            extent,
+            scale_factor,
+            minimized: false,
+            suspended: false,
+            focused: false,
+            images,
             image_views,
             commands,
+            exporter,
+            pipeline_libraries,
             render_pass,
+            render_pass_config,
+            clear_color,
+            attachment_images: AttachmentImages::default(),
             framebuffers,
             viewports,
             scissors,
-            pipelines: Vec::new(),
+            letterbox: None,
+            flip_y: true,
+            pipelines: PipelineRegistry::default(),
+            split_screen: None,
+            show_previous: false,
+            wireframe: false,
+            depth_convention: DepthConvention::default(),
             shader_modules: HashMap::new(),
-            shader_set: Vec::new(),
+            shader_hashes: HashMap::new(),
+            push_constant_sizes: HashMap::new(),
+            entry_points: HashMap::new(),
+            shader_params: [0.0; 8],
+            clock: ShaderClock::new(false),
+            keymap: Keymap::default(),
+            plugins: Vec::new(),
+            latency_markers: None,
+            frame_timer,
+            synchronization2,
+            subgroup,
+            frame_ring,
+            debug_draw: DebugDraw::default(),
+            camera: Camera::default(),
+            camera_mode: CameraMode::Orbit,
+            cursor_position: None,
+            pick_requests: Vec::new(),
+            perf_counters: None,
+            layout_cache: RefCell::new(LayoutCache::default()),
+            sampler_cache: RefCell::new(SamplerCache::default()),
             compiler_thread: None,
         }
     }
 
-    pub fn create_pipeline_layout(&self) -> vk::PipelineLayout {
-        let push_constant_range = vk::PushConstantRange::builder()
-            .offset(0)
-            .size(std::mem::size_of::<ShaderConstants>() as u32)
-            .stage_flags(vk::ShaderStageFlags::all())
-            .build();
-        let layout_create_info = vk::PipelineLayoutCreateInfo::builder()
-            .push_constant_ranges(&[push_constant_range])
-            .build();
-        unsafe {
-            self.base
-                .device
-                .create_pipeline_layout(&layout_create_info, None)
-                .unwrap()
+    fn create_exporter(base: &RenderBase, extent: vk::Extent2D) -> Option<Exporter> {
+        let address = base.export_memory.as_ref()?;
+        assert!(
+            base.surface_capabilities()
+                .supported_usage_flags
+                .contains(vk::ImageUsageFlags::TRANSFER_SRC),
+            "The surface doesn't support copying from swapchain images, which exporting needs"
+        );
+        Some(Exporter::new(base, address, extent))
+    }
+
+    /// Covers the whole `extent`.  With `flip_y` the viewport is flipped so +Y points up.
+    pub fn create_viewports_scissors(
+        extent: vk::Extent2D,
+        flip_y: bool,
+    ) -> (Box<[vk::Viewport]>, Box<[vk::Rect2D]>) {
+        Self::create_viewports_scissors_in(
+            vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            },
+            flip_y,
+        )
+    }
+
+    /// Covers `rect` only.  With `flip_y` the viewport is flipped so +Y points up.
+    pub fn create_viewports_scissors_in(
+        rect: vk::Rect2D,
+        flip_y: bool,
+    ) -> (Box<[vk::Viewport]>, Box<[vk::Rect2D]>) {
+        let (y, height) = if flip_y {
+            (
+                (rect.offset.y as u32 + rect.extent.height) as f32,
+                -(rect.extent.height as f32),
+            )
+        } else {
+            (rect.offset.y as f32, rect.extent.height as f32)
+        };
+        (
+            Box::new([vk::Viewport {
+                x: rect.offset.x as f32,
+                y,
+                width: rect.extent.width as f32,
+                height,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }]),
+            Box::new([rect]),
+        )
+    }
+
+    /// The part of the swapchain drawn to.
+    pub fn render_area(&self) -> vk::Rect2D {
+        match self.letterbox {
+            Some(letterbox) => letterbox.rect(self.extent),
+            None => vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            },
         }
     }
 
-    pub fn rebuild_pipelines(&mut self, pipeline_cache: vk::PipelineCache) {
-        let pipeline_layout = self.create_pipeline_layout();
-        let modules_names = self
-            .shader_set
+    /// The size reported to the shaders.
+    pub fn render_extent(&self) -> vk::Extent2D {
+        match self.letterbox {
+            Some(Letterbox::Resolution(resolution)) => resolution,
+            _ => self.render_area().extent,
+        }
+    }
+
+    /// `camera`'s transform from world to clip space for the render area, following the depth
+    /// convention and with +Y pointing up on screen either way `flip_y` is set.
+    pub fn view_projection(&self) -> [[f32; 4]; 4] {
+        self.view_projection_for(self.render_extent())
+    }
+
+    fn view_projection_for(&self, extent: vk::Extent2D) -> [[f32; 4]; 4] {
+        let aspect_ratio = extent.width as f32 / extent.height.max(1) as f32;
+        let view_projection = self
+            .camera
+            .view_projection(aspect_ratio, self.depth_convention);
+        self.flipped(view_projection)
+    }
+
+    /// Flips y in clip space unless `flip_y`, to keep a y up matrix upright in the viewport.
+    pub fn flipped(&self, mut view_projection: [[f32; 4]; 4]) -> [[f32; 4]; 4] {
+        if !self.flip_y {
+            for column in view_projection.iter_mut() {
+                column[1] = -column[1];
+            }
+        }
+        view_projection
+    }
+
+    pub fn set_letterbox(&mut self, letterbox: Option<Letterbox>) {
+        self.letterbox = letterbox;
+        self.update_viewports();
+    }
+
+    /// Whether the viewports are flipped so +Y points up in clip space, as in OpenGL, rather than
+    /// down as Vulkan defines it.  Flipping also reverses the winding order of what's drawn, so
+    /// pipelines culling back faces need the opposite `front_face` without it.
+    pub fn set_flip_y(&mut self, flip_y: bool) {
+        self.flip_y = flip_y;
+        self.update_viewports();
+    }
+
+    fn update_viewports(&mut self) {
+        let (viewports, scissors) =
+            Self::create_viewports_scissors_in(self.render_area(), self.flip_y);
+        self.viewports = viewports;
+        self.scissors = scissors;
+    }
+
+    /// A layout without descriptor sets.  Shared like the ones from `pipeline_layout`.
+    pub fn create_pipeline_layout(
+        &self,
+        push_constant_range: Option<vk::PushConstantRange>,
+    ) -> vk::PipelineLayout {
+        self.pipeline_layout(&[], push_constant_range)
+    }
+
+    /// The descriptor set layout of `bindings`, created the first time it is asked for and shared
+    /// with everyone asking for the same bindings after.  It lives until the context is dropped, so
+    /// don't destroy it.
+    pub fn descriptor_set_layout(
+        &self,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+    ) -> vk::DescriptorSetLayout {
+        self.layout_cache
+            .borrow_mut()
+            .descriptor_set_layout(&self.base.device, bindings)
+    }
+
+    /// Like `descriptor_set_layout`, for pipeline layouts.  Pipelines built with it only destroy
+    /// their `vk::Pipeline`.
+    pub fn pipeline_layout(
+        &self,
+        set_layouts: &[vk::DescriptorSetLayout],
+        push_constant_range: Option<vk::PushConstantRange>,
+    ) -> vk::PipelineLayout {
+        self.layout_cache.borrow_mut().pipeline_layout(
+            &self.base.device,
+            set_layouts,
+            push_constant_range,
+        )
+    }
+
+    /// The sampler for `desc`, shared like the layouts.  It lives until the context is dropped, so
+    /// don't destroy it.
+    pub fn sampler(&self, desc: SamplerDesc) -> vk::Sampler {
+        self.sampler_cache.borrow_mut().sampler(&self.base, desc)
+    }
+
+    /// The push constant range covering the blocks used by the vertex and fragment entry points,
+    /// visible to only the stages that use one.
+    pub(crate) fn push_constant_range(
+        &self,
+        vert: &VertexShaderEntryPoint,
+        frag: &FragmentShaderEntryPoint,
+    ) -> Option<vk::PushConstantRange> {
+        let size_of = |module: &String, entry_point: &String| {
+            self.push_constant_sizes
+                .get(module)
+                .and_then(|sizes| sizes.get(entry_point))
+                .copied()
+        };
+        let stages = [
+            (
+                vk::ShaderStageFlags::VERTEX,
+                size_of(&vert.module, &vert.entry_point),
+            ),
+            (
+                vk::ShaderStageFlags::FRAGMENT,
+                size_of(&frag.module, &frag.entry_point),
+            ),
+        ];
+        stages
             .iter()
-            .map(|(vert, frag)| {
-                let vert_module = *self.shader_modules.get(&vert.module).unwrap();
-                let vert_name = CString::new(vert.entry_point.clone()).unwrap();
-                let frag_module = *self.shader_modules.get(&frag.module).unwrap();
-                let frag_name = CString::new(frag.entry_point.clone()).unwrap();
-                ((frag_module, frag_name), (vert_module, vert_name))
+            .filter_map(|&(stage, size)| size.map(|size| (stage, size)))
+            .fold(
+                None,
+                |range: Option<vk::PushConstantRange>, (stage, size)| {
+                    let range = range.unwrap_or_default();
+                    Some(vk::PushConstantRange {
+                        stage_flags: range.stage_flags | stage,
+                        offset: 0,
+                        size: range.size.max(size),
+                    })
+                },
+            )
+    }
+
+    /// Rebuilds the pipelines of every registry entry, enabled or not.
+    /// The pipelines they replace are kept as the entries' previous pipelines.
+    pub fn rebuild_pipelines(&mut self, pipeline_cache: vk::PipelineCache) {
+        let _zone = profiling::zone("rebuild pipelines");
+        let pipelines = self.create_registry_pipelines(pipeline_cache);
+        let device = &self.base.device;
+        // the pipelines two generations back may still be in use by the last frame
+        unsafe { device.device_wait_idle().unwrap() };
+        for (entry, pipeline) in self.pipelines.entries_mut().zip(pipelines) {
+            if let Some(old) = entry.previous.take() {
+                unsafe { device.destroy_pipeline(old.pipeline, None) };
+            }
+            entry.previous = entry.pipeline.replace(pipeline);
+        }
+        self.run_plugins(|plugin, ctx| plugin.pipelines_rebuilt(ctx));
+    }
+
+    /// Takes the pipeline out of the registry and destroys it.
+    pub fn remove_pipeline(&mut self, handle: PipelineHandle) {
+        let entry = match self.pipelines.remove(handle) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let device = &self.base.device;
+        unsafe { device.device_wait_idle().unwrap() };
+        for pipeline in entry.pipeline.iter().chain(entry.previous.iter()) {
+            unsafe { device.destroy_pipeline(pipeline.pipeline, None) };
+        }
+    }
+
+    pub fn add_plugin(&mut self, mut plugin: Box<dyn Plugin>) {
+        plugin.init(self);
+        self.plugins.push(plugin);
+    }
+
+    /// Runs a hook on every plugin, which needs them out of `self` while they have it.
+    fn run_plugins(&mut self, mut hook: impl FnMut(&mut dyn Plugin, &mut Self)) {
+        let mut plugins = std::mem::take(&mut self.plugins);
+        for plugin in plugins.iter_mut() {
+            hook(plugin.as_mut(), self);
+        }
+        // plugins added by a hook go after the existing ones
+        plugins.append(&mut self.plugins);
+        self.plugins = plugins;
+    }
+
+    fn create_registry_pipelines(&self, pipeline_cache: vk::PipelineCache) -> Vec<Pipeline> {
+        if let Some(libraries) = &self.pipeline_libraries {
+            let (_entry_points, descs) = self.pipeline_descriptors();
+            let layouts = descs
+                .iter()
+                .map(|desc| self.create_pipeline_layout(desc.push_constant_range))
+                .collect::<Vec<_>>();
+            return libraries
+                .link(&self.base.device, self.render_pass, &descs, &layouts)
+                .into_iter()
+                .zip(layouts)
+                .zip(descs)
+                .zip(self.pipelines.entries())
+                .map(|(((pipeline, pipeline_layout), desc), entry)| Pipeline {
+                    pipeline,
+                    pipeline_layout,
+                    push_constant_range: desc.push_constant_range,
+                    color_blend_attachments: desc.color_blend_attachments,
+                    dynamic_state: desc.dynamic_state,
+                    debug_label: Some(debug_label(&entry.fragment)),
+                })
+                .collect();
+        }
+        // the previous pipelines only differ in their shaders, deriving from them lets the driver
+        // reuse the rest
+        let base_pipelines = self
+            .pipelines
+            .entries()
+            .map(|entry| {
+                entry
+                    .pipeline
+                    .as_ref()
+                    .map_or(vk::Pipeline::null(), |pipeline| pipeline.pipeline)
             })
             .collect::<Vec<_>>();
-        let viewport = vk::PipelineViewportStateCreateInfo::builder();
-        let descs = modules_names
+        self.create_pipelines(self.render_pass, pipeline_cache, &base_pipelines)
+    }
+
+    /// Creates a pipeline for every registry entry, in draw order, for use in `render_pass`.  Each
+    /// one is a derivative of the pipeline at the same index in `base_pipelines`, unless that is
+    /// missing or null.
+    pub fn create_pipelines(
+        &self,
+        render_pass: vk::RenderPass,
+        pipeline_cache: vk::PipelineCache,
+        base_pipelines: &[vk::Pipeline],
+    ) -> Vec<Pipeline> {
+        let (_entry_points, descs) = self.pipeline_descriptors();
+        let layouts = descs
             .iter()
-            .map(|((frag_module, frag_name), (vert_module, vert_name))| {
-                PipelineDescriptor::new(Box::new([
-                    vk::PipelineShaderStageCreateInfo {
-                        module: *vert_module,
-                        p_name: (*vert_name).as_ptr(),
-                        stage: vk::ShaderStageFlags::VERTEX,
-                        ..Default::default()
-                    },
-                    vk::PipelineShaderStageCreateInfo {
-                        s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
-                        module: *frag_module,
-                        p_name: (*frag_name).as_ptr(),
-                        stage: vk::ShaderStageFlags::FRAGMENT,
-                        ..Default::default()
-                    },
-                ]))
-            })
+            .map(|desc| self.create_pipeline_layout(desc.push_constant_range))
             .collect::<Vec<_>>();
+        let viewport = vk::PipelineViewportStateCreateInfo::builder();
         let pipeline_info = descs
             .iter()
-            .map(|desc| {
+            .zip(layouts.iter())
+            .enumerate()
+            .map(|(index, (desc, &pipeline_layout))| {
+                let (flags, base_pipeline) = match base_pipelines.get(index) {
+                    Some(&base) if base != vk::Pipeline::null() => (
+                        vk::PipelineCreateFlags::ALLOW_DERIVATIVES
+                            | vk::PipelineCreateFlags::DERIVATIVE,
+                        base,
+                    ),
+                    _ => (
+                        vk::PipelineCreateFlags::ALLOW_DERIVATIVES,
+                        vk::Pipeline::null(),
+                    ),
+                };
                 vk::GraphicsPipelineCreateInfo::builder()
+                    .flags(flags)
+                    .base_pipeline_handle(base_pipeline)
+                    .base_pipeline_index(-1)
                     .stages(&desc.shader_stages)
                     .vertex_input_state(&desc.vertex_input)
                     .input_assembly_state(&desc.input_assembly)
@@ -800,40 +3352,117 @@ impl RenderCtx {
                     .dynamic_state(&desc.dynamic_state_info)
                     .viewport_state(&viewport)
                     .layout(pipeline_layout)
-                    .render_pass(self.render_pass)
+                    .render_pass(render_pass)
                     .build()
             })
             .collect::<Vec<_>>();
-        self.pipelines = unsafe {
-            self.base
-                .device
-                .create_graphics_pipelines(pipeline_cache, &pipeline_info, None)
+        let device = &self.base.device;
+        let create = |infos: &[vk::GraphicsPipelineCreateInfo]| unsafe {
+            device
+                .create_graphics_pipelines(pipeline_cache, infos, None)
                 .expect("Unable to create graphics pipeline")
-        }
-        .iter()
-        .zip(descs)
-        .map(|(&pipeline, desc)| Pipeline {
-            pipeline,
-            pipeline_layout,
-            color_blend_attachments: desc.color_blend_attachments,
-            dynamic_state: desc.dynamic_state,
-        })
-        .collect();
+        };
+        // pipeline creation is free threaded, so larger shader sets are spread over the thread pool
+        let pipelines = if pipeline_info.len() > PIPELINES_PER_JOB {
+            pipeline_info
+                .chunks(PIPELINES_PER_JOB)
+                .map(PipelineCreateInfos)
+                .collect::<Vec<_>>()
+                .into_par_iter()
+                .map(|infos| create(infos.0))
+                .collect::<Vec<_>>()
+                .concat()
+        } else {
+            create(&pipeline_info)
+        };
+        pipelines
+            .iter()
+            .zip(layouts)
+            .zip(descs)
+            .zip(self.pipelines.entries())
+            .map(|(((&pipeline, pipeline_layout), desc), entry)| Pipeline {
+                pipeline,
+                pipeline_layout,
+                push_constant_range: desc.push_constant_range,
+                color_blend_attachments: desc.color_blend_attachments,
+                dynamic_state: desc.dynamic_state,
+                debug_label: Some(debug_label(&entry.fragment)),
+            })
+            .collect()
     }
 
-    pub fn build_pipelines(
-        &mut self,
-        pipeline_cache: vk::PipelineCache,
-        shader_set: Vec<(VertexShaderEntryPoint, FragmentShaderEntryPoint)>,
-    ) {
-        self.shader_set = shader_set;
-        self.rebuild_pipelines(pipeline_cache);
+    /// Descriptors for every registry entry, in draw order.  Their shader stages point into the
+    /// returned entry point names, which must be kept alive while they are used.
+    fn pipeline_descriptors(&self) -> (Vec<CString>, Vec<PipelineDescriptor>) {
+        let mut entry_points = Vec::with_capacity(self.pipelines.len() * 2);
+        let descs = self
+            .pipelines
+            .entries()
+            .map(|entry| {
+                let (vert, frag) = (&entry.vertex, &entry.fragment);
+                let vert_module = *self.shader_modules.get(&vert.module).unwrap();
+                let vert_name = CString::new(vert.entry_point.clone()).unwrap();
+                let frag_module = *self.shader_modules.get(&frag.module).unwrap();
+                let frag_name = CString::new(frag.entry_point.clone()).unwrap();
+                let color_attachments = self.render_pass_config.color_attachment_count();
+                let mut desc = PipelineDescriptor::builder(Box::new([
+                    vk::PipelineShaderStageCreateInfo {
+                        module: vert_module,
+                        p_name: vert_name.as_ptr(),
+                        stage: vk::ShaderStageFlags::VERTEX,
+                        ..Default::default()
+                    },
+                    vk::PipelineShaderStageCreateInfo {
+                        s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
+                        module: frag_module,
+                        p_name: frag_name.as_ptr(),
+                        stage: vk::ShaderStageFlags::FRAGMENT,
+                        ..Default::default()
+                    },
+                ]))
+                .color_blend_attachments(&vec![
+                    BlendMode::Opaque.attachment_state();
+                    color_attachments
+                ])
+                .push_constant_range(self.push_constant_range(vert, frag))
+                .config(&entry.config)
+                .polygon_mode(if self.wireframe {
+                    vk::PolygonMode::LINE
+                } else {
+                    vk::PolygonMode::FILL
+                })
+                .build();
+                for replaced in self.base.restrict_pipeline(&mut desc) {
+                    println!("\"{}\": the device has no {}", entry.name, replaced);
+                }
+                // moving a CString doesn't move the string the stages point to
+                entry_points.push(vert_name);
+                entry_points.push(frag_name);
+                desc
+            })
+            .collect();
+        (entry_points, descs)
     }
 
-    /// Add a shader module to the hash map of shader modules.  returns a handle to the module, and the
-    /// old shader module if there was one with the same name already.  Does not rebuild pipelines
-    /// that may be using the shader module, nor does it invalidate them.
-    pub fn insert_shader_module(&mut self, name: String, spirv: Vec<u32>) {
+    /// Add a shader module to the hash map of shader modules, replacing the old shader module if
+    /// there was one with the same name already.  Returns false without creating a module if the
+    /// SPIR-V is identical to the loaded one.  Does not rebuild pipelines that may be using the
+    /// shader module, nor does it invalidate them.
+    pub fn insert_shader_module(&mut self, name: String, spirv: Vec<u32>) -> bool {
+        let mut hasher = DefaultHasher::new();
+        spirv.hash(&mut hasher);
+        let hash = hasher.finish();
+        if self.shader_modules.contains_key(&name) && self.shader_hashes.get(&name) == Some(&hash) {
+            return false;
+        }
+        self.shader_hashes.insert(name.clone(), hash);
+        self.push_constant_sizes
+            .insert(name.clone(), reflect::push_constant_sizes(&spirv));
+        self.entry_points
+            .insert(name.clone(), reflect::entry_points(&spirv));
+        for problem in self.subgroup.check(&spirv) {
+            eprintln!("warning: shader module \"{}\" {}", name, problem);
+        }
         let shader_info = vk::ShaderModuleCreateInfo::builder().code(&spirv);
         let shader_module = unsafe {
             self.base
@@ -844,86 +3473,344 @@ impl RenderCtx {
         if let Some(old_module) = self.shader_modules.insert(name, shader_module) {
             unsafe { self.base.device.destroy_shader_module(old_module, None) }
         };
+        true
     }
 
     // Recreates the swapchain, but does not recreate the pipelines because they use dynamic state.
     pub fn recreate_swapchain(&mut self) {
+        if self.suspended {
+            // there is no surface to size it from, `resume` recreates it at the size by then
+            return;
+        }
+        let extent = self.base.surface_resolution();
+        self.minimized = extent.width == 0 || extent.height == 0;
+        if self.minimized {
+            // a swapchain can't be zero-sized, keep the old one until the window is restored
+            return;
+        }
         // cleanup
         unsafe {
             self.base.device.device_wait_idle().unwrap();
-            // framebuffers
+            // framebuffers
+            for framebuffer in self.framebuffers.drain(..) {
+                self.base.device.destroy_framebuffer(framebuffer, None)
+            }
+            // command buffers
+            self.base.device.free_command_buffers(
+                self.commands.pool,
+                &[
+                    self.commands.draw_command_buffer,
+                    self.commands.setup_command_buffer,
+                ],
+            );
+            // render pass
+            self.base.device.destroy_render_pass(self.render_pass, None);
+            // image views
+            for image_view in self.image_views.drain(..) {
+                self.base.device.destroy_image_view(image_view, None);
+            }
+            self.attachment_images.destroy(&self.base.device);
+            // swapchain
+            self.base
+                .swapchain_loader
+                .destroy_swapchain(self.swapchain, None);
+        }
+        // swapchain
+        self.extent = extent;
+        self.swapchain = self.base.create_swapchain(self.present_mode, self.extent);
+        #[cfg(windows)]
+        if let Some(full_screen_exclusive) = self.base.full_screen_exclusive.as_mut() {
+            // exclusive mode went with the old swapchain
+            full_screen_exclusive.acquired = false;
+            if self.focused {
+                full_screen_exclusive.acquire(&self.base.device, self.swapchain);
+            }
+        }
+        // image_views
+        self.images = self.base.swapchain_images(self.swapchain);
+        self.image_views = self.base.create_image_views(&self.images);
+        // render_pass
+        self.render_pass = self.base.create_render_pass(&self.render_pass_config);
+        self.attachment_images =
+            AttachmentImages::new(&self.base, &self.render_pass_config, extent);
+        // command buffers
+        let command_buffers = {
+            let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_buffer_count(2)
+                .command_pool(self.commands.pool)
+                .level(vk::CommandBufferLevel::PRIMARY);
+
+            unsafe {
+                self.base
+                    .device
+                    .allocate_command_buffers(&command_buffer_allocate_info)
+                    .unwrap()
+            }
+        };
+        self.commands.setup_command_buffer = command_buffers[0];
+        self.commands.draw_command_buffer = command_buffers[1];
+        // framebuffers
+        self.framebuffers = self.base.create_framebuffers(
+            &self.image_views,
+            &self.attachment_images.views(),
+            self.render_pass,
+            self.extent,
+        );
+        self.prepare_attachments();
+        self.update_viewports();
+        // exported image, announced to the importers
+        if let Some(exporter) = self.exporter.as_mut() {
+            exporter.replace_image(&self.base, self.extent);
+        }
+        self.run_plugins(|plugin, ctx| plugin.swapchain_recreated(ctx));
+    }
+
+    /// Sets the color frames start from, switching the render pass to loading the previous frame
+    /// for `ClearColor::DontClear`.
+    pub fn set_clear_color(&mut self, clear_color: ClearColor) {
+        self.clear_color = clear_color;
+        let color_load_op = match clear_color {
+            ClearColor::Color(_) => vk::AttachmentLoadOp::CLEAR,
+            ClearColor::DontClear => vk::AttachmentLoadOp::LOAD,
+        };
+        if color_load_op != self.render_pass_config.color_load_op {
+            self.set_render_pass_config(RenderPassConfig {
+                color_load_op,
+                ..self.render_pass_config.clone()
+            });
+        }
+    }
+
+    /// Switches to a render pass with different attachments, rebuilding the pipelines for it.
+    pub fn set_render_pass_config(&mut self, config: RenderPassConfig) {
+        self.render_pass_config = config;
+        // recreating the swapchain recreates everything that depends on the render pass
+        self.recreate_swapchain();
+        self.rebuild_pipelines(vk::PipelineCache::null());
+    }
+
+    /// Moves freshly created images into the layouts the render pass expects when it loads them,
+    /// instead of clearing.
+    fn prepare_attachments(&self) {
+        let mut barriers = self
+            .attachment_images
+            .initial_barriers(&self.render_pass_config);
+        if self.render_pass_config.color_load_op == vk::AttachmentLoadOp::LOAD {
+            barriers.extend(self.images.iter().map(|&image| {
+                render_pass::layout_barrier(
+                    image,
+                    vk::ImageAspectFlags::COLOR,
+                    vk::ImageLayout::PRESENT_SRC_KHR,
+                )
+            }));
+        }
+        if barriers.is_empty() {
+            return;
+        }
+        let device = &self.base.device;
+        let command_buffer = self.commands.setup_command_buffer;
+        let fence = self.sync.setup_commands_reuse_fence;
+        unsafe {
+            device
+                .wait_for_fences(&[fence], true, std::u64::MAX)
+                .expect("Wait for fence failed.");
+            device.reset_fences(&[fence]).expect("Reset fences failed.");
+            device
+                .begin_command_buffer(
+                    command_buffer,
+                    &vk::CommandBufferBeginInfo::builder()
+                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                )
+                .expect("Begin commandbuffer");
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            );
+            device
+                .end_command_buffer(command_buffer)
+                .expect("End commandbuffer");
+            let command_buffers = [command_buffer];
+            let submit_info = vk::SubmitInfo::builder().command_buffers(&command_buffers);
+            device
+                .queue_submit(self.base.present_queue, &[submit_info.build()], fence)
+                .expect("queue submit failed.");
+        }
+    }
+
+    /// Destroys the surface and the swapchain built on it.  Needed on Android, where the native
+    /// window goes away whenever the app is sent to the background.
+    pub fn suspend(&mut self) {
+        unsafe {
+            self.base.device.device_wait_idle().unwrap();
             for framebuffer in self.framebuffers.drain(..) {
                 self.base.device.destroy_framebuffer(framebuffer, None)
             }
-            // command buffers
-            self.base.device.free_command_buffers(
-                self.commands.pool,
-                &[
-                    self.commands.draw_command_buffer,
-                    self.commands.setup_command_buffer,
-                ],
-            );
-            // render pass
-            self.base.device.destroy_render_pass(self.render_pass, None);
-            // image views
             for image_view in self.image_views.drain(..) {
                 self.base.device.destroy_image_view(image_view, None);
             }
-            // swapchain
+            if let Some(exporter) = self.exporter.as_mut() {
+                exporter.release_image(&self.base.device);
+            }
             self.base
                 .swapchain_loader
                 .destroy_swapchain(self.swapchain, None);
+            self.base
+                .surface_loader
+                .destroy_surface(self.base.surface, None);
         }
-        // swapchain
-        self.swapchain = self.base.create_swapchain();
-        // image_views
-        self.image_views = self.base.create_image_views(self.swapchain);
-        // render_pass
-        self.render_pass = self.base.create_render_pass();
-        // command buffers
-        let command_buffers = {
-            let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::builder()
-                .command_buffer_count(2)
-                .command_pool(self.commands.pool)
-                .level(vk::CommandBufferLevel::PRIMARY);
+        self.images.clear();
+        self.swapchain = vk::SwapchainKHR::null();
+        self.base.surface = vk::SurfaceKHR::null();
+        self.suspended = true;
+    }
 
-            unsafe {
-                self.base
-                    .device
-                    .allocate_command_buffers(&command_buffer_allocate_info)
-                    .unwrap()
-            }
+    /// Recreates the surface and swapchain destroyed by `suspend`.
+    pub fn resume(&mut self) {
+        self.base.surface =
+            unsafe { create_surface(&self.base.entry, &self.base.instance, &*self.base.window) };
+        self.suspended = false;
+        // the old handles are null now, which the cleanup in here treats as a no-op
+        self.recreate_swapchain();
+    }
+
+    /// Switches the present mode, falling back along `PRESENT_MODE_PREFERENCE` if unsupported, and
+    /// recreates the swapchain.
+    pub fn set_present_mode(&mut self, present_mode: vk::PresentModeKHR) {
+        self.present_mode = self.base.choose_present_mode(present_mode);
+        self.recreate_swapchain();
+    }
+
+    /// Toggles between FIFO (vsync) and MAILBOX, or IMMEDIATE where the surface lacks MAILBOX.
+    pub fn toggle_vsync(&mut self) {
+        let present_mode = if self.present_mode == vk::PresentModeKHR::FIFO {
+            vk::PresentModeKHR::MAILBOX
+        } else {
+            vk::PresentModeKHR::FIFO
         };
-        self.commands.setup_command_buffer = command_buffers[0];
-        self.commands.draw_command_buffer = command_buffers[1];
-        // framebuffers
-        self.framebuffers = self
-            .base
-            .create_framebuffers(&self.image_views, self.render_pass);
+        self.set_present_mode(present_mode);
+    }
+
+    /// Switches all pipelines between filled and wireframe rendering and rebuilds them.  Wireframes
+    /// need the `fillModeNonSolid` feature, without it this does nothing.
+    pub fn set_wireframe(&mut self, wireframe: bool) {
+        if wireframe && !self.base.fill_mode_non_solid {
+            println!("wireframe rendering isn't supported by the device");
+            return;
+        }
+        self.wireframe = wireframe;
+        self.rebuild_pipelines(vk::PipelineCache::null());
+    }
+
+    /// Exclusive fullscreen is only held while the window has focus, so other windows can be
+    /// switched to.
+    pub fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+        #[cfg(windows)]
+        if let Some(full_screen_exclusive) = self.base.full_screen_exclusive.as_mut() {
+            if focused {
+                full_screen_exclusive.acquire(&self.base.device, self.swapchain);
+            } else {
+                full_screen_exclusive.release(&self.base.device, self.swapchain);
+            }
+        }
+    }
+
+    pub fn toggle_wireframe(&mut self) {
+        self.set_wireframe(!self.wireframe);
     }
 
     pub fn render(&mut self) {
-        let (present_index, _) = unsafe {
-            self.base
-                .swapchain_loader
-                .acquire_next_image(
-                    self.swapchain,
-                    std::u64::MAX,
-                    self.sync.present_complete_semaphore,
-                    vk::Fence::null(),
-                )
-                .expect("failed to acquire next image")
+        let _frame_zone = profiling::zone("frame");
+        let frame_start = Instant::now();
+        if let Some(latency_markers) = self.latency_markers.as_mut() {
+            latency_markers.frame_start();
+        }
+        self.frame_timer.frame_start(&self.base.device);
+        if let Some(perf_counters) = self.perf_counters.as_mut() {
+            perf_counters.frame_start(&self.base);
+        }
+        self.clock.begin_frame();
+        self.debug_draw.clear();
+        {
+            let _zone = profiling::zone("update");
+            self.run_plugins(|plugin, ctx| plugin.update(ctx));
+        }
+        let acquire_zone = profiling::zone("acquire");
+        let acquire_start = Instant::now();
+        let present_index = match unsafe {
+            self.base.swapchain_loader.acquire_next_image(
+                self.swapchain,
+                std::u64::MAX,
+                self.sync.present_complete_semaphore,
+                vk::Fence::null(),
+            )
+        } {
+            Ok((present_index, _)) => present_index,
+            // exclusive fullscreen is acquired again for the new swapchain
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR)
+            | Err(vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT) => {
+                self.recreate_swapchain();
+                return;
+            }
+            Err(err) => panic!("failed to acquire next image: {:?}", err),
         };
+        let acquire_wait = acquire_start.elapsed();
+        drop(acquire_zone);
 
         let framebuffer = self.framebuffers[present_index as usize];
-        let clear_values = [vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0.0, 0.0, 1.0, 0.0],
-            },
-        }];
+        let image = self.images[present_index as usize];
+        let clear_values = self.clear_values();
 
-        for pipeline in self.pipelines.iter() {
-            self.draw(pipeline, framebuffer, &clear_values);
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            })
+            .clear_values(&clear_values)
+            .build();
+        self.record_submit_commandbuffer(
+            &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT],
+            |device, draw_command_buffer| unsafe {
+                let _zone = profiling::zone("record");
+                self.frame_timer.begin(device, draw_command_buffer);
+                for plugin in self.plugins.iter() {
+                    plugin.before_render_pass(self, draw_command_buffer);
+                }
+                self.frame_timer
+                    .begin_pass(device, draw_command_buffer, "window pass");
+                if let Some(perf_counters) = &self.perf_counters {
+                    perf_counters.begin(device, draw_command_buffer, "window pass");
+                }
+                device.cmd_begin_render_pass(
+                    draw_command_buffer,
+                    &render_pass_begin_info,
+                    vk::SubpassContents::INLINE,
+                );
+                self.draw_frame(draw_command_buffer);
+                device.cmd_end_render_pass(draw_command_buffer);
+                if let Some(perf_counters) = &self.perf_counters {
+                    perf_counters.end(device, draw_command_buffer);
+                }
+                self.frame_timer.end_pass(device, draw_command_buffer);
+                for plugin in self.plugins.iter() {
+                    plugin.after_render_pass(self, draw_command_buffer, image);
+                }
+                if let Some(exported_image) = self.exporter.as_ref().and_then(Exporter::image) {
+                    exported_image.record_copy(device, draw_command_buffer, image);
+                }
+                self.frame_timer.end(device, draw_command_buffer);
+            },
+        );
+        if let Some(exporter) = self.exporter.as_mut() {
+            exporter.signal(&self.base.device, self.base.present_queue);
+            exporter.accept();
         }
 
         let wait_semaphors = [self.sync.rendering_complete_semaphore];
@@ -933,65 +3820,290 @@ impl RenderCtx {
             .wait_semaphores(&wait_semaphors)
             .swapchains(&swapchains)
             .image_indices(&image_indices);
-        unsafe {
+        let present_zone = profiling::zone("present");
+        let present_start = Instant::now();
+        match unsafe {
             self.base
                 .swapchain_loader
                 .queue_present(self.base.present_queue, &present_info)
-                .expect("failed to present queue");
+        } {
+            Ok(_) => {}
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR)
+            | Err(vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT) => {
+                self.recreate_swapchain()
+            }
+            Err(err) => panic!("failed to present queue: {:?}", err),
+        }
+        let present_wait = present_start.elapsed();
+        drop(present_zone);
+        profiling::frame_mark();
+        self.frame_timer.frame_submitted(
+            &self.base.device,
+            FrameTiming {
+                cpu: frame_start.elapsed() - acquire_wait - present_wait,
+                gpu: None,
+                latency: None,
+                acquire_wait,
+                present_wait,
+            },
+        );
+        if let Some(perf_counters) = self.perf_counters.as_mut() {
+            perf_counters.frame_submitted(&self.base.device);
+        }
+        if let Some(latency_markers) = self.latency_markers.as_mut() {
+            latency_markers.presented();
+            let _zone = profiling::zone("wait for GPU");
+            unsafe {
+                self.base
+                    .device
+                    .wait_for_fences(&[self.sync.draw_commands_reuse_fence], true, std::u64::MAX)
+                    .expect("Wait for fence failed.");
+            }
+            latency_markers.gpu_done();
         }
+        self.resolve_picks();
     }
 
-    pub fn draw(
-        &self,
-        pipeline: &Pipeline,
-        framebuffer: vk::Framebuffer,
-        clear_values: &[vk::ClearValue],
-    ) {
-        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
-            .render_pass(self.render_pass)
-            .framebuffer(framebuffer)
-            .render_area(vk::Rect2D {
-                offset: vk::Offset2D { x: 0, y: 0 },
-                extent: self.base.surface_resolution(),
-            })
-            .clear_values(clear_values)
-            .build();
-        self.record_submit_commandbuffer(
-            &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT],
-            |device, draw_command_buffer| {
-                unsafe {
-                    device.cmd_begin_render_pass(
-                        draw_command_buffer,
-                        &render_pass_begin_info,
-                        vk::SubpassContents::INLINE,
-                    );
-                    device.cmd_bind_pipeline(
-                        draw_command_buffer,
-                        vk::PipelineBindPoint::GRAPHICS,
-                        pipeline.pipeline,
-                    );
-                    device.cmd_set_viewport(draw_command_buffer, 0, &self.viewports);
-                    device.cmd_set_scissor(draw_command_buffer, 0, &self.scissors);
+    /// Reads back the pixel under the cursor once the next frame is rendered and passes it to
+    /// `Plugin::picked`.  Dropped if the cursor isn't over the frame by then.
+    pub fn request_pick(&mut self, target: PickTarget) {
+        self.pick_requests.push(target);
+    }
 
-                    let push_constants = ShaderConstants {
-                        width: 1920, // ash runner currently does not support resizing.
-                        height: 720,
-                    };
-                    device.cmd_push_constants(
-                        draw_command_buffer,
-                        pipeline.pipeline_layout,
-                        ash::vk::ShaderStageFlags::all(),
-                        0,
-                        any_as_u8_slice(&push_constants),
-                    );
+    fn resolve_picks(&mut self) {
+        if self.pick_requests.is_empty() {
+            return;
+        }
+        let requests = std::mem::take(&mut self.pick_requests);
+        let position = match self.cursor_position {
+            Some([x, y])
+                if x >= 0.0
+                    && y >= 0.0
+                    && x < f64::from(self.extent.width)
+                    && y < f64::from(self.extent.height) =>
+            {
+                [x as u32, y as u32]
+            }
+            _ => return,
+        };
+        let region = vk::Rect2D {
+            offset: vk::Offset2D {
+                x: position[0] as i32,
+                y: position[1] as i32,
+            },
+            extent: vk::Extent2D {
+                width: 1,
+                height: 1,
+            },
+        };
+        for target in requests {
+            let texel = match target {
+                PickTarget::Color => self.read_pixels(region),
+                PickTarget::Attachment(index) => self.read_attachment(index, region),
+            };
+            let pick = Pick {
+                target,
+                position,
+                texel,
+            };
+            self.run_plugins(|plugin, ctx| plugin.picked(ctx, &pick));
+        }
+    }
 
-                    device.cmd_draw(draw_command_buffer, 3, 1, 0, 0);
-                    device.cmd_end_render_pass(draw_command_buffer);
+    /// Records what goes inside the window's render pass: the enabled pipelines, or the split screen
+    /// pair, then the plugins' draws.
+    pub(crate) fn draw_frame(&self, command_buffer: vk::CommandBuffer) {
+        match self.split_screen_pipelines() {
+            Some(halves) => {
+                for (pipeline, scissor) in halves.iter() {
+                    self.draw_to(
+                        pipeline,
+                        command_buffer,
+                        self.render_extent(),
+                        &self.viewports,
+                        std::slice::from_ref(scissor),
+                    );
                 }
+            }
+            None => {
+                let pipelines = if self.show_previous {
+                    self.pipelines.enabled_previous().collect::<Vec<_>>()
+                } else {
+                    self.pipelines.enabled().collect()
+                };
+                for pipeline in pipelines {
+                    self.draw(pipeline, command_buffer);
+                }
+            }
+        }
+        for plugin in self.plugins.iter() {
+            plugin.draw(self, command_buffer);
+        }
+    }
+
+    /// Renders the window's frame again, plugins' draws included, and reads back `region` of it as
+    /// RGBA with 8 bits per channel, top row first.  The color channels are as the window shows
+    /// them, i.e. encoded with the swapchain's transfer function, and the frame starts cleared even
+    /// with `ClearColor::DontClear`.  Waits for the device, so it's for screenshots, picking and
+    /// tests rather than every frame.
+    pub fn read_pixels(&self, region: vk::Rect2D) -> Vec<u8> {
+        readback::read_pixels(self, region)
+    }
+
+    /// Like `read_pixels`, for the extra color attachment at `index` in `render_pass_config`.  The
+    /// texels are as stored in the attachment's format, e.g. 4 bytes per texel of an ID attachment
+    /// of `R32_UINT`.
+    pub fn read_attachment(&self, index: usize, region: vk::Rect2D) -> Vec<u8> {
+        readback::read_attachment(self, index, region)
+    }
+
+    /// One per attachment of the render pass.
+    pub fn clear_values(&self) -> Vec<vk::ClearValue> {
+        let color = match self.clear_color {
+            ClearColor::Color(color) => color,
+            // ignored when loading
+            ClearColor::DontClear => [0.0; 4],
+        };
+        let mut clear_values = vec![vk::ClearValue {
+            color: vk::ClearColorValue { float32: color },
+        }];
+        clear_values.extend(
+            self.render_pass_config
+                .extra_attachments
+                .iter()
+                .map(|attachment| attachment.clear_value),
+        );
+        clear_values
+    }
+
+    /// The split screen pipelines with the scissor of their half, if both are built.
+    fn split_screen_pipelines(&self) -> Option<[(&Pipeline, vk::Rect2D); 2]> {
+        let (left, right) = self.split_screen?;
+        let left = self.pipelines.get(left)?.pipeline.as_ref()?;
+        let right = self.pipelines.get(right)?.pipeline.as_ref()?;
+        let area = self.render_area();
+        let left_width = area.extent.width / 2;
+        let left_scissor = vk::Rect2D {
+            offset: area.offset,
+            extent: vk::Extent2D {
+                width: left_width,
+                height: area.extent.height,
+            },
+        };
+        let right_scissor = vk::Rect2D {
+            offset: vk::Offset2D {
+                x: area.offset.x + left_width as i32,
+                y: area.offset.y,
+            },
+            extent: vk::Extent2D {
+                width: area.extent.width - left_width,
+                height: area.extent.height,
             },
+        };
+        Some([(left, left_scissor), (right, right_scissor)])
+    }
+
+    /// Records drawing `pipeline` into the render pass begun in `render`.
+    pub fn draw(&self, pipeline: &Pipeline, draw_command_buffer: vk::CommandBuffer) {
+        self.draw_to(
+            pipeline,
+            draw_command_buffer,
+            self.render_extent(),
+            &self.viewports,
+            &self.scissors,
+        );
+    }
+
+    /// The push constants for drawing to a target of this size.
+    pub fn shader_constants(&self, extent: vk::Extent2D) -> ShaderConstants {
+        ShaderConstants {
+            width: extent.width,
+            height: extent.height,
+            scale_factor: self.scale_factor as f32,
+            params: self.shader_params,
+            time: self.clock.time(),
+            frame: self.clock.frame,
+            seed: self.clock.seed,
+            view_projection: self.view_projection_for(extent),
+        }
+    }
+
+    /// Like `draw`, but for a render target other than the swapchain, e.g. an XR eye.
+    pub fn draw_to(
+        &self,
+        pipeline: &Pipeline,
+        draw_command_buffer: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        viewports: &[vk::Viewport],
+        scissors: &[vk::Rect2D],
+    ) {
+        let push_constants = self.shader_constants(extent);
+        self.draw_with_constants(
+            pipeline,
+            draw_command_buffer,
+            &push_constants,
+            viewports,
+            scissors,
         );
     }
 
+    /// Like `draw_to`, with the push constants given instead of `shader_constants`.
+    pub fn draw_with_constants(
+        &self,
+        pipeline: &Pipeline,
+        draw_command_buffer: vk::CommandBuffer,
+        push_constants: &ShaderConstants,
+        viewports: &[vk::Viewport],
+        scissors: &[vk::Rect2D],
+    ) {
+        let device = &self.base.device;
+        if let Some(label) = &pipeline.debug_label {
+            self.begin_debug_label(draw_command_buffer, label);
+        }
+        unsafe {
+            device.cmd_bind_pipeline(
+                draw_command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.pipeline,
+            );
+            device.cmd_set_viewport(draw_command_buffer, 0, viewports);
+            device.cmd_set_scissor(draw_command_buffer, 0, scissors);
+
+            if let Some(range) = pipeline.push_constant_range {
+                // a smaller block only reads a prefix of the constants
+                let bytes = any_as_u8_slice(push_constants);
+                let size = bytes.len().min(range.size as usize);
+                device.cmd_push_constants(
+                    draw_command_buffer,
+                    pipeline.pipeline_layout,
+                    range.stage_flags,
+                    0,
+                    &bytes[..size],
+                );
+            }
+
+            device.cmd_draw(draw_command_buffer, 3, 1, 0, 0);
+        }
+        if pipeline.debug_label.is_some() {
+            self.end_debug_label(draw_command_buffer);
+        }
+    }
+
+    /// Opens a region of commands named `label` in captures, closed by `end_debug_label`.  Does
+    /// nothing where the instance lacks `VK_EXT_debug_utils`.
+    pub fn begin_debug_label(&self, command_buffer: vk::CommandBuffer, label: &CStr) {
+        if let Some(debug_utils) = &self.base.debug_utils_loader {
+            let label = vk::DebugUtilsLabelEXT::builder().label_name(label);
+            unsafe { debug_utils.cmd_begin_debug_utils_label(command_buffer, &label) };
+        }
+    }
+
+    pub fn end_debug_label(&self, command_buffer: vk::CommandBuffer) {
+        if let Some(debug_utils) = &self.base.debug_utils_loader {
+            unsafe { debug_utils.cmd_end_debug_utils_label(command_buffer) };
+        }
+    }
+
     /// Helper function for submitting command buffers. Immediately waits for the fence before the command buffer
     /// is executed. That way we can delay the waiting for the fences by 1 frame which is good for performance.
     /// Make sure to create the fence in a signaled state on the first use.
@@ -1005,6 +4117,7 @@ impl RenderCtx {
                 .device
                 .wait_for_fences(&[self.sync.draw_commands_reuse_fence], true, std::u64::MAX)
                 .expect("Wait for fence failed.");
+            self.frame_ring.reclaim();
 
             self.base
                 .device
@@ -1037,23 +4150,31 @@ impl RenderCtx {
                 .end_command_buffer(self.commands.draw_command_buffer)
                 .expect("End commandbuffer");
 
-            let command_buffers = vec![self.commands.draw_command_buffer];
-            let wait_semaphores = &[self.sync.present_complete_semaphore];
-            let signal_semaphores = &[self.sync.rendering_complete_semaphore];
-            let submit_info = vk::SubmitInfo::builder()
-                .wait_semaphores(wait_semaphores)
-                .wait_dst_stage_mask(wait_mask)
-                .command_buffers(&command_buffers)
-                .signal_semaphores(signal_semaphores);
-
-            self.base
-                .device
-                .queue_submit(
+            let wait_stage = wait_mask
+                .iter()
+                .fold(PipelineStageFlags2::NONE, |stages, &stage| {
+                    stages | PipelineStageFlags2::from(stage)
+                });
+            let submit = Submit {
+                wait: &[SemaphoreSubmit {
+                    semaphore: self.sync.present_complete_semaphore,
+                    stage: wait_stage,
+                }],
+                command_buffers: &[self.commands.draw_command_buffer],
+                signal: &[SemaphoreSubmit {
+                    semaphore: self.sync.rendering_complete_semaphore,
+                    stage: PipelineStageFlags2::ALL_COMMANDS,
+                }],
+            };
+            self.synchronization2
+                .submit(
+                    &self.base.device,
                     self.base.present_queue,
-                    &[submit_info.build()],
+                    &submit,
                     self.sync.draw_commands_reuse_fence,
                 )
                 .expect("queue submit failed.");
+            self.frame_ring.frame_submitted();
         }
     }
 }
@@ -1062,6 +4183,8 @@ impl Drop for RenderCtx {
     fn drop(&mut self) {
         unsafe {
             self.base.device.device_wait_idle().unwrap();
+            self.run_plugins(|plugin, ctx| plugin.destroy(ctx));
+            self.plugins.clear();
             self.base
                 .device
                 .destroy_semaphore(self.sync.present_complete_semaphore, None);
@@ -1077,6 +4200,20 @@ impl Drop for RenderCtx {
             for &image_view in self.image_views.iter() {
                 self.base.device.destroy_image_view(image_view, None);
             }
+            if let Some(exporter) = self.exporter.as_mut() {
+                exporter.destroy(&self.base.device);
+            }
+            if let Some(libraries) = self.pipeline_libraries.take() {
+                libraries.destroy(&self.base.device);
+            }
+            self.attachment_images.destroy(&self.base.device);
+            self.frame_timer.destroy(&self.base.device);
+            self.frame_ring.destroy(&self.base.device);
+            if let Some(mut perf_counters) = self.perf_counters.take() {
+                perf_counters.destroy(&self.base.device);
+            }
+            self.layout_cache.get_mut().destroy(&self.base.device);
+            self.sampler_cache.get_mut().destroy(&self.base.device);
             self.base
                 .device
                 .destroy_command_pool(self.commands.pool, None);
@@ -1087,31 +4224,75 @@ impl Drop for RenderCtx {
     }
 }
 
+const PIPELINES_PER_JOB: usize = 4;
+
+/// Converts scrolling by touchpads, which report pixels, to lines of a mouse wheel.
+const PIXELS_PER_SCROLL_LINE: f64 = 20.0;
+
+/// Enough for the dynamic data of a few frames in flight.
+const FRAME_RING_SIZE: vk::DeviceSize = 4 << 20;
+
+/// Create infos point into descriptors and strings that outlive the parallel creation and are only
+/// read from there.
+struct PipelineCreateInfos<'a>(&'a [vk::GraphicsPipelineCreateInfo]);
+
+unsafe impl Send for PipelineCreateInfos<'_> {}
+
+#[derive(Deserialize)]
 pub struct VertexShaderEntryPoint {
     pub module: String,
     pub entry_point: String,
 }
 
+#[derive(Deserialize)]
 pub struct FragmentShaderEntryPoint {
-    module: String,
-    entry_point: String,
+    pub module: String,
+    pub entry_point: String,
+}
+
+#[derive(Deserialize)]
+pub struct ComputeShaderEntryPoint {
+    pub module: String,
+    pub entry_point: String,
 }
 
 pub struct Pipeline {
     pub pipeline: vk::Pipeline,
     pub pipeline_layout: vk::PipelineLayout,
+    pub push_constant_range: Option<vk::PushConstantRange>,
     pub color_blend_attachments: Box<[vk::PipelineColorBlendAttachmentState]>,
     pub dynamic_state: Box<[vk::DynamicState]>,
+    /// Wrapped around the pipeline's draws as a debug label, for captures.
+    pub debug_label: Option<CString>,
 }
 
 impl Pipeline {
+    /// Labels the draws with the fragment shader as `module::entry_point`.
+    pub fn labeled(mut self, fragment: &FragmentShaderEntryPoint) -> Self {
+        self.debug_label = Some(debug_label(fragment));
+        self
+    }
+
     pub fn new(
         ctx: &RenderCtx,
         desc: PipelineDescriptor,
         pipeline_cache: vk::PipelineCache,
+    ) -> Self {
+        let pipeline_layout = ctx.create_pipeline_layout(desc.push_constant_range);
+        Self::with_layout(ctx, desc, pipeline_layout, ctx.render_pass, pipeline_cache)
+    }
+
+    /// For pipelines with descriptor sets, or drawing into a render pass other than the window's.
+    /// The layout should come from `RenderCtx::pipeline_layout`, which keeps it alive for as long
+    /// as the pipeline.
+    pub fn with_layout(
+        ctx: &RenderCtx,
+        desc: PipelineDescriptor,
+        pipeline_layout: vk::PipelineLayout,
+        render_pass: vk::RenderPass,
+        pipeline_cache: vk::PipelineCache,
     ) -> Self {
         let viewport = vk::PipelineViewportStateCreateInfo::builder();
-        let pipeline_layout = ctx.create_pipeline_layout();
 
         let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
             .stages(&desc.shader_stages)
@@ -1124,7 +4305,7 @@ impl Pipeline {
             .dynamic_state(&desc.dynamic_state_info)
             .viewport_state(&viewport)
             .layout(pipeline_layout)
-            .render_pass(ctx.render_pass);
+            .render_pass(render_pass);
 
         let pipeline = unsafe {
             ctx.base
@@ -1138,16 +4319,33 @@ impl Pipeline {
         Self {
             pipeline_layout,
             pipeline,
+            push_constant_range: desc.push_constant_range,
             color_blend_attachments: desc.color_blend_attachments,
             dynamic_state: desc.dynamic_state,
+            debug_label: None,
         }
     }
 }
 
+/// `module::entry_point`, the name captures show for the draws of a pipeline with the shader.
+fn debug_label(entry_point: &FragmentShaderEntryPoint) -> CString {
+    CString::new(format!(
+        "{}::{}",
+        entry_point.module, entry_point.entry_point
+    ))
+    .unwrap()
+}
+
+/// Everything needed to create a graphics pipeline.  The create infos point into the boxed slices,
+/// which must only be replaced through `PipelineDescriptorBuilder`.
 pub struct PipelineDescriptor {
+    pub vertex_bindings: Box<[vk::VertexInputBindingDescription]>,
+    pub vertex_attributes: Box<[vk::VertexInputAttributeDescription]>,
     pub color_blend_attachments: Box<[vk::PipelineColorBlendAttachmentState]>,
     pub dynamic_state: Box<[vk::DynamicState]>,
     pub shader_stages: Box<[vk::PipelineShaderStageCreateInfo]>,
+    /// The pipeline layout's only push constant range, if the shaders use push constants.
+    pub push_constant_range: Option<vk::PushConstantRange>,
     pub vertex_input: vk::PipelineVertexInputStateCreateInfo,
     pub input_assembly: vk::PipelineInputAssemblyStateCreateInfo,
     pub rasterization: vk::PipelineRasterizationStateCreateInfo,
@@ -1158,7 +4356,9 @@ pub struct PipelineDescriptor {
 }
 
 impl PipelineDescriptor {
-    fn new(shader_stages: Box<[vk::PipelineShaderStageCreateInfo]>) -> Self {
+    /// A descriptor for drawing a fullscreen pass without vertex buffers: no culling, depth test or
+    /// blending, with dynamic viewport and scissor.
+    pub fn new(shader_stages: Box<[vk::PipelineShaderStageCreateInfo]>) -> Self {
         let vertex_input = vk::PipelineVertexInputStateCreateInfo {
             vertex_attribute_description_count: 0,
             vertex_binding_description_count: 0,
@@ -1195,16 +4395,7 @@ impl PipelineDescriptor {
             max_depth_bounds: 1.0,
             ..Default::default()
         };
-        let color_blend_attachments = Box::new([vk::PipelineColorBlendAttachmentState {
-            blend_enable: 0,
-            src_color_blend_factor: vk::BlendFactor::SRC_COLOR,
-            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_DST_COLOR,
-            color_blend_op: vk::BlendOp::ADD,
-            src_alpha_blend_factor: vk::BlendFactor::ZERO,
-            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
-            alpha_blend_op: vk::BlendOp::ADD,
-            color_write_mask: vk::ColorComponentFlags::all(),
-        }]);
+        let color_blend_attachments = Box::new([BlendMode::Opaque.attachment_state()]);
         let color_blend = vk::PipelineColorBlendStateCreateInfo::builder()
             .logic_op(vk::LogicOp::CLEAR)
             .attachments(color_blend_attachments.as_ref())
@@ -1217,6 +4408,9 @@ impl PipelineDescriptor {
 
         Self {
             shader_stages,
+            push_constant_range: None,
+            vertex_bindings: Box::new([]),
+            vertex_attributes: Box::new([]),
             vertex_input,
             input_assembly,
             rasterization,
@@ -1228,9 +4422,190 @@ impl PipelineDescriptor {
             dynamic_state_info,
         }
     }
+
+    /// Starts from the defaults of `new`.
+    pub fn builder(
+        shader_stages: Box<[vk::PipelineShaderStageCreateInfo]>,
+    ) -> PipelineDescriptorBuilder {
+        PipelineDescriptorBuilder {
+            desc: Self::new(shader_stages),
+        }
+    }
+}
+
+/// Overrides parts of the default `PipelineDescriptor`, keeping the create infos pointing at the
+/// slices they describe.
+pub struct PipelineDescriptorBuilder {
+    desc: PipelineDescriptor,
+}
+
+impl PipelineDescriptorBuilder {
+    /// Applies a registry entry's fixed function state: culling, front face, depth bias and the
+    /// blend mode of every color attachment.
+    pub fn config(self, config: &PipelineConfig) -> Self {
+        let builder = self
+            .cull_mode(config.cull_mode)
+            .front_face(config.front_face)
+            .blend(config.blend);
+        match config.depth_bias {
+            Some(depth_bias) => builder.depth_bias(
+                depth_bias.constant_factor,
+                depth_bias.clamp,
+                depth_bias.slope_factor,
+            ),
+            None => builder,
+        }
+    }
+
+    pub fn push_constant_range(mut self, range: Option<vk::PushConstantRange>) -> Self {
+        self.desc.push_constant_range = range;
+        self
+    }
+
+    pub fn vertex_input(
+        mut self,
+        bindings: &[vk::VertexInputBindingDescription],
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) -> Self {
+        let desc = &mut self.desc;
+        desc.vertex_bindings = bindings.into();
+        desc.vertex_attributes = attributes.into();
+        desc.vertex_input.vertex_binding_description_count = desc.vertex_bindings.len() as u32;
+        desc.vertex_input.p_vertex_binding_descriptions = desc.vertex_bindings.as_ptr();
+        desc.vertex_input.vertex_attribute_description_count = desc.vertex_attributes.len() as u32;
+        desc.vertex_input.p_vertex_attribute_descriptions = desc.vertex_attributes.as_ptr();
+        self
+    }
+
+    pub fn topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.desc.input_assembly.topology = topology;
+        self
+    }
+
+    pub fn primitive_restart(mut self, enable: bool) -> Self {
+        self.desc.input_assembly.primitive_restart_enable = enable as vk::Bool32;
+        self
+    }
+
+    /// Anything but `FILL` needs the `fillModeNonSolid` feature.
+    pub fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> Self {
+        self.desc.rasterization.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags) -> Self {
+        self.desc.rasterization.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn front_face(mut self, front_face: vk::FrontFace) -> Self {
+        self.desc.rasterization.front_face = front_face;
+        self
+    }
+
+    pub fn line_width(mut self, line_width: f32) -> Self {
+        self.desc.rasterization.line_width = line_width;
+        self
+    }
+
+    pub fn depth_bias(mut self, constant_factor: f32, clamp: f32, slope_factor: f32) -> Self {
+        let rasterization = &mut self.desc.rasterization;
+        rasterization.depth_bias_enable = vk::TRUE;
+        rasterization.depth_bias_constant_factor = constant_factor;
+        rasterization.depth_bias_clamp = clamp;
+        rasterization.depth_bias_slope_factor = slope_factor;
+        self
+    }
+
+    pub fn rasterization_samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.desc.multisample.rasterization_samples = samples;
+        self
+    }
+
+    /// Enables the depth test.  The render pass needs a depth attachment for it to do anything.
+    pub fn depth_test(mut self, compare_op: vk::CompareOp, write: bool) -> Self {
+        let depth_stencil = &mut self.desc.depth_stencil;
+        depth_stencil.depth_test_enable = vk::TRUE;
+        depth_stencil.depth_write_enable = write as vk::Bool32;
+        depth_stencil.depth_compare_op = compare_op;
+        self
+    }
+
+    /// Enables the same stencil test for front and back faces: compares `reference` with the
+    /// stencil value using `compare_op`, then applies `pass_op` where both the stencil and depth
+    /// tests pass, `fail_op` where the stencil test fails, and `depth_fail_op` where only the depth
+    /// test does.  The render pass needs an attachment with a stencil aspect, see
+    /// `render_pass::stencil_format`.
+    pub fn stencil(
+        self,
+        compare_op: vk::CompareOp,
+        reference: u32,
+        pass_op: vk::StencilOp,
+        fail_op: vk::StencilOp,
+        depth_fail_op: vk::StencilOp,
+    ) -> Self {
+        let state = vk::StencilOpState {
+            fail_op,
+            pass_op,
+            depth_fail_op,
+            compare_op,
+            compare_mask: 0xff,
+            write_mask: 0xff,
+            reference,
+        };
+        self.stencil_test(state, state)
+    }
+
+    pub fn stencil_test(mut self, front: vk::StencilOpState, back: vk::StencilOpState) -> Self {
+        let depth_stencil = &mut self.desc.depth_stencil;
+        depth_stencil.stencil_test_enable = vk::TRUE;
+        depth_stencil.front = front;
+        depth_stencil.back = back;
+        self
+    }
+
+    /// One state per color attachment of the subpass.
+    pub fn color_blend_attachments(
+        mut self,
+        attachments: &[vk::PipelineColorBlendAttachmentState],
+    ) -> Self {
+        let desc = &mut self.desc;
+        desc.color_blend_attachments = attachments.into();
+        desc.color_blend.attachment_count = desc.color_blend_attachments.len() as u32;
+        desc.color_blend.p_attachments = desc.color_blend_attachments.as_ptr();
+        self
+    }
+
+    /// Blends every color attachment the same way.
+    pub fn blend(mut self, blend: BlendMode) -> Self {
+        // assigned in place, the color blend state points at the attachments
+        for attachment in self.desc.color_blend_attachments.iter_mut() {
+            *attachment = blend.attachment_state();
+        }
+        self
+    }
+
+    pub fn blend_constants(mut self, blend_constants: [f32; 4]) -> Self {
+        self.desc.color_blend.blend_constants = blend_constants;
+        self
+    }
+
+    /// Replaces the default dynamic viewport and scissor.  Pipelines don't have a static viewport,
+    /// so keep those two; drawing sets only them, any other dynamic state is up to the caller.
+    pub fn dynamic_states(mut self, dynamic_states: &[vk::DynamicState]) -> Self {
+        let desc = &mut self.desc;
+        desc.dynamic_state = dynamic_states.into();
+        desc.dynamic_state_info.dynamic_state_count = desc.dynamic_state.len() as u32;
+        desc.dynamic_state_info.p_dynamic_states = desc.dynamic_state.as_ptr();
+        self
+    }
+
+    pub fn build(self) -> PipelineDescriptor {
+        self.desc
+    }
 }
 
-unsafe fn any_as_u8_slice<T: Sized>(p: &T) -> &[u8] {
+pub(crate) unsafe fn any_as_u8_slice<T: Sized>(p: &T) -> &[u8] {
     ::std::slice::from_raw_parts((p as *const T) as *const u8, ::std::mem::size_of::<T>())
 }
 
@@ -0,0 +1,82 @@
+//! CPU events stamped in the GPU's timestamp domain with `VK_EXT_calibrated_timestamps`, enabled
+//! whenever the device can calibrate its timestamps.  The start of a frame, where the plugins
+//! sample input, is then comparable with the timestamp the GPU writes when it finishes the frame,
+//! which gives the end-to-end latency the frame graph and `--timing-csv` show.
+
+use ash::{
+    version::{EntryV1_0, InstanceV1_0},
+    vk,
+};
+
+use std::{ffi::CStr, ptr};
+
+pub fn extension_name() -> &'static CStr {
+    vk::ExtCalibratedTimestampsFn::name()
+}
+
+/// Whether the device has the extension and can calibrate the timestamps it writes.
+pub fn is_supported<E: EntryV1_0>(
+    entry: &E,
+    instance: &ash::Instance,
+    pdevice: vk::PhysicalDevice,
+) -> bool {
+    let has_extension = unsafe { instance.enumerate_device_extension_properties(pdevice) }
+        .unwrap_or_default()
+        .iter()
+        .any(|ext| unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) } == extension_name());
+    if !has_extension {
+        return false;
+    }
+    let fns = vk::ExtCalibratedTimestampsFn::load(|name| unsafe {
+        std::mem::transmute(entry.get_instance_proc_addr(instance.handle(), name.as_ptr()))
+    });
+    let mut count = 0;
+    let result = (fns.get_physical_device_calibrateable_time_domains_ext)(
+        pdevice,
+        &mut count,
+        ptr::null_mut(),
+    );
+    if result != vk::Result::SUCCESS {
+        return false;
+    }
+    let mut time_domains = vec![vk::TimeDomainEXT::default(); count as usize];
+    let result = (fns.get_physical_device_calibrateable_time_domains_ext)(
+        pdevice,
+        &mut count,
+        time_domains.as_mut_ptr(),
+    );
+    result == vk::Result::SUCCESS && time_domains.contains(&vk::TimeDomainEXT::DEVICE)
+}
+
+pub struct CalibratedTimestamps {
+    fns: vk::ExtCalibratedTimestampsFn,
+}
+
+impl CalibratedTimestamps {
+    /// The device must have been created with the extension.
+    pub fn new(instance: &ash::Instance, device: &ash::Device) -> Self {
+        let fns = vk::ExtCalibratedTimestampsFn::load(|name| unsafe {
+            std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+        });
+        Self { fns }
+    }
+
+    /// The GPU's timestamp at this moment on the CPU, in the ticks `vkCmdWriteTimestamp` writes.
+    pub fn now(&self, device: &ash::Device) -> Option<u64> {
+        let info = vk::CalibratedTimestampInfoEXT::builder()
+            .time_domain(vk::TimeDomainEXT::DEVICE)
+            .build();
+        let mut timestamp = 0;
+        let mut max_deviation = 0;
+        match (self.fns.get_calibrated_timestamps_ext)(
+            device.handle(),
+            1,
+            &info,
+            &mut timestamp,
+            &mut max_deviation,
+        ) {
+            vk::Result::SUCCESS => Some(timestamp),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! The camera the runner's 3D helpers look through, like the debug lines and the grid, and that the
+//! camera controllers move.  It lives in `ctx.camera`; `RenderCtx::view_projection` combines it
+//! with the render area's aspect ratio, the depth convention and the Y flip.
+//!
+//! Matrices are column major, `[[f32; 4]; 4]` arrays of columns as GLSL's `mat4` lays them out, so
+//! they can go into push constants and uniforms as they are.
+
+use crate::render_pass::DepthConvention;
+
+use std::f32::consts::PI;
+
+/// Which camera controller moves `ctx.camera`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Dragging orbits around the target, see `OrbitCamera`.
+    Orbit,
+    /// Moving with the keys and looking around with the mouse, see `FlyCamera`.
+    Fly,
+}
+
+/// Looks from `eye` at `target`, with +y up.
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    /// Vertical field of view, in radians.
+    pub fov_y: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            eye: [0.0, 2.0, 5.0],
+            target: [0.0; 3],
+            fov_y: PI / 3.0,
+            near: 0.1,
+            far: 1000.0,
+        }
+    }
+}
+
+impl Camera {
+    /// The unit vectors pointing right, up and forward from the camera's point of view.
+    pub fn basis(&self) -> ([f32; 3], [f32; 3], [f32; 3]) {
+        let forward = normalize(sub(self.target, self.eye));
+        let right = normalize(cross(forward, [0.0, 1.0, 0.0]));
+        let up = cross(right, forward);
+        (right, up, forward)
+    }
+
+    /// From world space to a right-handed view space looking down -z.
+    pub fn view(&self) -> [[f32; 4]; 4] {
+        let (right, up, forward) = self.basis();
+        let eye = self.eye;
+        [
+            [right[0], up[0], -forward[0], 0.0],
+            [right[1], up[1], -forward[1], 0.0],
+            [right[2], up[2], -forward[2], 0.0],
+            [-dot(right, eye), -dot(up, eye), dot(forward, eye), 1.0],
+        ]
+    }
+
+    /// From world space to clip space, with y up in clip space.
+    pub fn view_projection(
+        &self,
+        aspect_ratio: f32,
+        depth_convention: DepthConvention,
+    ) -> [[f32; 4]; 4] {
+        let projection =
+            depth_convention.perspective(self.fov_y, aspect_ratio, self.near, self.far);
+        mul(&projection, &self.view())
+    }
+}
+
+/// `a * b`, applying `b` first.
+pub fn mul(a: &[[f32; 4]; 4], b: &[[f32; 4]; 4]) -> [[f32; 4]; 4] {
+    let mut product = [[0.0; 4]; 4];
+    for (column, b_column) in product.iter_mut().zip(b.iter()) {
+        for (row, value) in column.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[k][row] * b_column[k]).sum();
+        }
+    }
+    product
+}
+
+pub fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+pub fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub fn scale(a: [f32; 3], factor: f32) -> [f32; 3] {
+    [a[0] * factor, a[1] * factor, a[2] * factor]
+}
+
+pub fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub fn length(a: [f32; 3]) -> f32 {
+    dot(a, a).sqrt()
+}
+
+pub fn normalize(a: [f32; 3]) -> [f32; 3] {
+    scale(a, 1.0 / length(a))
+}
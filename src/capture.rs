@@ -0,0 +1,342 @@
+//! Capturing a frame at full float precision.  The pipelines are rendered once more into a
+//! `R32G32B32A32_SFLOAT` target instead of the swapchain, read back, and written as an uncompressed
+//! OpenEXR file, so values above 1.0 survive for inspecting HDR and tonemapping shaders.  With
+//! `--watermark` a `Watermark` is burned into the pixels before they are written.
+
+use crate::ash_runner::RenderCtx;
+use crate::render_pass::AttachmentImages;
+use crate::watermark::Watermark;
+
+use ash::{version::DeviceV1_0, vk};
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+const FORMAT: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+const PIXEL_SIZE: usize = 16;
+
+/// Renders the current pipelines at the swapchain's size and writes the result to `path`.
+pub fn capture_exr(ctx: &RenderCtx, path: &Path, watermark: Option<&Watermark>) -> io::Result<()> {
+    let mut pixels = render_float(ctx, ctx.extent);
+    if let Some(watermark) = watermark {
+        watermark.burn(ctx.extent, &mut pixels);
+    }
+    write_exr(path, ctx.extent, &pixels)
+}
+
+/// Renders one frame of the enabled pipelines into a float image of any size, and returns its RGBA
+/// pixels, top row first.
+pub(crate) fn render_float(ctx: &RenderCtx, extent: vk::Extent2D) -> Vec<f32> {
+    let base = &ctx.base;
+    let device = &base.device;
+
+    // the swapchain's pipelines are tied to its format, so build a set for the float render pass
+    let config = ctx.render_pass_config.cleared();
+    let render_pass =
+        config.create_render_pass(device, FORMAT, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+    let mut attachment_images = AttachmentImages::new(base, &config, extent);
+    let pipelines = ctx.create_pipelines(render_pass, vk::PipelineCache::null(), &[]);
+
+    let image_info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(FORMAT)
+        .extent(vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        })
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .initial_layout(vk::ImageLayout::UNDEFINED);
+    let image = unsafe { device.create_image(&image_info, None).unwrap() };
+    let image_memory = unsafe {
+        let requirements = device.get_image_memory_requirements(image);
+        let memory_type_index = base
+            .find_memory_type(
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .expect("No device local memory type for the capture image");
+        let allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type_index);
+        let memory = device.allocate_memory(&allocate_info, None).unwrap();
+        device.bind_image_memory(image, memory, 0).unwrap();
+        memory
+    };
+    let image_view = {
+        let create_view_info = vk::ImageViewCreateInfo::builder()
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(FORMAT)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image(image);
+        unsafe { device.create_image_view(&create_view_info, None).unwrap() }
+    };
+    let framebuffer = base.create_framebuffers(
+        &[image_view],
+        &attachment_images.views(),
+        render_pass,
+        extent,
+    )[0];
+
+    let buffer_size = extent.width as usize * extent.height as usize * PIXEL_SIZE;
+    let buffer_info = vk::BufferCreateInfo::builder()
+        .size(buffer_size as vk::DeviceSize)
+        .usage(vk::BufferUsageFlags::TRANSFER_DST)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let buffer = unsafe { device.create_buffer(&buffer_info, None).unwrap() };
+    let buffer_memory = unsafe {
+        let requirements = device.get_buffer_memory_requirements(buffer);
+        let memory_type_index = base
+            .find_memory_type(
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )
+            .expect("No host visible memory type for reading back the capture");
+        let allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type_index);
+        let memory = device.allocate_memory(&allocate_info, None).unwrap();
+        device.bind_buffer_memory(buffer, memory, 0).unwrap();
+        memory
+    };
+
+    let (viewports, scissors) = RenderCtx::create_viewports_scissors(extent, ctx.flip_y);
+    let clear_values = ctx.clear_values();
+    let command_buffer = ctx.commands.setup_command_buffer;
+    let fence = ctx.sync.setup_commands_reuse_fence;
+    unsafe {
+        device
+            .wait_for_fences(&[fence], true, std::u64::MAX)
+            .expect("Wait for fence failed.");
+        device.reset_fences(&[fence]).expect("Reset fences failed.");
+        device
+            .begin_command_buffer(
+                command_buffer,
+                &vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )
+            .expect("Begin commandbuffer");
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(render_pass)
+            .framebuffer(framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            })
+            .clear_values(&clear_values);
+        device.cmd_begin_render_pass(
+            command_buffer,
+            &render_pass_begin_info,
+            vk::SubpassContents::INLINE,
+        );
+        let enabled = ctx.pipelines.entries().map(|entry| entry.enabled);
+        for (pipeline, _) in pipelines
+            .iter()
+            .zip(enabled)
+            .filter(|&(_, enabled)| enabled)
+        {
+            ctx.draw_to(pipeline, command_buffer, extent, &viewports, &scissors);
+        }
+        device.cmd_end_render_pass(command_buffer);
+        // the render pass leaves the image in TRANSFER_SRC_OPTIMAL, the copy waits for its writes
+        let image_barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .build();
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[image_barrier],
+        );
+        let region = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D::default(),
+            image_extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+        };
+        device.cmd_copy_image_to_buffer(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            buffer,
+            &[region],
+        );
+        let barrier = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build();
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[barrier],
+            &[],
+        );
+        device
+            .end_command_buffer(command_buffer)
+            .expect("End commandbuffer");
+        let command_buffers = [command_buffer];
+        let submit_info = vk::SubmitInfo::builder().command_buffers(&command_buffers);
+        device
+            .queue_submit(base.present_queue, &[submit_info.build()], fence)
+            .expect("queue submit failed.");
+        device
+            .wait_for_fences(&[fence], true, std::u64::MAX)
+            .expect("Wait for fence failed.");
+    }
+
+    let mut pixels = vec![0.0f32; buffer_size / 4];
+    unsafe {
+        let mapped = device
+            .map_memory(
+                buffer_memory,
+                0,
+                vk::WHOLE_SIZE,
+                vk::MemoryMapFlags::empty(),
+            )
+            .unwrap();
+        std::ptr::copy_nonoverlapping(mapped as *const f32, pixels.as_mut_ptr(), pixels.len());
+        device.unmap_memory(buffer_memory);
+
+        for pipeline in pipelines.iter() {
+            device.destroy_pipeline(pipeline.pipeline, None);
+        }
+        device.destroy_framebuffer(framebuffer, None);
+        device.destroy_image_view(image_view, None);
+        device.destroy_image(image, None);
+        device.free_memory(image_memory, None);
+        device.destroy_buffer(buffer, None);
+        device.free_memory(buffer_memory, None);
+        device.destroy_render_pass(render_pass, None);
+        attachment_images.destroy(device);
+    }
+    pixels
+}
+
+/// Writes RGBA float pixels as a single part, uncompressed, scanline OpenEXR file.
+pub fn write_exr(path: &Path, extent: vk::Extent2D, pixels: &[f32]) -> io::Result<()> {
+    const PIXEL_TYPE_FLOAT: i32 = 2;
+    let width = extent.width as usize;
+    let height = extent.height as usize;
+
+    fn attribute(header: &mut Vec<u8>, name: &str, ty: &str, value: &[u8]) {
+        header.extend_from_slice(name.as_bytes());
+        header.push(0);
+        header.extend_from_slice(ty.as_bytes());
+        header.push(0);
+        header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        header.extend_from_slice(value);
+    }
+
+    // magic number, then version 2 with no flags: single part scanline image
+    let mut header = Vec::new();
+    header.extend_from_slice(&20_000_630i32.to_le_bytes());
+    header.extend_from_slice(&2i32.to_le_bytes());
+
+    // channels must be listed, and stored, in alphabetical order
+    let channels = ["A", "B", "G", "R"];
+    let mut channel_list = Vec::new();
+    for name in channels.iter() {
+        channel_list.extend_from_slice(name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend_from_slice(&PIXEL_TYPE_FLOAT.to_le_bytes());
+        // pLinear and reserved bytes, then x and y sampling
+        channel_list.extend_from_slice(&[0, 0, 0, 0]);
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+    attribute(&mut header, "channels", "chlist", &channel_list);
+    attribute(&mut header, "compression", "compression", &[0]);
+    let mut window = Vec::new();
+    for coord in [0, 0, width as i32 - 1, height as i32 - 1].iter() {
+        window.extend_from_slice(&coord.to_le_bytes());
+    }
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(&header)?;
+
+    // offset table, pointing at each scanline's y coordinate
+    let line_data_size = width * channels.len() * 4;
+    let data_start = (header.len() + height * 8) as u64;
+    for y in 0..height {
+        out.write_all(&(data_start + (y * (8 + line_data_size)) as u64).to_le_bytes())?;
+    }
+
+    for (y, row) in pixels.chunks(width * 4).enumerate().take(height) {
+        out.write_all(&(y as i32).to_le_bytes())?;
+        out.write_all(&(line_data_size as i32).to_le_bytes())?;
+        // A, B, G, R from RGBA pixels
+        for &channel in [3, 2, 1, 0].iter() {
+            for pixel in row.chunks(4) {
+                out.write_all(&pixel[channel].to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
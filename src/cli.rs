@@ -0,0 +1,338 @@
+//! The command line: `run` opens the window, the other subcommands are tools around the shader
+//! crate that exit when done.
+
+use crate::ash_runner::{
+    build_shaders, compile_shaders, default_entry_points, default_shader_crate_dir, Options,
+    ShaderBuildOptions, SpirvShader,
+};
+use crate::info;
+use crate::particles::ParticleDescription;
+use crate::passes::SceneDescription;
+use crate::reflect;
+use crate::scene::SceneConfig;
+
+use structopt::{clap, StructOpt};
+
+use std::{collections::HashMap, fs, path::PathBuf};
+
+/// Runs rust-gpu shaders, and the tools around them.
+#[derive(Debug, StructOpt)]
+pub struct Cli {
+    /// `run` with default options if left out
+    #[structopt(subcommand)]
+    pub command: Option<Subcommand>,
+}
+
+impl Cli {
+    /// `from_args`, also rejecting the combinations of run options `Options::check` finds, with the
+    /// same usage error.
+    pub fn parse() -> Self {
+        let cli = Self::from_args();
+        let run = match &cli.command {
+            Some(Subcommand::Run(options)) => Some(options),
+            Some(Subcommand::Bench(options)) => Some(&options.run),
+            Some(Subcommand::Test(options)) => Some(&options.run),
+            _ => None,
+        };
+        if let Err(message) = run.map_or(Ok(()), Options::check) {
+            clap::Error::with_description(&message, clap::ErrorKind::ArgumentConflict).exit();
+        }
+        cli
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Subcommand {
+    /// Open a window rendering the shaders, rebuilding them on F5
+    Run(Options),
+    /// Build and check the shader crate, without a window or Vulkan device, e.g. for CI
+    Compile(CompileOptions),
+    /// Check that the entry points used by a scene or pass description are in the built shaders
+    Validate(ValidateOptions),
+    /// Print the Vulkan devices, their queue families, surface support and limits
+    Info(InfoOptions),
+    /// Render each pipeline at a fixed resolution and compare it with a reference PNG
+    Test(TestOptions),
+    /// Render a number of frames without a frame limit and print frame time statistics
+    Bench(BenchOptions),
+}
+
+/// How to build the shader crate, shared by the subcommands building it.
+#[derive(Debug, StructOpt)]
+pub struct ShaderBuildArgs {
+    /// Directory of the shader crate to build, by default "shaders" in the runner's source tree or
+    /// next to the executable
+    #[structopt(long, parse(from_os_str))]
+    shader_crate: Option<PathBuf>,
+
+    /// Cargo target directory for shader builds, by default "target" in the shader crate.  Can be
+    /// shared by any number of shader crates
+    #[structopt(long, parse(from_os_str))]
+    shader_target_dir: Option<PathBuf>,
+
+    /// Cargo features to enable when building the shader crate, comma separated
+    #[structopt(long, use_delimiter = true)]
+    shader_features: Vec<String>,
+
+    /// Build shaders in the debug profile, with debug info and named variables for shader debuggers
+    /// like RenderDoc, at the cost of slower builds and shaders
+    #[structopt(long)]
+    shader_debug: bool,
+
+    /// Extra argument passed verbatim to the cargo command building the shaders, e.g. --offline;
+    /// repeat for several
+    #[structopt(long, number_of_values = 1, allow_hyphen_values = true)]
+    cargo_arg: Vec<String>,
+}
+
+impl ShaderBuildArgs {
+    pub fn build_options(&self) -> ShaderBuildOptions {
+        ShaderBuildOptions {
+            crate_dir: self
+                .shader_crate
+                .clone()
+                .unwrap_or_else(default_shader_crate_dir),
+            target_dir: self.shader_target_dir.clone(),
+            features: self.shader_features.clone(),
+            debug: self.shader_debug,
+            cargo_args: self.cargo_arg.clone(),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct CompileOptions {
+    #[structopt(flatten)]
+    shader_build: ShaderBuildArgs,
+
+    /// Directory to write the checked modules to, as <module>.spv
+    #[structopt(long, parse(from_os_str))]
+    out_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ValidateOptions {
+    #[structopt(flatten)]
+    shader_build: ShaderBuildArgs,
+
+    /// Json scene file to check, see `run --scene`
+    #[structopt(long, parse(from_os_str))]
+    scene: Option<PathBuf>,
+
+    /// RON pass description to check, see `run --passes`
+    #[structopt(long, parse(from_os_str))]
+    passes: Option<PathBuf>,
+
+    /// RON particle description to check, see `run --particles`
+    #[structopt(long, parse(from_os_str))]
+    particles: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct InfoOptions {
+    /// Skip opening a hidden window to query surface formats and present modes, e.g. without a
+    /// display
+    #[structopt(long)]
+    no_surface: bool,
+
+    /// Print json, including the instance and device extensions and features
+    #[structopt(long)]
+    json: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct BenchOptions {
+    #[structopt(flatten)]
+    pub run: Options,
+
+    /// Frames to measure
+    #[structopt(long, default_value = "1000", parse(try_from_str = parse_frames))]
+    pub frames: u32,
+
+    /// Frames to render before measuring, while caches and clocks settle
+    #[structopt(long, default_value = "100")]
+    pub warmup: u32,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct TestOptions {
+    /// The pipelines come from `--scene`, or are the default one without it, and render at
+    /// `--resolution`, 256x256 by default
+    #[structopt(flatten)]
+    pub run: Options,
+
+    /// Directory of the reference images, named <pipeline>.png
+    #[structopt(long, parse(from_os_str), default_value = "golden")]
+    pub references: PathBuf,
+
+    /// Directory to write the rendered images and diffs of failed pipelines to
+    #[structopt(long, parse(from_os_str), default_value = "golden-out")]
+    pub out_dir: PathBuf,
+
+    /// Largest difference of a color channel, out of 255, that still counts as the same
+    #[structopt(long, default_value = "2")]
+    pub tolerance: u8,
+
+    /// Write the rendered images as the new references instead of comparing
+    #[structopt(long)]
+    pub update: bool,
+}
+
+fn parse_frames(s: &str) -> Result<u32, String> {
+    match s.trim().parse::<u32>() {
+        Ok(frames) if frames > 0 => Ok(frames),
+        _ => Err(format!("expected at least one frame, got \"{}\"", s)),
+    }
+}
+
+/// Exits with status 1 if the build fails, a module isn't valid, or can't be written.
+pub fn compile(options: &CompileOptions) {
+    let shaders = match build_shaders(&options.shader_build.build_options()) {
+        Ok(shaders) => shaders,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    if let Some(out_dir) = &options.out_dir {
+        if let Err(err) = fs::create_dir_all(out_dir) {
+            eprintln!("failed to create {}: {}", out_dir.display(), err);
+            std::process::exit(1);
+        }
+    }
+
+    let mut failed = false;
+    for SpirvShader { name, spirv } in shaders {
+        if let Err(err) = reflect::validate(&spirv) {
+            eprintln!("{}: {}", name, err);
+            failed = true;
+            continue;
+        }
+        println!("{}: {}", name, reflect::entry_points(&spirv).join(", "));
+        if let Some(out_dir) = &options.out_dir {
+            let path = out_dir.join(&name).with_extension("spv");
+            let bytes = spirv
+                .iter()
+                .flat_map(|word| word.to_le_bytes().to_vec())
+                .collect::<Vec<_>>();
+            if let Err(err) = fs::write(&path, bytes) {
+                eprintln!("failed to write {}: {}", path.display(), err);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// Exits with status 1 if anything is missing.
+pub fn validate(options: &ValidateOptions) {
+    let mut errors = Vec::new();
+    // (what uses it, module, entry point)
+    let mut entry_points = Vec::new();
+    if let Some(path) = &options.scene {
+        match SceneConfig::load(path) {
+            Ok(scene) => {
+                for pipeline in scene.pipelines {
+                    let user = format!("pipeline \"{}\"", pipeline.name);
+                    entry_points.push((
+                        user.clone(),
+                        pipeline.vertex.module,
+                        pipeline.vertex.entry_point,
+                    ));
+                    entry_points.push((
+                        user,
+                        pipeline.fragment.module,
+                        pipeline.fragment.entry_point,
+                    ));
+                }
+            }
+            Err(err) => errors.push(format!("{}: {}", path.display(), err)),
+        }
+    }
+    if let Some(path) = &options.passes {
+        match SceneDescription::load(path) {
+            Ok(description) => {
+                for pass in description.passes {
+                    let user = format!("pass \"{}\"", pass.name);
+                    for draw in pass.draws {
+                        entry_points.push((
+                            user.clone(),
+                            draw.vertex.module,
+                            draw.vertex.entry_point,
+                        ));
+                        entry_points.push((
+                            user.clone(),
+                            draw.fragment.module,
+                            draw.fragment.entry_point,
+                        ));
+                    }
+                }
+            }
+            Err(err) => errors.push(format!("{}: {}", path.display(), err)),
+        }
+    }
+    if let Some(path) = &options.particles {
+        match ParticleDescription::load(path) {
+            Ok(description) => {
+                let user = String::from("particles");
+                entry_points.push((
+                    user.clone(),
+                    description.compute.module,
+                    description.compute.entry_point,
+                ));
+                entry_points.push((
+                    user.clone(),
+                    description.vertex.module,
+                    description.vertex.entry_point,
+                ));
+                entry_points.push((
+                    user,
+                    description.fragment.module,
+                    description.fragment.entry_point,
+                ));
+            }
+            Err(err) => errors.push(format!("{}: {}", path.display(), err)),
+        }
+    }
+    if options.scene.is_none() {
+        let (vertex, fragment) = default_entry_points();
+        let user = String::from("default pipeline");
+        entry_points.push((user.clone(), vertex.module, vertex.entry_point));
+        entry_points.push((user, fragment.module, fragment.entry_point));
+    }
+
+    let modules = compile_shaders(&options.shader_build.build_options())
+        .into_iter()
+        .map(|SpirvShader { name, spirv }| (name, reflect::entry_points(&spirv)))
+        .collect::<HashMap<_, _>>();
+    for (user, module, entry_point) in entry_points {
+        match modules.get(&module) {
+            Some(names) if names.contains(&entry_point) => {}
+            Some(_) => errors.push(format!(
+                "{}: no entry point \"{}\" in module \"{}\"",
+                user, entry_point, module
+            )),
+            None => errors.push(format!("{}: no shader module \"{}\"", user, module)),
+        }
+    }
+
+    if errors.is_empty() {
+        println!("ok");
+        return;
+    }
+    for error in errors {
+        eprintln!("{}", error);
+    }
+    std::process::exit(1);
+}
+
+pub fn print_info(options: &InfoOptions) {
+    let report = info::report(!options.no_surface);
+    if options.json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        info::print_devices(&report.devices);
+    }
+}
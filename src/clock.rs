@@ -0,0 +1,115 @@
+//! The time, frame number and random seed the shaders get in their push constants.
+//!
+//! Normally the time follows the wall clock and the seed is different every run.  With
+//! `--deterministic` the time advances a fixed step per frame and the seed is fixed, so two runs
+//! render the same frames whatever the frame rate, for comparing captures and golden images.
+//!
+//! By default Space pauses the time and frame number, `.` steps them one frame while paused, and
+//! `-` and `=` halve and double the rate the time runs at, between a quarter and four times normal
+//! speed.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub struct ShaderClock {
+    /// Set in deterministic mode.
+    fixed_step: Option<Duration>,
+    /// Whether the first frame has begun.
+    started: bool,
+    last_frame: Instant,
+    elapsed: Duration,
+    paused: bool,
+    /// Set by `step` until the next frame.
+    step_pending: bool,
+    speed: f32,
+    /// Frames begun before the current one.
+    pub frame: u32,
+    pub seed: u32,
+}
+
+impl ShaderClock {
+    /// The time step of deterministic mode, a 60 Hz frame.
+    pub const FIXED_STEP: Duration = Duration::from_nanos(16_666_667);
+    pub const FIXED_SEED: u32 = 0x9e37_79b9;
+    pub const MIN_SPEED: f32 = 0.25;
+    pub const MAX_SPEED: f32 = 4.0;
+
+    pub fn new(deterministic: bool) -> Self {
+        let seed = if deterministic {
+            Self::FIXED_SEED
+        } else {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.subsec_nanos())
+        };
+        Self {
+            fixed_step: if deterministic {
+                Some(Self::FIXED_STEP)
+            } else {
+                None
+            },
+            started: false,
+            last_frame: Instant::now(),
+            elapsed: Duration::from_secs(0),
+            paused: false,
+            step_pending: false,
+            speed: 1.0,
+            frame: 0,
+            seed,
+        }
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.fixed_step.is_some()
+    }
+
+    /// Advances to the next frame, unless paused.  The first frame is at time zero.
+    pub fn begin_frame(&mut self) {
+        let now = Instant::now();
+        let frame_time = now - self.last_frame;
+        self.last_frame = now;
+        if !self.started {
+            self.started = true;
+            return;
+        }
+        let step = if !self.paused {
+            self.fixed_step.unwrap_or(frame_time)
+        } else if self.step_pending {
+            self.step_pending = false;
+            self.fixed_step.unwrap_or(Self::FIXED_STEP)
+        } else {
+            return;
+        };
+        self.frame = self.frame.wrapping_add(1);
+        self.elapsed += step.mul_f32(self.speed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.step_pending = false;
+    }
+
+    /// Advances by one `FIXED_STEP` frame on the next `begin_frame`, if paused.
+    pub fn step(&mut self) {
+        if self.paused {
+            self.step_pending = true;
+        }
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Clamped between `MIN_SPEED` and `MAX_SPEED`.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.max(Self::MIN_SPEED).min(Self::MAX_SPEED);
+    }
+
+    /// Seconds since the first frame.
+    pub fn time(&self) -> f32 {
+        self.elapsed.as_secs_f32()
+    }
+}
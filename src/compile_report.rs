@@ -0,0 +1,193 @@
+//! The outcome of a shader build, parsed from cargo's json messages: the .spv artifacts, and the
+//! compiler's diagnostics with their source locations.
+
+use serde::Deserialize;
+
+use std::{fmt, path::PathBuf};
+
+#[derive(Debug, Clone, Default)]
+pub struct CompileReport {
+    /// From cargo's final message; false if the build was killed before it got there.
+    pub success: bool,
+    /// The .spv files of the last artifact cargo reported, the shader crate itself.
+    pub artifacts: Vec<PathBuf>,
+    /// In the order rustc emitted them.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Diagnostic {
+    /// "error", "warning", "note", "help", or "error: internal compiler error".
+    pub level: String,
+    pub message: String,
+    #[serde(default)]
+    pub spans: Vec<DiagnosticSpan>,
+    /// Notes and help attached to this diagnostic.
+    #[serde(default)]
+    pub children: Vec<Diagnostic>,
+    /// The diagnostic as rustc prints it, with source snippets.  Only set at the top level.
+    pub rendered: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiagnosticSpan {
+    /// As rustc reports it, relative to the shader workspace root for the crate's own sources.
+    pub file_name: PathBuf,
+    /// 1-based.
+    pub line_start: usize,
+    pub line_end: usize,
+    /// 1-based, in characters.
+    pub column_start: usize,
+    pub column_end: usize,
+    /// Whether this is the span the diagnostic is about, rather than related context.
+    pub is_primary: bool,
+    pub label: Option<String>,
+}
+
+/// Any line of cargo's json output; which fields are set depends on `reason`.
+#[derive(Deserialize)]
+struct CargoMessage {
+    reason: String,
+    filenames: Option<Vec<PathBuf>>,
+    message: Option<Diagnostic>,
+    success: Option<bool>,
+}
+
+impl CompileReport {
+    /// Parses the stdout of cargo run with `--message-format json`.  Lines that aren't cargo
+    /// messages, like output of build scripts, are skipped.
+    pub fn parse(cargo_stdout: &[u8]) -> Self {
+        let mut report = Self::default();
+        for line in String::from_utf8_lossy(cargo_stdout).lines() {
+            let message = match serde_json::from_str::<CargoMessage>(line) {
+                Ok(message) => message,
+                Err(_) => continue,
+            };
+            match message.reason.as_str() {
+                "compiler-artifact" => {
+                    report.artifacts = message
+                        .filenames
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter(|filename| {
+                            filename
+                                .extension()
+                                .map_or(false, |extension| extension == "spv")
+                        })
+                        .collect();
+                }
+                "compiler-message" => report.diagnostics.extend(message.message),
+                "build-finished" => report.success = message.success.unwrap_or(false),
+                _ => {}
+            }
+        }
+        report
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.is_error())
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.level == "warning")
+    }
+
+    /// Prints the diagnostics to stderr the way cargo would have.
+    pub fn print(&self) {
+        for diagnostic in &self.diagnostics {
+            match &diagnostic.rendered {
+                Some(rendered) => eprint!("{}", rendered),
+                None => eprintln!("{}", diagnostic),
+            }
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.level.starts_with("error")
+    }
+
+    /// Where the diagnostic points to, if anywhere.
+    pub fn primary_span(&self) -> Option<&DiagnosticSpan> {
+        self.spans.iter().find(|span| span.is_primary)
+    }
+}
+
+/// One line, as "file:line:column: level: message".
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(span) = self.primary_span() {
+            write!(
+                f,
+                "{}:{}:{}: ",
+                span.file_name.display(),
+                span.line_start,
+                span.column_start
+            )?;
+        }
+        write!(f, "{}: {}", self.level, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_lines_that_arent_cargo_messages() {
+        let report = CompileReport::parse(
+            b"   Compiling shaders v0.1.0\n\
+              {\"not\": \"a cargo message\"}\n\
+              {\"reason\": \"build-finished\", \"success\": true}\n\
+              {truncated",
+        );
+        assert!(report.success);
+        assert!(report.artifacts.is_empty());
+        assert!(report.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn failed_build() {
+        let report = CompileReport::parse(
+            br#"{"reason": "compiler-message", "message": {"level": "error", "message": "mismatched types", "spans": [{"file_name": "src/lib.rs", "line_start": 3, "line_end": 3, "column_start": 5, "column_end": 9, "is_primary": true, "label": null}], "rendered": "error: mismatched types\n"}}
+{"reason": "compiler-message", "message": {"level": "warning", "message": "unused variable", "rendered": null}}
+{"reason": "build-finished", "success": false}"#,
+        );
+        assert!(!report.success);
+        assert_eq!(report.errors().count(), 1);
+        assert_eq!(report.warnings().count(), 1);
+        assert_eq!(
+            report.errors().next().unwrap().to_string(),
+            "src/lib.rs:3:5: error: mismatched types"
+        );
+    }
+
+    #[test]
+    fn unfinished_build_is_no_success() {
+        let report =
+            CompileReport::parse(b"{\"reason\": \"compiler-artifact\", \"filenames\": []}");
+        assert!(!report.success);
+    }
+
+    #[test]
+    fn keeps_the_last_artifacts_spv_files() {
+        let report = CompileReport::parse(
+            br#"{"reason": "compiler-artifact", "filenames": ["target/libspirv_std.rlib", "target/dep.spv"]}
+{"reason": "compiler-artifact", "filenames": ["target/shaders.spv", "target/shaders.d", "target/shaders.spv.json", "target/sky.spv"]}
+{"reason": "build-finished", "success": true}"#,
+        );
+        assert!(report.success);
+        assert_eq!(
+            report.artifacts,
+            vec![
+                PathBuf::from("target/shaders.spv"),
+                PathBuf::from("target/sky.spv")
+            ]
+        );
+    }
+}
@@ -0,0 +1,185 @@
+//! Rebuilding the shader crate in the background while the window keeps rendering.
+
+use crate::ash_runner::{
+    is_build_current, read_shader_artifacts, shader_build_command, shader_source_hash,
+    CompilerEvent, ShaderBuildOptions, UserEvent,
+};
+use crate::compile_report::CompileReport;
+use crate::profiling;
+
+use winit::event_loop::EventLoopProxy;
+
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Child, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Runs cargo on a background thread, which reports back to the event loop with a `CompilerEvent`.
+pub struct ShaderCompiler {
+    proxy: EventLoopProxy<UserEvent>,
+    /// Identifies the latest build, so results of cancelled builds can be told apart.
+    build_id: u64,
+    current: Option<ShaderBuild>,
+    /// Whether a request cancels the build in progress, or waits for it to finish.
+    cancel_builds: bool,
+    /// Set when a build was requested while another was running without cancelling it.
+    pending: bool,
+    /// Set when the crate was switched, until a build of the new crate completes.
+    switched: bool,
+    build: ShaderBuildOptions,
+}
+
+struct ShaderBuild {
+    cargo: Arc<Mutex<Child>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ShaderCompiler {
+    pub fn new(
+        proxy: EventLoopProxy<UserEvent>,
+        cancel_builds: bool,
+        build: ShaderBuildOptions,
+    ) -> Self {
+        Self {
+            proxy,
+            build_id: 0,
+            current: None,
+            cancel_builds,
+            pending: false,
+            switched: false,
+            build,
+        }
+    }
+
+    /// Starts a build, cancelling the one in progress since its result would be stale anyway.
+    /// Without cancelling, any number of requests during a build result in exactly one more build
+    /// once it finishes.  Nothing is built if the sources are the same as for the last build.
+    pub fn request(&mut self) {
+        if self.current.is_some() && !self.cancel_builds {
+            self.pending = true;
+            return;
+        }
+        // hashed before the build starts, so changes made during it aren't considered built
+        let source_hash = shader_source_hash(&self.build);
+        if self.current.is_none() && is_build_current(&self.build, source_hash) {
+            println!("shader sources unchanged, skipping build");
+            return;
+        }
+        self.cancel();
+        self.start(source_hash);
+    }
+
+    /// Builds the crate in `crate_dir` from now on, starting right away even if it was built before,
+    /// since its shaders aren't loaded.  The build in progress is cancelled.
+    pub fn switch_crate(&mut self, crate_dir: PathBuf) {
+        println!("switching shader crate to {}", crate_dir.display());
+        self.build.crate_dir = crate_dir;
+        self.switched = true;
+        self.pending = false;
+        self.cancel();
+        self.start(shader_source_hash(&self.build));
+    }
+
+    /// The shader crate built, the one switched to last if any.
+    pub fn crate_dir(&self) -> &Path {
+        &self.build.crate_dir
+    }
+
+    /// Whether completed shaders are the first of a switched crate, replacing the whole shader set
+    /// rather than reloading modules.  Clears the flag.
+    pub fn take_switched(&mut self) -> bool {
+        std::mem::replace(&mut self.switched, false)
+    }
+
+    fn cancel(&mut self) {
+        if let Some(build) = self.current.take() {
+            build.cancelled.store(true, Ordering::SeqCst);
+            // killing cargo ends the build, rustc processes it already started finish on their own
+            build.cargo.lock().unwrap().kill().ok();
+            println!("cancelled shader build");
+        }
+    }
+
+    fn start(&mut self, source_hash: u64) {
+        self.build_id += 1;
+        let build_id = self.build_id;
+        let spawned = shader_build_command(&self.build)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn();
+        let mut cargo = match spawned {
+            Ok(cargo) => cargo,
+            Err(err) => {
+                // e.g. cargo isn't installed or the crate directory is gone, which fails this
+                // build like any other instead of taking the runner down
+                eprintln!(
+                    "failed to run cargo in {}: {}",
+                    self.build.crate_dir.display(),
+                    err
+                );
+                let event = CompilerEvent::Failed(CompileReport::default());
+                self.proxy
+                    .send_event(UserEvent::Compiler { build_id, event })
+                    .ok();
+                return;
+            }
+        };
+        let mut stdout = cargo.stdout.take().unwrap();
+        let build = ShaderBuild {
+            cargo: Arc::new(Mutex::new(cargo)),
+            cancelled: Arc::new(AtomicBool::new(false)),
+        };
+        let cargo = build.cargo.clone();
+        let cancelled = build.cancelled.clone();
+        let proxy = self.proxy.clone();
+        let shader_build = self.build.clone();
+        profiling::message("shader build started");
+        std::thread::spawn(move || {
+            let _zone = profiling::zone("shader build");
+            // reading to the end waits for cargo to exit, or to be killed
+            let mut output = Vec::new();
+            stdout.read_to_end(&mut output).ok();
+            let status = cargo.lock().unwrap().wait();
+            if cancelled.load(Ordering::SeqCst) {
+                return;
+            }
+            let report = CompileReport::parse(&output);
+            let event = match status {
+                // a missing or unreadable artifact panics, which must not leave the event loop
+                // waiting forever
+                Ok(status) if status.success() => {
+                    match std::panic::catch_unwind(|| {
+                        read_shader_artifacts(&shader_build, &report, source_hash)
+                    }) {
+                        Ok(shaders) => CompilerEvent::Complete(shaders, report),
+                        Err(_) => CompilerEvent::Failed(report),
+                    }
+                }
+                _ => CompilerEvent::Failed(report),
+            };
+            proxy
+                .send_event(UserEvent::Compiler { build_id, event })
+                .ok();
+        });
+        self.current = Some(build);
+    }
+
+    /// Returns whether the event is from the latest build, otherwise it must be ignored.  Starts the
+    /// pending build, if any.
+    pub fn finished(&mut self, build_id: u64) -> bool {
+        if build_id != self.build_id {
+            return false;
+        }
+        self.current = None;
+        if self.pending {
+            self.pending = false;
+            self.request();
+        }
+        true
+    }
+}
@@ -0,0 +1,251 @@
+//! Immediate mode lines for visualizing what shaders get, like light directions or bounding volumes.
+//! Plugins add lines in world space to `ctx.debug_draw` in `Plugin::update`, and
+//! `DebugDrawRenderer` draws them through `ctx.camera` over the frame with a line list pipeline of
+//! its own; they are cleared before the next frame:
+//!
+//! ```ignore
+//! ctx.debug_draw.line([0.0; 3], light_direction, [1.0, 1.0, 0.0, 1.0]);
+//! ctx.debug_draw.aabb(bounds.min, bounds.max, [0.0, 1.0, 0.0, 1.0]);
+//! ```
+//!
+//! The shaders are `debug_draw.vert` and `debug_draw.frag` next to this file, compiled to SPIR-V by
+//! `build.rs`: the vertex shader transforms the positions with the view projection matrix from the
+//! push constants, and the fragment shader writes the vertex color.
+
+use crate::ash_runner::{any_as_u8_slice, Pipeline, PipelineDescriptor, RenderCtx};
+use crate::pipeline_registry::BlendMode;
+use crate::plugin::Plugin;
+
+use ash::{util::read_spv, version::DeviceV1_0, vk};
+
+use std::{f32::consts::PI, ffi::CStr, io::Cursor, mem};
+
+/// Segments of each of the three circles drawn for a sphere.
+const SPHERE_SEGMENTS: usize = 32;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Vertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+/// The lines of the current frame.
+#[derive(Default)]
+pub struct DebugDraw {
+    vertices: Vec<Vertex>,
+}
+
+impl DebugDraw {
+    /// `color` is in linear RGB with straight alpha.
+    pub fn line(&mut self, a: [f32; 3], b: [f32; 3], color: [f32; 4]) {
+        self.vertices.push(Vertex { position: a, color });
+        self.vertices.push(Vertex { position: b, color });
+    }
+
+    /// The edges of the axis aligned box between the corners `min` and `max`.
+    pub fn aabb(&mut self, min: [f32; 3], max: [f32; 3], color: [f32; 4]) {
+        let corner = |index: usize| {
+            let pick = |axis: usize| {
+                if index & (1 << axis) == 0 {
+                    min[axis]
+                } else {
+                    max[axis]
+                }
+            };
+            [pick(0), pick(1), pick(2)]
+        };
+        for index in 0..8 {
+            // each edge once, from the corner with the lower coordinate along it
+            for axis in 0..3 {
+                if index & (1 << axis) == 0 {
+                    self.line(corner(index), corner(index | (1 << axis)), color);
+                }
+            }
+        }
+    }
+
+    /// Circles around the three axes.
+    pub fn sphere(&mut self, center: [f32; 3], radius: f32, color: [f32; 4]) {
+        for axis in 0..3 {
+            let point = |segment: usize| {
+                let angle = 2.0 * PI * segment as f32 / SPHERE_SEGMENTS as f32;
+                let mut point = center;
+                point[(axis + 1) % 3] += radius * angle.cos();
+                point[(axis + 2) % 3] += radius * angle.sin();
+                point
+            };
+            for segment in 0..SPHERE_SEGMENTS {
+                self.line(point(segment), point(segment + 1), color);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    /// Called by the runner before each frame.
+    pub(crate) fn clear(&mut self) {
+        self.vertices.clear();
+    }
+}
+
+/// Draws `ctx.debug_draw` at the end of the window's render pass, without depth testing.  The
+/// runner adds it after the plugins it was started with, so the lines go over their draws.
+#[derive(Default)]
+pub struct DebugDrawRenderer {
+    /// Created in `init`.
+    modules: Option<(vk::ShaderModule, vk::ShaderModule)>,
+    pipeline: Option<Pipeline>,
+}
+
+impl DebugDrawRenderer {
+    fn create_pipeline(&mut self, ctx: &RenderCtx) {
+        let (vertex_module, fragment_module) = match self.modules {
+            Some(modules) => modules,
+            None => return,
+        };
+        let entry_point = unsafe { CStr::from_bytes_with_nul_unchecked(b"main\0") };
+        let bindings = [vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: mem::size_of::<Vertex>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }];
+        let attributes = [
+            vk::VertexInputAttributeDescription {
+                location: 0,
+                binding: 0,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: 0,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 1,
+                binding: 0,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: 12,
+            },
+        ];
+        // the fragment shader only writes the swapchain image, leave any other attachment alone
+        let mut attachments = vec![
+            BlendMode::Opaque.attachment_state();
+            ctx.render_pass_config.color_attachment_count()
+        ];
+        for attachment in attachments.iter_mut() {
+            attachment.color_write_mask = vk::ColorComponentFlags::empty();
+        }
+        attachments[0] = BlendMode::AlphaBlend.attachment_state();
+        let mut desc = PipelineDescriptor::builder(Box::new([
+            vk::PipelineShaderStageCreateInfo {
+                module: vertex_module,
+                p_name: entry_point.as_ptr(),
+                stage: vk::ShaderStageFlags::VERTEX,
+                ..Default::default()
+            },
+            vk::PipelineShaderStageCreateInfo {
+                module: fragment_module,
+                p_name: entry_point.as_ptr(),
+                stage: vk::ShaderStageFlags::FRAGMENT,
+                ..Default::default()
+            },
+        ]))
+        .vertex_input(&bindings, &attributes)
+        .topology(vk::PrimitiveTopology::LINE_LIST)
+        .color_blend_attachments(&attachments)
+        .build();
+        desc.push_constant_range = Some(vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            offset: 0,
+            size: mem::size_of::<[[f32; 4]; 4]>() as u32,
+        });
+        self.pipeline = Some(Pipeline::new(ctx, desc, vk::PipelineCache::null()));
+    }
+
+    fn destroy_pipeline(&mut self, device: &ash::Device) {
+        if let Some(pipeline) = self.pipeline.take() {
+            unsafe { device.destroy_pipeline(pipeline.pipeline, None) };
+        }
+    }
+}
+
+impl Plugin for DebugDrawRenderer {
+    fn name(&self) -> &str {
+        "debug draw"
+    }
+
+    fn init(&mut self, ctx: &mut RenderCtx) {
+        let device = &ctx.base.device;
+        let create_module = |bytes: &[u8]| {
+            let spirv = read_spv(&mut Cursor::new(bytes)).expect("Invalid SPIR-V");
+            let shader_info = vk::ShaderModuleCreateInfo::builder().code(&spirv);
+            unsafe {
+                device
+                    .create_shader_module(&shader_info, None)
+                    .expect("Shader module error")
+            }
+        };
+        self.modules = Some((
+            create_module(include_bytes!(concat!(
+                env!("OUT_DIR"),
+                "/debug_draw.vert.spv"
+            ))),
+            create_module(include_bytes!(concat!(
+                env!("OUT_DIR"),
+                "/debug_draw.frag.spv"
+            ))),
+        ));
+        self.create_pipeline(ctx);
+    }
+
+    fn draw(&self, ctx: &RenderCtx, command_buffer: vk::CommandBuffer) {
+        let pipeline = match &self.pipeline {
+            Some(pipeline) if !ctx.debug_draw.is_empty() => pipeline,
+            _ => return,
+        };
+        let vertices = &ctx.debug_draw.vertices;
+        let offset = match ctx.frame_ring.push(vertices) {
+            Some(offset) => offset,
+            None => {
+                eprintln!("debug draw: no room for {} lines", vertices.len() / 2);
+                return;
+            }
+        };
+        let view_projection = ctx.view_projection();
+        let device = &ctx.base.device;
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.pipeline,
+            );
+            device.cmd_set_viewport(command_buffer, 0, &ctx.viewports);
+            device.cmd_set_scissor(command_buffer, 0, &ctx.scissors);
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                any_as_u8_slice(&view_projection),
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[ctx.frame_ring.buffer], &[offset]);
+            device.cmd_draw(command_buffer, vertices.len() as u32, 1, 0, 0);
+        }
+    }
+
+    fn pipelines_rebuilt(&mut self, ctx: &mut RenderCtx) {
+        // the runner waited for the device before destroying its own old pipelines
+        self.destroy_pipeline(&ctx.base.device);
+        self.create_pipeline(ctx);
+    }
+
+    fn destroy(&mut self, ctx: &mut RenderCtx) {
+        let device = &ctx.base.device;
+        self.destroy_pipeline(device);
+        if let Some((vertex_module, fragment_module)) = self.modules.take() {
+            unsafe {
+                device.destroy_shader_module(vertex_module, None);
+                device.destroy_shader_module(fragment_module, None);
+            }
+        }
+    }
+}
@@ -0,0 +1,15 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    mat4 view_projection;
+};
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    out_color = color;
+    gl_Position = view_projection * vec4(position, 1.0);
+}
@@ -0,0 +1,188 @@
+//! Device features and extensions that shaders or plugins need, declared before the device is
+//! created.  A device lacking a required one is passed over when picking the GPU, and if none has
+//! them all the runner stops with the list of what each device is missing, instead of failing with
+//! validation errors once a pipeline uses them.  Optional ones are enabled where supported, and
+//! `RenderBase::enabled_features` and `enabled_extensions` tell whether they were.
+//!
+//! Extensions the runner doesn't wrap can be required like any other, and the feature structs
+//! enabling their features chained into the device create info with `chain_features`.  Those are
+//! passed on as they are, so it's up to the caller to check the device supports what they enable.
+//!
+//! Plugins declare theirs in `Plugin::device_requirements`:
+//!
+//! ```ignore
+//! requirements
+//!     .require_feature("shaderInt64", |features| &mut features.shader_int64)
+//!     .require_extension(vk::ExtFragmentShaderInterlockFn::name())
+//!     .chain_features(
+//!         vk::PhysicalDeviceFragmentShaderInterlockFeaturesEXT::builder()
+//!             .fragment_shader_pixel_interlock(true)
+//!             .build(),
+//!     );
+//! ```
+
+use ash::{version::InstanceV1_0, vk};
+
+use std::{
+    ffi::{CStr, CString},
+    os::raw::c_void,
+};
+
+/// Selects a field of `vk::PhysicalDeviceFeatures`.
+pub type FeatureField = fn(&mut vk::PhysicalDeviceFeatures) -> &mut vk::Bool32;
+
+struct Feature {
+    /// The Vulkan name, for messages.
+    name: &'static str,
+    field: FeatureField,
+    required: bool,
+}
+
+struct Extension {
+    name: CString,
+    required: bool,
+}
+
+/// A struct that can go in the `pNext` chain of `VkDeviceCreateInfo`.
+trait DeviceCreateInfoNext {
+    fn as_base(&mut self) -> *mut vk::BaseOutStructure;
+}
+
+impl<T: vk::ExtendsDeviceCreateInfo> DeviceCreateInfoNext for T {
+    fn as_base(&mut self) -> *mut vk::BaseOutStructure {
+        // every Vulkan struct starts with sType and pNext
+        self as *mut T as *mut vk::BaseOutStructure
+    }
+}
+
+#[derive(Default)]
+pub struct DeviceRequirements {
+    features: Vec<Feature>,
+    extensions: Vec<Extension>,
+    /// Boxed so they keep their address once chained.
+    chained: Vec<Box<dyn DeviceCreateInfoNext>>,
+}
+
+impl DeviceRequirements {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only devices with the feature are used.  `name` is the feature's Vulkan name, e.g.
+    /// "shaderInt64", and `field` selects it, e.g. `|features| &mut features.shader_int64`.
+    pub fn require_feature(&mut self, name: &'static str, field: FeatureField) -> &mut Self {
+        self.features.push(Feature {
+            name,
+            field,
+            required: true,
+        });
+        self
+    }
+
+    /// Enables the feature if the device has it.
+    pub fn request_feature(&mut self, name: &'static str, field: FeatureField) -> &mut Self {
+        self.features.push(Feature {
+            name,
+            field,
+            required: false,
+        });
+        self
+    }
+
+    /// Only devices with the extension are used.
+    pub fn require_extension(&mut self, name: &CStr) -> &mut Self {
+        self.extensions.push(Extension {
+            name: name.to_owned(),
+            required: true,
+        });
+        self
+    }
+
+    /// Enables the extension if the device has it.
+    pub fn request_extension(&mut self, name: &CStr) -> &mut Self {
+        self.extensions.push(Extension {
+            name: name.to_owned(),
+            required: false,
+        });
+        self
+    }
+
+    /// Chains `features`, e.g. a `vk::PhysicalDeviceFragmentShaderInterlockFeaturesEXT`, into the
+    /// device create info.  Its `p_next` is overwritten.  The runner chains the pipeline library
+    /// features itself with `--pipeline-library`, which mustn't be chained again.
+    pub fn chain_features<T: vk::ExtendsDeviceCreateInfo + 'static>(
+        &mut self,
+        features: T,
+    ) -> &mut Self {
+        self.chained.push(Box::new(features));
+        self
+    }
+
+    /// The names of the required features and extensions `pdevice` lacks.
+    pub fn missing(&self, instance: &ash::Instance, pdevice: vk::PhysicalDevice) -> Vec<String> {
+        let (mut supported_features, supported_extensions) = supported(instance, pdevice);
+        let features = self
+            .features
+            .iter()
+            .filter(|feature| feature.required && *(feature.field)(&mut supported_features) == 0)
+            .map(|feature| feature.name.to_owned());
+        let extensions = self
+            .extensions
+            .iter()
+            .filter(|ext| ext.required && !supported_extensions.contains(&ext.name))
+            .map(|ext| ext.name.to_string_lossy().into_owned());
+        features.chain(extensions).collect()
+    }
+
+    /// Sets the supported features in `features` and adds the supported extensions to `extensions`,
+    /// skipping any already there.
+    pub(crate) fn enable(
+        &self,
+        instance: &ash::Instance,
+        pdevice: vk::PhysicalDevice,
+        features: &mut vk::PhysicalDeviceFeatures,
+        extensions: &mut Vec<CString>,
+    ) {
+        let (mut supported_features, supported_extensions) = supported(instance, pdevice);
+        for feature in self.features.iter() {
+            if *(feature.field)(&mut supported_features) != 0 {
+                *(feature.field)(features) = vk::TRUE;
+            } else {
+                println!("{} isn't supported, continuing without it", feature.name);
+            }
+        }
+        for ext in self.extensions.iter() {
+            if !supported_extensions.contains(&ext.name) {
+                println!("{:?} isn't supported, continuing without it", ext.name);
+            } else if !extensions.contains(&ext.name) {
+                extensions.push(ext.name.clone());
+            }
+        }
+    }
+
+    /// Prepends the chained feature structs to `create_info`'s `p_next` chain.  `self` has to stay
+    /// alive until the device is created.
+    pub(crate) fn chain(&mut self, create_info: &mut vk::DeviceCreateInfo) {
+        for next in self.chained.iter_mut() {
+            let base = next.as_base();
+            unsafe { (*base).p_next = create_info.p_next as *mut vk::BaseOutStructure };
+            create_info.p_next = base as *const c_void;
+        }
+    }
+}
+
+fn supported(
+    instance: &ash::Instance,
+    pdevice: vk::PhysicalDevice,
+) -> (vk::PhysicalDeviceFeatures, Vec<CString>) {
+    unsafe {
+        let features = instance.get_physical_device_features(pdevice);
+        let extensions = instance
+            .enumerate_device_extension_properties(pdevice)
+            .unwrap()
+            .iter()
+            .map(|ext| CStr::from_ptr(ext.extension_name.as_ptr()).to_owned())
+            .collect();
+        (features, extensions)
+    }
+}
@@ -0,0 +1,397 @@
+//! Draws egui's output over the window, for tools with a UI of their own like the puffin viewer.
+//! The owner runs egui in `Plugin::update`, hands the font texture to `set_font_texture` and draws
+//! the tessellated meshes from `Plugin::draw`, their vertices and indices going through the frame
+//! ring.
+//!
+//! The shaders are `egui.vert` and `egui.frag` in `egui_painter/`, compiled to SPIR-V by
+//! `build.rs`: the vertex shader scales points to clip space and decodes egui's sRGB vertex colors,
+//! and the fragment shader multiplies them with the font texture's coverage.
+
+use crate::ash_runner::{any_as_u8_slice, Pipeline, PipelineDescriptor, RenderCtx};
+use crate::pipeline_registry::BlendMode;
+use crate::sampler_cache::SamplerDesc;
+use crate::sprite;
+
+use ash::{util::read_spv, version::DeviceV1_0, vk};
+
+use std::{ffi::CStr, io::Cursor, mem};
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Vertex {
+    /// In points from the window's top left corner.
+    position: [f32; 2],
+    uv: [f32; 2],
+    /// sRGB encoded, with premultiplied alpha.
+    color: [u8; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PushConstants {
+    /// In points.
+    screen_size: [f32; 2],
+}
+
+/// egui's font texture, as uploaded.
+struct Font {
+    version: u64,
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    view: vk::ImageView,
+}
+
+/// Owned by the plugin drawing the UI, which creates it in `Plugin::init`, calls
+/// `recreate_pipeline` from `Plugin::pipelines_rebuilt` and `destroy` from `Plugin::destroy`.
+pub struct EguiPainter {
+    vertex_module: vk::ShaderModule,
+    fragment_module: vk::ShaderModule,
+    sampler: vk::Sampler,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    /// `None` until the first `set_font_texture`, nothing is drawn before.
+    font: Option<Font>,
+    pipeline: Pipeline,
+}
+
+impl EguiPainter {
+    pub fn new(ctx: &RenderCtx) -> Self {
+        let device = &ctx.base.device;
+        let create_module = |bytes: &[u8]| {
+            let spirv = read_spv(&mut Cursor::new(bytes)).expect("Invalid SPIR-V");
+            let shader_info = vk::ShaderModuleCreateInfo::builder().code(&spirv);
+            unsafe {
+                device
+                    .create_shader_module(&shader_info, None)
+                    .expect("Shader module error")
+            }
+        };
+        let vertex_module =
+            create_module(include_bytes!(concat!(env!("OUT_DIR"), "/egui.vert.spv")));
+        let fragment_module =
+            create_module(include_bytes!(concat!(env!("OUT_DIR"), "/egui.frag.spv")));
+        let sampler = ctx.sampler(SamplerDesc {
+            filter: vk::Filter::LINEAR,
+            ..SamplerDesc::NEAREST_CLAMP
+        });
+
+        let bindings = [vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        }];
+        let descriptor_set_layout = ctx.descriptor_set_layout(&bindings);
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(1)
+            .pool_sizes(&pool_sizes);
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+        let set_layouts = [descriptor_set_layout];
+        let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&set_layouts);
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&allocate_info).unwrap()[0] };
+
+        Self {
+            vertex_module,
+            fragment_module,
+            sampler,
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_set,
+            font: None,
+            pipeline: create_pipeline(ctx, vertex_module, fragment_module, descriptor_set_layout),
+        }
+    }
+
+    /// Uploads egui's font texture, unless this version of it already is.  Waits for the device
+    /// when it wasn't, as earlier frames may still sample the old one, which happens when egui
+    /// first lays out text of a new size.
+    pub fn set_font_texture(&mut self, ctx: &RenderCtx, texture: &egui::Texture) {
+        if self.font.as_ref().map(|font| font.version) == Some(texture.version) {
+            return;
+        }
+        let device = &ctx.base.device;
+        if let Some(font) = self.font.take() {
+            unsafe {
+                device.device_wait_idle().unwrap();
+                destroy_font(device, &font);
+            }
+        }
+        // coverage in alpha, the atlas' colors are ignored
+        let mut pixels = Vec::with_capacity(texture.pixels.len() * 4);
+        for &coverage in texture.pixels.iter() {
+            pixels.extend_from_slice(&[255, 255, 255, coverage]);
+        }
+        let extent = vk::Extent2D {
+            width: texture.width as u32,
+            height: texture.height as u32,
+        };
+        let (image, memory, view) = sprite::create_atlas(ctx, extent, &pixels);
+        let image_infos = [vk::DescriptorImageInfo {
+            sampler: self.sampler,
+            image_view: view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos)
+            .build();
+        unsafe { device.update_descriptor_sets(&[write], &[]) };
+        self.font = Some(Font {
+            version: texture.version,
+            image,
+            memory,
+            view,
+        });
+    }
+
+    /// Recreates the pipeline for the current render pass.  The device has to be idle.
+    pub fn recreate_pipeline(&mut self, ctx: &RenderCtx) {
+        unsafe {
+            ctx.base
+                .device
+                .destroy_pipeline(self.pipeline.pipeline, None)
+        };
+        self.pipeline = create_pipeline(
+            ctx,
+            self.vertex_module,
+            self.fragment_module,
+            self.descriptor_set_layout,
+        );
+    }
+
+    /// Records drawing `meshes` over the whole window, clipped to their rectangles.  Meshes with
+    /// user textures are left out, and the rest once the frame ring has no room for them.
+    pub fn draw(
+        &self,
+        ctx: &RenderCtx,
+        command_buffer: vk::CommandBuffer,
+        meshes: &[egui::ClippedMesh],
+        pixels_per_point: f32,
+    ) {
+        if self.font.is_none() || meshes.is_empty() {
+            return;
+        }
+        let device = &ctx.base.device;
+        let extent = ctx.extent;
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        let push_constants = PushConstants {
+            screen_size: [
+                extent.width as f32 / pixels_per_point,
+                extent.height as f32 / pixels_per_point,
+            ],
+        };
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline,
+            );
+            device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                any_as_u8_slice(&push_constants),
+            );
+        }
+        for egui::ClippedMesh(clip_rect, mesh) in meshes {
+            if mesh.texture_id != egui::TextureId::Egui || mesh.indices.is_empty() {
+                continue;
+            }
+            let scissor = match scissor(*clip_rect, pixels_per_point, extent) {
+                Some(scissor) => scissor,
+                None => continue,
+            };
+            let vertices: Vec<Vertex> = mesh
+                .vertices
+                .iter()
+                .map(|vertex| Vertex {
+                    position: [vertex.pos.x, vertex.pos.y],
+                    uv: [vertex.uv.x, vertex.uv.y],
+                    color: [
+                        vertex.color.r(),
+                        vertex.color.g(),
+                        vertex.color.b(),
+                        vertex.color.a(),
+                    ],
+                })
+                .collect();
+            let offsets = ctx
+                .frame_ring
+                .push(&vertices)
+                .zip(ctx.frame_ring.push(&mesh.indices));
+            let (vertex_offset, index_offset) = match offsets {
+                Some(offsets) => offsets,
+                None => {
+                    eprintln!("egui: no room for {} vertices", vertices.len());
+                    return;
+                }
+            };
+            unsafe {
+                device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+                device.cmd_bind_vertex_buffers(
+                    command_buffer,
+                    0,
+                    &[ctx.frame_ring.buffer],
+                    &[vertex_offset],
+                );
+                device.cmd_bind_index_buffer(
+                    command_buffer,
+                    ctx.frame_ring.buffer,
+                    index_offset,
+                    vk::IndexType::UINT32,
+                );
+                device.cmd_draw_indexed(command_buffer, mesh.indices.len() as u32, 1, 0, 0, 0);
+            }
+        }
+    }
+
+    /// The device has to be idle.
+    pub fn destroy(&mut self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline.pipeline, None);
+            if let Some(font) = self.font.take() {
+                destroy_font(device, &font);
+            }
+            // frees the set along with it
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_shader_module(self.vertex_module, None);
+            device.destroy_shader_module(self.fragment_module, None);
+        }
+    }
+}
+
+/// `clip_rect` in the physical pixels of a window of `extent`, `None` if nothing of it is inside.
+fn scissor(
+    clip_rect: egui::Rect,
+    pixels_per_point: f32,
+    extent: vk::Extent2D,
+) -> Option<vk::Rect2D> {
+    let clamp = |points: f32, size: u32| {
+        (points * pixels_per_point)
+            .round()
+            .max(0.0)
+            .min(size as f32)
+    };
+    let min_x = clamp(clip_rect.min.x, extent.width);
+    let min_y = clamp(clip_rect.min.y, extent.height);
+    let max_x = clamp(clip_rect.max.x, extent.width);
+    let max_y = clamp(clip_rect.max.y, extent.height);
+    if max_x <= min_x || max_y <= min_y {
+        return None;
+    }
+    Some(vk::Rect2D {
+        offset: vk::Offset2D {
+            x: min_x as i32,
+            y: min_y as i32,
+        },
+        extent: vk::Extent2D {
+            width: (max_x - min_x) as u32,
+            height: (max_y - min_y) as u32,
+        },
+    })
+}
+
+unsafe fn destroy_font(device: &ash::Device, font: &Font) {
+    device.destroy_image_view(font.view, None);
+    device.destroy_image(font.image, None);
+    device.free_memory(font.memory, None);
+}
+
+fn create_pipeline(
+    ctx: &RenderCtx,
+    vertex_module: vk::ShaderModule,
+    fragment_module: vk::ShaderModule,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+) -> Pipeline {
+    let entry_point = unsafe { CStr::from_bytes_with_nul_unchecked(b"main\0") };
+    let bindings = [vk::VertexInputBindingDescription {
+        binding: 0,
+        stride: mem::size_of::<Vertex>() as u32,
+        input_rate: vk::VertexInputRate::VERTEX,
+    }];
+    let attributes = [
+        vk::VertexInputAttributeDescription {
+            location: 0,
+            binding: 0,
+            format: vk::Format::R32G32_SFLOAT,
+            offset: 0,
+        },
+        vk::VertexInputAttributeDescription {
+            location: 1,
+            binding: 0,
+            format: vk::Format::R32G32_SFLOAT,
+            offset: 8,
+        },
+        vk::VertexInputAttributeDescription {
+            location: 2,
+            binding: 0,
+            format: vk::Format::R8G8B8A8_UNORM,
+            offset: 16,
+        },
+    ];
+    // the fragment shader only writes the swapchain image, leave any other attachment alone
+    let mut attachments =
+        vec![BlendMode::Opaque.attachment_state(); ctx.render_pass_config.color_attachment_count()];
+    for attachment in attachments.iter_mut() {
+        attachment.color_write_mask = vk::ColorComponentFlags::empty();
+    }
+    attachments[0] = BlendMode::Premultiplied.attachment_state();
+    let desc = PipelineDescriptor::builder(Box::new([
+        vk::PipelineShaderStageCreateInfo {
+            module: vertex_module,
+            p_name: entry_point.as_ptr(),
+            stage: vk::ShaderStageFlags::VERTEX,
+            ..Default::default()
+        },
+        vk::PipelineShaderStageCreateInfo {
+            module: fragment_module,
+            p_name: entry_point.as_ptr(),
+            stage: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        },
+    ]))
+    .vertex_input(&bindings, &attributes)
+    .color_blend_attachments(&attachments)
+    .build();
+
+    let push_constant_range = vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::VERTEX,
+        offset: 0,
+        size: mem::size_of::<PushConstants>() as u32,
+    };
+    let pipeline_layout = ctx.pipeline_layout(&[descriptor_set_layout], Some(push_constant_range));
+    Pipeline::with_layout(
+        ctx,
+        desc,
+        pipeline_layout,
+        ctx.render_pass,
+        vk::PipelineCache::null(),
+    )
+}
@@ -0,0 +1,25 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    vec2 screen_size;
+};
+
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 uv;
+layout(location = 2) in vec4 color;
+
+layout(location = 0) out vec2 out_uv;
+layout(location = 1) out vec4 out_color;
+
+vec3 linear_from_srgb(vec3 srgb) {
+    vec3 lower = srgb / 12.92;
+    vec3 higher = pow((srgb + 0.055) / 1.055, vec3(2.4));
+    return mix(higher, lower, lessThan(srgb, vec3(0.04045)));
+}
+
+void main() {
+    out_uv = uv;
+    // egui's colors are sRGB encoded, the window's render pass expects linear ones
+    out_color = vec4(linear_from_srgb(color.rgb), color.a);
+    gl_Position = vec4(2.0 * position / screen_size - 1.0, 0.0, 1.0);
+}
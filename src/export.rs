@@ -0,0 +1,742 @@
+//! Sharing the rendered frames with other processes (compositors, capture plugins) without a copy
+//! through the CPU, using `VK_KHR_external_memory_fd` or `VK_KHR_external_memory_win32`.
+//!
+//! Importers connect to the socket given with `--export-memory`: a Unix socket path, or on Windows
+//! a local TCP address.  Each is sent an `Announcement` as a line of JSON for the current image
+//! right away, and again whenever the image is replaced, e.g. when the window is resized.  On Unix
+//! the memory and semaphore fds are attached to the line with `SCM_RIGHTS`, on Windows the handles
+//! are named and opened by the names in the announcement.
+//!
+//! The semaphore is a timeline semaphore, signaled with the frame number once that frame has been
+//! copied into the image.  The next frame's copy overwrites it, so importers that need a frame for
+//! longer copy it out after waiting for it.
+
+use crate::ash_runner::RenderBase;
+
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0, InstanceV1_1},
+    vk,
+};
+
+use serde::Serialize;
+
+use std::{ffi::CStr, fs::File, io};
+
+#[cfg(windows)]
+use std::{
+    io::Write,
+    net::{TcpListener, TcpStream},
+};
+#[cfg(not(windows))]
+use std::{
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        net::{UnixListener, UnixStream},
+    },
+    path::PathBuf,
+};
+
+#[cfg(windows)]
+pub const HANDLE_TYPE: vk::ExternalMemoryHandleTypeFlags =
+    vk::ExternalMemoryHandleTypeFlags::EXTERNAL_MEMORY_HANDLE_TYPE_OPAQUE_WIN32;
+#[cfg(not(windows))]
+pub const HANDLE_TYPE: vk::ExternalMemoryHandleTypeFlags =
+    vk::ExternalMemoryHandleTypeFlags::EXTERNAL_MEMORY_HANDLE_TYPE_OPAQUE_FD;
+
+#[cfg(windows)]
+pub const SEMAPHORE_HANDLE_TYPE: vk::ExternalSemaphoreHandleTypeFlags =
+    vk::ExternalSemaphoreHandleTypeFlags::EXTERNAL_SEMAPHORE_HANDLE_TYPE_OPAQUE_WIN32;
+#[cfg(not(windows))]
+pub const SEMAPHORE_HANDLE_TYPE: vk::ExternalSemaphoreHandleTypeFlags =
+    vk::ExternalSemaphoreHandleTypeFlags::EXTERNAL_SEMAPHORE_HANDLE_TYPE_OPAQUE_FD;
+
+const USAGE: vk::ImageUsageFlags = vk::ImageUsageFlags::from_raw(
+    vk::ImageUsageFlags::TRANSFER_DST.as_raw() | vk::ImageUsageFlags::SAMPLED.as_raw(),
+);
+
+/// The device extensions needed to export memory and semaphores on this platform.  Timeline
+/// semaphores also need their feature enabled.
+pub fn extension_names() -> [&'static CStr; 3] {
+    cfg_if::cfg_if! {
+        if #[cfg(windows)] {
+            [
+                vk::KhrExternalMemoryWin32Fn::name(),
+                vk::KhrExternalSemaphoreWin32Fn::name(),
+                vk::KhrTimelineSemaphoreFn::name(),
+            ]
+        } else {
+            [
+                ash::extensions::khr::ExternalMemoryFd::name(),
+                vk::KhrExternalSemaphoreFdFn::name(),
+                vk::KhrTimelineSemaphoreFn::name(),
+            ]
+        }
+    }
+}
+
+/// An OS handle to the memory of an `ExportedImage` or to the `Exporter`'s semaphore.
+#[derive(Debug, Clone, Copy)]
+pub enum ExternalHandle {
+    Fd(i32),
+    Win32(vk::HANDLE),
+}
+
+// a win32 HANDLE refers to a kernel object, not to memory owned by the creating thread
+unsafe impl Send for ExternalHandle {}
+
+impl ExternalHandle {
+    /// Releases this process' reference, importers that received the handle keep their own.
+    fn close(self) {
+        // File closes the fd/handle when dropped
+        let _file = match self {
+            #[cfg(not(windows))]
+            ExternalHandle::Fd(fd) => unsafe {
+                <File as std::os::unix::io::FromRawFd>::from_raw_fd(fd)
+            },
+            #[cfg(windows)]
+            ExternalHandle::Win32(handle) => unsafe {
+                <File as std::os::windows::io::FromRawHandle>::from_raw_handle(handle)
+            },
+            #[allow(unreachable_patterns)]
+            _ => return,
+        };
+    }
+}
+
+/// Sent to each importer as a line of JSON when it connects and whenever the image is replaced.
+/// The image is 2D, optimally tiled, with a single mip level and layer, and bound at offset 0 of
+/// the memory.  After the first copy it stays in `GENERAL` layout.
+#[derive(Serialize)]
+struct Announcement {
+    /// Counts up with every replaced image, starting at 1.
+    generation: u64,
+    /// A `VkFormat`.
+    format: i32,
+    width: u32,
+    height: u32,
+    /// `VkImageUsageFlags` the image was created with.
+    usage: u32,
+    allocation_size: vk::DeviceSize,
+    /// The memory has to be imported with `VkMemoryDedicatedAllocateInfo`.
+    dedicated: bool,
+    /// Importers have to be on the same device and driver, see `VkPhysicalDeviceIDProperties`.
+    device_uuid: [u8; vk::UUID_SIZE],
+    driver_uuid: [u8; vk::UUID_SIZE],
+    /// The value the timeline semaphore was last signaled with.
+    frame: u64,
+    /// Names to open the handles with, in `VkImportMemoryWin32HandleInfoKHR` and
+    /// `VkImportSemaphoreWin32HandleInfoKHR`.
+    #[cfg(windows)]
+    memory_name: String,
+    #[cfg(windows)]
+    semaphore_name: String,
+}
+
+/// A null terminated UTF-16 copy of `name`, for the `name` of the export infos.
+#[cfg(windows)]
+fn wide(name: &str) -> Vec<u16> {
+    name.encode_utf16().chain(Some(0)).collect()
+}
+
+/// Hands the `ExportedImage` and a semaphore signaled after each copy to importers, announcing
+/// replacements of the image.
+pub struct Exporter {
+    #[cfg(not(windows))]
+    listener: UnixListener,
+    #[cfg(not(windows))]
+    path: PathBuf,
+    #[cfg(not(windows))]
+    importers: Vec<UnixStream>,
+    #[cfg(windows)]
+    listener: TcpListener,
+    #[cfg(windows)]
+    importers: Vec<TcpStream>,
+    image: Option<ExportedImage>,
+    generation: u64,
+    pub semaphore: vk::Semaphore,
+    semaphore_handle: ExternalHandle,
+    #[cfg(windows)]
+    semaphore_name: String,
+    frame: u64,
+    device_uuid: [u8; vk::UUID_SIZE],
+    driver_uuid: [u8; vk::UUID_SIZE],
+}
+
+impl Exporter {
+    /// Listens at `address` and exports an image of `extent` in the surface format.
+    pub fn new(base: &RenderBase, address: &str, extent: vk::Extent2D) -> Self {
+        #[cfg(not(windows))]
+        let (listener, path) = {
+            let path = PathBuf::from(address);
+            // left behind by an earlier run that didn't exit cleanly
+            let _ = std::fs::remove_file(&path);
+            let listener = UnixListener::bind(&path).unwrap_or_else(|err| {
+                panic!("Unable to listen for importers at {}: {}", address, err)
+            });
+            (listener, path)
+        };
+        #[cfg(windows)]
+        let listener = TcpListener::bind(address)
+            .unwrap_or_else(|err| panic!("Unable to listen for importers at {}: {}", address, err));
+        // polled once per frame
+        listener.set_nonblocking(true).unwrap();
+
+        let mut id_properties = vk::PhysicalDeviceIDProperties::default();
+        let mut properties = vk::PhysicalDeviceProperties2::builder().push_next(&mut id_properties);
+        unsafe {
+            base.instance
+                .get_physical_device_properties2(base.pdevice, &mut properties)
+        };
+
+        #[cfg(windows)]
+        let semaphore_name = format!("Local\\ash-runner-{}-semaphore", std::process::id());
+        let semaphore = {
+            let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+                .semaphore_type(vk::SemaphoreType::TIMELINE)
+                .initial_value(0);
+            let external_info = vk::PhysicalDeviceExternalSemaphoreInfo::builder()
+                .handle_type(SEMAPHORE_HANDLE_TYPE)
+                .push_next(&mut type_info);
+            let mut external_properties = vk::ExternalSemaphoreProperties::default();
+            unsafe {
+                base.instance
+                    .get_physical_device_external_semaphore_properties(
+                        base.pdevice,
+                        &external_info,
+                        &mut external_properties,
+                    )
+            };
+            assert!(
+                external_properties.external_semaphore_features.contains(
+                    vk::ExternalSemaphoreFeatureFlags::EXTERNAL_SEMAPHORE_FEATURE_EXPORTABLE
+                ),
+                "The device can't export timeline semaphores"
+            );
+
+            let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+                .semaphore_type(vk::SemaphoreType::TIMELINE)
+                .initial_value(0);
+            let mut export_info =
+                vk::ExportSemaphoreCreateInfo::builder().handle_types(SEMAPHORE_HANDLE_TYPE);
+            #[allow(unused_mut)]
+            let mut create_info = vk::SemaphoreCreateInfo::builder()
+                .push_next(&mut type_info)
+                .push_next(&mut export_info);
+            #[cfg(windows)]
+            let name = wide(&semaphore_name);
+            #[cfg(windows)]
+            let mut win32_info =
+                vk::ExportSemaphoreWin32HandleInfoKHR::builder().name(name.as_ptr());
+            #[cfg(windows)]
+            {
+                create_info = create_info.push_next(&mut win32_info);
+            }
+            unsafe { base.device.create_semaphore(&create_info, None).unwrap() }
+        };
+        let semaphore_handle = Self::export_semaphore(base, semaphore);
+
+        let mut exporter = Self {
+            listener,
+            #[cfg(not(windows))]
+            path,
+            importers: Vec::new(),
+            image: None,
+            generation: 0,
+            semaphore,
+            semaphore_handle,
+            #[cfg(windows)]
+            semaphore_name,
+            frame: 0,
+            device_uuid: id_properties.device_uuid,
+            driver_uuid: id_properties.driver_uuid,
+        };
+        exporter.replace_image(base, extent);
+        exporter
+    }
+
+    #[cfg(not(windows))]
+    fn export_semaphore(base: &RenderBase, semaphore: vk::Semaphore) -> ExternalHandle {
+        let external_semaphore_fd = vk::KhrExternalSemaphoreFdFn::load(|name| unsafe {
+            std::mem::transmute(
+                base.instance
+                    .get_device_proc_addr(base.device.handle(), name.as_ptr()),
+            )
+        });
+        let get_fd_info = vk::SemaphoreGetFdInfoKHR::builder()
+            .semaphore(semaphore)
+            .handle_type(SEMAPHORE_HANDLE_TYPE);
+        let mut fd = -1;
+        let result = unsafe {
+            external_semaphore_fd.get_semaphore_fd_khr(base.device.handle(), &*get_fd_info, &mut fd)
+        };
+        assert_eq!(result, vk::Result::SUCCESS, "Unable to export semaphore");
+        ExternalHandle::Fd(fd)
+    }
+
+    #[cfg(windows)]
+    fn export_semaphore(base: &RenderBase, semaphore: vk::Semaphore) -> ExternalHandle {
+        let external_semaphore_win32 = vk::KhrExternalSemaphoreWin32Fn::load(|name| unsafe {
+            std::mem::transmute(
+                base.instance
+                    .get_device_proc_addr(base.device.handle(), name.as_ptr()),
+            )
+        });
+        let get_handle_info = vk::SemaphoreGetWin32HandleInfoKHR::builder()
+            .semaphore(semaphore)
+            .handle_type(SEMAPHORE_HANDLE_TYPE);
+        let mut handle = std::ptr::null_mut();
+        let result = unsafe {
+            external_semaphore_win32.get_semaphore_win32_handle_khr(
+                base.device.handle(),
+                &*get_handle_info,
+                &mut handle,
+            )
+        };
+        assert_eq!(result, vk::Result::SUCCESS, "Unable to export semaphore");
+        ExternalHandle::Win32(handle)
+    }
+
+    /// The image frames are copied into, `None` while the swapchain is gone.
+    pub fn image(&self) -> Option<&ExportedImage> {
+        self.image.as_ref()
+    }
+
+    /// Exports a new image of `extent`, e.g. after a resize, and announces it to the importers.
+    /// The device must be idle.
+    pub fn replace_image(&mut self, base: &RenderBase, extent: vk::Extent2D) {
+        self.release_image(&base.device);
+        self.generation += 1;
+        self.image = Some(ExportedImage::new(
+            base,
+            base.surface_format.format,
+            extent,
+            self.generation,
+        ));
+        let mut importers = std::mem::take(&mut self.importers);
+        importers.retain(|importer| self.announce(importer));
+        self.importers = importers;
+    }
+
+    /// Destroys the image, e.g. along with the swapchain.  The device must be idle.
+    pub fn release_image(&mut self, device: &ash::Device) {
+        if let Some(image) = self.image.take() {
+            image.destroy(device);
+        }
+    }
+
+    /// Announces the current image to importers that connected since the last call.
+    pub fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((importer, _)) => {
+                    // the announcements are small and rare, blocking on them is fine
+                    if let Err(err) = importer.set_nonblocking(false) {
+                        eprintln!("export: {}", err);
+                        continue;
+                    }
+                    if self.announce(&importer) {
+                        self.importers.push(importer);
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    eprintln!("export: {}", err);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Submits a signal of the semaphore with the next frame number, after the copy submitted
+    /// before it.
+    pub fn signal(&mut self, device: &ash::Device, queue: vk::Queue) {
+        self.frame += 1;
+        let values = [self.frame];
+        let semaphores = [self.semaphore];
+        let mut timeline_info =
+            vk::TimelineSemaphoreSubmitInfo::builder().signal_semaphore_values(&values);
+        let submit_info = vk::SubmitInfo::builder()
+            .signal_semaphores(&semaphores)
+            .push_next(&mut timeline_info);
+        unsafe {
+            device
+                .queue_submit(queue, &[submit_info.build()], vk::Fence::null())
+                .expect("queue submit failed.");
+        }
+    }
+
+    /// Sends the current image to `importer`, false if it went away.
+    fn announce(&self, importer: &Stream) -> bool {
+        let image = match &self.image {
+            Some(image) => image,
+            None => return true,
+        };
+        let announcement = Announcement {
+            generation: self.generation,
+            format: image.format.as_raw(),
+            width: image.extent.width,
+            height: image.extent.height,
+            usage: USAGE.as_raw(),
+            allocation_size: image.allocation_size,
+            dedicated: image.dedicated,
+            device_uuid: self.device_uuid,
+            driver_uuid: self.driver_uuid,
+            frame: self.frame,
+            #[cfg(windows)]
+            memory_name: image.name.clone(),
+            #[cfg(windows)]
+            semaphore_name: self.semaphore_name.clone(),
+        };
+        let mut line = serde_json::to_string(&announcement).unwrap();
+        line.push('\n');
+        #[cfg(not(windows))]
+        let result = match (image.handle, self.semaphore_handle) {
+            (ExternalHandle::Fd(memory), ExternalHandle::Fd(semaphore)) => {
+                send_with_fds(importer, line.as_bytes(), &[memory, semaphore])
+            }
+            _ => unreachable!(),
+        };
+        #[cfg(windows)]
+        let result = (&*importer).write_all(line.as_bytes());
+        match result {
+            Ok(()) => true,
+            Err(err) => {
+                eprintln!("export: dropping importer: {}", err);
+                false
+            }
+        }
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        self.release_image(device);
+        unsafe { device.destroy_semaphore(self.semaphore, None) };
+        self.semaphore_handle.close();
+        #[cfg(not(windows))]
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(not(windows))]
+type Stream = UnixStream;
+#[cfg(windows)]
+type Stream = TcpStream;
+
+/// Writes `bytes` to `stream` with `fds` attached to the first of them as `SCM_RIGHTS`, which
+/// gives the receiving process its own copies of the fds.
+#[cfg(not(windows))]
+fn send_with_fds(stream: &UnixStream, bytes: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    use std::io::Write;
+
+    let fds_size = std::mem::size_of_val(fds) as u32;
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fds_size) } as usize];
+    let mut iov = libc::iovec {
+        iov_base: bytes.as_ptr() as *mut libc::c_void,
+        iov_len: bytes.len(),
+    };
+    let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+    header.msg_iov = &mut iov;
+    header.msg_iovlen = 1;
+    header.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    header.msg_controllen = control.len() as _;
+    let sent = unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&header);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fds_size) as _;
+        std::ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+        libc::sendmsg(stream.as_raw_fd(), &header, 0)
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // the fds went along with the first byte, the rest is plain
+    (&*stream).write_all(&bytes[sent as usize..])
+}
+
+/// An optimally tiled image with exportable memory that each frame is copied into after rendering.
+pub struct ExportedImage {
+    pub image: vk::Image,
+    pub memory: vk::DeviceMemory,
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub allocation_size: vk::DeviceSize,
+    /// The memory is a dedicated allocation, which the device requires for exporting the format.
+    pub dedicated: bool,
+    pub handle: ExternalHandle,
+    /// Of the memory's handle, unique per generation.
+    #[cfg(windows)]
+    pub name: String,
+}
+
+impl ExportedImage {
+    /// `generation` tells the images apart for naming their handles.
+    pub fn new(
+        base: &RenderBase,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        #[allow(unused_variables)] generation: u64,
+    ) -> Self {
+        let features = Self::external_memory_features(base, format);
+        assert!(
+            features.contains(vk::ExternalMemoryFeatureFlags::EXTERNAL_MEMORY_FEATURE_EXPORTABLE),
+            "The device can't export the memory of {:?} images",
+            format
+        );
+        let dedicated = features
+            .contains(vk::ExternalMemoryFeatureFlags::EXTERNAL_MEMORY_FEATURE_DEDICATED_ONLY);
+
+        let mut external_image_info =
+            vk::ExternalMemoryImageCreateInfo::builder().handle_types(HANDLE_TYPE);
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(USAGE)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .push_next(&mut external_image_info);
+        let image = unsafe { base.device.create_image(&image_info, None).unwrap() };
+
+        let requirements = unsafe { base.device.get_image_memory_requirements(image) };
+        let memory_type_index = base
+            .find_memory_type(
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .expect("No device local memory type for the exported image");
+        let mut export_info = vk::ExportMemoryAllocateInfo::builder().handle_types(HANDLE_TYPE);
+        let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::builder().image(image);
+        let mut allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type_index)
+            .push_next(&mut export_info);
+        if dedicated {
+            allocate_info = allocate_info.push_next(&mut dedicated_info);
+        }
+        #[cfg(windows)]
+        let name = format!(
+            "Local\\ash-runner-{}-memory-{}",
+            std::process::id(),
+            generation
+        );
+        #[cfg(windows)]
+        let wide_name = wide(&name);
+        #[cfg(windows)]
+        let mut win32_info = vk::ExportMemoryWin32HandleInfoKHR::builder().name(wide_name.as_ptr());
+        #[cfg(windows)]
+        {
+            allocate_info = allocate_info.push_next(&mut win32_info);
+        }
+        let memory = unsafe {
+            let memory = base.device.allocate_memory(&allocate_info, None).unwrap();
+            base.device.bind_image_memory(image, memory, 0).unwrap();
+            memory
+        };
+
+        Self {
+            image,
+            memory,
+            format,
+            extent,
+            allocation_size: requirements.size,
+            dedicated,
+            handle: Self::export_handle(base, memory),
+            #[cfg(windows)]
+            name,
+        }
+    }
+
+    /// What the device can do with the memory of optimally tiled `format` images of the handle
+    /// type, e.g. whether it can export it at all.
+    fn external_memory_features(
+        base: &RenderBase,
+        format: vk::Format,
+    ) -> vk::ExternalMemoryFeatureFlags {
+        let mut external_info =
+            vk::PhysicalDeviceExternalImageFormatInfo::builder().handle_type(HANDLE_TYPE);
+        let format_info = vk::PhysicalDeviceImageFormatInfo2::builder()
+            .format(format)
+            .ty(vk::ImageType::TYPE_2D)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(USAGE)
+            .push_next(&mut external_info);
+        let mut external_properties = vk::ExternalImageFormatProperties::default();
+        let mut properties =
+            vk::ImageFormatProperties2::builder().push_next(&mut external_properties);
+        let result = unsafe {
+            base.instance.get_physical_device_image_format_properties2(
+                base.pdevice,
+                &format_info,
+                &mut properties,
+            )
+        };
+        match result {
+            Ok(()) => {
+                external_properties
+                    .external_memory_properties
+                    .external_memory_features
+            }
+            // the format doesn't support the usage at all
+            Err(vk::Result::ERROR_FORMAT_NOT_SUPPORTED) => vk::ExternalMemoryFeatureFlags::empty(),
+            Err(err) => panic!("Unable to query external memory support: {:?}", err),
+        }
+    }
+
+    #[cfg(not(windows))]
+    fn export_handle(base: &RenderBase, memory: vk::DeviceMemory) -> ExternalHandle {
+        let external_memory_fd =
+            ash::extensions::khr::ExternalMemoryFd::new(&base.instance, &base.device);
+        let get_fd_info = vk::MemoryGetFdInfoKHR::builder()
+            .memory(memory)
+            .handle_type(HANDLE_TYPE);
+        let fd = unsafe { external_memory_fd.get_memory_fd(&get_fd_info).unwrap() };
+        ExternalHandle::Fd(fd)
+    }
+
+    #[cfg(windows)]
+    fn export_handle(base: &RenderBase, memory: vk::DeviceMemory) -> ExternalHandle {
+        let external_memory_win32 = vk::KhrExternalMemoryWin32Fn::load(|name| unsafe {
+            std::mem::transmute(
+                base.instance
+                    .get_device_proc_addr(base.device.handle(), name.as_ptr()),
+            )
+        });
+        let get_handle_info = vk::MemoryGetWin32HandleInfoKHR::builder()
+            .memory(memory)
+            .handle_type(HANDLE_TYPE);
+        let mut handle = std::ptr::null_mut();
+        let result = unsafe {
+            external_memory_win32.get_memory_win32_handle_khr(
+                base.device.handle(),
+                &*get_handle_info,
+                &mut handle,
+            )
+        };
+        assert_eq!(result, vk::Result::SUCCESS, "Unable to export memory");
+        ExternalHandle::Win32(handle)
+    }
+
+    /// Records copying `src`, a swapchain image that was just rendered to and is in
+    /// `PRESENT_SRC_KHR` layout, into the exported image.  `src` is back in `PRESENT_SRC_KHR` and
+    /// the exported image in `GENERAL` layout afterwards.
+    pub fn record_copy(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        src: vk::Image,
+    ) {
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let barrier = |image, old_layout, new_layout, src_access_mask, dst_access_mask| {
+            vk::ImageMemoryBarrier::builder()
+                .image(image)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .subresource_range(subresource_range)
+                .build()
+        };
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let region = vk::ImageCopy {
+            src_subresource: subresource,
+            src_offset: vk::Offset3D::default(),
+            dst_subresource: subresource,
+            dst_offset: vk::Offset3D::default(),
+            extent: vk::Extent3D {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            },
+        };
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    barrier(
+                        src,
+                        vk::ImageLayout::PRESENT_SRC_KHR,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                        vk::AccessFlags::TRANSFER_READ,
+                    ),
+                    // the previous frame is overwritten entirely, no need to keep it
+                    barrier(
+                        self.image,
+                        vk::ImageLayout::UNDEFINED,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::TRANSFER_WRITE,
+                    ),
+                ],
+            );
+            device.cmd_copy_image(
+                command_buffer,
+                src,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[
+                    barrier(
+                        src,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::ImageLayout::PRESENT_SRC_KHR,
+                        vk::AccessFlags::TRANSFER_READ,
+                        vk::AccessFlags::empty(),
+                    ),
+                    barrier(
+                        self.image,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::ImageLayout::GENERAL,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::empty(),
+                    ),
+                ],
+            );
+        }
+    }
+
+    /// Destroys the image and releases this process' handle to its memory.  Importers that already
+    /// received the handle keep their own reference.
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_image(self.image, None);
+            device.free_memory(self.memory, None);
+        }
+        self.handle.close();
+    }
+}
@@ -0,0 +1,170 @@
+//! Moves `ctx.camera` first-person style, to walk through a scene instead of looking at it from
+//! outside: F6, or the key bound to `Action::ToggleFlyCamera`, switches between it and the orbit
+//! camera.  While flying, W, A, S and D move forward, left, back and right, E and Q up and down,
+//! holding shift moves faster, dragging with the left button looks around, and scrolling changes
+//! the speed.  The movement keys go to the camera while flying even if they are bound to actions,
+//! like W to the wireframe.
+
+use crate::ash_runner::RenderCtx;
+use crate::camera::{self, CameraMode};
+use crate::keymap::Action;
+use crate::plugin::Plugin;
+
+use winit::event::{MouseButton, VirtualKeyCode};
+
+use std::{collections::HashSet, f32::consts::FRAC_PI_2, time::Instant};
+
+/// The keys the fly camera takes while flying.
+pub const MOVEMENT_KEYS: [VirtualKeyCode; 8] = [
+    VirtualKeyCode::W,
+    VirtualKeyCode::A,
+    VirtualKeyCode::S,
+    VirtualKeyCode::D,
+    VirtualKeyCode::E,
+    VirtualKeyCode::Q,
+    VirtualKeyCode::LShift,
+    VirtualKeyCode::RShift,
+];
+
+/// Radians per physical pixel dragged.
+const LOOK_SPEED: f32 = 0.003;
+/// Speed multiplier while shift is held.
+const FAST_FACTOR: f32 = 4.0;
+/// Factor the speed is multiplied with per line scrolled away from the user.
+const SPEED_FACTOR: f32 = 1.25;
+/// Longer frames move as if they took this long, so a stall doesn't throw the camera far away.
+const MAX_FRAME_TIME: f32 = 0.1;
+/// Keeps the view off straight up and down, where it has no right direction.
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+pub struct FlyCamera {
+    /// In world units per second.
+    speed: f32,
+    held: HashSet<VirtualKeyCode>,
+    looking: bool,
+    cursor: Option<[f64; 2]>,
+    last_update: Option<Instant>,
+}
+
+impl FlyCamera {
+    /// `speed` is in world units per second.
+    pub fn new(speed: f32) -> Self {
+        Self {
+            speed,
+            held: HashSet::new(),
+            looking: false,
+            cursor: None,
+            last_update: None,
+        }
+    }
+
+    fn held(&self, key: VirtualKeyCode) -> f32 {
+        if self.held.contains(&key) {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Plugin for FlyCamera {
+    fn name(&self) -> &str {
+        "fly camera"
+    }
+
+    fn update(&mut self, ctx: &mut RenderCtx) {
+        // real time, so the camera moves while the clock is paused
+        let now = Instant::now();
+        let frame_time = match self.last_update.replace(now) {
+            Some(last) => (now - last).as_secs_f32().min(MAX_FRAME_TIME),
+            None => return,
+        };
+        if ctx.camera_mode != CameraMode::Fly {
+            return;
+        }
+        let (right, _, forward) = ctx.camera.basis();
+        let mut direction = [0.0; 3];
+        for &(key, sign, axis) in [
+            (VirtualKeyCode::W, 1.0, forward),
+            (VirtualKeyCode::S, -1.0, forward),
+            (VirtualKeyCode::D, 1.0, right),
+            (VirtualKeyCode::A, -1.0, right),
+            (VirtualKeyCode::E, 1.0, [0.0, 1.0, 0.0]),
+            (VirtualKeyCode::Q, -1.0, [0.0, 1.0, 0.0]),
+        ]
+        .iter()
+        {
+            direction = camera::add(direction, camera::scale(axis, sign * self.held(key)));
+        }
+        if camera::length(direction) == 0.0 {
+            return;
+        }
+        let fast = self
+            .held(VirtualKeyCode::LShift)
+            .max(self.held(VirtualKeyCode::RShift));
+        let speed = self.speed * (1.0 + fast * (FAST_FACTOR - 1.0));
+        let step = camera::scale(camera::normalize(direction), speed * frame_time);
+        ctx.camera.eye = camera::add(ctx.camera.eye, step);
+        ctx.camera.target = camera::add(ctx.camera.target, step);
+    }
+
+    fn key(&mut self, ctx: &mut RenderCtx, key: VirtualKeyCode, pressed: bool) {
+        if ctx.keymap.action(key) == Some(Action::ToggleFlyCamera) && pressed {
+            ctx.camera_mode = match ctx.camera_mode {
+                CameraMode::Orbit => CameraMode::Fly,
+                CameraMode::Fly => CameraMode::Orbit,
+            };
+            self.held.clear();
+            self.looking = false;
+            println!(
+                "fly camera {}",
+                if ctx.camera_mode == CameraMode::Fly {
+                    "on"
+                } else {
+                    "off"
+                }
+            );
+        } else if MOVEMENT_KEYS.contains(&key) {
+            if pressed && ctx.camera_mode == CameraMode::Fly {
+                self.held.insert(key);
+            } else {
+                self.held.remove(&key);
+            }
+        }
+    }
+
+    fn mouse_button(&mut self, ctx: &mut RenderCtx, button: MouseButton, pressed: bool) {
+        if button == MouseButton::Left {
+            self.looking = pressed && ctx.camera_mode == CameraMode::Fly;
+        }
+    }
+
+    fn cursor_moved(&mut self, ctx: &mut RenderCtx, position: [f64; 2]) {
+        let previous = self.cursor.replace(position);
+        let previous = match previous {
+            Some(previous) if self.looking => previous,
+            _ => return,
+        };
+        let dx = (position[0] - previous[0]) as f32;
+        let dy = (position[1] - previous[1]) as f32;
+        // turns the view direction in place, keeping the target as far away as it was
+        let offset = camera::sub(ctx.camera.target, ctx.camera.eye);
+        let distance = camera::length(offset);
+        let forward = camera::scale(offset, 1.0 / distance);
+        let yaw = forward[0].atan2(-forward[2]) + dx * LOOK_SPEED;
+        let pitch = (forward[1].max(-1.0).min(1.0).asin() - dy * LOOK_SPEED)
+            .max(-MAX_PITCH)
+            .min(MAX_PITCH);
+        let (yaw_sin, yaw_cos) = yaw.sin_cos();
+        let (pitch_sin, pitch_cos) = pitch.sin_cos();
+        let forward = [pitch_cos * yaw_sin, pitch_sin, -pitch_cos * yaw_cos];
+        ctx.camera.target = camera::add(ctx.camera.eye, camera::scale(forward, distance));
+    }
+
+    fn mouse_wheel(&mut self, ctx: &mut RenderCtx, delta: f32) {
+        if ctx.camera_mode == CameraMode::Fly {
+            self.speed *= SPEED_FACTOR.powf(delta);
+            println!("fly speed {:.3}", self.speed);
+        }
+    }
+}
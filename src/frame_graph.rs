@@ -0,0 +1,146 @@
+//! A graph of the CPU and GPU times of the last few hundred frames in the window's corner, toggled
+//! with F3 by default, so a shader edit that costs frame time shows right away.
+//!
+//! Each frame is two columns, CPU time in green then GPU time in orange, over a background spanning
+//! two 60 Hz frames with a line at one.  With calibrated timestamps each frame's end-to-end latency
+//! is a tick across both columns in blue.  The bars are cleared rectangles, so it needs no pipeline
+//! and draws the same whatever shaders are loaded.
+
+use crate::ash_runner::RenderCtx;
+use crate::frame_timing::FrameTimer;
+use crate::keymap::Action;
+use crate::plugin::Plugin;
+
+use ash::{version::DeviceV1_0, vk};
+use winit::event::VirtualKeyCode;
+
+use std::time::Duration;
+
+/// Frame time at the top of the graph.
+const RANGE: Duration = Duration::from_micros(33_333);
+const TARGET: Duration = Duration::from_micros(16_667);
+/// In logical pixels.
+const HEIGHT: f64 = 100.0;
+const MARGIN: f64 = 8.0;
+
+const BACKGROUND: [f32; 4] = [0.05, 0.05, 0.05, 1.0];
+const TARGET_LINE: [f32; 4] = [0.4, 0.4, 0.4, 1.0];
+const CPU_COLOR: [f32; 4] = [0.2, 0.8, 0.2, 1.0];
+const GPU_COLOR: [f32; 4] = [1.0, 0.5, 0.1, 1.0];
+const LATENCY_COLOR: [f32; 4] = [0.3, 0.6, 1.0, 1.0];
+
+pub struct FrameGraph {
+    visible: bool,
+}
+
+impl FrameGraph {
+    pub fn new(visible: bool) -> Self {
+        Self { visible }
+    }
+}
+
+impl Plugin for FrameGraph {
+    fn name(&self) -> &str {
+        "frame graph"
+    }
+
+    fn key(&mut self, ctx: &mut RenderCtx, key: VirtualKeyCode, pressed: bool) {
+        if ctx.keymap.action(key) == Some(Action::ToggleFrameGraph) && pressed {
+            self.visible = !self.visible;
+        }
+    }
+
+    fn draw(&self, ctx: &RenderCtx, command_buffer: vk::CommandBuffer) {
+        if !self.visible {
+            return;
+        }
+        let scale = ctx.scale_factor;
+        let height = (HEIGHT * scale) as u32;
+        let width = 2 * FrameTimer::HISTORY as u32;
+        let margin = (MARGIN * scale) as i32;
+        // bottom left, inside the window even if it is smaller than the graph
+        let left = margin;
+        let bottom = ctx.extent.height as i32 - margin;
+        let width = width.min(ctx.extent.width.saturating_sub(2 * margin as u32));
+        let height = height.min(ctx.extent.height.saturating_sub(2 * margin as u32));
+        if width == 0 || height == 0 {
+            return;
+        }
+        let bar = |x: i32, bar_width: u32, fraction: f64| {
+            let bar_height = ((fraction.min(1.0) * f64::from(height)) as u32).max(1);
+            vk::ClearRect {
+                rect: vk::Rect2D {
+                    offset: vk::Offset2D {
+                        x,
+                        y: bottom - bar_height as i32,
+                    },
+                    extent: vk::Extent2D {
+                        width: bar_width,
+                        height: bar_height,
+                    },
+                },
+                base_array_layer: 0,
+                layer_count: 1,
+            }
+        };
+        let fraction = |time: Duration| time.as_secs_f64() / RANGE.as_secs_f64();
+
+        // the newest frames at the right edge
+        let history = &ctx.frame_timer.history;
+        let columns = (width / 2) as usize;
+        let first_x = left + width as i32 - 2 * history.len().min(columns) as i32;
+        let frames = history.iter().skip(history.len().saturating_sub(columns));
+        let mut cpu_bars = Vec::with_capacity(columns);
+        let mut gpu_bars = Vec::with_capacity(columns);
+        let mut latency_ticks = Vec::new();
+        for (index, timing) in frames.enumerate() {
+            let x = first_x + 2 * index as i32;
+            cpu_bars.push(bar(x, 1, fraction(timing.cpu)));
+            if let Some(gpu) = timing.gpu {
+                gpu_bars.push(bar(x + 1, 1, fraction(gpu)));
+            }
+            if let Some(latency) = timing.latency {
+                let mut tick = bar(x, 2, fraction(latency));
+                tick.rect.extent.height = 1;
+                latency_ticks.push(tick);
+            }
+        }
+        let target_y = bottom - (fraction(TARGET) * f64::from(height)) as i32;
+        let target_line = vk::ClearRect {
+            rect: vk::Rect2D {
+                offset: vk::Offset2D {
+                    x: left,
+                    y: target_y,
+                },
+                extent: vk::Extent2D { width, height: 1 },
+            },
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        let layers = [
+            (BACKGROUND, vec![bar(left, width, 1.0)]),
+            (TARGET_LINE, vec![target_line]),
+            (CPU_COLOR, cpu_bars),
+            (GPU_COLOR, gpu_bars),
+            (LATENCY_COLOR, latency_ticks),
+        ];
+        for (color, rects) in layers.iter() {
+            if rects.is_empty() {
+                continue;
+            }
+            let attachment = vk::ClearAttachment {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                color_attachment: 0,
+                clear_value: vk::ClearValue {
+                    color: vk::ClearColorValue { float32: *color },
+                },
+            };
+            unsafe {
+                ctx.base
+                    .device
+                    .cmd_clear_attachments(command_buffer, &[attachment], rects)
+            };
+        }
+    }
+}
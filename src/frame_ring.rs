@@ -0,0 +1,328 @@
+//! A persistently mapped, host visible buffer for data that changes every frame, like per-object
+//! uniforms or streamed vertices.  Each frame pushes its data after the previous frame's, wrapping
+//! around at the end, and the space is reclaimed once the fence of the frame that used it signals,
+//! so the CPU never writes what the GPU may still read:
+//!
+//! ```ignore
+//! // in Plugin::update or while recording
+//! let offset = ctx.frame_ring.push(&vertices).expect("frame ring full");
+//! device.cmd_bind_vertex_buffers(command_buffer, 0, &[ctx.frame_ring.buffer], &[offset]);
+//! ```
+//!
+//! Offsets are aligned for any use of the buffer, so one can be bound as a uniform or storage
+//! buffer as well.  `DynamicUniforms` binds it as a dynamic uniform buffer, letting many draws
+//! share one descriptor set with each picking its own data with a dynamic offset:
+//!
+//! ```ignore
+//! let range = mem::size_of::<Object>() as vk::DeviceSize;
+//! let uniforms = DynamicUniforms::new(ctx, vk::ShaderStageFlags::VERTEX, range);
+//! // with `uniforms.set_layout` in the pipeline layout, for every object
+//! let offset = uniforms.push(&ctx.frame_ring, &object).expect("frame ring full");
+//! uniforms.bind(device, command_buffer, pipeline.pipeline_layout, 0, offset);
+//! device.cmd_draw(command_buffer, 6, 1, 0, 0);
+//! ```
+
+use crate::ash_runner::{RenderBase, RenderCtx};
+
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk,
+};
+
+use std::{cell::Cell, mem};
+
+pub struct FrameRing {
+    pub buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapped: *mut u8,
+    size: vk::DeviceSize,
+    alignment: vk::DeviceSize,
+    /// Positions counting every byte ever allocated, so they only grow.  The buffer offset is the
+    /// position modulo `size`.
+    head: Cell<vk::DeviceSize>,
+    /// The oldest position the GPU may still read.
+    tail: Cell<vk::DeviceSize>,
+    /// Where the last submitted frame's data ends.
+    submitted: Cell<vk::DeviceSize>,
+}
+
+// `mapped` is only written through `push` and `push_uniform`, which the render thread calls
+unsafe impl Send for FrameRing {}
+
+impl FrameRing {
+    /// `size` has to be a multiple of the offset alignment, which a power of two of at least 256
+    /// bytes always is.
+    pub fn new(base: &RenderBase, size: vk::DeviceSize) -> Self {
+        let device = &base.device;
+        let limits = unsafe {
+            base.instance
+                .get_physical_device_properties(base.pdevice)
+                .limits
+        };
+        // 16 bytes covers every vertex and index format
+        let alignment = limits
+            .min_uniform_buffer_offset_alignment
+            .max(limits.min_storage_buffer_offset_alignment)
+            .max(16);
+        assert_eq!(
+            size % alignment,
+            0,
+            "The frame ring size isn't a multiple of the offset alignment"
+        );
+        let buffer_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(
+                vk::BufferUsageFlags::VERTEX_BUFFER
+                    | vk::BufferUsageFlags::INDEX_BUFFER
+                    | vk::BufferUsageFlags::UNIFORM_BUFFER
+                    | vk::BufferUsageFlags::STORAGE_BUFFER,
+            )
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = unsafe { device.create_buffer(&buffer_info, None).unwrap() };
+        let memory = unsafe {
+            let requirements = device.get_buffer_memory_requirements(buffer);
+            let memory_type_index = base
+                .find_memory_type(
+                    requirements.memory_type_bits,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+                .expect("No host visible memory type for the frame ring");
+            let allocate_info = vk::MemoryAllocateInfo::builder()
+                .allocation_size(requirements.size)
+                .memory_type_index(memory_type_index);
+            let memory = device.allocate_memory(&allocate_info, None).unwrap();
+            device.bind_buffer_memory(buffer, memory, 0).unwrap();
+            memory
+        };
+        let mapped = unsafe {
+            device
+                .map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+                .unwrap() as *mut u8
+        };
+        Self {
+            buffer,
+            memory,
+            mapped,
+            size,
+            alignment,
+            head: Cell::new(0),
+            tail: Cell::new(0),
+            submitted: Cell::new(0),
+        }
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    /// The alignment of the offsets `push` returns.
+    pub fn alignment(&self) -> vk::DeviceSize {
+        self.alignment
+    }
+
+    /// Copies `data` into the current frame's part of the ring and returns its offset in `buffer`,
+    /// valid for the commands of this frame.  `None` if the frames the GPU may still be reading
+    /// leave no room.
+    pub fn push<T: Copy>(&self, data: &[T]) -> Option<vk::DeviceSize> {
+        let len = mem::size_of_val(data) as vk::DeviceSize;
+        let offset = self.reserve(len)?;
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr() as *const u8,
+                self.mapped.add(offset as usize),
+                len as usize,
+            );
+        }
+        Some(offset)
+    }
+
+    /// Like `push`, for data bound as a dynamic uniform buffer of `range` bytes: the whole range
+    /// is reserved, so the binding stays inside the buffer.
+    pub fn push_uniform<T: Copy>(&self, data: &T, range: vk::DeviceSize) -> Option<u32> {
+        assert!(
+            mem::size_of::<T>() as vk::DeviceSize <= range,
+            "Pushing uniforms larger than their binding"
+        );
+        let offset = self.reserve(range)?;
+        unsafe { (self.mapped.add(offset as usize) as *mut T).write_unaligned(*data) };
+        Some(offset as u32)
+    }
+
+    /// Moves the head past `len` bytes and returns their offset.
+    fn reserve(&self, len: vk::DeviceSize) -> Option<vk::DeviceSize> {
+        assert!(len <= self.size, "Pushing more than the frame ring holds");
+        let (start, end) = place(
+            self.head.get(),
+            self.tail.get(),
+            self.size,
+            self.alignment,
+            len,
+        )?;
+        self.head.set(end);
+        Some(start % self.size)
+    }
+
+    /// Every frame submitted so far has finished, so their data can be overwritten.
+    pub(crate) fn reclaim(&self) {
+        self.tail.set(self.submitted.get());
+    }
+
+    /// The data pushed so far belongs to the frame just submitted.
+    pub(crate) fn frame_submitted(&self) {
+        self.submitted.set(self.head.get());
+    }
+
+    /// The device has to be done with the buffer.
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.unmap_memory(self.memory);
+            device.destroy_buffer(self.buffer, None);
+            device.free_memory(self.memory, None);
+        }
+    }
+}
+
+/// Where `len` bytes go in a ring of `size` bytes, with `head` and `tail` counting the bytes pushed
+/// and reclaimed since it was created: their start and the new head, counted the same way, or
+/// `None` if they would overwrite bytes not reclaimed yet.
+fn place(
+    head: vk::DeviceSize,
+    tail: vk::DeviceSize,
+    size: vk::DeviceSize,
+    alignment: vk::DeviceSize,
+    len: vk::DeviceSize,
+) -> Option<(vk::DeviceSize, vk::DeviceSize)> {
+    let align_up =
+        |position: vk::DeviceSize, alignment| (position + alignment - 1) / alignment * alignment;
+    let mut start = align_up(head, alignment);
+    // allocations don't wrap, the rest of the buffer is skipped instead
+    if start % size + len > size {
+        start = align_up(start, size);
+    }
+    let end = start + len;
+    if end - tail > size {
+        return None;
+    }
+    Some((start, end))
+}
+
+/// A descriptor set with the frame ring as a `UNIFORM_BUFFER_DYNAMIC` of `range` bytes at binding 0.
+/// Its layout comes from `RenderCtx::descriptor_set_layout`, so it is shared with every other set of
+/// the same stages.
+pub struct DynamicUniforms {
+    pub set_layout: vk::DescriptorSetLayout,
+    pub descriptor_set: vk::DescriptorSet,
+    pub range: vk::DeviceSize,
+    descriptor_pool: vk::DescriptorPool,
+}
+
+impl DynamicUniforms {
+    pub fn new(ctx: &RenderCtx, stage_flags: vk::ShaderStageFlags, range: vk::DeviceSize) -> Self {
+        let device = &ctx.base.device;
+        let bindings = [vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_count: 1,
+            stage_flags,
+            ..Default::default()
+        }];
+        let set_layout = ctx.descriptor_set_layout(&bindings);
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_count: 1,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(1)
+            .pool_sizes(&pool_sizes);
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+        let set_layouts = [set_layout];
+        let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&set_layouts);
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&allocate_info).unwrap()[0] };
+        let buffer_info = [vk::DescriptorBufferInfo {
+            buffer: ctx.frame_ring.buffer,
+            offset: 0,
+            range,
+        }];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .buffer_info(&buffer_info)
+            .build();
+        unsafe { device.update_descriptor_sets(&[write], &[]) };
+        Self {
+            set_layout,
+            descriptor_set,
+            range,
+            descriptor_pool,
+        }
+    }
+
+    /// Copies `data` into the ring for this frame's draws, returning the dynamic offset to bind.
+    pub fn push<T: Copy>(&self, ring: &FrameRing, data: &T) -> Option<u32> {
+        ring.push_uniform(data, self.range)
+    }
+
+    /// Binds the set at `set` for graphics pipelines, with the data at `offset`.
+    pub fn bind(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        set: u32,
+        offset: u32,
+    ) {
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                set,
+                &[self.descriptor_set],
+                &[offset],
+            );
+        }
+    }
+
+    /// The device has to be done with the set.
+    pub fn destroy(&self, device: &ash::Device) {
+        // frees the set along with it
+        unsafe { device.destroy_descriptor_pool(self.descriptor_pool, None) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligns_the_start() {
+        assert_eq!(place(1, 0, 256, 16, 8), Some((16, 24)));
+        assert_eq!(place(32, 0, 256, 16, 8), Some((32, 40)));
+    }
+
+    #[test]
+    fn skips_the_rest_of_the_buffer_instead_of_wrapping() {
+        // 48 bytes are left at the end, the allocation starts over at offset 0 of the next lap
+        assert_eq!(place(200, 200, 256, 16, 64), Some((256, 320)));
+        // where the previous lap's data is in the way
+        assert_eq!(place(200, 32, 256, 16, 64), None);
+    }
+
+    #[test]
+    fn fills_the_ring_exactly() {
+        assert_eq!(place(0, 0, 256, 16, 256), Some((0, 256)));
+        assert_eq!(place(192, 0, 256, 16, 64), Some((192, 256)));
+        assert_eq!(place(256, 256, 256, 16, 256), Some((256, 512)));
+    }
+
+    #[test]
+    fn none_when_full() {
+        assert_eq!(place(256, 0, 256, 16, 16), None);
+        assert_eq!(place(128, 0, 256, 16, 160), None);
+        // until the frames using it are reclaimed
+        assert_eq!(place(256, 16, 256, 16, 16), Some((256, 272)));
+    }
+}
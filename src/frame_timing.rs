@@ -0,0 +1,389 @@
+//! CPU and GPU time of each frame.  The GPU time comes from timestamps at the start and end of the
+//! frame's command buffer, in one of two query pools in turn, so one frame's results can be read
+//! while the next one writes the other pool.
+//!
+//! With `VK_EXT_calibrated_timestamps` the start of each frame is also stamped in the GPU's
+//! timestamp domain, so the frame's end-to-end latency, from sampling input to the GPU finishing,
+//! is known along with its GPU time.
+//!
+//! With `--timing-csv` every frame is also written to a CSV file once its GPU time is known, one
+//! row per frame with the times in milliseconds.
+//!
+//! Each render pass, the window's and those of `--passes`, is also wrapped in a pair of
+//! timestamps, and the GPU times of the passes averaged over a few seconds at a time, for the pass
+//! times overlay and, with `--pass-times`, a table printed to the console.  With a single pass the
+//! table would only repeat the frame's GPU time, so it is printed once there are more.
+
+use crate::ash_runner::RenderBase;
+use crate::calibrated_timestamps::{self, CalibratedTimestamps};
+use crate::profiling;
+
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk,
+};
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// Passes timed per frame; the rest are left out.
+const MAX_PASSES: u32 = 32;
+/// The frame's timestamps, then a pair per pass.
+const QUERY_COUNT: u32 = 2 + 2 * MAX_PASSES;
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameTiming {
+    /// Time spent in `RenderCtx::render`, less the waits for a swapchain image and for present.
+    pub cpu: Duration,
+    /// `None` if the device has no timestamps, or until the frame has completed.
+    pub gpu: Option<Duration>,
+    /// From the start of `RenderCtx::render` to the GPU finishing the frame.  `None` without
+    /// calibrated timestamps, or until the frame has completed.
+    pub latency: Option<Duration>,
+    /// Time blocked acquiring the swapchain image, mostly waiting for vsync.
+    pub acquire_wait: Duration,
+    /// Time blocked in `vkQueuePresentKHR`.
+    pub present_wait: Duration,
+}
+
+/// A render pass's average GPU time over the last report interval.
+#[derive(Debug, Clone)]
+pub struct PassTime {
+    pub label: String,
+    pub gpu: Duration,
+}
+
+/// The sum of a pass's GPU times over the frames since the last report.
+struct PassTotal {
+    label: String,
+    sum: Duration,
+    frames: u32,
+}
+
+pub struct FrameTimer {
+    /// Empty if the queue doesn't support timestamps.
+    query_pools: Vec<vk::QueryPool>,
+    /// Nanoseconds per timestamp tick.
+    timestamp_period: f64,
+    /// The bits of the timestamps that are valid.
+    timestamp_mask: u64,
+    /// `None` without the extension, or timestamps at all.
+    calibrated_timestamps: Option<CalibratedTimestamps>,
+    /// The GPU's timestamp at the start of the frames writing `query_pools[i]`.
+    frame_starts: [Option<u64>; 2],
+    /// Frames recorded so far, the current one writing `query_pools[frame % 2]`.
+    frame: usize,
+    /// The last `HISTORY` frames, oldest first.
+    pub history: VecDeque<FrameTiming>,
+    /// The labels of the passes begun in the frames writing `query_pools[i]`.
+    passes: RefCell<[Vec<String>; 2]>,
+    pass_totals: Vec<PassTotal>,
+    /// In the order the passes were recorded, as of the last report.
+    pub pass_times: Vec<PassTime>,
+    /// Prints `pass_times` at each report.
+    pub print_pass_times: bool,
+    last_report: Instant,
+    csv: Option<BufWriter<File>>,
+}
+
+impl FrameTimer {
+    pub const HISTORY: usize = 300;
+
+    pub fn new(base: &RenderBase) -> Self {
+        let timestamp_valid_bits = unsafe {
+            base.instance
+                .get_physical_device_queue_family_properties(base.pdevice)
+        }[base.queue_family_index as usize]
+            .timestamp_valid_bits;
+        let timestamp_period =
+            unsafe { base.instance.get_physical_device_properties(base.pdevice) }
+                .limits
+                .timestamp_period;
+        let query_pools = if timestamp_valid_bits > 0 && timestamp_period > 0.0 {
+            let create_info = vk::QueryPoolCreateInfo::builder()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count(QUERY_COUNT);
+            (0..2)
+                .map(|_| unsafe { base.device.create_query_pool(&create_info, None).unwrap() })
+                .collect()
+        } else {
+            println!("the queue has no timestamps, GPU frame times are unavailable");
+            Vec::new()
+        };
+        let calibrated = base
+            .enabled_extensions
+            .iter()
+            .any(|name| name.as_c_str() == calibrated_timestamps::extension_name());
+        let calibrated_timestamps = if calibrated && !query_pools.is_empty() {
+            Some(CalibratedTimestamps::new(&base.instance, &base.device))
+        } else {
+            None
+        };
+        Self {
+            query_pools,
+            timestamp_period: f64::from(timestamp_period),
+            timestamp_mask: if timestamp_valid_bits >= 64 {
+                std::u64::MAX
+            } else {
+                (1 << timestamp_valid_bits) - 1
+            },
+            calibrated_timestamps,
+            frame_starts: [None; 2],
+            frame: 0,
+            history: VecDeque::with_capacity(Self::HISTORY),
+            passes: RefCell::new([Vec::new(), Vec::new()]),
+            pass_totals: Vec::new(),
+            pass_times: Vec::new(),
+            print_pass_times: false,
+            last_report: Instant::now(),
+            csv: None,
+        }
+    }
+
+    /// Starts writing the frames from now on to a CSV file, replacing it if it exists.
+    pub fn write_csv(&mut self, path: &Path) -> io::Result<()> {
+        let mut csv = BufWriter::new(File::create(path)?);
+        writeln!(
+            csv,
+            "frame,cpu_ms,gpu_ms,latency_ms,acquire_wait_ms,present_wait_ms"
+        )?;
+        self.csv = Some(csv);
+        Ok(())
+    }
+
+    fn write_csv_row(&mut self, frame: usize, timing: &FrameTiming) {
+        let csv = match self.csv.as_mut() {
+            Some(csv) => csv,
+            None => return,
+        };
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let optional_ms = |duration: Option<Duration>| {
+            duration.map_or_else(String::new, |duration| format!("{:.4}", ms(duration)))
+        };
+        let result = writeln!(
+            csv,
+            "{},{:.4},{},{},{:.4},{:.4}",
+            frame,
+            ms(timing.cpu),
+            optional_ms(timing.gpu),
+            optional_ms(timing.latency),
+            ms(timing.acquire_wait),
+            ms(timing.present_wait)
+        );
+        if let Err(err) = result {
+            eprintln!("failed to write frame timings, stopping: {}", err);
+            self.csv = None;
+        }
+    }
+
+    fn query_pool(&self, frame: usize) -> Option<vk::QueryPool> {
+        self.query_pools.get(frame % 2).copied()
+    }
+
+    /// At the start of the frame, before the plugins update.
+    pub fn frame_start(&mut self, device: &ash::Device) {
+        self.frame_starts[self.frame % 2] = self
+            .calibrated_timestamps
+            .as_ref()
+            .and_then(|calibrated_timestamps| calibrated_timestamps.now(device));
+        self.passes.borrow_mut()[self.frame % 2].clear();
+    }
+
+    /// Record at the start of the frame's command buffer, outside a render pass.
+    pub fn begin(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        if let Some(query_pool) = self.query_pool(self.frame) {
+            unsafe {
+                device.cmd_reset_query_pool(command_buffer, query_pool, 0, QUERY_COUNT);
+                device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    query_pool,
+                    0,
+                );
+            }
+        }
+    }
+
+    /// Record at the end of the frame's command buffer.
+    pub fn end(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        if let Some(query_pool) = self.query_pool(self.frame) {
+            unsafe {
+                device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    query_pool,
+                    1,
+                );
+            }
+        }
+    }
+
+    /// Starts timing a render pass, outside it.  Passes can't nest.
+    pub fn begin_pass(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, label: &str) {
+        let query_pool = match self.query_pool(self.frame) {
+            Some(query_pool) => query_pool,
+            None => return,
+        };
+        let mut passes = self.passes.borrow_mut();
+        let passes = &mut passes[self.frame % 2];
+        if passes.len() as u32 == MAX_PASSES {
+            return;
+        }
+        passes.push(label.to_owned());
+        let query = 2 + 2 * (passes.len() as u32 - 1);
+        unsafe {
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                query_pool,
+                query,
+            );
+        }
+    }
+
+    /// Ends the pass begun last, outside it.
+    pub fn end_pass(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        let query_pool = match self.query_pool(self.frame) {
+            Some(query_pool) => query_pool,
+            None => return,
+        };
+        let query = match self.passes.borrow()[self.frame % 2].len() {
+            0 => return,
+            len => 2 + 2 * (len as u32 - 1) + 1,
+        };
+        unsafe {
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                query_pool,
+                query,
+            );
+        }
+    }
+
+    /// Adds a frame after it was submitted, which means the one before it has completed and its
+    /// GPU time can be read.
+    pub fn frame_submitted(&mut self, device: &ash::Device, timing: FrameTiming) {
+        if self.frame > 0 {
+            if let Some(query_pool) = self.query_pool(self.frame - 1) {
+                let labels = std::mem::take(&mut self.passes.borrow_mut()[(self.frame - 1) % 2]);
+                let mut timestamps = vec![0u64; 2 + 2 * labels.len()];
+                let result = unsafe {
+                    device.get_query_pool_results(
+                        query_pool,
+                        0,
+                        timestamps.len() as u32,
+                        &mut timestamps,
+                        vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+                    )
+                };
+                let (mask, period) = (self.timestamp_mask, self.timestamp_period);
+                let between = |start: u64, end: u64| {
+                    let ticks = end.wrapping_sub(start) & mask;
+                    Duration::from_nanos((ticks as f64 * period) as u64)
+                };
+                if let (Ok(()), Some(previous)) = (result, self.history.back_mut()) {
+                    let gpu = between(timestamps[0], timestamps[1]);
+                    profiling::gpu_time(gpu.as_secs_f64() * 1000.0);
+                    previous.gpu = Some(gpu);
+                    previous.latency = self.frame_starts[(self.frame - 1) % 2]
+                        .map(|start| between(start, timestamps[1]));
+                }
+                if result.is_ok() {
+                    for (label, pair) in labels.into_iter().zip(timestamps[2..].chunks(2)) {
+                        self.add_pass(label, between(pair[0], pair[1]));
+                    }
+                }
+            }
+            if let Some(&previous) = self.history.back() {
+                self.write_csv_row(self.frame - 1, &previous);
+            }
+        }
+        if self.history.len() == Self::HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(timing);
+        self.frame += 1;
+        if self.last_report.elapsed() >= REPORT_INTERVAL {
+            self.report_passes();
+        }
+    }
+
+    fn add_pass(&mut self, label: String, gpu: Duration) {
+        let position = self
+            .pass_totals
+            .iter()
+            .position(|total| total.label == label);
+        let total = match position {
+            Some(position) => &mut self.pass_totals[position],
+            None => {
+                self.pass_totals.push(PassTotal {
+                    label,
+                    sum: Duration::default(),
+                    frames: 0,
+                });
+                self.pass_totals.last_mut().unwrap()
+            }
+        };
+        total.sum += gpu;
+        total.frames += 1;
+    }
+
+    fn report_passes(&mut self) {
+        self.pass_times = self
+            .pass_totals
+            .drain(..)
+            .map(|total| PassTime {
+                label: total.label,
+                gpu: total.sum / total.frames.max(1),
+            })
+            .collect();
+        self.last_report = Instant::now();
+        if !self.print_pass_times || self.pass_times.len() < 2 {
+            return;
+        }
+        let width = self
+            .pass_times
+            .iter()
+            .map(|pass| pass.label.len())
+            .max()
+            .unwrap_or(0)
+            .max("total".len());
+        println!("GPU time per pass:");
+        for pass in self.pass_times.iter() {
+            println!(
+                "  {:<width$} {:>8.3} ms",
+                pass.label,
+                pass.gpu.as_secs_f64() * 1000.0,
+                width = width
+            );
+        }
+        let total = self
+            .pass_times
+            .iter()
+            .map(|pass| pass.gpu)
+            .sum::<Duration>();
+        println!(
+            "  {:<width$} {:>8.3} ms",
+            "total",
+            total.as_secs_f64() * 1000.0,
+            width = width
+        );
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        if let Some(mut csv) = self.csv.take() {
+            csv.flush().ok();
+        }
+        for query_pool in self.query_pools.drain(..) {
+            unsafe { device.destroy_query_pool(query_pool, None) };
+        }
+    }
+}
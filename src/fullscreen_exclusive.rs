@@ -0,0 +1,70 @@
+//! Application controlled exclusive fullscreen with `VK_EXT_full_screen_exclusive` on Windows, which
+//! skips the compositor for the lowest presentation latency.  Exclusive mode is held only while the
+//! window has focus, so switching to other windows still works.
+
+use ash::{version::InstanceV1_0, vk};
+
+use std::ffi::CStr;
+
+/// The instance extension `VK_EXT_full_screen_exclusive` depends on.
+pub fn instance_extension_name() -> &'static CStr {
+    vk::KhrGetSurfaceCapabilities2Fn::name()
+}
+
+pub fn extension_name() -> &'static CStr {
+    vk::ExtFullScreenExclusiveFn::name()
+}
+
+pub fn is_supported(instance: &ash::Instance, pdevice: vk::PhysicalDevice) -> bool {
+    unsafe { instance.enumerate_device_extension_properties(pdevice) }
+        .unwrap_or_default()
+        .iter()
+        .any(|ext| unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) } == extension_name())
+}
+
+pub struct FullScreenExclusive {
+    fns: vk::ExtFullScreenExclusiveFn,
+    /// The monitor the window is fullscreen on, which swapchains are created for.
+    pub hmonitor: vk::HMONITOR,
+    /// Exclusive mode is held for the current swapchain.
+    pub acquired: bool,
+}
+
+// an HMONITOR identifies a monitor system wide, not something owned by the creating thread
+unsafe impl Send for FullScreenExclusive {}
+
+impl FullScreenExclusive {
+    pub fn new(instance: &ash::Instance, device: &ash::Device, hmonitor: vk::HMONITOR) -> Self {
+        let fns = vk::ExtFullScreenExclusiveFn::load(|name| unsafe {
+            std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+        });
+        Self {
+            fns,
+            hmonitor,
+            acquired: false,
+        }
+    }
+
+    /// Fails while the window doesn't cover the monitor or another application holds it, in which
+    /// case presenting goes through the compositor as usual.
+    pub fn acquire(&mut self, device: &ash::Device, swapchain: vk::SwapchainKHR) {
+        if self.acquired {
+            return;
+        }
+        match (self.fns.acquire_full_screen_exclusive_mode_ext)(device.handle(), swapchain) {
+            vk::Result::SUCCESS => {
+                self.acquired = true;
+                println!("acquired exclusive fullscreen");
+            }
+            err => eprintln!("failed to acquire exclusive fullscreen: {:?}", err),
+        }
+    }
+
+    pub fn release(&mut self, device: &ash::Device, swapchain: vk::SwapchainKHR) {
+        if !self.acquired {
+            return;
+        }
+        (self.fns.release_full_screen_exclusive_mode_ext)(device.handle(), swapchain);
+        self.acquired = false;
+    }
+}
@@ -0,0 +1,363 @@
+//! Visual regression tests for shader crates: `test` renders each pipeline of a scene on its own at
+//! a fixed resolution, and compares the result with a reference PNG named after the pipeline.
+//!
+//! Rendering is deterministic, at time zero of the first frame with the fixed seed, so with the
+//! same resolution and shader params a pipeline always renders the same image, up to differences
+//! between drivers that `--tolerance` absorbs.  Failed pipelines get the rendered image and a diff,
+//! with differing pixels in red, written next to each other in `--out-dir`.  There's no window, the
+//! surface comes from `VK_EXT_headless_surface`, so no display is needed.  On machines without a
+//! GPU, `--prefer-software` renders with lavapipe or SwiftShader.
+
+use crate::ash_runner::{
+    build_shaders, default_entry_points, HeadlessWindow, RenderBase, ShaderOutput, SpirvShader,
+};
+use crate::capture;
+use crate::cli::TestOptions;
+use crate::clock::ShaderClock;
+use crate::pipeline_registry::BlendMode;
+use crate::scene::SceneConfig;
+
+use ash::vk;
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::Path,
+    sync::Arc,
+};
+
+const DEFAULT_EXTENT: vk::Extent2D = vk::Extent2D {
+    width: 256,
+    height: 256,
+};
+
+/// An RGBA image with 8 bits per channel, top row first.
+struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+/// Exits with status 1 if the build fails or any pipeline doesn't match its reference.
+pub fn run_tests(options: &TestOptions) {
+    let shaders = match build_shaders(&options.run.shader_build.build_options()) {
+        Ok(shaders) => shaders,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let extent = options.run.resolution.unwrap_or(DEFAULT_EXTENT);
+    let window = HeadlessWindow { extent };
+    let mut ctx = RenderBase::new(Arc::new(window), &options.run).into_ctx();
+    ctx.clock = ShaderClock::new(true);
+    for SpirvShader { name, spirv } in shaders {
+        ctx.insert_shader_module(name, spirv);
+    }
+
+    let mut failures = Vec::new();
+    match &options.run.scene {
+        Some(path) => {
+            let scene = SceneConfig::load(path).unwrap_or_else(|err| {
+                eprintln!("{}: {}", path.display(), err);
+                std::process::exit(1);
+            });
+            if let Some(clear_color) = scene.clear_color {
+                ctx.set_clear_color(clear_color);
+            }
+            for pipeline in scene.pipelines {
+                let modules = [&pipeline.vertex.module, &pipeline.fragment.module];
+                if let Some(module) = modules
+                    .iter()
+                    .find(|module| !ctx.shader_modules.contains_key(**module))
+                {
+                    failures.push(format!(
+                        "{}: no shader module \"{}\"",
+                        pipeline.name, module
+                    ));
+                    continue;
+                }
+                let handle =
+                    ctx.pipelines
+                        .insert(pipeline.name, pipeline.vertex, pipeline.fragment);
+                ctx.pipelines.get_mut(handle).unwrap().config.blend =
+                    pipeline.blend.unwrap_or(BlendMode::Opaque);
+            }
+        }
+        None => {
+            let (vertex, fragment) = default_entry_points();
+            ctx.pipelines.insert("sky", vertex, fragment);
+        }
+    }
+    if let Some(clear_color) = options.run.clear_color {
+        ctx.set_clear_color(clear_color);
+    }
+
+    let encode = options.run.shader_output == ShaderOutput::Linear;
+    let names = ctx
+        .pipelines
+        .entries()
+        .map(|entry| entry.name.clone())
+        .collect::<Vec<_>>();
+    for name in names.iter() {
+        for other in names.iter() {
+            let handle = ctx.pipelines.handle(other).unwrap();
+            ctx.pipelines.set_enabled(handle, other == name);
+        }
+        let image = Image {
+            width: extent.width,
+            height: extent.height,
+            pixels: to_rgba8(&capture::render_float(&ctx, extent), encode),
+        };
+        if let Err(failure) = check(options, name, &image) {
+            failures.push(format!("{}: {}", name, failure));
+        } else {
+            println!("{}: ok", name);
+        }
+    }
+
+    drop(ctx);
+    if failures.is_empty() {
+        return;
+    }
+    for failure in failures.iter() {
+        eprintln!("{}", failure);
+    }
+    eprintln!("{} failed", failures.len());
+    std::process::exit(1);
+}
+
+/// Compares with the reference, or replaces it with `--update`.
+fn check(options: &TestOptions, name: &str, image: &Image) -> Result<(), String> {
+    let reference_path = options.references.join(name).with_extension("png");
+    if options.update {
+        fs::create_dir_all(&options.references)
+            .and_then(|()| write_png(&reference_path, image))
+            .map_err(|err| format!("failed to write {}: {}", reference_path.display(), err))?;
+        println!("{}: updated {}", name, reference_path.display());
+        return Ok(());
+    }
+
+    let reference = read_png(&reference_path)
+        .map_err(|err| format!("failed to read {}: {}", reference_path.display(), err))?;
+    let failure = if (reference.width, reference.height) != (image.width, image.height) {
+        format!(
+            "rendered {}x{}, the reference is {}x{}",
+            image.width, image.height, reference.width, reference.height
+        )
+    } else {
+        let (differing, max_difference, diff) = compare(&reference, image, options.tolerance);
+        if differing == 0 {
+            return Ok(());
+        }
+        let out_path = options.out_dir.join(format!("{}-diff.png", name));
+        fs::create_dir_all(&options.out_dir)
+            .and_then(|()| write_png(&out_path, &diff))
+            .map_err(|err| format!("failed to write {}: {}", out_path.display(), err))?;
+        format!(
+            "{} pixels ({:.2}%) differ by up to {}",
+            differing,
+            100.0 * differing as f64 / f64::from(image.width * image.height),
+            max_difference
+        )
+    };
+    let out_path = options.out_dir.join(name).with_extension("png");
+    fs::create_dir_all(&options.out_dir)
+        .and_then(|()| write_png(&out_path, image))
+        .map_err(|err| format!("failed to write {}: {}", out_path.display(), err))?;
+    Err(failure)
+}
+
+/// Returns the number of pixels with a channel differing by more than `tolerance`, the largest
+/// difference, and an image of the reference dimmed to grey with those pixels in red.
+fn compare(reference: &Image, image: &Image, tolerance: u8) -> (usize, u8, Image) {
+    let mut differing = 0;
+    let mut max_difference = 0;
+    let mut diff = Vec::with_capacity(image.pixels.len());
+    for (expected, actual) in reference
+        .pixels
+        .chunks_exact(4)
+        .zip(image.pixels.chunks_exact(4))
+    {
+        let difference = expected
+            .iter()
+            .zip(actual)
+            .map(|(&a, &b)| (i16::from(a) - i16::from(b)).abs() as u8)
+            .max()
+            .unwrap();
+        max_difference = max_difference.max(difference);
+        if difference > tolerance {
+            differing += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            let grey = ((u16::from(expected[0]) + u16::from(expected[1]) + u16::from(expected[2]))
+                / 6) as u8;
+            diff.extend_from_slice(&[grey, grey, grey, 255]);
+        }
+    }
+    let diff = Image {
+        width: image.width,
+        height: image.height,
+        pixels: diff,
+    };
+    (differing, max_difference, diff)
+}
+
+/// Quantizes float pixels, applying the sRGB transfer function to the color channels if they are
+/// linear.
+pub(crate) fn to_rgba8(pixels: &[f32], encode: bool) -> Vec<u8> {
+    pixels
+        .chunks_exact(4)
+        .flat_map(|pixel| {
+            let mut rgba = [0u8; 4];
+            for (channel, (out, &value)) in rgba.iter_mut().zip(pixel).enumerate() {
+                let value = value.max(0.0).min(1.0);
+                let value = if encode && channel < 3 {
+                    if value <= 0.003_130_8 {
+                        value * 12.92
+                    } else {
+                        1.055 * value.powf(1.0 / 2.4) - 0.055
+                    }
+                } else {
+                    value
+                };
+                *out = (value * 255.0).round() as u8;
+            }
+            rgba.to_vec()
+        })
+        .collect()
+}
+
+fn read_png(path: &Path) -> io::Result<Image> {
+    let to_io = |err: png::DecodingError| io::Error::new(io::ErrorKind::InvalidData, err);
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    // palettes and low bit depths become 8 bit grey or RGB(A)
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let (info, mut reader) = decoder.read_info().map_err(to_io)?;
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data).map_err(to_io)?;
+    Ok(Image {
+        width: info.width,
+        height: info.height,
+        pixels: expand_to_rgba8(&data, info.color_type, info.bit_depth),
+    })
+}
+
+/// Widens decoded grey or RGB pixels to RGBA, and 16 bit samples to 8 bit.
+fn expand_to_rgba8(data: &[u8], color_type: png::ColorType, bit_depth: png::BitDepth) -> Vec<u8> {
+    let channels = match color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        png::ColorType::Indexed => unreachable!("expanded while decoding"),
+    };
+    // the high byte of each 16 bit sample
+    let sample_size = if bit_depth == png::BitDepth::Sixteen {
+        2
+    } else {
+        1
+    };
+    data.chunks_exact(channels * sample_size)
+        .flat_map(|pixel| {
+            let sample = |index: usize| pixel[index * sample_size];
+            match channels {
+                1 => [sample(0), sample(0), sample(0), 255],
+                2 => [sample(0), sample(0), sample(0), sample(1)],
+                3 => [sample(0), sample(1), sample(2), 255],
+                _ => [sample(0), sample(1), sample(2), sample(3)],
+            }
+            .to_vec()
+        })
+        .collect()
+}
+
+fn write_png(path: &Path, image: &Image) -> io::Result<()> {
+    let to_io = |err: png::EncodingError| io::Error::new(io::ErrorKind::Other, err);
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        image.width,
+        image.height,
+    );
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(to_io)?;
+    writer.write_image_data(&image.pixels).map_err(to_io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(pixels: &[[u8; 4]]) -> Image {
+        Image {
+            width: pixels.len() as u32,
+            height: 1,
+            pixels: pixels.iter().flatten().copied().collect(),
+        }
+    }
+
+    #[test]
+    fn differences_up_to_the_tolerance_pass() {
+        let reference = image(&[[100, 100, 100, 255], [100, 100, 100, 255]]);
+        let rendered = image(&[[102, 100, 98, 255], [100, 103, 100, 255]]);
+        let (differing, max_difference, diff) = compare(&reference, &rendered, 2);
+        assert_eq!(differing, 1);
+        assert_eq!(max_difference, 3);
+        assert_eq!(&diff.pixels[4..], &[255, 0, 0, 255]);
+        assert_eq!(compare(&reference, &rendered, 3).0, 0);
+    }
+
+    #[test]
+    fn max_difference_covers_every_channel() {
+        let reference = image(&[[0, 0, 0, 0], [255, 255, 255, 255]]);
+        let rendered = image(&[[0, 0, 0, 255], [255, 250, 255, 255]]);
+        let (differing, max_difference, _) = compare(&reference, &rendered, 10);
+        assert_eq!(differing, 1);
+        assert_eq!(max_difference, 255);
+    }
+
+    #[test]
+    fn srgb_encoding() {
+        let pixels = [0.0, 1.0, 0.5, 0.5, -1.0, 2.0, 0.003_130_8, 1.0];
+        assert_eq!(
+            to_rgba8(&pixels, true),
+            vec![0, 255, 188, 128, 0, 255, 10, 255]
+        );
+        assert_eq!(
+            to_rgba8(&pixels, false),
+            vec![0, 255, 128, 128, 0, 255, 1, 255]
+        );
+    }
+
+    #[test]
+    fn expands_grey_and_16_bit_pixels() {
+        use png::{BitDepth, ColorType};
+        assert_eq!(
+            expand_to_rgba8(&[7, 9], ColorType::Grayscale, BitDepth::Eight),
+            vec![7, 7, 7, 255, 9, 9, 9, 255]
+        );
+        assert_eq!(
+            expand_to_rgba8(&[7, 200], ColorType::GrayscaleAlpha, BitDepth::Eight),
+            vec![7, 7, 7, 200]
+        );
+        // big endian, the high byte first
+        assert_eq!(
+            expand_to_rgba8(&[0x12, 0x34], ColorType::Grayscale, BitDepth::Sixteen),
+            vec![0x12, 0x12, 0x12, 255]
+        );
+        assert_eq!(
+            expand_to_rgba8(
+                &[1, 0, 2, 0, 3, 0, 4, 0],
+                ColorType::RGBA,
+                BitDepth::Sixteen
+            ),
+            vec![1, 2, 3, 4]
+        );
+        assert_eq!(
+            expand_to_rgba8(&[1, 0, 2, 0, 3, 0], ColorType::RGB, BitDepth::Sixteen),
+            vec![1, 2, 3, 255]
+        );
+    }
+}
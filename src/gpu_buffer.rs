@@ -0,0 +1,234 @@
+//! Buffers in device local memory, e.g. the storage buffers compute shaders write, and reading them
+//! back to the CPU without stalling the frame.  `GpuBuffer::read_back` submits a copy into a host
+//! visible buffer on its own, with a fence the caller polls from later frames:
+//!
+//! ```ignore
+//! // in Plugin::update, once the dispatch writing the results was submitted
+//! self.pending = Some(self.results.read_back(&ctx.base));
+//! // and in the frames after
+//! if let Some(bytes) = self.pending.as_ref().and_then(|pending| pending.try_read(device)) {
+//!     self.pending.take().unwrap().destroy(device);
+//! }
+//! ```
+
+use crate::ash_runner::RenderBase;
+
+use ash::{version::DeviceV1_0, vk};
+
+pub struct GpuBuffer {
+    pub buffer: vk::Buffer,
+    pub memory: vk::DeviceMemory,
+    pub size: vk::DeviceSize,
+}
+
+impl GpuBuffer {
+    /// Device local, with `usage` plus copying from and to it.
+    pub fn new(base: &RenderBase, size: vk::DeviceSize, usage: vk::BufferUsageFlags) -> Self {
+        let device = &base.device;
+        let buffer_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(usage | vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = unsafe { device.create_buffer(&buffer_info, None).unwrap() };
+        let memory = unsafe {
+            let requirements = device.get_buffer_memory_requirements(buffer);
+            let memory_type_index = base
+                .find_memory_type(
+                    requirements.memory_type_bits,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                )
+                .expect("No device local memory type for a buffer");
+            let allocate_info = vk::MemoryAllocateInfo::builder()
+                .allocation_size(requirements.size)
+                .memory_type_index(memory_type_index);
+            let memory = device.allocate_memory(&allocate_info, None).unwrap();
+            device.bind_buffer_memory(buffer, memory, 0).unwrap();
+            memory
+        };
+        Self {
+            buffer,
+            memory,
+            size,
+        }
+    }
+
+    /// Submits copying the buffer as the work submitted so far leaves it, e.g. the last frame's
+    /// dispatches, into host visible memory.  Returns right away; the bytes are there once
+    /// `PendingReadback::try_read` returns them.
+    pub fn read_back(&self, base: &RenderBase) -> PendingReadback {
+        let device = &base.device;
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(base.queue_family_index);
+        let pool = unsafe { device.create_command_pool(&pool_info, None).unwrap() };
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_buffer_count(1)
+            .command_pool(pool)
+            .level(vk::CommandBufferLevel::PRIMARY);
+        let command_buffer = unsafe { device.allocate_command_buffers(&allocate_info).unwrap()[0] };
+        let fence = unsafe {
+            device
+                .create_fence(&vk::FenceCreateInfo::default(), None)
+                .unwrap()
+        };
+
+        let buffer_info = vk::BufferCreateInfo::builder()
+            .size(self.size)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let staging = unsafe { device.create_buffer(&buffer_info, None).unwrap() };
+        let staging_memory = unsafe {
+            let requirements = device.get_buffer_memory_requirements(staging);
+            let memory_type_index = base
+                .find_memory_type(
+                    requirements.memory_type_bits,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )
+                .expect("No host visible memory type for reading back a buffer");
+            let allocate_info = vk::MemoryAllocateInfo::builder()
+                .allocation_size(requirements.size)
+                .memory_type_index(memory_type_index);
+            let memory = device.allocate_memory(&allocate_info, None).unwrap();
+            device.bind_buffer_memory(staging, memory, 0).unwrap();
+            memory
+        };
+
+        unsafe {
+            device
+                .begin_command_buffer(
+                    command_buffer,
+                    &vk::CommandBufferBeginInfo::builder()
+                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                )
+                .expect("Begin commandbuffer");
+            // covers the writes of everything submitted before
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                .build();
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            );
+            let region = vk::BufferCopy {
+                src_offset: 0,
+                dst_offset: 0,
+                size: self.size,
+            };
+            device.cmd_copy_buffer(command_buffer, self.buffer, staging, &[region]);
+            let barrier = vk::BufferMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::HOST_READ)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(staging)
+                .offset(0)
+                .size(vk::WHOLE_SIZE)
+                .build();
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[barrier],
+                &[],
+            );
+            device
+                .end_command_buffer(command_buffer)
+                .expect("End commandbuffer");
+            let command_buffers = [command_buffer];
+            let submit_info = vk::SubmitInfo::builder().command_buffers(&command_buffers);
+            device
+                .queue_submit(base.present_queue, &[submit_info.build()], fence)
+                .expect("queue submit failed.");
+        }
+
+        PendingReadback {
+            pool,
+            fence,
+            staging,
+            staging_memory,
+            size: self.size,
+        }
+    }
+
+    /// The device has to be done with the buffer.
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_buffer(self.buffer, None);
+            device.free_memory(self.memory, None);
+        }
+    }
+}
+
+/// A copy submitted by `GpuBuffer::read_back`, holding its own command buffer, fence and staging
+/// buffer until `destroy`ed.
+pub struct PendingReadback {
+    pool: vk::CommandPool,
+    fence: vk::Fence,
+    staging: vk::Buffer,
+    staging_memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+}
+
+impl PendingReadback {
+    pub fn is_ready(&self, device: &ash::Device) -> bool {
+        unsafe { device.get_fence_status(self.fence).unwrap() }
+    }
+
+    /// The buffer's bytes if the copy has finished, without waiting.
+    pub fn try_read(&self, device: &ash::Device) -> Option<Vec<u8>> {
+        if self.is_ready(device) {
+            Some(self.read(device))
+        } else {
+            None
+        }
+    }
+
+    /// Blocks until the copy has finished.
+    pub fn wait(&self, device: &ash::Device) -> Vec<u8> {
+        unsafe {
+            device
+                .wait_for_fences(&[self.fence], true, std::u64::MAX)
+                .expect("Wait for fence failed.");
+        }
+        self.read(device)
+    }
+
+    fn read(&self, device: &ash::Device) -> Vec<u8> {
+        let mut bytes = vec![0u8; self.size as usize];
+        unsafe {
+            let mapped = device
+                .map_memory(
+                    self.staging_memory,
+                    0,
+                    vk::WHOLE_SIZE,
+                    vk::MemoryMapFlags::empty(),
+                )
+                .unwrap();
+            std::ptr::copy_nonoverlapping(mapped as *const u8, bytes.as_mut_ptr(), bytes.len());
+            device.unmap_memory(self.staging_memory);
+        }
+        bytes
+    }
+
+    /// Waits for the copy if it is still running.
+    pub fn destroy(self, device: &ash::Device) {
+        unsafe {
+            device
+                .wait_for_fences(&[self.fence], true, std::u64::MAX)
+                .expect("Wait for fence failed.");
+            // frees the command buffer along with it
+            device.destroy_command_pool(self.pool, None);
+            device.destroy_fence(self.fence, None);
+            device.destroy_buffer(self.staging, None);
+            device.free_memory(self.staging_memory, None);
+        }
+    }
+}
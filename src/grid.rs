@@ -0,0 +1,220 @@
+//! A ground grid on the y = 0 plane and the x, y and z axes, for a sense of position and scale in
+//! 3D shader experiments.  Enabled with `--grid`.  It draws through `ctx.camera` over the frame,
+//! fading out with distance; the x axis is red, y green and z blue.
+//!
+//! The grid is a fullscreen triangle whose fragment shader intersects the view ray with the plane,
+//! so it goes on to the horizon.  Its shaders are `grid.vert` and `grid.frag` next to this file,
+//! compiled to SPIR-V by `build.rs`.  The axes are debug lines.
+
+use crate::ash_runner::{any_as_u8_slice, Pipeline, PipelineDescriptor, RenderCtx};
+use crate::pipeline_registry::BlendMode;
+use crate::plugin::Plugin;
+
+use ash::{util::read_spv, version::DeviceV1_0, vk};
+
+use std::{ffi::CStr, io::Cursor, mem};
+
+/// In world units.
+const AXIS_LENGTH: f32 = 1.0;
+/// Covers the window, with the corners outside it cut off by clipping.
+const TRIANGLE: [[f32; 2]; 3] = [[-1.0, -1.0], [3.0, -1.0], [-1.0, 3.0]];
+
+#[repr(C)]
+struct PushConstants {
+    inverse_view_projection: [[f32; 4]; 4],
+    /// The depth of the near plane in the depth convention.
+    near_depth: f32,
+}
+
+#[derive(Default)]
+pub struct Grid {
+    /// Created in `init`.
+    modules: Option<(vk::ShaderModule, vk::ShaderModule)>,
+    pipeline: Option<Pipeline>,
+}
+
+impl Grid {
+    fn create_pipeline(&mut self, ctx: &RenderCtx) {
+        let (vertex_module, fragment_module) = match self.modules {
+            Some(modules) => modules,
+            None => return,
+        };
+        let entry_point = unsafe { CStr::from_bytes_with_nul_unchecked(b"main\0") };
+        let bindings = [vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: mem::size_of::<[f32; 2]>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }];
+        let attributes = [vk::VertexInputAttributeDescription {
+            location: 0,
+            binding: 0,
+            format: vk::Format::R32G32_SFLOAT,
+            offset: 0,
+        }];
+        // the fragment shader only writes the swapchain image, leave any other attachment alone
+        let mut attachments = vec![
+            BlendMode::Opaque.attachment_state();
+            ctx.render_pass_config.color_attachment_count()
+        ];
+        for attachment in attachments.iter_mut() {
+            attachment.color_write_mask = vk::ColorComponentFlags::empty();
+        }
+        attachments[0] = BlendMode::AlphaBlend.attachment_state();
+        let mut desc = PipelineDescriptor::builder(Box::new([
+            vk::PipelineShaderStageCreateInfo {
+                module: vertex_module,
+                p_name: entry_point.as_ptr(),
+                stage: vk::ShaderStageFlags::VERTEX,
+                ..Default::default()
+            },
+            vk::PipelineShaderStageCreateInfo {
+                module: fragment_module,
+                p_name: entry_point.as_ptr(),
+                stage: vk::ShaderStageFlags::FRAGMENT,
+                ..Default::default()
+            },
+        ]))
+        .vertex_input(&bindings, &attributes)
+        .color_blend_attachments(&attachments)
+        .build();
+        desc.push_constant_range = Some(vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: mem::size_of::<PushConstants>() as u32,
+        });
+        self.pipeline = Some(Pipeline::new(ctx, desc, vk::PipelineCache::null()));
+    }
+
+    fn destroy_pipeline(&mut self, device: &ash::Device) {
+        if let Some(pipeline) = self.pipeline.take() {
+            unsafe { device.destroy_pipeline(pipeline.pipeline, None) };
+        }
+    }
+}
+
+impl Plugin for Grid {
+    fn name(&self) -> &str {
+        "grid"
+    }
+
+    fn init(&mut self, ctx: &mut RenderCtx) {
+        let device = &ctx.base.device;
+        let create_module = |bytes: &[u8]| {
+            let spirv = read_spv(&mut Cursor::new(bytes)).expect("Invalid SPIR-V");
+            let shader_info = vk::ShaderModuleCreateInfo::builder().code(&spirv);
+            unsafe {
+                device
+                    .create_shader_module(&shader_info, None)
+                    .expect("Shader module error")
+            }
+        };
+        self.modules = Some((
+            create_module(include_bytes!(concat!(env!("OUT_DIR"), "/grid.vert.spv"))),
+            create_module(include_bytes!(concat!(env!("OUT_DIR"), "/grid.frag.spv"))),
+        ));
+        self.create_pipeline(ctx);
+    }
+
+    fn update(&mut self, ctx: &mut RenderCtx) {
+        let origin = [0.0; 3];
+        ctx.debug_draw
+            .line(origin, [AXIS_LENGTH, 0.0, 0.0], [1.0, 0.2, 0.2, 1.0]);
+        ctx.debug_draw
+            .line(origin, [0.0, AXIS_LENGTH, 0.0], [0.2, 1.0, 0.2, 1.0]);
+        ctx.debug_draw
+            .line(origin, [0.0, 0.0, AXIS_LENGTH], [0.2, 0.2, 1.0, 1.0]);
+    }
+
+    fn draw(&self, ctx: &RenderCtx, command_buffer: vk::CommandBuffer) {
+        let pipeline = match &self.pipeline {
+            Some(pipeline) => pipeline,
+            None => return,
+        };
+        let inverse_view_projection = match invert(&ctx.view_projection()) {
+            Some(inverse) => inverse,
+            None => return,
+        };
+        let offset = match ctx.frame_ring.push(&TRIANGLE) {
+            Some(offset) => offset,
+            None => return,
+        };
+        let push_constants = PushConstants {
+            inverse_view_projection,
+            near_depth: 1.0 - ctx.depth_convention.clear_depth(),
+        };
+        let device = &ctx.base.device;
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.pipeline,
+            );
+            device.cmd_set_viewport(command_buffer, 0, &ctx.viewports);
+            device.cmd_set_scissor(command_buffer, 0, &ctx.scissors);
+            device.cmd_push_constants(
+                command_buffer,
+                pipeline.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                any_as_u8_slice(&push_constants),
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[ctx.frame_ring.buffer], &[offset]);
+            device.cmd_draw(command_buffer, TRIANGLE.len() as u32, 1, 0, 0);
+        }
+    }
+
+    fn pipelines_rebuilt(&mut self, ctx: &mut RenderCtx) {
+        // the runner waited for the device before destroying its own old pipelines
+        self.destroy_pipeline(&ctx.base.device);
+        self.create_pipeline(ctx);
+    }
+
+    fn destroy(&mut self, ctx: &mut RenderCtx) {
+        let device = &ctx.base.device;
+        self.destroy_pipeline(device);
+        if let Some((vertex_module, fragment_module)) = self.modules.take() {
+            unsafe {
+                device.destroy_shader_module(vertex_module, None);
+                device.destroy_shader_module(fragment_module, None);
+            }
+        }
+    }
+}
+
+/// The inverse of a transform, `None` if it is singular.
+fn invert(m: &[[f32; 4]; 4]) -> Option<[[f32; 4]; 4]> {
+    // Gauss-Jordan elimination with partial pivoting, on rows of the transposed matrix
+    let mut a = *m;
+    let mut inverse = [
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ];
+    for column in 0..4 {
+        let pivot = (column..4)
+            .max_by(|&i, &j| a[i][column].abs().partial_cmp(&a[j][column].abs()).unwrap())
+            .unwrap();
+        if a[pivot][column].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(column, pivot);
+        inverse.swap(column, pivot);
+        let scale = 1.0 / a[column][column];
+        for k in 0..4 {
+            a[column][k] *= scale;
+            inverse[column][k] *= scale;
+        }
+        for row in 0..4 {
+            if row != column {
+                let factor = a[row][column];
+                for k in 0..4 {
+                    a[row][k] -= factor * a[column][k];
+                    inverse[row][k] -= factor * inverse[column][k];
+                }
+            }
+        }
+    }
+    // the inverse of the transpose is the transpose of the inverse, so it is column major again
+    Some(inverse)
+}
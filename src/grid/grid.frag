@@ -0,0 +1,43 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+    mat4 inverse_view_projection;
+    float near_depth;
+};
+
+layout(location = 0) in vec2 ndc;
+
+layout(location = 0) out vec4 out_color;
+
+const vec3 GRAY = vec3(0.5, 0.5, 0.5);
+const vec3 RED = vec3(1.0, 0.2, 0.2);
+const vec3 BLUE = vec3(0.2, 0.2, 1.0);
+const float FADE_DISTANCE = 100.0;
+
+vec3 unproject(float depth) {
+    vec4 point = inverse_view_projection * vec4(ndc, depth, 1.0);
+    return point.xyz / point.w;
+}
+
+void main() {
+    // where the view ray through this pixel hits the y = 0 plane
+    vec3 near = unproject(near_depth);
+    vec3 middle = unproject(0.5);
+    float t = near.y / (near.y - middle.y);
+    vec3 point = near + (middle - near) * t;
+
+    vec2 coord = point.xz;
+    vec2 width = fwidth(coord);
+    vec2 grid = abs(fract(coord - 0.5) - 0.5) / width;
+    float line = 1.0 - min(min(grid.x, grid.y), 1.0);
+
+    // the x axis runs along z = 0, the z axis along x = 0
+    float x_axis = abs(point.z) < width.y ? 1.0 : 0.0;
+    float z_axis = abs(point.x) < width.x ? 1.0 : 0.0;
+    vec3 color = GRAY + (RED - GRAY) * x_axis;
+    color = color + (BLUE - color) * z_axis;
+
+    float fade = max(1.0 - distance(point, near) / FADE_DISTANCE, 0.0);
+    // nothing where the ray points away from the plane, or misses it
+    out_color = vec4(color, t > 0.0 ? line * fade : 0.0);
+}
@@ -0,0 +1,113 @@
+//! Help drawn over the frame when toggled with F1: the hotkeys as currently bound, the loaded shader
+//! modules with their entry points, and the current settings.
+
+use crate::ash_runner::RenderCtx;
+use crate::keymap::Action;
+use crate::plugin::Plugin;
+use crate::text::{Text, TextRenderer};
+
+use ash::vk;
+use winit::event::VirtualKeyCode;
+
+/// In logical pixels.
+const MARGIN: f64 = 16.0;
+const COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.75];
+
+#[derive(Default)]
+pub struct HelpOverlay {
+    visible: bool,
+    /// Created in `init`.
+    text: Option<TextRenderer>,
+}
+
+impl Plugin for HelpOverlay {
+    fn name(&self) -> &str {
+        "help"
+    }
+
+    fn init(&mut self, ctx: &mut RenderCtx) {
+        self.text = Some(TextRenderer::new(ctx));
+    }
+
+    fn key(&mut self, ctx: &mut RenderCtx, key: VirtualKeyCode, pressed: bool) {
+        if ctx.keymap.action(key) == Some(Action::ToggleHelp) && pressed {
+            self.visible = !self.visible;
+        }
+    }
+
+    fn draw(&self, ctx: &RenderCtx, command_buffer: vk::CommandBuffer) {
+        let renderer = match &self.text {
+            Some(renderer) if self.visible => renderer,
+            _ => return,
+        };
+        let text = lines(ctx).join("\n");
+        let margin = (MARGIN * ctx.scale_factor) as f32;
+        renderer.draw(
+            ctx,
+            command_buffer,
+            &[Text {
+                text: &text,
+                position: [margin, margin],
+                scale: (2.0 * ctx.scale_factor).round() as u32,
+                color: COLOR,
+                background: Some(BACKGROUND),
+            }],
+        );
+    }
+
+    fn pipelines_rebuilt(&mut self, ctx: &mut RenderCtx) {
+        if let Some(renderer) = self.text.as_mut() {
+            renderer.recreate_pipeline(ctx);
+        }
+    }
+
+    fn destroy(&mut self, ctx: &mut RenderCtx) {
+        if let Some(renderer) = self.text.as_mut() {
+            renderer.destroy(&ctx.base.device);
+        }
+    }
+}
+
+pub fn lines(ctx: &RenderCtx) -> Vec<String> {
+    let mut lines = vec!["keys:".to_owned()];
+    for &(action, _) in Action::DEFAULT_KEYS.iter() {
+        if action == Action::TogglePuffin && !cfg!(feature = "puffin") {
+            continue;
+        }
+        let key = ctx
+            .keymap
+            .key(action)
+            .map_or_else(|| "unbound".to_owned(), |key| format!("{:?}", key));
+        lines.push(format!("  {:<10} {}", key, action.description()));
+    }
+
+    lines.push("shader modules:".to_owned());
+    let mut modules = ctx.entry_points.iter().collect::<Vec<_>>();
+    modules.sort();
+    for (module, entry_points) in modules {
+        lines.push(format!("  {}: {}", module, entry_points.join(", ")));
+    }
+
+    let extent = ctx.extent;
+    let render_extent = ctx.render_extent();
+    let clock = &ctx.clock;
+    lines.push("settings:".to_owned());
+    lines.push(format!("  present mode: {:?}", ctx.present_mode));
+    lines.push(format!(
+        "  resolution: {}x{}, window {}x{}",
+        render_extent.width, render_extent.height, extent.width, extent.height
+    ));
+    lines.push(format!(
+        "  wireframe: {}",
+        if ctx.wireframe { "on" } else { "off" }
+    ));
+    lines.push(format!(
+        "  time: {:.3} s, frame {}, {}x speed{}",
+        clock.time(),
+        clock.frame,
+        clock.speed(),
+        if clock.is_paused() { ", paused" } else { "" }
+    ));
+    lines
+}
@@ -0,0 +1,379 @@
+//! What the Vulkan implementation offers, for `info`, to find out why the runner can't find a
+//! suitable device.  `info --json` prints the same as json, with the extensions and features, for
+//! bug reports and other tools.
+
+use crate::subgroup::SubgroupSupport;
+
+use ash::{
+    extensions::khr,
+    version::{EntryV1_0, InstanceV1_0},
+    vk,
+};
+use serde::Serialize;
+use winit::{event_loop::EventLoop, window::WindowBuilder};
+
+use std::ffi::{CStr, CString};
+
+#[derive(Serialize)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub device_type: String,
+    pub api_version: String,
+    pub driver_version: u32,
+    /// Has a queue family with graphics and present support, and the swapchain extension, which is
+    /// what the runner looks for.
+    pub suitable: bool,
+    pub swapchain: bool,
+    pub queue_families: Vec<QueueFamilyInfo>,
+    /// `None` when queried without a surface.
+    pub surface: Option<SurfaceInfo>,
+    pub limits: Limits,
+    pub features: Features,
+    pub subgroup: SubgroupInfo,
+    pub extensions: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct QueueFamilyInfo {
+    pub flags: String,
+    pub queue_count: u32,
+    /// `None` when queried without a surface.
+    pub present: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct SurfaceInfo {
+    pub formats: Vec<String>,
+    pub present_modes: Vec<String>,
+    pub min_image_count: u32,
+    /// 0 if there is no maximum.
+    pub max_image_count: u32,
+}
+
+/// The limits most likely to matter to shaders and the runner.
+#[derive(Serialize)]
+pub struct Limits {
+    pub max_image_dimension_2d: u32,
+    pub max_push_constants_size: u32,
+    pub max_bound_descriptor_sets: u32,
+    pub max_per_stage_descriptor_samplers: u32,
+    pub max_color_attachments: u32,
+    pub max_compute_work_group_invocations: u32,
+    pub max_sampler_anisotropy: f32,
+    pub timestamp_period: f32,
+}
+
+/// The optional features shaders are most likely to need.
+#[derive(Serialize)]
+pub struct Features {
+    pub fill_mode_non_solid: bool,
+    pub sampler_anisotropy: bool,
+    pub independent_blend: bool,
+    pub geometry_shader: bool,
+    pub tessellation_shader: bool,
+    pub shader_float64: bool,
+    pub shader_int64: bool,
+    pub shader_int16: bool,
+    pub shader_storage_image_write_without_format: bool,
+}
+
+/// What shaders' `GroupNonUniform*` capabilities are checked against.
+#[derive(Serialize)]
+pub struct SubgroupInfo {
+    /// 0 on a Vulkan 1.0 device, which has no subgroups.
+    pub size: u32,
+    pub stages: String,
+    pub operations: String,
+    pub quad_operations_in_all_stages: bool,
+}
+
+/// Everything `info` prints.
+#[derive(Serialize)]
+pub struct Report {
+    pub instance_extensions: Vec<String>,
+    pub devices: Vec<DeviceInfo>,
+}
+
+/// Queries every physical device, and what it can present to a hidden window's surface unless
+/// `surface` is false, as there may be no display to open one on.
+pub fn report(surface: bool) -> Report {
+    cfg_if::cfg_if! {
+        if #[cfg(any(target_os = "macos", target_os = "ios"))] {
+            let entry = ash_molten::MoltenEntry::load().unwrap();
+        } else {
+            let entry = ash::Entry::new().unwrap();
+        }
+    }
+    let (event_loop, window) = if surface {
+        let event_loop = EventLoop::new();
+        let window = WindowBuilder::new()
+            .with_title("ash-runner info")
+            .with_visible(false)
+            .build(&event_loop)
+            .unwrap();
+        (Some(event_loop), Some(window))
+    } else {
+        (None, None)
+    };
+
+    let instance_extensions = entry
+        .enumerate_instance_extension_properties()
+        .unwrap_or_default()
+        .iter()
+        .map(extension_name)
+        .collect();
+
+    let app_name = CString::new("ash-runner info").unwrap();
+    let appinfo = vk::ApplicationInfo::builder()
+        .application_name(&app_name)
+        .api_version(vk::make_version(1, 1, 0));
+    let extension_names_raw = match &window {
+        Some(window) => ash_window::enumerate_required_extensions(window)
+            .unwrap()
+            .iter()
+            .map(|ext| ext.as_ptr())
+            .collect(),
+        None => Vec::new(),
+    };
+    let instance_create_info = vk::InstanceCreateInfo::builder()
+        .application_info(&appinfo)
+        .enabled_extension_names(&extension_names_raw);
+    let instance = unsafe {
+        entry
+            .create_instance(&instance_create_info, None)
+            .expect("Instance creation error")
+    };
+    let surface_loader = khr::Surface::new(&entry, &instance);
+    let surface = window.as_ref().map(|window| unsafe {
+        ash_window::create_surface(&entry, &instance, window, None).unwrap()
+    });
+
+    let pdevices = unsafe {
+        instance
+            .enumerate_physical_devices()
+            .expect("Physical device error")
+    };
+    let devices = pdevices
+        .into_iter()
+        .map(|pdevice| unsafe { device_info(&instance, &surface_loader, surface, pdevice) })
+        .collect();
+
+    unsafe {
+        if let Some(surface) = surface {
+            surface_loader.destroy_surface(surface, None);
+        }
+        instance.destroy_instance(None);
+    }
+    drop(window);
+    drop(event_loop);
+    Report {
+        instance_extensions,
+        devices,
+    }
+}
+
+fn extension_name(extension: &vk::ExtensionProperties) -> String {
+    unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+unsafe fn device_info(
+    instance: &ash::Instance,
+    surface_loader: &khr::Surface,
+    surface: Option<vk::SurfaceKHR>,
+    pdevice: vk::PhysicalDevice,
+) -> DeviceInfo {
+    let properties = instance.get_physical_device_properties(pdevice);
+    let extensions = instance
+        .enumerate_device_extension_properties(pdevice)
+        .unwrap_or_default()
+        .iter()
+        .map(extension_name)
+        .collect::<Vec<_>>();
+    let swapchain = extensions
+        .iter()
+        .any(|name| name.as_bytes() == khr::Swapchain::name().to_bytes());
+
+    let queue_families = instance
+        .get_physical_device_queue_family_properties(pdevice)
+        .iter()
+        .enumerate()
+        .map(|(index, family)| QueueFamilyInfo {
+            flags: format!("{:?}", family.queue_flags),
+            queue_count: family.queue_count,
+            present: surface.map(|surface| {
+                surface_loader
+                    .get_physical_device_surface_support(pdevice, index as u32, surface)
+                    .unwrap_or(false)
+            }),
+        })
+        .collect::<Vec<_>>();
+    let suitable = swapchain
+        && instance
+            .get_physical_device_queue_family_properties(pdevice)
+            .iter()
+            .zip(&queue_families)
+            .any(|(family, info)| {
+                family.queue_flags.contains(vk::QueueFlags::GRAPHICS) && info.present != Some(false)
+            });
+
+    let surface = surface.map(|surface| {
+        let capabilities = surface_loader
+            .get_physical_device_surface_capabilities(pdevice, surface)
+            .unwrap_or_default();
+        SurfaceInfo {
+            formats: surface_loader
+                .get_physical_device_surface_formats(pdevice, surface)
+                .unwrap_or_default()
+                .iter()
+                .map(|format| format!("{:?} {:?}", format.format, format.color_space))
+                .collect(),
+            present_modes: surface_loader
+                .get_physical_device_surface_present_modes(pdevice, surface)
+                .unwrap_or_default()
+                .iter()
+                .map(|present_mode| format!("{:?}", present_mode))
+                .collect(),
+            min_image_count: capabilities.min_image_count,
+            max_image_count: capabilities.max_image_count,
+        }
+    });
+
+    let features = instance.get_physical_device_features(pdevice);
+    let subgroup = SubgroupSupport::new(instance, pdevice);
+    let limits = &properties.limits;
+    DeviceInfo {
+        name: CStr::from_ptr(properties.device_name.as_ptr())
+            .to_string_lossy()
+            .into_owned(),
+        device_type: format!("{:?}", properties.device_type),
+        api_version: format!(
+            "{}.{}.{}",
+            vk::version_major(properties.api_version),
+            vk::version_minor(properties.api_version),
+            vk::version_patch(properties.api_version)
+        ),
+        driver_version: properties.driver_version,
+        suitable,
+        swapchain,
+        queue_families,
+        surface,
+        limits: Limits {
+            max_image_dimension_2d: limits.max_image_dimension2_d,
+            max_push_constants_size: limits.max_push_constants_size,
+            max_bound_descriptor_sets: limits.max_bound_descriptor_sets,
+            max_per_stage_descriptor_samplers: limits.max_per_stage_descriptor_samplers,
+            max_color_attachments: limits.max_color_attachments,
+            max_compute_work_group_invocations: limits.max_compute_work_group_invocations,
+            max_sampler_anisotropy: limits.max_sampler_anisotropy,
+            timestamp_period: limits.timestamp_period,
+        },
+        features: Features {
+            fill_mode_non_solid: features.fill_mode_non_solid == vk::TRUE,
+            sampler_anisotropy: features.sampler_anisotropy == vk::TRUE,
+            independent_blend: features.independent_blend == vk::TRUE,
+            geometry_shader: features.geometry_shader == vk::TRUE,
+            tessellation_shader: features.tessellation_shader == vk::TRUE,
+            shader_float64: features.shader_float64 == vk::TRUE,
+            shader_int64: features.shader_int64 == vk::TRUE,
+            shader_int16: features.shader_int16 == vk::TRUE,
+            shader_storage_image_write_without_format: features
+                .shader_storage_image_write_without_format
+                == vk::TRUE,
+        },
+        subgroup: SubgroupInfo {
+            size: subgroup.size,
+            stages: format!("{:?}", subgroup.stages),
+            operations: format!("{:?}", subgroup.operations),
+            quad_operations_in_all_stages: subgroup.quad_operations_in_all_stages,
+        },
+        extensions,
+    }
+}
+
+pub fn print_devices(devices: &[DeviceInfo]) {
+    if devices.is_empty() {
+        println!("no Vulkan devices");
+    }
+    for (index, device) in devices.iter().enumerate() {
+        println!(
+            "{}: {} ({}, Vulkan {}, driver {:#x}){}",
+            index,
+            device.name,
+            device.device_type,
+            device.api_version,
+            device.driver_version,
+            if device.suitable { "" } else { ", not usable" }
+        );
+        if !device.swapchain {
+            println!("  no {:?}", khr::Swapchain::name());
+        }
+        println!("  queue families:");
+        for (index, family) in device.queue_families.iter().enumerate() {
+            let present = match family.present {
+                Some(true) => ", present",
+                Some(false) => ", no present",
+                None => "",
+            };
+            println!(
+                "    {}: {}, {} queues{}",
+                index, family.flags, family.queue_count, present
+            );
+        }
+        if let Some(surface) = &device.surface {
+            println!("  surface formats: {}", surface.formats.join(", "));
+            println!("  present modes: {}", surface.present_modes.join(", "));
+            let max_image_count = match surface.max_image_count {
+                0 => String::from("unbounded"),
+                count => count.to_string(),
+            };
+            println!(
+                "  swapchain images: {} to {}",
+                surface.min_image_count, max_image_count
+            );
+        }
+        let limits = &device.limits;
+        println!("  limits:");
+        println!("    max image size: {}", limits.max_image_dimension_2d);
+        println!(
+            "    max push constants: {} bytes",
+            limits.max_push_constants_size
+        );
+        println!(
+            "    max bound descriptor sets: {}",
+            limits.max_bound_descriptor_sets
+        );
+        println!(
+            "    max samplers per stage: {}",
+            limits.max_per_stage_descriptor_samplers
+        );
+        println!(
+            "    max color attachments: {}",
+            limits.max_color_attachments
+        );
+        println!(
+            "    max compute invocations: {}",
+            limits.max_compute_work_group_invocations
+        );
+        println!("    max anisotropy: {}", limits.max_sampler_anisotropy);
+        println!("    timestamp period: {} ns", limits.timestamp_period);
+        let subgroup = &device.subgroup;
+        if subgroup.size == 0 {
+            println!("  subgroups: none");
+        } else {
+            println!("  subgroups:");
+            println!("    size: {}", subgroup.size);
+            println!("    stages: {}", subgroup.stages);
+            println!("    operations: {}", subgroup.operations);
+            println!(
+                "    quad operations: {}",
+                if subgroup.quad_operations_in_all_stages {
+                    "all stages"
+                } else {
+                    "fragment and compute only"
+                }
+            );
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! The runner's hotkeys, rebindable with `--keymap` and a RON file binding actions to keys:
+//!
+//! ```ron
+//! {
+//!     toggle_vsync: F2,
+//!     toggle_pause: P,
+//! }
+//! ```
+//!
+//! Actions left out keep their default key.  A key bound to an action in the file is taken from
+//! whatever action it was bound to by default, which is then left without a key.  Keys are named
+//! like winit's `VirtualKeyCode`, e.g. `Key1`, `Numpad0` or `Grave`.
+
+use serde::Deserialize;
+use winit::event::VirtualKeyCode;
+
+use std::{collections::HashMap, fs::File, io::BufReader, path::Path};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Exit,
+    /// Builds the shader crate again.
+    RebuildShaders,
+    ToggleVsync,
+    ToggleWireframe,
+    /// Shows the shaders from before the last reload while held.
+    ShowPrevious,
+    /// Saves the frame to an EXR file.
+    Capture,
+    TogglePause,
+    /// Steps the time one frame while paused.
+    StepTime,
+    SlowerTime,
+    FasterTime,
+    ToggleHelp,
+    ToggleFrameGraph,
+    /// Shows the GPU time of each render pass.
+    TogglePassTimes,
+    /// Switches between the orbit and the fly camera.
+    ToggleFlyCamera,
+    /// Draws the last `.spv` file dropped onto the window instead of the enabled pipelines.
+    BindDroppedShader,
+    /// Only with the puffin feature.
+    TogglePuffin,
+}
+
+impl Action {
+    /// Every action with its default key, in the order the help lists them.
+    pub const DEFAULT_KEYS: [(Action, VirtualKeyCode); 16] = [
+        (Action::Exit, VirtualKeyCode::Escape),
+        (Action::RebuildShaders, VirtualKeyCode::F5),
+        (Action::ToggleVsync, VirtualKeyCode::V),
+        (Action::ToggleWireframe, VirtualKeyCode::W),
+        (Action::ShowPrevious, VirtualKeyCode::B),
+        (Action::Capture, VirtualKeyCode::F12),
+        (Action::TogglePause, VirtualKeyCode::Space),
+        (Action::StepTime, VirtualKeyCode::Period),
+        (Action::SlowerTime, VirtualKeyCode::Minus),
+        (Action::FasterTime, VirtualKeyCode::Equals),
+        (Action::ToggleHelp, VirtualKeyCode::F1),
+        (Action::ToggleFrameGraph, VirtualKeyCode::F3),
+        (Action::TogglePuffin, VirtualKeyCode::F4),
+        (Action::ToggleFlyCamera, VirtualKeyCode::F6),
+        (Action::TogglePassTimes, VirtualKeyCode::F7),
+        (Action::BindDroppedShader, VirtualKeyCode::Return),
+    ];
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::Exit => "exit",
+            Action::RebuildShaders => "rebuild shaders",
+            Action::ToggleVsync => "toggle vsync",
+            Action::ToggleWireframe => "toggle wireframe",
+            Action::ShowPrevious => "show previous shaders while held",
+            Action::Capture => "capture frame to EXR",
+            Action::TogglePause => "pause time",
+            Action::StepTime => "step time while paused",
+            Action::SlowerTime => "halve time speed",
+            Action::FasterTime => "double time speed",
+            Action::ToggleHelp => "toggle help",
+            Action::ToggleFrameGraph => "toggle frame time graph",
+            Action::TogglePuffin => "toggle puffin profiling",
+            Action::TogglePassTimes => "toggle GPU time per pass",
+            Action::ToggleFlyCamera => "toggle fly camera",
+            Action::BindDroppedShader => "draw the dropped shader",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    actions: HashMap<VirtualKeyCode, Action>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self {
+            actions: Action::DEFAULT_KEYS
+                .iter()
+                .map(|&(action, key)| (key, action))
+                .collect(),
+        }
+    }
+}
+
+impl Keymap {
+    /// The defaults with the bindings of the file applied.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|err| err.to_string())?;
+        let bindings: HashMap<Action, VirtualKeyCode> =
+            ron::de::from_reader(BufReader::new(file)).map_err(|err| err.to_string())?;
+        let mut keymap = Self::default();
+        for (&action, &key) in bindings.iter() {
+            keymap.bind(action, key);
+        }
+        Ok(keymap)
+    }
+
+    /// Binds `action` to `key` instead of its current key, unbinding whatever `key` was bound to.
+    pub fn bind(&mut self, action: Action, key: VirtualKeyCode) {
+        self.actions.retain(|_, bound| *bound != action);
+        self.actions.insert(key, action);
+    }
+
+    pub fn action(&self, key: VirtualKeyCode) -> Option<Action> {
+        self.actions.get(&key).copied()
+    }
+
+    pub fn key(&self, action: Action) -> Option<VirtualKeyCode> {
+        self.actions
+            .iter()
+            .find(|(_, &bound)| bound == action)
+            .map(|(&key, _)| key)
+    }
+}
@@ -0,0 +1,79 @@
+//! Lower input latency without driver support, as `VK_NV_low_latency2` is newer than the ash
+//! version used here.  Instead each frame waits for the GPU to finish right after presenting, so
+//! the next frame samples input and updates as late as possible rather than queueing up behind the
+//! previous one; with `--max-fps` the limiter's sleep lands before the update as well.
+//!
+//! Markers along the frame measure the latency from the update to the GPU finishing, printed every
+//! few seconds.  What the presentation engine adds after that isn't visible to Vulkan 1.1.
+
+use std::time::{Duration, Instant};
+
+pub struct LatencyMarkers {
+    /// When the current frame started updating.
+    frame_start: Option<Instant>,
+    /// Update to present, and update to GPU done, of the frames since the last report, in
+    /// milliseconds.
+    present_latencies: Vec<f64>,
+    gpu_latencies: Vec<f64>,
+    last_report: Instant,
+}
+
+impl LatencyMarkers {
+    const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+    pub fn new() -> Self {
+        Self {
+            frame_start: None,
+            present_latencies: Vec::new(),
+            gpu_latencies: Vec::new(),
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Before the plugins update, which is when input is sampled.
+    pub fn frame_start(&mut self) {
+        self.frame_start = Some(Instant::now());
+    }
+
+    pub fn presented(&mut self) {
+        if let Some(frame_start) = self.frame_start {
+            self.present_latencies
+                .push(frame_start.elapsed().as_secs_f64() * 1000.0);
+        }
+    }
+
+    /// After waiting for the frame's commands to complete.
+    pub fn gpu_done(&mut self) {
+        if let Some(frame_start) = self.frame_start.take() {
+            self.gpu_latencies
+                .push(frame_start.elapsed().as_secs_f64() * 1000.0);
+        }
+        if self.last_report.elapsed() >= Self::REPORT_INTERVAL {
+            self.report();
+        }
+    }
+
+    fn report(&mut self) {
+        if !self.gpu_latencies.is_empty() {
+            println!(
+                "latency over {} frames: update to present {:.2} ms, update to GPU done {:.2} ms",
+                self.gpu_latencies.len(),
+                average(&self.present_latencies),
+                average(&self.gpu_latencies),
+            );
+        }
+        self.present_latencies.clear();
+        self.gpu_latencies.clear();
+        self.last_report = Instant::now();
+    }
+}
+
+impl Default for LatencyMarkers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn average(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len().max(1) as f64
+}
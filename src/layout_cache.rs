@@ -0,0 +1,100 @@
+//! Descriptor set layouts and pipeline layouts created once per distinct description and shared by
+//! every pipeline asking for an identical one, e.g. all the pipelines whose shaders reflect the
+//! same push constant block.  The layouts live as long as the `RenderCtx`, so pipelines using
+//! them only destroy the pipelines themselves.
+
+use ash::{version::DeviceV1_0, vk};
+
+use std::collections::HashMap;
+
+/// A `vk::DescriptorSetLayoutBinding` without immutable samplers, which the cache doesn't support.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct BindingKey {
+    binding: u32,
+    descriptor_type: vk::DescriptorType,
+    descriptor_count: u32,
+    stage_flags: vk::ShaderStageFlags,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PipelineLayoutKey {
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    /// Stage flags and size, always at offset 0.
+    push_constant_range: Option<(vk::ShaderStageFlags, u32)>,
+}
+
+#[derive(Default)]
+pub struct LayoutCache {
+    set_layouts: HashMap<Vec<BindingKey>, vk::DescriptorSetLayout>,
+    pipeline_layouts: HashMap<PipelineLayoutKey, vk::PipelineLayout>,
+}
+
+impl LayoutCache {
+    /// The layout of `bindings`, in any order.
+    pub fn descriptor_set_layout(
+        &mut self,
+        device: &ash::Device,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+    ) -> vk::DescriptorSetLayout {
+        let mut key = bindings
+            .iter()
+            .map(|binding| {
+                assert!(
+                    binding.p_immutable_samplers.is_null(),
+                    "Cached descriptor set layouts can't have immutable samplers"
+                );
+                BindingKey {
+                    binding: binding.binding,
+                    descriptor_type: binding.descriptor_type,
+                    descriptor_count: binding.descriptor_count,
+                    stage_flags: binding.stage_flags,
+                }
+            })
+            .collect::<Vec<_>>();
+        key.sort_by_key(|binding| binding.binding);
+        *self.set_layouts.entry(key).or_insert_with(|| {
+            let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+            unsafe {
+                device
+                    .create_descriptor_set_layout(&layout_info, None)
+                    .unwrap()
+            }
+        })
+    }
+
+    /// The layout of `set_layouts` at sets 0, 1, ..., and the push constant range, which has to
+    /// start at offset 0.
+    pub fn pipeline_layout(
+        &mut self,
+        device: &ash::Device,
+        set_layouts: &[vk::DescriptorSetLayout],
+        push_constant_range: Option<vk::PushConstantRange>,
+    ) -> vk::PipelineLayout {
+        if let Some(range) = push_constant_range {
+            assert_eq!(range.offset, 0, "Push constant ranges have to start at 0");
+        }
+        let key = PipelineLayoutKey {
+            set_layouts: set_layouts.to_vec(),
+            push_constant_range: push_constant_range.map(|range| (range.stage_flags, range.size)),
+        };
+        *self.pipeline_layouts.entry(key).or_insert_with(|| {
+            let push_constant_ranges = push_constant_range
+                .as_ref()
+                .map_or(&[][..], std::slice::from_ref);
+            let layout_info = vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(set_layouts)
+                .push_constant_ranges(push_constant_ranges);
+            unsafe { device.create_pipeline_layout(&layout_info, None).unwrap() }
+        })
+    }
+
+    /// No pipeline may use the layouts anymore.
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for (_, layout) in self.pipeline_layouts.drain() {
+            unsafe { device.destroy_pipeline_layout(layout, None) };
+        }
+        for (_, layout) in self.set_layouts.drain() {
+            unsafe { device.destroy_descriptor_set_layout(layout, None) };
+        }
+    }
+}
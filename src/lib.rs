@@ -1 +1,61 @@
 pub mod ash_runner;
+pub mod calibrated_timestamps;
+pub mod camera;
+pub mod capture;
+pub mod cli;
+pub mod clock;
+pub mod compile_report;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod compiler;
+pub mod debug_draw;
+pub mod device_requirements;
+#[cfg(feature = "egui")]
+pub mod egui_painter;
+pub mod export;
+pub mod fly_camera;
+pub mod frame_graph;
+pub mod frame_ring;
+pub mod frame_timing;
+#[cfg(windows)]
+pub mod fullscreen_exclusive;
+pub mod golden;
+pub mod gpu_buffer;
+pub mod grid;
+pub mod help;
+pub mod info;
+pub mod keymap;
+pub mod latency;
+pub mod layout_cache;
+pub mod orbit_camera;
+pub mod particles;
+pub mod pass_times;
+pub mod passes;
+pub mod perf_counters;
+pub mod picking;
+pub mod pipeline_library;
+pub mod pipeline_registry;
+pub mod plugin;
+pub mod portability;
+pub mod profiling;
+pub mod readback;
+pub mod reflect;
+pub mod render_pass;
+pub mod sampler_cache;
+pub mod scene;
+#[cfg(feature = "rhai")]
+pub mod script;
+pub mod settings;
+pub mod shader_drop;
+pub mod sprite;
+pub mod subgroup;
+pub mod synchronization2;
+pub mod text;
+pub mod watermark;
+#[cfg(feature = "openxr")]
+pub mod xr;
+
+#[cfg(target_os = "android")]
+#[ndk_glue::main(backtrace = "on")]
+pub fn android_main() {
+    ash_runner::main();
+}
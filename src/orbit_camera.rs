@@ -0,0 +1,119 @@
+//! Moves `ctx.camera` around its target with the mouse, to look at a mesh or SDF from all sides:
+//! dragging with the left button orbits, with the right or middle button pans the target, and
+//! scrolling zooms.  Changes made to the camera elsewhere are picked up when a drag starts.  Does
+//! nothing while the fly camera is on.
+
+use crate::ash_runner::RenderCtx;
+use crate::camera::{self, Camera, CameraMode};
+use crate::plugin::Plugin;
+
+use winit::event::MouseButton;
+
+use std::f32::consts::FRAC_PI_2;
+
+/// Radians per physical pixel dragged.
+const ROTATE_SPEED: f32 = 0.005;
+/// Of the distance to the target, per physical pixel dragged.
+const PAN_SPEED: f32 = 0.002;
+/// Factor the distance is multiplied with per line scrolled towards the target.
+const ZOOM_FACTOR: f32 = 0.9;
+const MIN_DISTANCE: f32 = 0.01;
+/// Keeps the camera off the poles, where looking straight down leaves no right direction.
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Drag {
+    Orbit,
+    Pan,
+}
+
+#[derive(Default)]
+pub struct OrbitCamera {
+    /// Around +y, 0 looking down -z.
+    yaw: f32,
+    /// Above the target's horizon.
+    pitch: f32,
+    distance: f32,
+    drag: Option<Drag>,
+    cursor: Option<[f64; 2]>,
+}
+
+impl OrbitCamera {
+    /// Takes the angles and distance from where the camera is now.
+    fn sync(&mut self, camera: &Camera) {
+        let offset = camera::sub(camera.eye, camera.target);
+        self.distance = camera::length(offset).max(MIN_DISTANCE);
+        self.pitch = (offset[1] / self.distance)
+            .asin()
+            .max(-MAX_PITCH)
+            .min(MAX_PITCH);
+        self.yaw = offset[0].atan2(offset[2]);
+    }
+
+    fn apply(&self, camera: &mut Camera) {
+        let (yaw_sin, yaw_cos) = self.yaw.sin_cos();
+        let (pitch_sin, pitch_cos) = self.pitch.sin_cos();
+        let offset = [pitch_cos * yaw_sin, pitch_sin, pitch_cos * yaw_cos];
+        camera.eye = camera::add(camera.target, camera::scale(offset, self.distance));
+    }
+}
+
+impl Plugin for OrbitCamera {
+    fn name(&self) -> &str {
+        "orbit camera"
+    }
+
+    fn mouse_button(&mut self, ctx: &mut RenderCtx, button: MouseButton, pressed: bool) {
+        let drag = match button {
+            MouseButton::Left => Drag::Orbit,
+            MouseButton::Right | MouseButton::Middle => Drag::Pan,
+            MouseButton::Other(_) => return,
+        };
+        if pressed && ctx.camera_mode == CameraMode::Orbit {
+            self.sync(&ctx.camera);
+            self.drag = Some(drag);
+        } else if self.drag == Some(drag) {
+            self.drag = None;
+        }
+    }
+
+    fn cursor_moved(&mut self, ctx: &mut RenderCtx, position: [f64; 2]) {
+        let previous = self.cursor.replace(position);
+        let (drag, previous) = match (self.drag, previous) {
+            (Some(drag), Some(previous)) => (drag, previous),
+            _ => return,
+        };
+        let dx = (position[0] - previous[0]) as f32;
+        let dy = (position[1] - previous[1]) as f32;
+        match drag {
+            Drag::Orbit => {
+                self.yaw -= dx * ROTATE_SPEED;
+                self.pitch = (self.pitch + dy * ROTATE_SPEED)
+                    .max(-MAX_PITCH)
+                    .min(MAX_PITCH);
+            }
+            Drag::Pan => {
+                // the target follows the cursor across the screen
+                let (right, up, _) = ctx.camera.basis();
+                let step = self.distance * PAN_SPEED;
+                let offset = camera::add(
+                    camera::scale(right, -dx * step),
+                    camera::scale(up, dy * step),
+                );
+                ctx.camera.target = camera::add(ctx.camera.target, offset);
+            }
+        }
+        self.apply(&mut ctx.camera);
+    }
+
+    fn mouse_wheel(&mut self, ctx: &mut RenderCtx, delta: f32) {
+        if ctx.camera_mode != CameraMode::Orbit {
+            return;
+        }
+        if self.drag.is_none() {
+            self.sync(&ctx.camera);
+        }
+        self.distance = (self.distance * ZOOM_FACTOR.powf(delta)).max(MIN_DISTANCE);
+        self.apply(&mut ctx.camera);
+    }
+}
@@ -0,0 +1,503 @@
+//! Particles simulated by a compute shader and drawn by a vertex shader reading them straight from
+//! the storage buffer the compute shader wrote, described in a RON file.  The runner only provides
+//! the buffers, dispatch and draw; the entry points come from your shader crate, the bundled
+//! `shaders` crate has none.  For a crate named `particles` providing them:
+//!
+//! ```ron
+//! (
+//!     count: 65536,
+//!     stride: 32,
+//!     compute: (module: "particles", entry_point: "update_cs"),
+//!     vertex: (module: "particles", entry_point: "particle_vs"),
+//!     fragment: (module: "particles", entry_point: "particle_fs"),
+//!     vertices_per_particle: 6,
+//!     blend: Some(Additive),
+//! )
+//! ```
+//!
+//! The particles are `stride` bytes each, in two storage buffers taking turns holding the current
+//! state, so the compute shader reads the last frame's particles at binding 0 of descriptor set 0
+//! and writes the new ones to binding 1.  It is dispatched once per frame before the render pass,
+//! in workgroups of `workgroup_size` invocations along x, which has to match the shader's local
+//! size; with the particle count rounded up to whole workgroups, invocations at an index past
+//! `count` must not write.  Both buffers start zeroed, so a particle of all zeros is one the
+//! compute shader has yet to spawn.  While the time is paused the particles aren't updated.
+//!
+//! Then `vertices_per_particle` vertices are drawn per particle as instances of a triangle list,
+//! after the runner's pipelines and `--passes`, with the particles just written at binding 0 of
+//! set 0 and the instance index as the particle's.  All three shaders get the usual push
+//! constants.
+
+use crate::ash_runner::{
+    any_as_u8_slice, ComputeShaderEntryPoint, FragmentShaderEntryPoint, Pipeline,
+    PipelineDescriptor, RenderCtx, VertexShaderEntryPoint,
+};
+use crate::gpu_buffer::GpuBuffer;
+use crate::pipeline_registry::BlendMode;
+use crate::plugin::Plugin;
+
+use ash::{version::DeviceV1_0, vk};
+use serde::Deserialize;
+
+use std::{ffi::CString, fs::File, path::Path};
+
+#[derive(Deserialize)]
+pub struct ParticleDescription {
+    pub count: u32,
+    /// The size of a particle in bytes, a multiple of 4.
+    pub stride: u32,
+    #[serde(default = "default_workgroup_size")]
+    pub workgroup_size: u32,
+    pub compute: ComputeShaderEntryPoint,
+    pub vertex: VertexShaderEntryPoint,
+    pub fragment: FragmentShaderEntryPoint,
+    #[serde(default = "default_vertices_per_particle")]
+    pub vertices_per_particle: u32,
+    /// Defaults to additive.
+    pub blend: Option<BlendMode>,
+}
+
+fn default_workgroup_size() -> u32 {
+    64
+}
+
+fn default_vertices_per_particle() -> u32 {
+    6
+}
+
+impl ParticleDescription {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|err| err.to_string())?;
+        let description: Self = ron::de::from_reader(file).map_err(|err| err.to_string())?;
+        if description.count == 0 {
+            return Err("count must be at least 1".to_owned());
+        }
+        if description.stride == 0 || description.stride % 4 != 0 {
+            return Err(format!(
+                "stride must be a positive multiple of 4, not {}",
+                description.stride
+            ));
+        }
+        if description.workgroup_size == 0 {
+            return Err("workgroup_size must be at least 1".to_owned());
+        }
+        Ok(description)
+    }
+}
+
+/// Simulates and draws a `ParticleDescription`, rebuilding its pipelines along with the runner's.
+pub struct ParticleSystem {
+    description: ParticleDescription,
+    /// Created in `init`.
+    buffers: Vec<GpuBuffer>,
+    descriptor_pool: vk::DescriptorPool,
+    compute_set_layout: vk::DescriptorSetLayout,
+    draw_set_layout: vk::DescriptorSetLayout,
+    /// Reading `buffers[i]` and writing the other.
+    compute_sets: [vk::DescriptorSet; 2],
+    /// Reading `buffers[i]`.
+    draw_sets: [vk::DescriptorSet; 2],
+    /// `None` while the shader modules are missing.
+    compute_pipeline: Option<Pipeline>,
+    draw_pipeline: Option<Pipeline>,
+    /// Index of the buffer holding the latest particles.
+    current: usize,
+    /// Whether this frame updates the particles, into `buffers[current]`.
+    simulate: bool,
+    /// The clock's frame when the particles were last updated.
+    simulated_frame: Option<u32>,
+}
+
+impl ParticleSystem {
+    pub fn new(description: ParticleDescription) -> Self {
+        Self {
+            description,
+            buffers: Vec::new(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            compute_set_layout: vk::DescriptorSetLayout::null(),
+            draw_set_layout: vk::DescriptorSetLayout::null(),
+            compute_sets: [vk::DescriptorSet::null(); 2],
+            draw_sets: [vk::DescriptorSet::null(); 2],
+            compute_pipeline: None,
+            draw_pipeline: None,
+            current: 0,
+            simulate: false,
+            simulated_frame: None,
+        }
+    }
+
+    /// The latest particles, e.g. to `GpuBuffer::read_back` the simulation's results.  Only after
+    /// `init`.
+    pub fn particles(&self) -> &GpuBuffer {
+        &self.buffers[self.current]
+    }
+
+    fn create_buffers(&mut self, ctx: &RenderCtx) {
+        let device = &ctx.base.device;
+        let size = vk::DeviceSize::from(self.description.count)
+            * vk::DeviceSize::from(self.description.stride);
+        self.buffers = (0..2)
+            .map(|_| GpuBuffer::new(&ctx.base, size, vk::BufferUsageFlags::STORAGE_BUFFER))
+            .collect();
+
+        // zeroed, waiting for it to finish
+        let command_buffer = ctx.commands.setup_command_buffer;
+        let fence = ctx.sync.setup_commands_reuse_fence;
+        unsafe {
+            device
+                .wait_for_fences(&[fence], true, std::u64::MAX)
+                .expect("Wait for fence failed.");
+            device.reset_fences(&[fence]).expect("Reset fences failed.");
+            device
+                .begin_command_buffer(
+                    command_buffer,
+                    &vk::CommandBufferBeginInfo::builder()
+                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                )
+                .expect("Begin commandbuffer");
+            for buffer in self.buffers.iter() {
+                device.cmd_fill_buffer(command_buffer, buffer.buffer, 0, vk::WHOLE_SIZE, 0);
+            }
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+                .build();
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::VERTEX_SHADER,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            );
+            device
+                .end_command_buffer(command_buffer)
+                .expect("End commandbuffer");
+            let command_buffers = [command_buffer];
+            let submit_info = vk::SubmitInfo::builder().command_buffers(&command_buffers);
+            device
+                .queue_submit(ctx.base.present_queue, &[submit_info.build()], fence)
+                .expect("queue submit failed.");
+            device
+                .wait_for_fences(&[fence], true, std::u64::MAX)
+                .expect("Wait for fence failed.");
+        }
+    }
+
+    fn create_descriptor_sets(&mut self, ctx: &RenderCtx) {
+        let device = &ctx.base.device;
+        let binding = |binding, stage_flags| vk::DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+            stage_flags,
+            ..Default::default()
+        };
+        let compute_bindings = [
+            binding(0, vk::ShaderStageFlags::COMPUTE),
+            binding(1, vk::ShaderStageFlags::COMPUTE),
+        ];
+        let draw_bindings = [binding(0, vk::ShaderStageFlags::VERTEX)];
+        self.compute_set_layout = ctx.descriptor_set_layout(&compute_bindings);
+        self.draw_set_layout = ctx.descriptor_set_layout(&draw_bindings);
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 6,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(4)
+            .pool_sizes(&pool_sizes);
+        self.descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+        let set_layouts = [
+            self.compute_set_layout,
+            self.compute_set_layout,
+            self.draw_set_layout,
+            self.draw_set_layout,
+        ];
+        let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&set_layouts);
+        let sets = unsafe { device.allocate_descriptor_sets(&allocate_info).unwrap() };
+        self.compute_sets = [sets[0], sets[1]];
+        self.draw_sets = [sets[2], sets[3]];
+
+        let buffer_info = |index: usize| {
+            [vk::DescriptorBufferInfo {
+                buffer: self.buffers[index].buffer,
+                offset: 0,
+                range: vk::WHOLE_SIZE,
+            }]
+        };
+        let buffer_infos = [buffer_info(0), buffer_info(1)];
+        let mut writes = Vec::with_capacity(6);
+        for index in 0..2 {
+            let write = |set, binding, buffer_info: &[vk::DescriptorBufferInfo]| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(binding)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(buffer_info)
+                    .build()
+            };
+            writes.push(write(self.compute_sets[index], 0, &buffer_infos[index]));
+            writes.push(write(self.compute_sets[index], 1, &buffer_infos[1 - index]));
+            writes.push(write(self.draw_sets[index], 0, &buffer_infos[index]));
+        }
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+    }
+
+    /// Leaves the pipelines out if a shader module isn't loaded.
+    fn create_pipelines(&mut self, ctx: &RenderCtx) {
+        let desc = &self.description;
+        let module = |name: &String| {
+            let module = ctx.shader_modules.get(name).copied();
+            if module.is_none() {
+                eprintln!("particles: no shader module \"{}\"", name);
+            }
+            module
+        };
+        let (compute_module, vert_module, frag_module) = match (
+            module(&desc.compute.module),
+            module(&desc.vertex.module),
+            module(&desc.fragment.module),
+        ) {
+            (Some(compute), Some(vert), Some(frag)) => (compute, vert, frag),
+            _ => return,
+        };
+        let device = &ctx.base.device;
+        let create_layout = |set_layout, push_constant_range| {
+            ctx.pipeline_layout(&[set_layout], push_constant_range)
+        };
+
+        let compute_name = CString::new(desc.compute.entry_point.clone()).unwrap();
+        let compute_push_constant_range = ctx
+            .push_constant_sizes
+            .get(&desc.compute.module)
+            .and_then(|sizes| sizes.get(&desc.compute.entry_point))
+            .map(|&size| vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size,
+            });
+        let compute_layout = create_layout(self.compute_set_layout, compute_push_constant_range);
+        let compute_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(vk::PipelineShaderStageCreateInfo {
+                module: compute_module,
+                p_name: compute_name.as_ptr(),
+                stage: vk::ShaderStageFlags::COMPUTE,
+                ..Default::default()
+            })
+            .layout(compute_layout);
+        let compute_pipeline = unsafe {
+            device
+                .create_compute_pipelines(vk::PipelineCache::null(), &[compute_info.build()], None)
+                .expect("Unable to create the particle compute pipeline")
+                .pop()
+                .unwrap()
+        };
+        self.compute_pipeline = Some(Pipeline {
+            pipeline: compute_pipeline,
+            pipeline_layout: compute_layout,
+            push_constant_range: compute_push_constant_range,
+            color_blend_attachments: Box::new([]),
+            dynamic_state: Box::new([]),
+            debug_label: None,
+        });
+
+        let vert_name = CString::new(desc.vertex.entry_point.clone()).unwrap();
+        let frag_name = CString::new(desc.fragment.entry_point.clone()).unwrap();
+        let blend = desc.blend.unwrap_or(BlendMode::Additive);
+        let color_attachments = ctx.render_pass_config.color_attachment_count();
+        let mut pipeline_desc = PipelineDescriptor::builder(Box::new([
+            vk::PipelineShaderStageCreateInfo {
+                module: vert_module,
+                p_name: vert_name.as_ptr(),
+                stage: vk::ShaderStageFlags::VERTEX,
+                ..Default::default()
+            },
+            vk::PipelineShaderStageCreateInfo {
+                module: frag_module,
+                p_name: frag_name.as_ptr(),
+                stage: vk::ShaderStageFlags::FRAGMENT,
+                ..Default::default()
+            },
+        ]))
+        .color_blend_attachments(&vec![blend.attachment_state(); color_attachments])
+        .build();
+        pipeline_desc.push_constant_range = ctx.push_constant_range(&desc.vertex, &desc.fragment);
+        for replaced in ctx.base.restrict_pipeline(&mut pipeline_desc) {
+            println!("particles: the device has no {}", replaced);
+        }
+        let draw_layout = create_layout(self.draw_set_layout, pipeline_desc.push_constant_range);
+        self.draw_pipeline = Some(Pipeline::with_layout(
+            ctx,
+            pipeline_desc,
+            draw_layout,
+            ctx.render_pass,
+            vk::PipelineCache::null(),
+        ));
+    }
+
+    fn destroy_pipelines(&mut self, device: &ash::Device) {
+        for pipeline in self
+            .compute_pipeline
+            .take()
+            .into_iter()
+            .chain(self.draw_pipeline.take())
+        {
+            unsafe { device.destroy_pipeline(pipeline.pipeline, None) };
+        }
+    }
+
+    fn push_constants(
+        &self,
+        ctx: &RenderCtx,
+        command_buffer: vk::CommandBuffer,
+        pipeline: &Pipeline,
+    ) {
+        if let Some(range) = pipeline.push_constant_range {
+            let push_constants = ctx.shader_constants(ctx.render_extent());
+            let bytes = unsafe { any_as_u8_slice(&push_constants) };
+            let size = bytes.len().min(range.size as usize);
+            unsafe {
+                ctx.base.device.cmd_push_constants(
+                    command_buffer,
+                    pipeline.pipeline_layout,
+                    range.stage_flags,
+                    0,
+                    &bytes[..size],
+                );
+            }
+        }
+    }
+}
+
+impl Plugin for ParticleSystem {
+    fn name(&self) -> &str {
+        "particles"
+    }
+
+    fn init(&mut self, ctx: &mut RenderCtx) {
+        self.create_buffers(ctx);
+        self.create_descriptor_sets(ctx);
+        self.create_pipelines(ctx);
+    }
+
+    fn update(&mut self, ctx: &mut RenderCtx) {
+        // the frame number only moves on while the time isn't paused, or when stepping
+        let frame = ctx.clock.frame;
+        self.simulate = self.simulated_frame != Some(frame) && self.compute_pipeline.is_some();
+        if self.simulate {
+            self.simulated_frame = Some(frame);
+            self.current = 1 - self.current;
+        }
+    }
+
+    fn before_render_pass(&self, ctx: &RenderCtx, command_buffer: vk::CommandBuffer) {
+        let pipeline = match &self.compute_pipeline {
+            Some(pipeline) if self.simulate => pipeline,
+            _ => return,
+        };
+        let device = &ctx.base.device;
+        let workgroups = (self.description.count + self.description.workgroup_size - 1)
+            / self.description.workgroup_size;
+        unsafe {
+            // the last frame's dispatch wrote what this one reads, and its draw read what this one
+            // overwrites
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+                .build();
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::VERTEX_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            );
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.pipeline_layout,
+                0,
+                &[self.compute_sets[1 - self.current]],
+                &[],
+            );
+            self.push_constants(ctx, command_buffer, pipeline);
+            device.cmd_dispatch(command_buffer, workgroups, 1, 1);
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build();
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::VERTEX_SHADER,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    fn draw(&self, ctx: &RenderCtx, command_buffer: vk::CommandBuffer) {
+        let pipeline = match &self.draw_pipeline {
+            Some(pipeline) => pipeline,
+            None => return,
+        };
+        let device = &ctx.base.device;
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.pipeline,
+            );
+            device.cmd_set_viewport(command_buffer, 0, &ctx.viewports);
+            device.cmd_set_scissor(command_buffer, 0, &ctx.scissors);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.pipeline_layout,
+                0,
+                &[self.draw_sets[self.current]],
+                &[],
+            );
+            self.push_constants(ctx, command_buffer, pipeline);
+            device.cmd_draw(
+                command_buffer,
+                self.description.vertices_per_particle,
+                self.description.count,
+                0,
+                0,
+            );
+        }
+    }
+
+    fn pipelines_rebuilt(&mut self, ctx: &mut RenderCtx) {
+        // the runner waited for the device before destroying its own old pipelines
+        self.destroy_pipelines(&ctx.base.device);
+        self.create_pipelines(ctx);
+    }
+
+    fn destroy(&mut self, ctx: &mut RenderCtx) {
+        let device = &ctx.base.device;
+        self.destroy_pipelines(device);
+        unsafe {
+            // frees the sets along with it
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+        }
+        for buffer in self.buffers.drain(..) {
+            buffer.destroy(device);
+        }
+    }
+}
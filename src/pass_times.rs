@@ -0,0 +1,105 @@
+//! The GPU time of each render pass in the window's top right corner, toggled with F7 by default,
+//! so a shader edit that slows down one of the `--passes` passes shows which one.  The times are
+//! `FrameTimer::pass_times`, averaged over a few seconds, so the table changes at that pace.
+
+use crate::ash_runner::RenderCtx;
+use crate::keymap::Action;
+use crate::plugin::Plugin;
+use crate::text::{Text, TextRenderer};
+
+use ash::vk;
+use winit::event::VirtualKeyCode;
+
+use std::time::Duration;
+
+/// In logical pixels.
+const MARGIN: f64 = 16.0;
+const COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.75];
+
+pub struct PassTimesOverlay {
+    visible: bool,
+    /// Created in `init`.
+    text: Option<TextRenderer>,
+}
+
+impl PassTimesOverlay {
+    pub fn new(visible: bool) -> Self {
+        Self {
+            visible,
+            text: None,
+        }
+    }
+}
+
+impl Plugin for PassTimesOverlay {
+    fn name(&self) -> &str {
+        "pass times"
+    }
+
+    fn init(&mut self, ctx: &mut RenderCtx) {
+        self.text = Some(TextRenderer::new(ctx));
+    }
+
+    fn key(&mut self, ctx: &mut RenderCtx, key: VirtualKeyCode, pressed: bool) {
+        if ctx.keymap.action(key) == Some(Action::TogglePassTimes) && pressed {
+            self.visible = !self.visible;
+        }
+    }
+
+    fn draw(&self, ctx: &RenderCtx, command_buffer: vk::CommandBuffer) {
+        let renderer = match &self.text {
+            Some(renderer) if self.visible => renderer,
+            _ => return,
+        };
+        let text = lines(ctx).join("\n");
+        let margin = (MARGIN * ctx.scale_factor) as f32;
+        let mut text = Text {
+            text: &text,
+            position: [0.0, margin],
+            scale: (2.0 * ctx.scale_factor).round() as u32,
+            color: COLOR,
+            background: Some(BACKGROUND),
+        };
+        text.position[0] = (ctx.extent.width as f32 - margin - text.size()[0]).max(0.0);
+        renderer.draw(ctx, command_buffer, &[text]);
+    }
+
+    fn pipelines_rebuilt(&mut self, ctx: &mut RenderCtx) {
+        if let Some(renderer) = self.text.as_mut() {
+            renderer.recreate_pipeline(ctx);
+        }
+    }
+
+    fn destroy(&mut self, ctx: &mut RenderCtx) {
+        if let Some(renderer) = self.text.as_mut() {
+            renderer.destroy(&ctx.base.device);
+        }
+    }
+}
+
+fn lines(ctx: &RenderCtx) -> Vec<String> {
+    let pass_times = &ctx.frame_timer.pass_times;
+    if pass_times.is_empty() {
+        return vec!["GPU time per pass: measuring".to_owned()];
+    }
+    let width = pass_times
+        .iter()
+        .map(|pass| pass.label.len())
+        .max()
+        .unwrap_or(0)
+        .max("total".len());
+    let row = |label: &str, gpu: Duration| {
+        format!(
+            "{:<width$} {:>7.3} ms",
+            label,
+            gpu.as_secs_f64() * 1000.0,
+            width = width
+        )
+    };
+    let mut lines = vec!["GPU time per pass:".to_owned()];
+    lines.extend(pass_times.iter().map(|pass| row(&pass.label, pass.gpu)));
+    let total = pass_times.iter().map(|pass| pass.gpu).sum();
+    lines.push(row("total", total));
+    lines
+}
@@ -0,0 +1,1041 @@
+//! Multi-pass rendering described in a RON file instead of code: offscreen targets, the passes
+//! drawing into them or the window, and which targets each pass samples.
+//!
+//! ```ron
+//! (
+//!     targets: [
+//!         (name: "scene", format: Rgba16Float),
+//!         (name: "bloom", scale: 0.5),
+//!     ],
+//!     passes: [
+//!         (
+//!             name: "scene",
+//!             target: Some("scene"),
+//!             draws: [(
+//!                 vertex: (module: "sky_shader", entry_point: "main_vs"),
+//!                 fragment: (module: "sky_shader", entry_point: "main_fs"),
+//!             )],
+//!         ),
+//!         (
+//!             name: "bloom",
+//!             target: Some("bloom"),
+//!             inputs: ["scene"],
+//!             draws: [(
+//!                 vertex: (module: "post", entry_point: "fullscreen_vs"),
+//!                 fragment: (module: "post", entry_point: "bloom_fs"),
+//!             )],
+//!         ),
+//!         (
+//!             name: "composite",
+//!             inputs: ["scene", "bloom"],
+//!             draws: [(
+//!                 vertex: (module: "post", entry_point: "fullscreen_vs"),
+//!                 fragment: (module: "post", entry_point: "composite_fs"),
+//!             )],
+//!         ),
+//!     ],
+//! )
+//! ```
+//!
+//! Passes run in the listed order.  Those without a `target` draw into the window's render pass
+//! after the runner's own pipelines.  A pass samples its `inputs` as combined image samplers at
+//! bindings 0, 1, ... of descriptor set 0, and each input has to be written by an earlier pass.
+//! Targets are cleared to transparent black every frame and sized `scale` times the render extent.
+//! Draws default to 3 vertices and 1 instance, enough for a fullscreen triangle.
+//!
+//! Targets only live from the first pass drawing into them to the last pass sampling them, with the
+//! window's passes drawing after all others, so targets whose lifetimes don't overlap share their
+//! memory.  A chain of post-processing passes ping-ponging through several targets then needs
+//! little more memory than the two largest.
+//!
+//! A target with `accumulate: true` isn't cleared but averages what is drawn into it over the
+//! frames, for Monte Carlo shaders like path tracers to converge: the shaders draw one sample per
+//! frame, varying their random numbers with the frame index or seed in the push constants, and a
+//! later pass samples the average.  The average starts over when the camera, the shader params or
+//! the shaders change, and on resize.  Rgba32Float keeps long runs from losing precision.
+
+use crate::ash_runner::{
+    any_as_u8_slice, FragmentShaderEntryPoint, Pipeline, PipelineDescriptor, RenderCtx,
+    VertexShaderEntryPoint,
+};
+use crate::pipeline_registry::BlendMode;
+use crate::plugin::Plugin;
+use crate::render_pass::RenderPassConfig;
+use crate::sampler_cache::SamplerDesc;
+use crate::synchronization2::{AccessFlags2, Dependency, MemoryBarrier, PipelineStageFlags2};
+
+use ash::{version::DeviceV1_0, vk};
+use serde::Deserialize;
+
+use std::{collections::HashMap, ffi::CString, fs::File, path::Path};
+
+#[derive(Deserialize)]
+pub struct SceneDescription {
+    #[serde(default)]
+    pub targets: Vec<TargetDescription>,
+    pub passes: Vec<PassDescription>,
+}
+
+#[derive(Deserialize)]
+pub struct TargetDescription {
+    pub name: String,
+    #[serde(default = "default_format")]
+    pub format: TargetFormat,
+    /// Relative to the render extent.
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    /// Keeps the running average of the frames drawn into it, instead of clearing it every frame.
+    #[serde(default)]
+    pub accumulate: bool,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum TargetFormat {
+    Rgba8,
+    Rgba16Float,
+    Rgba32Float,
+}
+
+#[derive(Deserialize)]
+pub struct PassDescription {
+    pub name: String,
+    /// The target to draw into, or `None` for the window.
+    #[serde(default)]
+    pub target: Option<String>,
+    /// Targets sampled by the pass, bound in this order.
+    #[serde(default)]
+    pub inputs: Vec<String>,
+    pub draws: Vec<DrawDescription>,
+}
+
+#[derive(Deserialize)]
+pub struct DrawDescription {
+    pub vertex: VertexShaderEntryPoint,
+    pub fragment: FragmentShaderEntryPoint,
+    #[serde(default = "default_vertex_count")]
+    pub vertex_count: u32,
+    #[serde(default = "default_instance_count")]
+    pub instance_count: u32,
+    pub blend: Option<BlendMode>,
+}
+
+/// `src * c + dst * (1 - c)` with the blend constant alpha `c` set to 1 / the sample count, so the
+/// target holds the average of all samples.
+fn accumulate_blend() -> vk::PipelineColorBlendAttachmentState {
+    vk::PipelineColorBlendAttachmentState {
+        blend_enable: vk::TRUE,
+        src_color_blend_factor: vk::BlendFactor::CONSTANT_ALPHA,
+        dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_CONSTANT_ALPHA,
+        color_blend_op: vk::BlendOp::ADD,
+        src_alpha_blend_factor: vk::BlendFactor::CONSTANT_ALPHA,
+        dst_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_CONSTANT_ALPHA,
+        alpha_blend_op: vk::BlendOp::ADD,
+        color_write_mask: vk::ColorComponentFlags::all(),
+    }
+}
+
+fn default_format() -> TargetFormat {
+    TargetFormat::Rgba16Float
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn default_vertex_count() -> u32 {
+    3
+}
+
+fn default_instance_count() -> u32 {
+    1
+}
+
+impl TargetFormat {
+    pub fn vk_format(self) -> vk::Format {
+        match self {
+            TargetFormat::Rgba8 => vk::Format::R8G8B8A8_UNORM,
+            TargetFormat::Rgba16Float => vk::Format::R16G16B16A16_SFLOAT,
+            TargetFormat::Rgba32Float => vk::Format::R32G32B32A32_SFLOAT,
+        }
+    }
+}
+
+impl SceneDescription {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|err| err.to_string())?;
+        let description: Self = ron::de::from_reader(file).map_err(|err| err.to_string())?;
+        description.validate()?;
+        Ok(description)
+    }
+
+    /// Checks that every target a pass names exists, and that inputs are written before they are
+    /// sampled, which also keeps a pass from sampling its own target.
+    fn validate(&self) -> Result<(), String> {
+        let mut written = HashMap::new();
+        for target in &self.targets {
+            if written.insert(target.name.as_str(), false).is_some() {
+                return Err(format!("target \"{}\" is declared twice", target.name));
+            }
+        }
+        for pass in &self.passes {
+            for input in &pass.inputs {
+                match written.get(input.as_str()) {
+                    Some(true) => {}
+                    Some(false) => {
+                        return Err(format!(
+                            "pass \"{}\" samples \"{}\" before any pass draws into it",
+                            pass.name, input
+                        ))
+                    }
+                    None => {
+                        return Err(format!(
+                            "pass \"{}\" samples unknown target \"{}\"",
+                            pass.name, input
+                        ))
+                    }
+                }
+            }
+            if let Some(target) = &pass.target {
+                let accumulate = self
+                    .targets
+                    .iter()
+                    .any(|desc| &desc.name == target && desc.accumulate);
+                if accumulate && pass.draws.iter().any(|draw| draw.blend.is_some()) {
+                    return Err(format!(
+                        "pass \"{}\" blends into accumulation target \"{}\", which averages instead",
+                        pass.name, target
+                    ));
+                }
+                match written.get_mut(target.as_str()) {
+                    Some(written) => *written = true,
+                    None => {
+                        return Err(format!(
+                            "pass \"{}\" draws into unknown target \"{}\"",
+                            pass.name, target
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn target_index(&self, name: &str) -> usize {
+        // validated on load
+        self.targets
+            .iter()
+            .position(|target| target.name == name)
+            .unwrap()
+    }
+
+    /// The first and last pass using each target, as indices into `passes` with the window's
+    /// passes counted after all others.  `None` for targets no pass draws into.
+    fn target_lifetimes(&self) -> Vec<Option<(usize, usize)>> {
+        let passes = &self.passes;
+        let mut lifetimes: Vec<Option<(usize, usize)>> = vec![None; self.targets.len()];
+        // accumulation targets keep their contents from frame to frame, so they can't share memory
+        for (lifetime, target) in lifetimes.iter_mut().zip(self.targets.iter()) {
+            if target.accumulate {
+                *lifetime = Some((0, passes.len()));
+            }
+        }
+        for (index, pass) in passes.iter().enumerate() {
+            let order = if pass.target.is_some() {
+                index
+            } else {
+                passes.len()
+            };
+            for name in pass.target.iter().chain(pass.inputs.iter()) {
+                let lifetime = &mut lifetimes[self.target_index(name)];
+                *lifetime = Some(match *lifetime {
+                    Some((first, last)) => (first.min(order), last.max(order)),
+                    None => (order, order),
+                });
+            }
+        }
+        lifetimes
+    }
+}
+
+/// Groups targets into the allocations they share, given their lifetimes and memory requirements:
+/// each target goes into the first slot holding only targets it never lives alongside, and whose
+/// memory types it can use.  Each slot has the indices of its targets and requirements covering all
+/// of them.
+fn assign_slots(
+    lifetimes: &[Option<(usize, usize)>],
+    requirements: &[vk::MemoryRequirements],
+) -> Vec<(Vec<usize>, vk::MemoryRequirements)> {
+    let overlap = |a: usize, b: usize| match (lifetimes[a], lifetimes[b]) {
+        (Some((first_a, last_a)), Some((first_b, last_b))) => {
+            first_a <= last_b && first_b <= last_a
+        }
+        _ => false,
+    };
+    let mut slots: Vec<(Vec<usize>, vk::MemoryRequirements)> = Vec::new();
+    for (index, &requirements) in requirements.iter().enumerate() {
+        let slot = slots.iter().position(|(members, slot_requirements)| {
+            slot_requirements.memory_type_bits & requirements.memory_type_bits != 0
+                && !members.iter().any(|&member| overlap(index, member))
+        });
+        match slot {
+            Some(slot) => {
+                let (members, slot_requirements) = &mut slots[slot];
+                members.push(index);
+                slot_requirements.size = slot_requirements.size.max(requirements.size);
+                slot_requirements.memory_type_bits &= requirements.memory_type_bits;
+            }
+            None => slots.push((vec![index], requirements)),
+        }
+    }
+    slots
+}
+
+/// Renders a `SceneDescription`, rebuilding its pipelines along with the runner's and resizing its
+/// targets with the swapchain.
+pub struct Passes {
+    description: SceneDescription,
+    sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    targets: Vec<Target>,
+    /// Backing the targets, some shared by several.
+    memories: Vec<vk::DeviceMemory>,
+    passes: Vec<PassResources>,
+    /// Frames averaged in the accumulation targets, this one included.
+    samples: u32,
+    /// What restarts the average when it changes.
+    accumulated_view: Option<AccumulatedView>,
+}
+
+/// The inputs besides the shaders themselves that change what an accumulation target converges to.
+#[derive(PartialEq)]
+struct AccumulatedView {
+    camera: [f32; 9],
+    params: [f32; 8],
+}
+
+impl AccumulatedView {
+    fn of(ctx: &RenderCtx) -> Self {
+        let camera = &ctx.camera;
+        let (eye, target) = (camera.eye, camera.target);
+        Self {
+            camera: [
+                eye[0],
+                eye[1],
+                eye[2],
+                target[0],
+                target[1],
+                target[2],
+                camera.fov_y,
+                camera.near,
+                camera.far,
+            ],
+            params: ctx.shader_params,
+        }
+    }
+}
+
+struct Target {
+    format: vk::Format,
+    /// Clears the target.
+    render_pass: vk::RenderPass,
+    /// For accumulation targets, loads the average so far to blend the next sample into.
+    load_render_pass: Option<vk::RenderPass>,
+    extent: vk::Extent2D,
+    image: vk::Image,
+    /// Shares its memory with other targets, so drawing into it has to wait for the passes that
+    /// sampled them.
+    aliased: bool,
+    view: vk::ImageView,
+    framebuffer: vk::Framebuffer,
+}
+
+struct PassResources {
+    /// Index into `targets`, or `None` for the window.
+    target: Option<usize>,
+    /// `None` for passes without inputs.
+    descriptor_set_layout: Option<vk::DescriptorSetLayout>,
+    descriptor_set: Option<vk::DescriptorSet>,
+    draws: Vec<Draw>,
+}
+
+struct Draw {
+    pipeline: Pipeline,
+    vertex_count: u32,
+    instance_count: u32,
+}
+
+impl Passes {
+    pub fn new(description: SceneDescription) -> Self {
+        Self {
+            description,
+            sampler: vk::Sampler::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            targets: Vec::new(),
+            memories: Vec::new(),
+            passes: Vec::new(),
+            samples: 0,
+            accumulated_view: None,
+        }
+    }
+
+    fn create_targets(&mut self, ctx: &RenderCtx) {
+        let device = &ctx.base.device;
+        let render_extent = ctx.render_extent();
+        for (desc, target) in self.description.targets.iter().zip(self.targets.iter_mut()) {
+            let extent = vk::Extent2D {
+                width: ((render_extent.width as f32 * desc.scale) as u32).max(1),
+                height: ((render_extent.height as f32 * desc.scale) as u32).max(1),
+            };
+            let image_info = vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(target.format)
+                .extent(vk::Extent3D {
+                    width: extent.width,
+                    height: extent.height,
+                    depth: 1,
+                })
+                .mip_levels(1)
+                .array_layers(1)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .initial_layout(vk::ImageLayout::UNDEFINED);
+            target.image = unsafe { device.create_image(&image_info, None).unwrap() };
+            target.extent = extent;
+        }
+
+        let requirements: Vec<_> = self
+            .targets
+            .iter()
+            .map(|target| unsafe { device.get_image_memory_requirements(target.image) })
+            .collect();
+        let slots = assign_slots(&self.description.target_lifetimes(), &requirements);
+        for (members, requirements) in slots.iter() {
+            let memory = unsafe {
+                let memory_type_index = ctx
+                    .base
+                    .find_memory_type(
+                        requirements.memory_type_bits,
+                        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                    )
+                    .expect("No device local memory type for a pass target");
+                let allocate_info = vk::MemoryAllocateInfo::builder()
+                    .allocation_size(requirements.size)
+                    .memory_type_index(memory_type_index);
+                device.allocate_memory(&allocate_info, None).unwrap()
+            };
+            for &member in members.iter() {
+                let target = &mut self.targets[member];
+                unsafe { device.bind_image_memory(target.image, memory, 0).unwrap() };
+                target.aliased = members.len() > 1;
+            }
+            self.memories.push(memory);
+        }
+
+        for target in self.targets.iter_mut() {
+            let (image, extent) = (target.image, target.extent);
+            let view_info = vk::ImageViewCreateInfo::builder()
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(target.format)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .image(image);
+            let view = unsafe { device.create_image_view(&view_info, None).unwrap() };
+            target.framebuffer =
+                ctx.base
+                    .create_framebuffers(&[view], &[], target.render_pass, extent)[0];
+            target.view = view;
+        }
+    }
+
+    fn destroy_targets(&mut self, device: &ash::Device) {
+        for target in self.targets.iter_mut() {
+            unsafe {
+                device.destroy_framebuffer(target.framebuffer, None);
+                device.destroy_image_view(target.view, None);
+                device.destroy_image(target.image, None);
+            }
+        }
+        for memory in self.memories.drain(..) {
+            unsafe { device.free_memory(memory, None) };
+        }
+    }
+
+    /// Points each pass' descriptor set at the current views of its inputs.
+    fn write_descriptor_sets(&self, device: &ash::Device) {
+        for (desc, pass) in self.description.passes.iter().zip(self.passes.iter()) {
+            let descriptor_set = match pass.descriptor_set {
+                Some(descriptor_set) => descriptor_set,
+                None => continue,
+            };
+            let image_infos = desc
+                .inputs
+                .iter()
+                .map(|input| vk::DescriptorImageInfo {
+                    sampler: self.sampler,
+                    image_view: self.targets[self.description.target_index(input)].view,
+                    image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                })
+                .collect::<Vec<_>>();
+            let write = vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&image_infos)
+                .build();
+            unsafe { device.update_descriptor_sets(&[write], &[]) };
+        }
+    }
+
+    /// Draws whose shader modules aren't loaded are left out.
+    fn create_pipelines(&mut self, ctx: &RenderCtx) {
+        for (desc, pass) in self.description.passes.iter().zip(self.passes.iter_mut()) {
+            let accumulate = match pass.target {
+                Some(target) => self.targets[target].load_render_pass.is_some(),
+                None => false,
+            };
+            let (render_pass, color_attachments) = match pass.target {
+                Some(target) => (self.targets[target].render_pass, 1),
+                None => (
+                    ctx.render_pass,
+                    ctx.render_pass_config.color_attachment_count(),
+                ),
+            };
+            pass.draws = desc
+                .draws
+                .iter()
+                .filter_map(|draw| {
+                    let (vert, frag) = (&draw.vertex, &draw.fragment);
+                    let module = |name: &String| {
+                        let module = ctx.shader_modules.get(name).copied();
+                        if module.is_none() {
+                            eprintln!("pass \"{}\": no shader module \"{}\"", desc.name, name);
+                        }
+                        module
+                    };
+                    let vert_module = module(&vert.module)?;
+                    let frag_module = module(&frag.module)?;
+                    let vert_name = CString::new(vert.entry_point.clone()).unwrap();
+                    let frag_name = CString::new(frag.entry_point.clone()).unwrap();
+                    let blend = if accumulate {
+                        accumulate_blend()
+                    } else {
+                        draw.blend.unwrap_or(BlendMode::Opaque).attachment_state()
+                    };
+                    let dynamic_states = if accumulate {
+                        &[
+                            vk::DynamicState::VIEWPORT,
+                            vk::DynamicState::SCISSOR,
+                            vk::DynamicState::BLEND_CONSTANTS,
+                        ][..]
+                    } else {
+                        &[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR][..]
+                    };
+                    let mut pipeline_desc = PipelineDescriptor::builder(Box::new([
+                        vk::PipelineShaderStageCreateInfo {
+                            module: vert_module,
+                            p_name: vert_name.as_ptr(),
+                            stage: vk::ShaderStageFlags::VERTEX,
+                            ..Default::default()
+                        },
+                        vk::PipelineShaderStageCreateInfo {
+                            module: frag_module,
+                            p_name: frag_name.as_ptr(),
+                            stage: vk::ShaderStageFlags::FRAGMENT,
+                            ..Default::default()
+                        },
+                    ]))
+                    .color_blend_attachments(&vec![blend; color_attachments])
+                    .dynamic_states(dynamic_states)
+                    .build();
+                    pipeline_desc.push_constant_range = ctx.push_constant_range(vert, frag);
+                    for replaced in ctx.base.restrict_pipeline(&mut pipeline_desc) {
+                        println!("pass \"{}\": the device has no {}", desc.name, replaced);
+                    }
+
+                    let set_layouts = pass
+                        .descriptor_set_layout
+                        .as_ref()
+                        .map_or(&[][..], std::slice::from_ref);
+                    let pipeline_layout =
+                        ctx.pipeline_layout(set_layouts, pipeline_desc.push_constant_range);
+                    Some(Draw {
+                        pipeline: Pipeline::with_layout(
+                            ctx,
+                            pipeline_desc,
+                            pipeline_layout,
+                            render_pass,
+                            vk::PipelineCache::null(),
+                        )
+                        .labeled(frag),
+                        vertex_count: draw.vertex_count,
+                        instance_count: draw.instance_count,
+                    })
+                })
+                .collect();
+        }
+    }
+
+    fn destroy_pipelines(&mut self, device: &ash::Device) {
+        for pass in self.passes.iter_mut() {
+            for draw in pass.draws.drain(..) {
+                unsafe { device.destroy_pipeline(draw.pipeline.pipeline, None) };
+            }
+        }
+    }
+
+    fn record_draws(
+        &self,
+        ctx: &RenderCtx,
+        command_buffer: vk::CommandBuffer,
+        pass: &PassResources,
+        extent: vk::Extent2D,
+        viewports: &[vk::Viewport],
+        scissors: &[vk::Rect2D],
+    ) {
+        let device = &ctx.base.device;
+        for draw in pass.draws.iter() {
+            let pipeline = &draw.pipeline;
+            if let Some(label) = &pipeline.debug_label {
+                ctx.begin_debug_label(command_buffer, label);
+            }
+            unsafe {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.pipeline,
+                );
+                device.cmd_set_viewport(command_buffer, 0, viewports);
+                device.cmd_set_scissor(command_buffer, 0, scissors);
+                if let Some(descriptor_set) = pass.descriptor_set {
+                    device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline.pipeline_layout,
+                        0,
+                        &[descriptor_set],
+                        &[],
+                    );
+                }
+                if let Some(range) = pipeline.push_constant_range {
+                    let push_constants = ctx.shader_constants(extent);
+                    let bytes = any_as_u8_slice(&push_constants);
+                    let size = bytes.len().min(range.size as usize);
+                    device.cmd_push_constants(
+                        command_buffer,
+                        pipeline.pipeline_layout,
+                        range.stage_flags,
+                        0,
+                        &bytes[..size],
+                    );
+                }
+                device.cmd_draw(command_buffer, draw.vertex_count, draw.instance_count, 0, 0);
+            }
+            if pipeline.debug_label.is_some() {
+                ctx.end_debug_label(command_buffer);
+            }
+        }
+    }
+}
+
+impl Plugin for Passes {
+    fn name(&self) -> &str {
+        "passes"
+    }
+
+    fn init(&mut self, ctx: &mut RenderCtx) {
+        let device = &ctx.base.device;
+        self.sampler = ctx.sampler(SamplerDesc::LINEAR_CLAMP);
+
+        // cleared every frame, and left ready for sampling by later passes
+        let target_pass_config = RenderPassConfig::default();
+        let load_pass_config = RenderPassConfig {
+            color_load_op: vk::AttachmentLoadOp::LOAD,
+            ..RenderPassConfig::default()
+        };
+        self.targets = self
+            .description
+            .targets
+            .iter()
+            .map(|desc| {
+                let format = desc.format.vk_format();
+                let create_render_pass = |config: &RenderPassConfig| {
+                    config.create_render_pass(
+                        device,
+                        format,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    )
+                };
+                Target {
+                    format,
+                    render_pass: create_render_pass(&target_pass_config),
+                    load_render_pass: if desc.accumulate {
+                        Some(create_render_pass(&load_pass_config))
+                    } else {
+                        None
+                    },
+                    extent: vk::Extent2D::default(),
+                    image: vk::Image::null(),
+                    aliased: false,
+                    view: vk::ImageView::null(),
+                    framebuffer: vk::Framebuffer::null(),
+                }
+            })
+            .collect();
+
+        let input_count = self
+            .description
+            .passes
+            .iter()
+            .map(|pass| pass.inputs.len() as u32)
+            .sum::<u32>();
+        let sampling_passes = self
+            .description
+            .passes
+            .iter()
+            .filter(|pass| !pass.inputs.is_empty())
+            .count() as u32;
+        if sampling_passes > 0 {
+            let pool_sizes = [vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: input_count,
+            }];
+            let pool_info = vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(sampling_passes)
+                .pool_sizes(&pool_sizes);
+            self.descriptor_pool =
+                unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+        }
+        let descriptor_pool = self.descriptor_pool;
+        let description = &self.description;
+        self.passes = description
+            .passes
+            .iter()
+            .map(|desc| {
+                let target = desc
+                    .target
+                    .as_ref()
+                    .map(|target| description.target_index(target));
+                if desc.inputs.is_empty() {
+                    return PassResources {
+                        target,
+                        descriptor_set_layout: None,
+                        descriptor_set: None,
+                        draws: Vec::new(),
+                    };
+                }
+                let bindings = (0..desc.inputs.len() as u32)
+                    .map(|binding| vk::DescriptorSetLayoutBinding {
+                        binding,
+                        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                        descriptor_count: 1,
+                        stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                        ..Default::default()
+                    })
+                    .collect::<Vec<_>>();
+                let descriptor_set_layout = ctx.descriptor_set_layout(&bindings);
+                let set_layouts = [descriptor_set_layout];
+                let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+                    .descriptor_pool(descriptor_pool)
+                    .set_layouts(&set_layouts);
+                let descriptor_set =
+                    unsafe { device.allocate_descriptor_sets(&allocate_info).unwrap()[0] };
+                PassResources {
+                    target,
+                    descriptor_set_layout: Some(descriptor_set_layout),
+                    descriptor_set: Some(descriptor_set),
+                    draws: Vec::new(),
+                }
+            })
+            .collect();
+
+        self.create_targets(ctx);
+        self.write_descriptor_sets(&ctx.base.device);
+        self.create_pipelines(ctx);
+    }
+
+    fn before_render_pass(&self, ctx: &RenderCtx, command_buffer: vk::CommandBuffer) {
+        let device = &ctx.base.device;
+        for (pass, description) in self.passes.iter().zip(self.description.passes.iter()) {
+            let target = match pass.target {
+                Some(target) => &self.targets[target],
+                None => continue,
+            };
+            let area = vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: target.extent,
+            };
+            let viewport = vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: target.extent.width as f32,
+                height: target.extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            };
+            // the first sample clears, the rest blend into the average so far
+            let render_pass = match target.load_render_pass {
+                Some(load_render_pass) if self.samples > 1 => load_render_pass,
+                _ => target.render_pass,
+            };
+            let clear_values = [vk::ClearValue::default()];
+            let begin_info = vk::RenderPassBeginInfo::builder()
+                .render_pass(render_pass)
+                .framebuffer(target.framebuffer)
+                .render_area(area)
+                .clear_values(&clear_values);
+            if target.aliased {
+                // done sampling the targets sharing its memory before clearing it
+                let barrier = MemoryBarrier {
+                    src_stage: PipelineStageFlags2::FRAGMENT_SHADER,
+                    dst_stage: PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                    ..MemoryBarrier::default()
+                };
+                ctx.synchronization2.pipeline_barrier(
+                    device,
+                    command_buffer,
+                    &Dependency {
+                        memory: &[barrier],
+                        ..Dependency::default()
+                    },
+                );
+            }
+            ctx.frame_timer
+                .begin_pass(device, command_buffer, &description.name);
+            if let Some(perf_counters) = &ctx.perf_counters {
+                perf_counters.begin(device, command_buffer, &description.name);
+            }
+            unsafe {
+                device.cmd_begin_render_pass(
+                    command_buffer,
+                    &begin_info,
+                    vk::SubpassContents::INLINE,
+                );
+                if target.load_render_pass.is_some() {
+                    let weight = 1.0 / self.samples.max(1) as f32;
+                    device.cmd_set_blend_constants(command_buffer, &[0.0, 0.0, 0.0, weight]);
+                }
+            }
+            self.record_draws(
+                ctx,
+                command_buffer,
+                pass,
+                target.extent,
+                &[viewport],
+                &[area],
+            );
+            unsafe {
+                device.cmd_end_render_pass(command_buffer);
+            }
+            if let Some(perf_counters) = &ctx.perf_counters {
+                perf_counters.end(device, command_buffer);
+            }
+            ctx.frame_timer.end_pass(device, command_buffer);
+            // later passes sample what this one wrote
+            let barrier = MemoryBarrier {
+                src_stage: PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                src_access: AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                dst_stage: PipelineStageFlags2::FRAGMENT_SHADER,
+                dst_access: AccessFlags2::SHADER_SAMPLED_READ,
+            };
+            ctx.synchronization2.pipeline_barrier(
+                device,
+                command_buffer,
+                &Dependency {
+                    memory: &[barrier],
+                    ..Dependency::default()
+                },
+            );
+        }
+    }
+
+    fn update(&mut self, ctx: &mut RenderCtx) {
+        let view = AccumulatedView::of(ctx);
+        if self.accumulated_view.as_ref() != Some(&view) {
+            self.samples = 0;
+            self.accumulated_view = Some(view);
+        }
+        self.samples = self.samples.saturating_add(1);
+    }
+
+    fn draw(&self, ctx: &RenderCtx, command_buffer: vk::CommandBuffer) {
+        for pass in self.passes.iter().filter(|pass| pass.target.is_none()) {
+            self.record_draws(
+                ctx,
+                command_buffer,
+                pass,
+                ctx.render_extent(),
+                &ctx.viewports,
+                &ctx.scissors,
+            );
+        }
+    }
+
+    fn swapchain_recreated(&mut self, ctx: &mut RenderCtx) {
+        // the device is idle, the swapchain was just recreated
+        self.destroy_targets(&ctx.base.device);
+        self.create_targets(ctx);
+        self.write_descriptor_sets(&ctx.base.device);
+        // the new targets have nothing accumulated
+        self.accumulated_view = None;
+    }
+
+    fn pipelines_rebuilt(&mut self, ctx: &mut RenderCtx) {
+        // the runner waited for the device before destroying its own old pipelines
+        self.destroy_pipelines(&ctx.base.device);
+        self.create_pipelines(ctx);
+        // the shaders may draw something else now
+        self.accumulated_view = None;
+    }
+
+    fn destroy(&mut self, ctx: &mut RenderCtx) {
+        let device = &ctx.base.device;
+        self.destroy_pipelines(device);
+        self.destroy_targets(device);
+        unsafe {
+            for target in self.targets.iter() {
+                device.destroy_render_pass(target.render_pass, None);
+                if let Some(load_render_pass) = target.load_render_pass {
+                    device.destroy_render_pass(load_render_pass, None);
+                }
+            }
+            // frees the sets along with it
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn description(ron: &str) -> SceneDescription {
+        let description: SceneDescription = ron::de::from_str(ron).unwrap();
+        description.validate().unwrap();
+        description
+    }
+
+    fn requirements(size: u64, memory_type_bits: u32) -> vk::MemoryRequirements {
+        vk::MemoryRequirements {
+            size,
+            alignment: 256,
+            memory_type_bits,
+        }
+    }
+
+    fn members(slots: &[(Vec<usize>, vk::MemoryRequirements)]) -> Vec<Vec<usize>> {
+        slots.iter().map(|(members, _)| members.clone()).collect()
+    }
+
+    #[test]
+    fn overlapping_targets_get_their_own_memory() {
+        // "scene" is sampled while "bloom" is drawn, and both by the window pass
+        let description = description(
+            r#"(
+                targets: [(name: "scene"), (name: "bloom")],
+                passes: [
+                    (name: "scene", target: Some("scene"), draws: []),
+                    (name: "bloom", target: Some("bloom"), inputs: ["scene"], draws: []),
+                    (name: "composite", inputs: ["scene", "bloom"], draws: []),
+                ],
+            )"#,
+        );
+        let lifetimes = description.target_lifetimes();
+        assert_eq!(lifetimes, vec![Some((0, 3)), Some((1, 3))]);
+        let slots = assign_slots(&lifetimes, &[requirements(100, 1), requirements(50, 1)]);
+        assert_eq!(members(&slots), vec![vec![0], vec![1]]);
+    }
+
+    #[test]
+    fn disjoint_targets_share_memory() {
+        // a ping-pong chain: "a" is done with once "b" is drawn, so "c" can reuse its memory
+        let description = description(
+            r#"(
+                targets: [(name: "a"), (name: "b"), (name: "c")],
+                passes: [
+                    (name: "a", target: Some("a"), draws: []),
+                    (name: "b", target: Some("b"), inputs: ["a"], draws: []),
+                    (name: "c", target: Some("c"), inputs: ["b"], draws: []),
+                    (name: "present", inputs: ["c"], draws: []),
+                ],
+            )"#,
+        );
+        let lifetimes = description.target_lifetimes();
+        assert_eq!(lifetimes, vec![Some((0, 1)), Some((1, 2)), Some((2, 4))]);
+        let slots = assign_slots(
+            &lifetimes,
+            &[
+                requirements(100, 0b011),
+                requirements(50, 1),
+                requirements(200, 0b110),
+            ],
+        );
+        assert_eq!(members(&slots), vec![vec![0, 2], vec![1]]);
+        // big enough for either, in a memory type both can use
+        assert_eq!(slots[0].1.size, 200);
+        assert_eq!(slots[0].1.memory_type_bits, 0b010);
+
+        // unless they can't agree on a memory type
+        let slots = assign_slots(
+            &lifetimes,
+            &[
+                requirements(100, 0b01),
+                requirements(50, 1),
+                requirements(200, 0b10),
+            ],
+        );
+        assert_eq!(members(&slots), vec![vec![0], vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn window_passes_draw_after_all_others() {
+        // listed before "b" is drawn, the first window pass still draws after it
+        let description = description(
+            r#"(
+                targets: [(name: "a"), (name: "b")],
+                passes: [
+                    (name: "a", target: Some("a"), draws: []),
+                    (name: "show a", inputs: ["a"], draws: []),
+                    (name: "b", target: Some("b"), draws: []),
+                    (name: "show b", inputs: ["b"], draws: []),
+                ],
+            )"#,
+        );
+        let lifetimes = description.target_lifetimes();
+        assert_eq!(lifetimes, vec![Some((0, 4)), Some((2, 4))]);
+        let slots = assign_slots(&lifetimes, &[requirements(100, 1), requirements(100, 1)]);
+        assert_eq!(members(&slots), vec![vec![0], vec![1]]);
+    }
+
+    #[test]
+    fn accumulation_targets_live_all_frame() {
+        // "average" is only drawn by the last pass, but keeps its contents into the next frame
+        let description = description(
+            r#"(
+                targets: [(name: "a"), (name: "b"), (name: "average", accumulate: true)],
+                passes: [
+                    (name: "a", target: Some("a"), draws: []),
+                    (name: "b", target: Some("b"), inputs: ["a"], draws: []),
+                    (name: "average", target: Some("average"), inputs: ["b"], draws: []),
+                ],
+            )"#,
+        );
+        let lifetimes = description.target_lifetimes();
+        assert_eq!(lifetimes, vec![Some((0, 1)), Some((1, 2)), Some((0, 3))]);
+        let slots = assign_slots(
+            &lifetimes,
+            &[
+                requirements(100, 1),
+                requirements(100, 1),
+                requirements(100, 1),
+            ],
+        );
+        assert_eq!(members(&slots), vec![vec![0], vec![1], vec![2]]);
+    }
+}
@@ -0,0 +1,418 @@
+//! Hardware performance counters with `VK_KHR_performance_query`, for `--perf-counters`.  The
+//! counters named there, e.g. "SM Occupancy" or "DRAM Throughput" depending on the driver, are
+//! sampled for the window's render pass and for each `--passes` pass drawing into a target, and
+//! their averages printed every few seconds.  `--perf-counters list` prints the counters the device
+//! offers instead.
+//!
+//! Counters that can only cover a whole command buffer aren't supported, and neither are
+//! selections the device can't sample in a single pass, as each frame is submitted once.  Results
+//! are read like the frame timer's timestamps, from one of two query pools in turn.
+
+use crate::ash_runner::RenderBase;
+use crate::device_requirements::DeviceRequirements;
+
+use ash::{
+    version::{DeviceV1_0, EntryV1_0, InstanceV1_0},
+    vk,
+};
+
+use std::{
+    cell::RefCell,
+    ffi::CStr,
+    mem, ptr,
+    time::{Duration, Instant},
+};
+
+/// Scopes sampled per frame at most.
+const MAX_SCOPES: u32 = 16;
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+pub fn extension_name() -> &'static CStr {
+    vk::KhrPerformanceQueryFn::name()
+}
+
+/// Only devices with performance queries are used.
+pub fn device_requirements(requirements: &mut DeviceRequirements) {
+    requirements
+        .require_extension(extension_name())
+        .chain_features(
+            vk::PhysicalDevicePerformanceQueryFeaturesKHR::builder()
+                .performance_counter_query_pools(true)
+                .build(),
+        );
+}
+
+struct Counter {
+    name: String,
+    unit: vk::PerformanceCounterUnitKHR,
+    scope: vk::PerformanceCounterScopeKHR,
+    storage: vk::PerformanceCounterStorageKHR,
+}
+
+impl Counter {
+    fn value(&self, result: &vk::PerformanceCounterResultKHR) -> f64 {
+        unsafe {
+            match self.storage {
+                vk::PerformanceCounterStorageKHR::INT32 => f64::from(result.int32),
+                vk::PerformanceCounterStorageKHR::INT64 => result.int64 as f64,
+                vk::PerformanceCounterStorageKHR::UINT32 => f64::from(result.uint32),
+                vk::PerformanceCounterStorageKHR::UINT64 => result.uint64 as f64,
+                vk::PerformanceCounterStorageKHR::FLOAT32 => f64::from(result.float32),
+                _ => result.float64,
+            }
+        }
+    }
+}
+
+fn unit_suffix(unit: vk::PerformanceCounterUnitKHR) -> &'static str {
+    match unit {
+        vk::PerformanceCounterUnitKHR::PERCENTAGE => " %",
+        vk::PerformanceCounterUnitKHR::NANOSECONDS => " ns",
+        vk::PerformanceCounterUnitKHR::BYTES => " B",
+        vk::PerformanceCounterUnitKHR::BYTES_PER_SECOND => " B/s",
+        vk::PerformanceCounterUnitKHR::KELVIN => " K",
+        vk::PerformanceCounterUnitKHR::WATTS => " W",
+        vk::PerformanceCounterUnitKHR::VOLTS => " V",
+        vk::PerformanceCounterUnitKHR::AMPS => " A",
+        vk::PerformanceCounterUnitKHR::HERTZ => " Hz",
+        vk::PerformanceCounterUnitKHR::CYCLES => " cycles",
+        _ => "",
+    }
+}
+
+/// The sums of a scope's counters over the frames since the last report.
+struct ScopeTotals {
+    label: String,
+    sums: Vec<f64>,
+    frames: u32,
+}
+
+pub struct PerfCounters {
+    fns: vk::KhrPerformanceQueryFn,
+    counters: Vec<Counter>,
+    query_pools: [vk::QueryPool; 2],
+    /// Resets `query_pools[i]`, submitted before each frame using it.
+    reset_pool: vk::CommandPool,
+    reset_command_buffers: Vec<vk::CommandBuffer>,
+    /// Frames recorded so far, the current one writing `query_pools[frame % 2]`.
+    frame: usize,
+    /// The labels of the scopes begun in the frames writing `query_pools[i]`.
+    scopes: RefCell<[Vec<String>; 2]>,
+    totals: Vec<ScopeTotals>,
+    last_report: Instant,
+}
+
+impl PerfCounters {
+    /// `names` is the comma separated list from `--perf-counters`.  `None` after printing why if
+    /// the counters can't be sampled, or after listing them for "list".
+    pub fn new(base: &RenderBase, names: &str) -> Option<Self> {
+        let fns = vk::KhrPerformanceQueryFn::load(|name| unsafe {
+            mem::transmute(
+                base.entry
+                    .get_instance_proc_addr(base.instance.handle(), name.as_ptr()),
+            )
+        });
+        let available = available_counters(&fns, base.pdevice, base.queue_family_index);
+        if names == "list" {
+            println!("performance counters:");
+            for (counter, description) in available.iter() {
+                println!("  {}: {}", counter.name, description);
+            }
+            return None;
+        }
+
+        let mut counters = Vec::new();
+        let mut indices = Vec::new();
+        for name in names.split(',').map(str::trim) {
+            let index = match available
+                .iter()
+                .position(|(counter, _)| counter.name == name)
+            {
+                Some(index) => index,
+                None => {
+                    eprintln!(
+                        "unknown performance counter {:?}, see --perf-counters list",
+                        name
+                    );
+                    return None;
+                }
+            };
+            let (counter, _) = &available[index];
+            if counter.scope == vk::PerformanceCounterScopeKHR::COMMAND_BUFFER {
+                eprintln!(
+                    "performance counter {:?} only covers whole command buffers, it can't be \
+                     sampled per pass",
+                    name
+                );
+                return None;
+            }
+            counters.push(Counter {
+                name: counter.name.clone(),
+                unit: counter.unit,
+                scope: counter.scope,
+                storage: counter.storage,
+            });
+            indices.push(index as u32);
+        }
+
+        let performance_info = vk::QueryPoolPerformanceCreateInfoKHR::builder()
+            .queue_family_index(base.queue_family_index)
+            .counter_indices(&indices);
+        let mut passes = 0;
+        (fns.get_physical_device_queue_family_performance_query_passes_khr)(
+            base.pdevice,
+            &*performance_info,
+            &mut passes,
+        );
+        if passes != 1 {
+            eprintln!(
+                "the device needs {} passes to sample these performance counters, select fewer",
+                passes
+            );
+            return None;
+        }
+
+        let device = &base.device;
+        let lock_info = vk::AcquireProfilingLockInfoKHR::builder().timeout(std::u64::MAX);
+        let result = (fns.acquire_profiling_lock_khr)(device.handle(), &*lock_info);
+        if result != vk::Result::SUCCESS {
+            eprintln!("failed to acquire the profiling lock: {:?}", result);
+            return None;
+        }
+
+        let mut performance_info = performance_info.build();
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::PERFORMANCE_QUERY_KHR)
+            .query_count(MAX_SCOPES)
+            .push_next(&mut performance_info);
+        let query_pools = unsafe {
+            [
+                device.create_query_pool(&create_info, None).unwrap(),
+                device.create_query_pool(&create_info, None).unwrap(),
+            ]
+        };
+
+        // performance queries can't be reset in the command buffer using them
+        let pool_info =
+            vk::CommandPoolCreateInfo::builder().queue_family_index(base.queue_family_index);
+        let reset_pool = unsafe { device.create_command_pool(&pool_info, None).unwrap() };
+        let allocate_info = vk::CommandBufferAllocateInfo::builder()
+            .command_buffer_count(2)
+            .command_pool(reset_pool)
+            .level(vk::CommandBufferLevel::PRIMARY);
+        let reset_command_buffers =
+            unsafe { device.allocate_command_buffers(&allocate_info) }.unwrap();
+        for (&command_buffer, &query_pool) in reset_command_buffers.iter().zip(query_pools.iter()) {
+            unsafe {
+                device
+                    .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())
+                    .expect("Begin commandbuffer");
+                device.cmd_reset_query_pool(command_buffer, query_pool, 0, MAX_SCOPES);
+                device
+                    .end_command_buffer(command_buffer)
+                    .expect("End commandbuffer");
+            }
+        }
+
+        println!(
+            "sampling performance counters: {}",
+            counters
+                .iter()
+                .map(|counter| counter.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        Some(Self {
+            fns,
+            counters,
+            query_pools,
+            reset_pool,
+            reset_command_buffers,
+            frame: 0,
+            scopes: RefCell::new([Vec::new(), Vec::new()]),
+            totals: Vec::new(),
+            last_report: Instant::now(),
+        })
+    }
+
+    /// Before recording the frame's command buffer, while the frame two before it has completed.
+    pub fn frame_start(&mut self, base: &RenderBase) {
+        let index = self.frame % 2;
+        self.scopes.borrow_mut()[index].clear();
+        let command_buffers = [self.reset_command_buffers[index]];
+        let submit_info = vk::SubmitInfo::builder().command_buffers(&command_buffers);
+        unsafe {
+            base.device
+                .queue_submit(
+                    base.present_queue,
+                    &[submit_info.build()],
+                    vk::Fence::null(),
+                )
+                .expect("queue submit failed.");
+        }
+    }
+
+    /// Starts sampling a scope, outside a render pass.  Scopes can't nest.
+    pub fn begin(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, label: &str) {
+        let mut scopes = self.scopes.borrow_mut();
+        let scopes = &mut scopes[self.frame % 2];
+        if scopes.len() as u32 == MAX_SCOPES {
+            return;
+        }
+        scopes.push(label.to_owned());
+        unsafe {
+            device.cmd_begin_query(
+                command_buffer,
+                self.query_pools[self.frame % 2],
+                scopes.len() as u32 - 1,
+                vk::QueryControlFlags::empty(),
+            );
+        }
+    }
+
+    /// Ends the scope begun last, outside a render pass.
+    pub fn end(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        let scopes = self.scopes.borrow();
+        let query = match scopes[self.frame % 2].len() {
+            0 => return,
+            len => len as u32 - 1,
+        };
+        unsafe { device.cmd_end_query(command_buffer, self.query_pools[self.frame % 2], query) };
+    }
+
+    /// After the frame was submitted, reading the results of the one before it.
+    pub fn frame_submitted(&mut self, device: &ash::Device) {
+        if self.frame > 0 {
+            let index = (self.frame - 1) % 2;
+            let labels = mem::take(&mut self.scopes.borrow_mut()[index]);
+            if !labels.is_empty() {
+                let stride =
+                    self.counters.len() * mem::size_of::<vk::PerformanceCounterResultKHR>();
+                let mut results = vec![
+                    vk::PerformanceCounterResultKHR::default();
+                    labels.len() * self.counters.len()
+                ];
+                let result = unsafe {
+                    device.fp_v1_0().get_query_pool_results(
+                        device.handle(),
+                        self.query_pools[index],
+                        0,
+                        labels.len() as u32,
+                        results.len() * mem::size_of::<vk::PerformanceCounterResultKHR>(),
+                        results.as_mut_ptr() as *mut _,
+                        stride as vk::DeviceSize,
+                        vk::QueryResultFlags::WAIT,
+                    )
+                };
+                if result == vk::Result::SUCCESS {
+                    for (label, results) in
+                        labels.into_iter().zip(results.chunks(self.counters.len()))
+                    {
+                        self.add(label, results);
+                    }
+                }
+            }
+        }
+        self.frame += 1;
+        if self.last_report.elapsed() >= REPORT_INTERVAL {
+            self.report();
+        }
+    }
+
+    fn add(&mut self, label: String, results: &[vk::PerformanceCounterResultKHR]) {
+        let position = self.totals.iter().position(|totals| totals.label == label);
+        let totals = match position {
+            Some(position) => &mut self.totals[position],
+            None => {
+                self.totals.push(ScopeTotals {
+                    label,
+                    sums: vec![0.0; self.counters.len()],
+                    frames: 0,
+                });
+                self.totals.last_mut().unwrap()
+            }
+        };
+        for ((sum, counter), result) in totals
+            .sums
+            .iter_mut()
+            .zip(self.counters.iter())
+            .zip(results)
+        {
+            *sum += counter.value(result);
+        }
+        totals.frames += 1;
+    }
+
+    fn report(&mut self) {
+        for totals in self.totals.iter() {
+            println!("{} over {} frames:", totals.label, totals.frames);
+            for (counter, sum) in self.counters.iter().zip(totals.sums.iter()) {
+                println!(
+                    "  {}: {:.2}{}",
+                    counter.name,
+                    sum / f64::from(totals.frames.max(1)),
+                    unit_suffix(counter.unit)
+                );
+            }
+        }
+        self.totals.clear();
+        self.last_report = Instant::now();
+    }
+
+    /// The device must be idle.
+    pub fn destroy(&mut self, device: &ash::Device) {
+        unsafe {
+            // frees the command buffers along with it
+            device.destroy_command_pool(self.reset_pool, None);
+            for &query_pool in self.query_pools.iter() {
+                device.destroy_query_pool(query_pool, None);
+            }
+        }
+        (self.fns.release_profiling_lock_khr)(device.handle());
+    }
+}
+
+/// The queue family's counters with their descriptions.
+fn available_counters(
+    fns: &vk::KhrPerformanceQueryFn,
+    pdevice: vk::PhysicalDevice,
+    queue_family_index: u32,
+) -> Vec<(Counter, String)> {
+    let mut count = 0;
+    (fns.enumerate_physical_device_queue_family_performance_query_counters_khr)(
+        pdevice,
+        queue_family_index,
+        &mut count,
+        ptr::null_mut(),
+        ptr::null_mut(),
+    );
+    let mut counters = vec![vk::PerformanceCounterKHR::default(); count as usize];
+    let mut descriptions = vec![vk::PerformanceCounterDescriptionKHR::default(); count as usize];
+    (fns.enumerate_physical_device_queue_family_performance_query_counters_khr)(
+        pdevice,
+        queue_family_index,
+        &mut count,
+        counters.as_mut_ptr(),
+        descriptions.as_mut_ptr(),
+    );
+    counters
+        .iter()
+        .zip(descriptions.iter())
+        .map(|(counter, description)| {
+            let string = |chars: &[std::os::raw::c_char]| {
+                unsafe { CStr::from_ptr(chars.as_ptr()) }
+                    .to_string_lossy()
+                    .into_owned()
+            };
+            (
+                Counter {
+                    name: string(&description.name),
+                    unit: counter.unit,
+                    scope: counter.scope,
+                    storage: counter.storage,
+                },
+                string(&description.description),
+            )
+        })
+        .collect()
+}
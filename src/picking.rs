@@ -0,0 +1,50 @@
+//! Reading back what's under the cursor, for click-to-inspect in scene demos.  A plugin requests a
+//! pick, e.g. on a click, and gets the pixel in `Plugin::picked` once the next frame is rendered:
+//!
+//! ```ignore
+//! fn mouse_button(&mut self, ctx: &mut RenderCtx, button: MouseButton, pressed: bool) {
+//!     if button == MouseButton::Left && pressed {
+//!         // an R32_UINT attachment the scene's fragment shaders write object IDs to
+//!         ctx.request_pick(PickTarget::Attachment(0));
+//!     }
+//! }
+//!
+//! fn picked(&mut self, _ctx: &mut RenderCtx, pick: &Pick) {
+//!     println!("object {:?} at {:?}", pick.id(), pick.position);
+//! }
+//! ```
+//!
+//! Picks go through `RenderCtx::read_pixels` and `read_attachment`, which wait for the device, so
+//! they are meant for clicks rather than every frame.
+
+use std::convert::TryInto;
+
+/// What a pick reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PickTarget {
+    /// The window's image, as RGBA8 with the swapchain's transfer function.
+    Color,
+    /// The extra color attachment at this index in `RenderCtx::render_pass_config`, e.g. of object
+    /// IDs, in its own format.
+    Attachment(usize),
+}
+
+/// The pixel under the cursor, passed to `Plugin::picked`.
+#[derive(Debug, Clone)]
+pub struct Pick {
+    pub target: PickTarget,
+    /// In physical pixels from the window's top left corner.
+    pub position: [u32; 2],
+    /// The texel's bytes: RGBA8 for `PickTarget::Color`, as stored in the attachment's format
+    /// otherwise.
+    pub texel: Vec<u8>,
+}
+
+impl Pick {
+    /// The first four bytes of the texel as a native endian `u32`, e.g. of an `R32_UINT` ID
+    /// attachment.  `None` for smaller texels.
+    pub fn id(&self) -> Option<u32> {
+        let bytes = self.texel.get(..4)?;
+        Some(u32::from_ne_bytes(bytes.try_into().unwrap()))
+    }
+}
@@ -0,0 +1,211 @@
+//! Faster hot reloading with `VK_EXT_graphics_pipeline_library`.  The vertex input part of the
+//! pipelines is compiled once, so a reload only compiles the shader stages and the cheap fragment
+//! output state, and links them with it.  ash 0.31 predates the extension, so the few definitions
+//! it needs are declared here.
+
+use crate::ash_runner::PipelineDescriptor;
+
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0, InstanceV1_1},
+    vk,
+};
+
+use std::{ffi::CStr, os::raw::c_void, ptr};
+
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_GRAPHICS_PIPELINE_LIBRARY_FEATURES: vk::StructureType =
+    vk::StructureType::from_raw(1_000_320_000);
+const STRUCTURE_TYPE_GRAPHICS_PIPELINE_LIBRARY_CREATE_INFO: vk::StructureType =
+    vk::StructureType::from_raw(1_000_320_002);
+
+// VkGraphicsPipelineLibraryFlagBitsEXT
+const VERTEX_INPUT_INTERFACE: u32 = 0x1;
+const PRE_RASTERIZATION_SHADERS: u32 = 0x2;
+const FRAGMENT_SHADER: u32 = 0x4;
+const FRAGMENT_OUTPUT_INTERFACE: u32 = 0x8;
+
+#[repr(C)]
+pub struct PhysicalDeviceGraphicsPipelineLibraryFeatures {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    graphics_pipeline_library: vk::Bool32,
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDeviceGraphicsPipelineLibraryFeatures {}
+
+impl PhysicalDeviceGraphicsPipelineLibraryFeatures {
+    /// The features to chain into the device create info to enable pipeline libraries.
+    pub fn enabled() -> Self {
+        Self {
+            s_type: STRUCTURE_TYPE_PHYSICAL_DEVICE_GRAPHICS_PIPELINE_LIBRARY_FEATURES,
+            p_next: ptr::null_mut(),
+            graphics_pipeline_library: vk::TRUE,
+        }
+    }
+}
+
+#[repr(C)]
+struct GraphicsPipelineLibraryCreateInfo {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    flags: u32,
+}
+
+pub fn extension_names() -> [&'static CStr; 2] {
+    [vk::KhrPipelineLibraryFn::name(), unsafe {
+        CStr::from_bytes_with_nul_unchecked(b"VK_EXT_graphics_pipeline_library\0")
+    }]
+}
+
+pub fn is_supported(instance: &ash::Instance, pdevice: vk::PhysicalDevice) -> bool {
+    let available = unsafe {
+        instance
+            .enumerate_device_extension_properties(pdevice)
+            .unwrap()
+    };
+    let has_extensions = extension_names().iter().all(|&name| {
+        available
+            .iter()
+            .any(|ext| unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) } == name)
+    });
+    if !has_extensions {
+        return false;
+    }
+    let mut library_features = PhysicalDeviceGraphicsPipelineLibraryFeatures {
+        graphics_pipeline_library: vk::FALSE,
+        ..PhysicalDeviceGraphicsPipelineLibraryFeatures::enabled()
+    };
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut library_features as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+    unsafe { instance.get_physical_device_features2(pdevice, &mut features) };
+    library_features.graphics_pipeline_library == vk::TRUE
+}
+
+/// The precompiled parts shared by all pipelines rendering into one render pass.
+pub struct PipelineLibraries {
+    vertex_input: vk::Pipeline,
+}
+
+impl PipelineLibraries {
+    pub fn new(device: &ash::Device) -> Self {
+        let desc = PipelineDescriptor::new(Box::new([]));
+        let vertex_input = create_library(
+            device,
+            VERTEX_INPUT_INTERFACE,
+            vk::GraphicsPipelineCreateInfo::builder()
+                .vertex_input_state(&desc.vertex_input)
+                .input_assembly_state(&desc.input_assembly)
+                .dynamic_state(&desc.dynamic_state_info)
+                .build(),
+        );
+        Self { vertex_input }
+    }
+
+    /// Compiles the vertex and fragment stages and the blend state of each descriptor into
+    /// libraries and links them with the shared parts, using the layout at the same index.  Linking
+    /// skips link time optimization, trading a bit of GPU time for a faster reload.
+    pub fn link(
+        &self,
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        descs: &[PipelineDescriptor],
+        layouts: &[vk::PipelineLayout],
+    ) -> Vec<vk::Pipeline> {
+        let viewport = vk::PipelineViewportStateCreateInfo::builder();
+        descs
+            .iter()
+            .zip(layouts.iter())
+            .map(|(desc, &layout)| {
+                let pre_rasterization = create_library(
+                    device,
+                    PRE_RASTERIZATION_SHADERS,
+                    vk::GraphicsPipelineCreateInfo::builder()
+                        .stages(&desc.shader_stages[..1])
+                        .viewport_state(&viewport)
+                        .rasterization_state(&desc.rasterization)
+                        .dynamic_state(&desc.dynamic_state_info)
+                        .layout(layout)
+                        .render_pass(render_pass)
+                        .build(),
+                );
+                let fragment_shader = create_library(
+                    device,
+                    FRAGMENT_SHADER,
+                    vk::GraphicsPipelineCreateInfo::builder()
+                        .stages(&desc.shader_stages[1..])
+                        .depth_stencil_state(&desc.depth_stencil)
+                        .multisample_state(&desc.multisample)
+                        .dynamic_state(&desc.dynamic_state_info)
+                        .layout(layout)
+                        .render_pass(render_pass)
+                        .build(),
+                );
+
+                // blending differs between pipelines
+                let fragment_output = create_library(
+                    device,
+                    FRAGMENT_OUTPUT_INTERFACE,
+                    vk::GraphicsPipelineCreateInfo::builder()
+                        .color_blend_state(&desc.color_blend)
+                        .multisample_state(&desc.multisample)
+                        .dynamic_state(&desc.dynamic_state_info)
+                        .render_pass(render_pass)
+                        .build(),
+                );
+
+                let libraries = [
+                    self.vertex_input,
+                    pre_rasterization,
+                    fragment_shader,
+                    fragment_output,
+                ];
+                let library_info =
+                    vk::PipelineLibraryCreateInfoKHR::builder().libraries(&libraries);
+                let mut pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+                    .layout(layout)
+                    .build();
+                pipeline_info.p_next = &*library_info as *const _ as *const c_void;
+                unsafe {
+                    let pipeline = device
+                        .create_graphics_pipelines(
+                            vk::PipelineCache::null(),
+                            &[pipeline_info],
+                            None,
+                        )
+                        .expect("Unable to link graphics pipeline")[0];
+                    // the linked pipeline doesn't depend on the libraries it was made from
+                    device.destroy_pipeline(pre_rasterization, None);
+                    device.destroy_pipeline(fragment_shader, None);
+                    device.destroy_pipeline(fragment_output, None);
+                    pipeline
+                }
+            })
+            .collect()
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.vertex_input, None);
+        }
+    }
+}
+
+fn create_library(
+    device: &ash::Device,
+    library_flags: u32,
+    mut pipeline_info: vk::GraphicsPipelineCreateInfo,
+) -> vk::Pipeline {
+    let library_info = GraphicsPipelineLibraryCreateInfo {
+        s_type: STRUCTURE_TYPE_GRAPHICS_PIPELINE_LIBRARY_CREATE_INFO,
+        p_next: pipeline_info.p_next as *mut c_void,
+        flags: library_flags,
+    };
+    pipeline_info.p_next = &library_info as *const _ as *const c_void;
+    pipeline_info.flags |= vk::PipelineCreateFlags::LIBRARY_KHR;
+    unsafe {
+        device
+            .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+            .expect("Unable to create pipeline library")[0]
+    }
+}
@@ -0,0 +1,232 @@
+//! The pipelines to draw, by name.  Handles stay valid while pipelines are added, removed,
+//! reordered and rebuilt, so user code can keep one to toggle or configure a specific pipeline.
+
+use crate::ash_runner::{FragmentShaderEntryPoint, Pipeline, VertexShaderEntryPoint};
+
+use ash::vk;
+use serde::Deserialize;
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PipelineHandle(usize);
+
+pub struct PipelineEntry {
+    pub name: String,
+    pub vertex: VertexShaderEntryPoint,
+    pub fragment: FragmentShaderEntryPoint,
+    /// Disabled pipelines are still rebuilt on reload, so enabling one again doesn't hitch.
+    pub enabled: bool,
+    /// Changes take effect on the next rebuild.
+    pub config: PipelineConfig,
+    /// `None` until the pipelines are rebuilt after adding the entry.
+    pub pipeline: Option<Pipeline>,
+    /// The pipeline before the last rebuild, kept for comparing against.
+    pub previous: Option<Pipeline>,
+}
+
+/// Fixed function state of a pipeline, applied with `PipelineDescriptorBuilder::config`.  The
+/// defaults suit fullscreen passes; 3D content will want back face culling and maybe a depth bias.
+#[derive(Debug, Clone, Copy)]
+pub struct PipelineConfig {
+    pub cull_mode: vk::CullModeFlags,
+    pub front_face: vk::FrontFace,
+    pub depth_bias: Option<DepthBias>,
+    pub blend: BlendMode,
+}
+
+/// A non-zero `clamp` needs the `depthBiasClamp` feature, which isn't enabled.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DepthBias {
+    pub constant_factor: f32,
+    pub clamp: f32,
+    pub slope_factor: f32,
+}
+
+/// How a pipeline's output is combined with what is already in the color attachment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendMode {
+    /// Overwrites the destination.
+    Opaque,
+    /// Straight alpha: `src * src.a + dst * (1 - src.a)`.
+    AlphaBlend,
+    /// `src * src.a + dst`, for glows and particles.
+    Additive,
+    /// For colors already multiplied by alpha: `src + dst * (1 - src.a)`.
+    Premultiplied,
+}
+
+impl BlendMode {
+    pub fn attachment_state(self) -> vk::PipelineColorBlendAttachmentState {
+        let (src_color, dst_color, src_alpha, dst_alpha) = match self {
+            BlendMode::Opaque => {
+                return vk::PipelineColorBlendAttachmentState {
+                    blend_enable: vk::FALSE,
+                    color_write_mask: vk::ColorComponentFlags::all(),
+                    ..Default::default()
+                }
+            }
+            BlendMode::AlphaBlend => (
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ),
+            BlendMode::Additive => (
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE,
+            ),
+            BlendMode::Premultiplied => (
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                vk::BlendFactor::ONE,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ),
+        };
+        vk::PipelineColorBlendAttachmentState {
+            blend_enable: vk::TRUE,
+            src_color_blend_factor: src_color,
+            dst_color_blend_factor: dst_color,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: src_alpha,
+            dst_alpha_blend_factor: dst_alpha,
+            alpha_blend_op: vk::BlendOp::ADD,
+            color_write_mask: vk::ColorComponentFlags::all(),
+        }
+    }
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            depth_bias: None,
+            blend: BlendMode::Opaque,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PipelineRegistry {
+    /// Indexed by handle.  Removed entries leave a hole so the other handles stay valid.
+    entries: Vec<Option<PipelineEntry>>,
+    names: HashMap<String, PipelineHandle>,
+    /// Draw order.
+    order: Vec<PipelineHandle>,
+}
+
+impl PipelineRegistry {
+    /// Adds a pipeline drawn after all others, or changes the shaders of the one with this name,
+    /// keeping its handle, place, configuration and built pipeline.  Takes effect on the next
+    /// rebuild.
+    pub fn insert(
+        &mut self,
+        name: impl Into<String>,
+        vertex: VertexShaderEntryPoint,
+        fragment: FragmentShaderEntryPoint,
+    ) -> PipelineHandle {
+        let name = name.into();
+        if let Some(&handle) = self.names.get(&name) {
+            let entry = self.get_mut(handle).unwrap();
+            entry.vertex = vertex;
+            entry.fragment = fragment;
+            return handle;
+        }
+        let handle = PipelineHandle(self.entries.len());
+        self.entries.push(Some(PipelineEntry {
+            name: name.clone(),
+            vertex,
+            fragment,
+            enabled: true,
+            config: PipelineConfig::default(),
+            pipeline: None,
+            previous: None,
+        }));
+        self.names.insert(name, handle);
+        self.order.push(handle);
+        handle
+    }
+
+    /// Removes the pipeline, leaving destroying it to the caller.
+    pub fn remove(&mut self, handle: PipelineHandle) -> Option<PipelineEntry> {
+        let entry = self.entries.get_mut(handle.0)?.take()?;
+        self.names.remove(&entry.name);
+        self.order.retain(|&other| other != handle);
+        Some(entry)
+    }
+
+    pub fn handle(&self, name: &str) -> Option<PipelineHandle> {
+        self.names.get(name).copied()
+    }
+
+    pub fn get(&self, handle: PipelineHandle) -> Option<&PipelineEntry> {
+        self.entries.get(handle.0)?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, handle: PipelineHandle) -> Option<&mut PipelineEntry> {
+        self.entries.get_mut(handle.0)?.as_mut()
+    }
+
+    pub fn set_enabled(&mut self, handle: PipelineHandle, enabled: bool) {
+        if let Some(entry) = self.get_mut(handle) {
+            entry.enabled = enabled;
+        }
+    }
+
+    /// Moves the pipeline to `index` in draw order, or to the end if `index` is out of range.
+    pub fn move_to(&mut self, handle: PipelineHandle, index: usize) {
+        if let Some(current) = self.order.iter().position(|&other| other == handle) {
+            self.order.remove(current);
+            let index = index.min(self.order.len());
+            self.order.insert(index, handle);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// All entries, in draw order.
+    pub fn entries(&self) -> impl Iterator<Item = &PipelineEntry> {
+        self.order
+            .iter()
+            .map(move |&handle| self.get(handle).unwrap())
+    }
+
+    pub(crate) fn entries_mut(&mut self) -> impl Iterator<Item = &mut PipelineEntry> {
+        // order holds each live handle exactly once
+        let mut entries = self
+            .entries
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, entry)| entry.as_mut().map(|entry| (index, entry)))
+            .collect::<HashMap<_, _>>();
+        self.order
+            .iter()
+            .map(|handle| entries.remove(&handle.0).unwrap())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// The built pipelines of the enabled entries, in draw order.
+    pub fn enabled(&self) -> impl Iterator<Item = &Pipeline> {
+        self.entries()
+            .filter(|entry| entry.enabled)
+            .filter_map(|entry| entry.pipeline.as_ref())
+    }
+
+    /// Like `enabled`, but the pipelines from before the last rebuild where there are any.
+    pub fn enabled_previous(&self) -> impl Iterator<Item = &Pipeline> {
+        self.entries()
+            .filter(|entry| entry.enabled)
+            .filter_map(|entry| entry.previous.as_ref().or(entry.pipeline.as_ref()))
+    }
+}
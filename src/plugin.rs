@@ -0,0 +1,71 @@
+//! Extension points for crates hosting their own passes in the runner.  Plugins are compiled in and
+//! handed to `ash_runner::main_with_plugins`; they live on the render thread inside the
+//! `RenderCtx`, and every hook gets access to it.
+
+use crate::ash_runner::RenderCtx;
+use crate::device_requirements::DeviceRequirements;
+use crate::picking::Pick;
+
+use ash::vk;
+use winit::event::{MouseButton, VirtualKeyCode};
+
+/// Hooks into the runner's frame.  All of them default to doing nothing.
+pub trait Plugin: Send {
+    fn name(&self) -> &str;
+
+    /// Called before the device is created, to declare the device features and extensions the
+    /// plugin or its shaders need.  Not applied to the device of an OpenXR runtime.
+    fn device_requirements(&self, _requirements: &mut DeviceRequirements) {}
+
+    /// Called when the plugin is added, e.g. to create resources.  Pipelines added to
+    /// `ctx.pipelines` are built on the next rebuild, which `main_with_plugins` does right after
+    /// adding its plugins.
+    fn init(&mut self, _ctx: &mut RenderCtx) {}
+
+    /// Called before each frame is rendered, for per-frame logic like updating uniforms.
+    fn update(&mut self, _ctx: &mut RenderCtx) {}
+
+    /// Records commands before the window's render pass begins, e.g. passes rendering to offscreen
+    /// targets.
+    fn before_render_pass(&self, _ctx: &RenderCtx, _command_buffer: vk::CommandBuffer) {}
+
+    /// Records commands into the window's render pass, after the runner's pipelines.  Not called
+    /// for captures or the XR views, which use render passes of their own.
+    fn draw(&self, _ctx: &RenderCtx, _command_buffer: vk::CommandBuffer) {}
+
+    /// Records commands after the render pass has ended, with the swapchain image about to be
+    /// presented in `PRESENT_SRC_KHR` layout.
+    fn after_render_pass(
+        &self,
+        _ctx: &RenderCtx,
+        _command_buffer: vk::CommandBuffer,
+        _image: vk::Image,
+    ) {
+    }
+
+    /// Called on presses and releases of keys the runner doesn't use itself.
+    fn key(&mut self, _ctx: &mut RenderCtx, _key: VirtualKeyCode, _pressed: bool) {}
+
+    /// Called on presses and releases of mouse buttons over the window.
+    fn mouse_button(&mut self, _ctx: &mut RenderCtx, _button: MouseButton, _pressed: bool) {}
+
+    /// Called when the cursor moves over the window, with its position in physical pixels from the
+    /// window's top left corner.
+    fn cursor_moved(&mut self, _ctx: &mut RenderCtx, _position: [f64; 2]) {}
+
+    /// Called on scrolling, in lines, positive away from the user.
+    fn mouse_wheel(&mut self, _ctx: &mut RenderCtx, _delta: f32) {}
+
+    /// Called with the pixel under the cursor after a frame in which `RenderCtx::request_pick` was
+    /// called, by any plugin.
+    fn picked(&mut self, _ctx: &mut RenderCtx, _pick: &Pick) {}
+
+    /// Called after the swapchain, its render pass and framebuffers were recreated, e.g. on resize.
+    fn swapchain_recreated(&mut self, _ctx: &mut RenderCtx) {}
+
+    /// Called after the pipelines were rebuilt, e.g. on a shader reload.
+    fn pipelines_rebuilt(&mut self, _ctx: &mut RenderCtx) {}
+
+    /// Destroys the plugin's Vulkan objects.  The device is idle.
+    fn destroy(&mut self, _ctx: &mut RenderCtx) {}
+}
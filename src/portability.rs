@@ -0,0 +1,96 @@
+//! `VK_KHR_portability_subset`, which implementations layered on other APIs like MoltenVK expose
+//! to tell which parts of Vulkan they can't do.  A device with the extension must have it enabled,
+//! and using what its features report as missing is undefined behavior, so pipelines are restricted
+//! to the rest by `RenderBase::restrict_pipeline`.  ash 0.31 predates the extension, so its
+//! definitions are declared here.
+
+use ash::{
+    version::{InstanceV1_0, InstanceV1_1},
+    vk,
+};
+
+use std::{ffi::CStr, os::raw::c_void, ptr};
+
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_PORTABILITY_SUBSET_FEATURES: vk::StructureType =
+    vk::StructureType::from_raw(1_000_163_000);
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PhysicalDevicePortabilitySubsetFeatures {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    pub constant_alpha_color_blend_factors: vk::Bool32,
+    pub events: vk::Bool32,
+    pub image_view_format_reinterpretation: vk::Bool32,
+    pub image_view_format_swizzle: vk::Bool32,
+    pub image_view2_d_on3_d_image: vk::Bool32,
+    pub multisample_array_image: vk::Bool32,
+    pub mutable_comparison_samplers: vk::Bool32,
+    pub point_polygons: vk::Bool32,
+    pub sampler_mip_lod_bias: vk::Bool32,
+    pub separate_stencil_mask_ref: vk::Bool32,
+    pub shader_sample_rate_interpolation_functions: vk::Bool32,
+    pub tessellation_isolines: vk::Bool32,
+    pub tessellation_point_mode: vk::Bool32,
+    pub triangle_fans: vk::Bool32,
+    pub vertex_attribute_access_beyond_stride: vk::Bool32,
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDevicePortabilitySubsetFeatures {}
+// `query` leaves `p_next` null
+unsafe impl Send for PhysicalDevicePortabilitySubsetFeatures {}
+unsafe impl Sync for PhysicalDevicePortabilitySubsetFeatures {}
+
+impl Default for PhysicalDevicePortabilitySubsetFeatures {
+    fn default() -> Self {
+        Self {
+            s_type: STRUCTURE_TYPE_PHYSICAL_DEVICE_PORTABILITY_SUBSET_FEATURES,
+            p_next: ptr::null_mut(),
+            constant_alpha_color_blend_factors: vk::FALSE,
+            events: vk::FALSE,
+            image_view_format_reinterpretation: vk::FALSE,
+            image_view_format_swizzle: vk::FALSE,
+            image_view2_d_on3_d_image: vk::FALSE,
+            multisample_array_image: vk::FALSE,
+            mutable_comparison_samplers: vk::FALSE,
+            point_polygons: vk::FALSE,
+            sampler_mip_lod_bias: vk::FALSE,
+            separate_stencil_mask_ref: vk::FALSE,
+            shader_sample_rate_interpolation_functions: vk::FALSE,
+            tessellation_isolines: vk::FALSE,
+            tessellation_point_mode: vk::FALSE,
+            triangle_fans: vk::FALSE,
+            vertex_attribute_access_beyond_stride: vk::FALSE,
+        }
+    }
+}
+
+pub fn extension_name() -> &'static CStr {
+    unsafe { CStr::from_bytes_with_nul_unchecked(b"VK_KHR_portability_subset\0") }
+}
+
+/// The features of the subset `pdevice` implements, or `None` if it is a full Vulkan
+/// implementation.  Chaining them into the device create info enables all of them.
+pub fn query(
+    instance: &ash::Instance,
+    pdevice: vk::PhysicalDevice,
+) -> Option<PhysicalDevicePortabilitySubsetFeatures> {
+    let is_subset = unsafe {
+        instance
+            .enumerate_device_extension_properties(pdevice)
+            .unwrap()
+            .iter()
+            .any(|ext| CStr::from_ptr(ext.extension_name.as_ptr()) == extension_name())
+    };
+    if !is_subset {
+        return None;
+    }
+    let mut subset_features = PhysicalDevicePortabilitySubsetFeatures::default();
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut subset_features as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+    unsafe { instance.get_physical_device_features2(pdevice, &mut features) };
+    subset_features.p_next = ptr::null_mut();
+    Some(subset_features)
+}
@@ -0,0 +1,279 @@
+//! Instrumentation for the Tracy profiler with the `tracy` feature and for puffin with the `puffin`
+//! feature, and no-ops without either, so the call sites don't need to care.
+//!
+//! CPU zones cover the stages of each frame and shader builds, frames are marked after present, and
+//! shader builds and reloads show up as messages.  The tracy-client version that builds with this
+//! toolchain has no GPU context API, so the GPU time of each frame, from `FrameTimer`'s
+//! timestamps, is plotted instead of drawn as zones.
+//!
+//! puffin records the same zones as scopes while switched on with F4.  With the `puffin_viewer`
+//! feature, puffin_egui's profiler window is drawn over the frame meanwhile, and closing it
+//! switches them off.  With the `puffin_server` feature, switching them on the first time starts a
+//! server on port 8585 to look at the frames with the puffin_viewer app, which also works for a
+//! runner on another machine.
+
+#[cfg(feature = "tracy")]
+use tracy_client::{create_plot, finish_continuous_frame, Plot, Span};
+
+#[cfg(feature = "puffin")]
+use crate::{ash_runner::RenderCtx, keymap::Action, plugin::Plugin};
+#[cfg(feature = "puffin")]
+use winit::event::VirtualKeyCode;
+
+#[cfg(feature = "puffin_viewer")]
+use crate::egui_painter::EguiPainter;
+#[cfg(feature = "puffin_viewer")]
+use ash::vk;
+#[cfg(feature = "puffin_viewer")]
+use std::{mem, time::Instant};
+#[cfg(feature = "puffin_viewer")]
+use winit::event::MouseButton;
+
+#[cfg(feature = "tracy")]
+static GPU_TIME: Plot = create_plot!("GPU frame time (ms)");
+
+/// A CPU zone, ended when dropped.
+#[must_use]
+pub struct Zone {
+    #[cfg(feature = "tracy")]
+    _span: Span,
+    /// `None` while puffin is switched off.
+    #[cfg(feature = "puffin")]
+    _scope: Option<puffin::ProfilerScope>,
+}
+
+/// Starts a zone named `name`, at the caller's location.
+#[track_caller]
+#[cfg_attr(
+    not(any(feature = "tracy", feature = "puffin")),
+    allow(unused_variables)
+)]
+pub fn zone(name: &'static str) -> Zone {
+    #[cfg(any(feature = "tracy", feature = "puffin"))]
+    let location = std::panic::Location::caller();
+    Zone {
+        #[cfg(feature = "tracy")]
+        _span: Span::new(name, name, location.file(), location.line(), 0),
+        #[cfg(feature = "puffin")]
+        _scope: if puffin::are_scopes_on() {
+            Some(puffin::ProfilerScope::new(
+                name,
+                &format!("{}:{}", location.file(), location.line()),
+                "",
+            ))
+        } else {
+            None
+        },
+    }
+}
+
+/// Ends a frame, after it was presented.
+pub fn frame_mark() {
+    #[cfg(feature = "tracy")]
+    finish_continuous_frame!();
+    #[cfg(feature = "puffin")]
+    puffin::GlobalProfiler::lock().new_frame();
+}
+
+#[cfg_attr(not(feature = "tracy"), allow(unused_variables))]
+pub fn message(text: &str) {
+    #[cfg(feature = "tracy")]
+    tracy_client::message(text, 0);
+}
+
+#[cfg_attr(not(feature = "tracy"), allow(unused_variables))]
+pub fn gpu_time(milliseconds: f64) {
+    #[cfg(feature = "tracy")]
+    GPU_TIME.point(milliseconds);
+}
+
+/// Switches puffin's scopes on and off with F4, or the key bound to `Action::TogglePuffin`, and
+/// with them the profiler window.
+#[cfg(feature = "puffin")]
+#[derive(Default)]
+pub struct PuffinToggle {
+    /// Started when the scopes are first switched on, and kept until the runner exits.
+    #[cfg(feature = "puffin_server")]
+    server: Option<puffin_http::Server>,
+    /// Created in `init`.
+    #[cfg(feature = "puffin_viewer")]
+    viewer: Option<PuffinViewer>,
+}
+
+#[cfg(feature = "puffin")]
+impl Plugin for PuffinToggle {
+    fn name(&self) -> &str {
+        "puffin"
+    }
+
+    fn key(&mut self, ctx: &mut RenderCtx, key: VirtualKeyCode, pressed: bool) {
+        if ctx.keymap.action(key) == Some(Action::TogglePuffin) && pressed {
+            let on = !puffin::are_scopes_on();
+            puffin::set_scopes_on(on);
+            println!("puffin {}", if on { "on" } else { "off" });
+            #[cfg(feature = "puffin_server")]
+            if on && self.server.is_none() {
+                self.start_server();
+            }
+        }
+    }
+
+    #[cfg(feature = "puffin_viewer")]
+    fn init(&mut self, ctx: &mut RenderCtx) {
+        self.viewer = Some(PuffinViewer::new(ctx));
+    }
+
+    #[cfg(feature = "puffin_viewer")]
+    fn update(&mut self, ctx: &mut RenderCtx) {
+        if let Some(viewer) = &mut self.viewer {
+            if !puffin::are_scopes_on() {
+                viewer.meshes.clear();
+            } else if !viewer.update(ctx) {
+                puffin::set_scopes_on(false);
+                println!("puffin off");
+            }
+        }
+    }
+
+    #[cfg(feature = "puffin_viewer")]
+    fn draw(&self, ctx: &RenderCtx, command_buffer: vk::CommandBuffer) {
+        if let Some(viewer) = &self.viewer {
+            let pixels_per_point = ctx.scale_factor as f32;
+            viewer
+                .painter
+                .draw(ctx, command_buffer, &viewer.meshes, pixels_per_point);
+        }
+    }
+
+    #[cfg(feature = "puffin_viewer")]
+    fn mouse_button(&mut self, _ctx: &mut RenderCtx, button: MouseButton, pressed: bool) {
+        let button = match button {
+            MouseButton::Left => egui::PointerButton::Primary,
+            MouseButton::Right => egui::PointerButton::Secondary,
+            MouseButton::Middle => egui::PointerButton::Middle,
+            MouseButton::Other(_) => return,
+        };
+        if let Some(viewer) = &mut self.viewer {
+            viewer.input(egui::Event::PointerButton {
+                pos: viewer.cursor,
+                button,
+                pressed,
+                modifiers: egui::Modifiers::default(),
+            });
+        }
+    }
+
+    #[cfg(feature = "puffin_viewer")]
+    fn cursor_moved(&mut self, ctx: &mut RenderCtx, position: [f64; 2]) {
+        if let Some(viewer) = &mut self.viewer {
+            let pixels_per_point = ctx.scale_factor as f32;
+            viewer.cursor = egui::pos2(
+                position[0] as f32 / pixels_per_point,
+                position[1] as f32 / pixels_per_point,
+            );
+            viewer.input(egui::Event::PointerMoved(viewer.cursor));
+        }
+    }
+
+    #[cfg(feature = "puffin_viewer")]
+    fn mouse_wheel(&mut self, _ctx: &mut RenderCtx, delta: f32) {
+        if let Some(viewer) = &mut self.viewer {
+            if puffin::are_scopes_on() {
+                viewer.scroll += delta * POINTS_PER_LINE;
+            }
+        }
+    }
+
+    #[cfg(feature = "puffin_viewer")]
+    fn pipelines_rebuilt(&mut self, ctx: &mut RenderCtx) {
+        if let Some(viewer) = &mut self.viewer {
+            // the runner waited for the device before destroying its own old pipelines
+            viewer.painter.recreate_pipeline(ctx);
+        }
+    }
+
+    #[cfg(feature = "puffin_viewer")]
+    fn destroy(&mut self, ctx: &mut RenderCtx) {
+        if let Some(viewer) = &mut self.viewer {
+            viewer.painter.destroy(&ctx.base.device);
+        }
+    }
+}
+
+/// How far a line of mouse wheel scrolling moves the profiler's views.
+#[cfg(feature = "puffin_viewer")]
+const POINTS_PER_LINE: f32 = 24.0;
+
+/// puffin_egui's profiler window, laid out and drawn while the scopes are on.
+#[cfg(feature = "puffin_viewer")]
+struct PuffinViewer {
+    egui: egui::CtxRef,
+    painter: EguiPainter,
+    /// For egui's animations.
+    start: Instant,
+    /// What happened since the last frame, in points.
+    events: Vec<egui::Event>,
+    scroll: f32,
+    cursor: egui::Pos2,
+    /// Laid out in `update`, drawn in `draw`.
+    meshes: Vec<egui::ClippedMesh>,
+}
+
+#[cfg(feature = "puffin_viewer")]
+impl PuffinViewer {
+    fn new(ctx: &RenderCtx) -> Self {
+        Self {
+            egui: egui::CtxRef::default(),
+            painter: EguiPainter::new(ctx),
+            start: Instant::now(),
+            events: Vec::new(),
+            scroll: 0.0,
+            cursor: egui::Pos2::ZERO,
+            meshes: Vec::new(),
+        }
+    }
+
+    /// Keeps `event` for the next frame, if the window is shown.
+    fn input(&mut self, event: egui::Event) {
+        if puffin::are_scopes_on() {
+            self.events.push(event);
+        }
+    }
+
+    /// Lays out the window for this frame.  Returns false once it was closed.
+    fn update(&mut self, ctx: &RenderCtx) -> bool {
+        let pixels_per_point = ctx.scale_factor as f32;
+        let size = egui::vec2(ctx.extent.width as f32, ctx.extent.height as f32) / pixels_per_point;
+        let input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(egui::Pos2::ZERO, size)),
+            pixels_per_point: Some(pixels_per_point),
+            time: Some(self.start.elapsed().as_secs_f64()),
+            scroll_delta: egui::vec2(0.0, mem::take(&mut self.scroll)),
+            events: mem::take(&mut self.events),
+            ..Default::default()
+        };
+        self.egui.begin_frame(input);
+        let open = puffin_egui::profiler_window(&self.egui);
+        let (_output, shapes) = self.egui.end_frame();
+        self.meshes = self.egui.tessellate(shapes);
+        self.painter.set_font_texture(ctx, &self.egui.texture());
+        open
+    }
+}
+
+#[cfg(feature = "puffin_server")]
+impl PuffinToggle {
+    fn start_server(&mut self) {
+        let address = format!("0.0.0.0:{}", puffin_http::DEFAULT_PORT);
+        match puffin_http::Server::new(&address) {
+            Ok(server) => {
+                println!(
+                    "puffin: serving frames on {}, view them with puffin_viewer",
+                    address
+                );
+                self.server = Some(server);
+            }
+            Err(err) => eprintln!("puffin: failed to serve on {}: {}", address, err),
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! Reading rendered pixels back to the CPU for `RenderCtx::read_pixels` and `read_attachment`.
+//! The frame is rendered again into an image of the swapchain's view format, through a render pass
+//! differing from the window's only in the layout it leaves the image in, so the pipelines built
+//! for the window, plugins' included, draw into it as they are.  The region of that image or of an
+//! extra attachment is then copied into a host visible buffer, and the swapchain format converted
+//! to RGBA8.
+
+use crate::ash_runner::RenderCtx;
+use crate::golden;
+use crate::render_pass::{AttachmentImages, AttachmentKind};
+
+use ash::{version::DeviceV1_0, vk};
+
+/// Bytes per texel of the extra attachment formats that can be read back, the usual ones for IDs
+/// and other values written for picking.
+fn texel_size(format: vk::Format) -> Option<usize> {
+    match format {
+        vk::Format::R8_UINT | vk::Format::R8_UNORM => Some(1),
+        vk::Format::R16_UINT | vk::Format::R16_SFLOAT => Some(2),
+        vk::Format::R32_UINT
+        | vk::Format::R32_SINT
+        | vk::Format::R32_SFLOAT
+        | vk::Format::R16G16_UINT
+        | vk::Format::R8G8B8A8_UINT
+        | vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB => Some(4),
+        vk::Format::R32G32_UINT | vk::Format::R32G32_SFLOAT | vk::Format::R16G16B16A16_SFLOAT => {
+            Some(8)
+        }
+        vk::Format::R32G32B32A32_UINT | vk::Format::R32G32B32A32_SFLOAT => Some(16),
+        _ => None,
+    }
+}
+
+/// Bytes per pixel of the swapchain formats that can be read back.
+fn pixel_size(format: vk::Format) -> Option<usize> {
+    match format {
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::A2B10G10R10_UNORM_PACK32
+        | vk::Format::A2R10G10B10_UNORM_PACK32 => Some(4),
+        vk::Format::R16G16B16A16_SFLOAT => Some(8),
+        _ => None,
+    }
+}
+
+pub(crate) fn read_pixels(ctx: &RenderCtx, region: vk::Rect2D) -> Vec<u8> {
+    let format = ctx.base.view_format;
+    let pixel_size = pixel_size(format)
+        .unwrap_or_else(|| panic!("Reading back {:?} images isn't supported", format));
+    to_rgba8(format, &read_region(ctx, None, pixel_size, region))
+}
+
+/// The texels of the extra color attachment at `index` in `ctx.render_pass_config`, as they are
+/// stored in its format.
+pub(crate) fn read_attachment(ctx: &RenderCtx, index: usize, region: vk::Rect2D) -> Vec<u8> {
+    let attachment = ctx.render_pass_config.extra_attachments.get(index);
+    let format = match attachment {
+        Some(attachment) if attachment.kind == AttachmentKind::Color => attachment.format,
+        _ => panic!("Extra attachment {} isn't a color attachment", index),
+    };
+    let texel_size = texel_size(format)
+        .unwrap_or_else(|| panic!("Reading back {:?} attachments isn't supported", format));
+    read_region(ctx, Some(index), texel_size, region)
+}
+
+/// Renders the frame and copies `region` of the swapchain image, or of the extra attachment at
+/// `attachment`, with `texel_size` bytes per texel.
+fn read_region(
+    ctx: &RenderCtx,
+    attachment: Option<usize>,
+    texel_size: usize,
+    region: vk::Rect2D,
+) -> Vec<u8> {
+    let base = &ctx.base;
+    let device = &base.device;
+    let extent = ctx.extent;
+    assert!(
+        region.offset.x >= 0
+            && region.offset.y >= 0
+            && region.offset.x as u32 + region.extent.width <= extent.width
+            && region.offset.y as u32 + region.extent.height <= extent.height,
+        "Reading back {:?} outside of the {}x{} frame",
+        region,
+        extent.width,
+        extent.height
+    );
+    let format = base.view_format;
+    if region.extent.width == 0 || region.extent.height == 0 {
+        return Vec::new();
+    }
+    // plugins rewrite their per-frame buffers when drawing
+    unsafe { device.device_wait_idle().unwrap() };
+
+    let mut config = ctx.render_pass_config.cleared();
+    if let Some(index) = attachment {
+        // kept past the render pass for the copy
+        config.extra_attachments[index].store_op = vk::AttachmentStoreOp::STORE;
+    }
+    let render_pass =
+        config.create_render_pass(device, format, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+    let mut attachment_images = AttachmentImages::new(base, &config, extent);
+
+    let image_info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(format)
+        .extent(vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        })
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .initial_layout(vk::ImageLayout::UNDEFINED);
+    let image = unsafe { device.create_image(&image_info, None).unwrap() };
+    let image_memory = unsafe {
+        let requirements = device.get_image_memory_requirements(image);
+        let memory_type_index = base
+            .find_memory_type(
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .expect("No device local memory type for the readback image");
+        let allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type_index);
+        let memory = device.allocate_memory(&allocate_info, None).unwrap();
+        device.bind_image_memory(image, memory, 0).unwrap();
+        memory
+    };
+    let image_view = {
+        let create_view_info = vk::ImageViewCreateInfo::builder()
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image(image);
+        unsafe { device.create_image_view(&create_view_info, None).unwrap() }
+    };
+    let framebuffer = base.create_framebuffers(
+        &[image_view],
+        &attachment_images.views(),
+        render_pass,
+        extent,
+    )[0];
+
+    let buffer_size = region.extent.width as usize * region.extent.height as usize * texel_size;
+    let buffer_info = vk::BufferCreateInfo::builder()
+        .size(buffer_size as vk::DeviceSize)
+        .usage(vk::BufferUsageFlags::TRANSFER_DST)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let buffer = unsafe { device.create_buffer(&buffer_info, None).unwrap() };
+    let buffer_memory = unsafe {
+        let requirements = device.get_buffer_memory_requirements(buffer);
+        let memory_type_index = base
+            .find_memory_type(
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )
+            .expect("No host visible memory type for reading back pixels");
+        let allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type_index);
+        let memory = device.allocate_memory(&allocate_info, None).unwrap();
+        device.bind_buffer_memory(buffer, memory, 0).unwrap();
+        memory
+    };
+
+    let clear_values = ctx.clear_values();
+    let command_buffer = ctx.commands.setup_command_buffer;
+    let fence = ctx.sync.setup_commands_reuse_fence;
+    unsafe {
+        device
+            .wait_for_fences(&[fence], true, std::u64::MAX)
+            .expect("Wait for fence failed.");
+        device.reset_fences(&[fence]).expect("Reset fences failed.");
+        device
+            .begin_command_buffer(
+                command_buffer,
+                &vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )
+            .expect("Begin commandbuffer");
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(render_pass)
+            .framebuffer(framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            })
+            .clear_values(&clear_values);
+        device.cmd_begin_render_pass(
+            command_buffer,
+            &render_pass_begin_info,
+            vk::SubpassContents::INLINE,
+        );
+        ctx.draw_frame(command_buffer);
+        device.cmd_end_render_pass(command_buffer);
+        // the render pass leaves the image in TRANSFER_SRC_OPTIMAL and extra attachments in their
+        // attachment layout, the copy waits for its writes
+        let (source, old_layout) = match attachment {
+            Some(index) => (
+                attachment_images.image(index),
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ),
+            None => (image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL),
+        };
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(old_layout)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(source)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .build();
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier],
+        );
+        let copy = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D {
+                x: region.offset.x,
+                y: region.offset.y,
+                z: 0,
+            },
+            image_extent: vk::Extent3D {
+                width: region.extent.width,
+                height: region.extent.height,
+                depth: 1,
+            },
+        };
+        device.cmd_copy_image_to_buffer(
+            command_buffer,
+            source,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            buffer,
+            &[copy],
+        );
+        let barrier = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build();
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[barrier],
+            &[],
+        );
+        device
+            .end_command_buffer(command_buffer)
+            .expect("End commandbuffer");
+        let command_buffers = [command_buffer];
+        let submit_info = vk::SubmitInfo::builder().command_buffers(&command_buffers);
+        device
+            .queue_submit(base.present_queue, &[submit_info.build()], fence)
+            .expect("queue submit failed.");
+        device
+            .wait_for_fences(&[fence], true, std::u64::MAX)
+            .expect("Wait for fence failed.");
+    }
+
+    let mut bytes = vec![0u8; buffer_size];
+    unsafe {
+        let mapped = device
+            .map_memory(
+                buffer_memory,
+                0,
+                vk::WHOLE_SIZE,
+                vk::MemoryMapFlags::empty(),
+            )
+            .unwrap();
+        std::ptr::copy_nonoverlapping(mapped as *const u8, bytes.as_mut_ptr(), bytes.len());
+        device.unmap_memory(buffer_memory);
+
+        device.destroy_framebuffer(framebuffer, None);
+        device.destroy_image_view(image_view, None);
+        device.destroy_image(image, None);
+        device.free_memory(image_memory, None);
+        device.destroy_buffer(buffer, None);
+        device.free_memory(buffer_memory, None);
+        device.destroy_render_pass(render_pass, None);
+        attachment_images.destroy(device);
+    }
+    bytes
+}
+
+/// Converts tightly packed pixels of one of the `pixel_size` formats.  Float pixels are linear and
+/// get the sRGB transfer function applied, like the other formats' stored values have.
+fn to_rgba8(format: vk::Format, bytes: &[u8]) -> Vec<u8> {
+    let unorm10 = |value: u32| ((value & 0x3ff) * 255 + 511) / 1023;
+    match format {
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => bytes.to_vec(),
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => bytes
+            .chunks_exact(4)
+            .flat_map(|bgra| vec![bgra[2], bgra[1], bgra[0], bgra[3]])
+            .collect(),
+        vk::Format::A2B10G10R10_UNORM_PACK32 | vk::Format::A2R10G10B10_UNORM_PACK32 => bytes
+            .chunks_exact(4)
+            .flat_map(|pixel| {
+                let pixel = u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                let (low, high) = (unorm10(pixel), unorm10(pixel >> 20));
+                let (red, blue) = if format == vk::Format::A2B10G10R10_UNORM_PACK32 {
+                    (low, high)
+                } else {
+                    (high, low)
+                };
+                vec![
+                    red as u8,
+                    unorm10(pixel >> 10) as u8,
+                    blue as u8,
+                    ((pixel >> 30) * 85) as u8,
+                ]
+            })
+            .collect(),
+        vk::Format::R16G16B16A16_SFLOAT => {
+            let pixels = bytes
+                .chunks_exact(2)
+                .map(|half| f16_to_f32(u16::from_le_bytes([half[0], half[1]])))
+                .collect::<Vec<_>>();
+            golden::to_rgba8(&pixels, true)
+        }
+        _ => unreachable!("checked by pixel_size"),
+    }
+}
+
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = i32::from((half >> 10) & 0x1f);
+    let mantissa = f32::from(half & 0x3ff);
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
@@ -0,0 +1,530 @@
+//! Just enough SPIR-V reflection to build pipeline layouts: which entry points use a push constant
+//! block, and how large it is.  Also lists the entry points, for checking pipelines against them,
+//! and checks that a module is well formed enough to be worth handing to the driver, and lists the
+//! subgroup operations it declares, for checking against the device.
+
+use std::collections::{HashMap, HashSet};
+
+const MAGIC: u32 = 0x0723_0203;
+const HEADER_WORDS: usize = 5;
+
+const OP_ENTRY_POINT: u32 = 15;
+const OP_CAPABILITY: u32 = 17;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_FUNCTION: u32 = 54;
+const OP_FUNCTION_END: u32 = 56;
+const OP_FUNCTION_CALL: u32 = 57;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+
+const CAPABILITY_GROUP_NON_UNIFORM: u32 = 61;
+const CAPABILITY_GROUP_NON_UNIFORM_VOTE: u32 = 62;
+const CAPABILITY_GROUP_NON_UNIFORM_ARITHMETIC: u32 = 63;
+const CAPABILITY_GROUP_NON_UNIFORM_BALLOT: u32 = 64;
+const CAPABILITY_GROUP_NON_UNIFORM_SHUFFLE: u32 = 65;
+const CAPABILITY_GROUP_NON_UNIFORM_SHUFFLE_RELATIVE: u32 = 66;
+const CAPABILITY_GROUP_NON_UNIFORM_CLUSTERED: u32 = 67;
+const CAPABILITY_GROUP_NON_UNIFORM_QUAD: u32 = 68;
+
+const EXECUTION_MODEL_VERTEX: u32 = 0;
+const EXECUTION_MODEL_FRAGMENT: u32 = 4;
+const EXECUTION_MODEL_GL_COMPUTE: u32 = 5;
+
+/// The stage an entry point runs in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Vertex,
+    Fragment,
+    Compute,
+    /// Geometry, tessellation, ray tracing and so on, which the runner doesn't build pipelines
+    /// from.
+    Other,
+}
+
+/// A class of subgroup operations, from the `GroupNonUniform*` capability a module declares to use
+/// them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubgroupOperation {
+    Basic,
+    Vote,
+    Arithmetic,
+    Ballot,
+    Shuffle,
+    ShuffleRelative,
+    Clustered,
+    Quad,
+}
+
+enum Type {
+    Scalar { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Array { element: u32, length: u32 },
+    Struct { members: Vec<u32> },
+}
+
+#[derive(Default)]
+struct Function {
+    /// Push constant variables the function's body refers to.
+    push_constants: HashSet<u32>,
+    callees: Vec<u32>,
+}
+
+#[derive(Default)]
+struct Module {
+    entry_points: Vec<(String, u32)>,
+    /// The execution model of each entry point, in the same order.  Names aren't unique, e.g. a
+    /// vertex and a fragment shader can both be `main`.
+    execution_models: Vec<u32>,
+    capabilities: Vec<u32>,
+    types: HashMap<u32, Type>,
+    pointee_types: HashMap<u32, u32>,
+    constants: HashMap<u32, u32>,
+    array_strides: HashMap<u32, u32>,
+    /// Offset and matrix stride of struct members, keyed by struct and member index.
+    member_offsets: HashMap<(u32, u32), u32>,
+    member_matrix_strides: HashMap<(u32, u32), u32>,
+    /// Push constant variables and their pointer types.
+    push_constants: HashMap<u32, u32>,
+    functions: HashMap<u32, Function>,
+}
+
+/// Returns the size of the push constant block used by each entry point of a SPIR-V module.  Entry
+/// points without push constants are left out, as is everything if the module can't be parsed.
+pub fn push_constant_sizes(spirv: &[u32]) -> HashMap<String, u32> {
+    let module = match parse(spirv) {
+        Some(module) => module,
+        None => return HashMap::new(),
+    };
+    module
+        .entry_points
+        .iter()
+        .filter_map(|(name, function)| {
+            let size = module
+                .reachable_push_constants(*function)
+                .iter()
+                .filter_map(|variable| {
+                    let pointer_type = module.push_constants.get(variable)?;
+                    module.size_of(*module.pointee_types.get(pointer_type)?)
+                })
+                .max()?;
+            Some((name.clone(), size))
+        })
+        .collect()
+}
+
+/// The names of the entry points of a SPIR-V module, or none if it can't be parsed.
+pub fn entry_points(spirv: &[u32]) -> Vec<String> {
+    parse(spirv).map_or_else(Vec::new, |module| {
+        module
+            .entry_points
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    })
+}
+
+/// The entry points of a SPIR-V module with their stages, or none if it can't be parsed.
+pub fn entry_point_stages(spirv: &[u32]) -> Vec<(String, Stage)> {
+    parse(spirv).map_or_else(Vec::new, |module| {
+        module
+            .entry_points
+            .into_iter()
+            .zip(module.execution_models)
+            .map(|((name, _), execution_model)| {
+                let stage = match execution_model {
+                    EXECUTION_MODEL_VERTEX => Stage::Vertex,
+                    EXECUTION_MODEL_FRAGMENT => Stage::Fragment,
+                    EXECUTION_MODEL_GL_COMPUTE => Stage::Compute,
+                    _ => Stage::Other,
+                };
+                (name, stage)
+            })
+            .collect()
+    })
+}
+
+/// The subgroup operations a SPIR-V module declares, or none if it can't be parsed.
+pub fn subgroup_operations(spirv: &[u32]) -> Vec<SubgroupOperation> {
+    parse(spirv).map_or_else(Vec::new, |module| {
+        module
+            .capabilities
+            .iter()
+            .filter_map(|capability| match *capability {
+                CAPABILITY_GROUP_NON_UNIFORM => Some(SubgroupOperation::Basic),
+                CAPABILITY_GROUP_NON_UNIFORM_VOTE => Some(SubgroupOperation::Vote),
+                CAPABILITY_GROUP_NON_UNIFORM_ARITHMETIC => Some(SubgroupOperation::Arithmetic),
+                CAPABILITY_GROUP_NON_UNIFORM_BALLOT => Some(SubgroupOperation::Ballot),
+                CAPABILITY_GROUP_NON_UNIFORM_SHUFFLE => Some(SubgroupOperation::Shuffle),
+                CAPABILITY_GROUP_NON_UNIFORM_SHUFFLE_RELATIVE => {
+                    Some(SubgroupOperation::ShuffleRelative)
+                }
+                CAPABILITY_GROUP_NON_UNIFORM_CLUSTERED => Some(SubgroupOperation::Clustered),
+                CAPABILITY_GROUP_NON_UNIFORM_QUAD => Some(SubgroupOperation::Quad),
+                _ => None,
+            })
+            .collect()
+    })
+}
+
+/// Checks the header and instruction stream of a SPIR-V module, and that it has entry points which
+/// all name a function.  This is no substitute for `spirv-val`, but catches truncated or corrupt
+/// files.
+pub fn validate(spirv: &[u32]) -> Result<(), String> {
+    if spirv.len() < HEADER_WORDS || spirv[0] != MAGIC {
+        return Err("not a SPIR-V module".into());
+    }
+    let module = parse(spirv).ok_or("malformed instruction stream")?;
+    if module.entry_points.is_empty() {
+        return Err("no entry points".into());
+    }
+    for (name, function) in &module.entry_points {
+        if !module.functions.contains_key(function) {
+            return Err(format!("entry point \"{}\" names no function", name));
+        }
+    }
+    Ok(())
+}
+
+fn parse(spirv: &[u32]) -> Option<Module> {
+    if spirv.len() < HEADER_WORDS || spirv[0] != MAGIC {
+        return None;
+    }
+    let mut module = Module::default();
+    let mut current_function = None;
+    let mut offset = HEADER_WORDS;
+    while offset < spirv.len() {
+        let word_count = (spirv[offset] >> 16) as usize;
+        let opcode = spirv[offset] & 0xffff;
+        if word_count == 0 || offset + word_count > spirv.len() {
+            return None;
+        }
+        let operands = &spirv[offset + 1..offset + word_count];
+        offset += word_count;
+
+        if let Some(id) = current_function {
+            match opcode {
+                OP_FUNCTION_END => current_function = None,
+                _ => {
+                    let function = module.functions.get_mut(&id).unwrap();
+                    if opcode == OP_FUNCTION_CALL {
+                        function.callees.push(*operands.get(2)?);
+                    }
+                    // any operand naming a push constant counts as a use, be it a load, an access
+                    // chain, or passing the pointer on to another function
+                    for operand in operands.iter() {
+                        if module.push_constants.contains_key(operand) {
+                            function.push_constants.insert(*operand);
+                        }
+                    }
+                }
+            }
+            continue;
+        }
+
+        match opcode {
+            OP_CAPABILITY => module.capabilities.push(*operands.first()?),
+            OP_ENTRY_POINT => {
+                let function = *operands.get(1)?;
+                module.execution_models.push(*operands.first()?);
+                module
+                    .entry_points
+                    .push((parse_string(operands.get(2..)?), function));
+            }
+            OP_TYPE_INT | OP_TYPE_FLOAT => {
+                module.types.insert(
+                    *operands.first()?,
+                    Type::Scalar {
+                        width: *operands.get(1)?,
+                    },
+                );
+            }
+            OP_TYPE_VECTOR => {
+                module.types.insert(
+                    *operands.first()?,
+                    Type::Vector {
+                        component: *operands.get(1)?,
+                        count: *operands.get(2)?,
+                    },
+                );
+            }
+            OP_TYPE_MATRIX => {
+                module.types.insert(
+                    *operands.first()?,
+                    Type::Matrix {
+                        column: *operands.get(1)?,
+                        count: *operands.get(2)?,
+                    },
+                );
+            }
+            OP_TYPE_ARRAY => {
+                module.types.insert(
+                    *operands.first()?,
+                    Type::Array {
+                        element: *operands.get(1)?,
+                        length: *operands.get(2)?,
+                    },
+                );
+            }
+            OP_TYPE_STRUCT => {
+                module.types.insert(
+                    *operands.first()?,
+                    Type::Struct {
+                        members: operands.get(1..)?.to_vec(),
+                    },
+                );
+            }
+            OP_TYPE_POINTER => {
+                module
+                    .pointee_types
+                    .insert(*operands.first()?, *operands.get(2)?);
+            }
+            OP_CONSTANT => {
+                module
+                    .constants
+                    .insert(*operands.get(1)?, *operands.get(2)?);
+            }
+            OP_VARIABLE if operands.get(2) == Some(&STORAGE_CLASS_PUSH_CONSTANT) => {
+                module
+                    .push_constants
+                    .insert(*operands.get(1)?, *operands.first()?);
+            }
+            OP_DECORATE if operands.get(1) == Some(&DECORATION_ARRAY_STRIDE) => {
+                module
+                    .array_strides
+                    .insert(*operands.first()?, *operands.get(2)?);
+            }
+            OP_MEMBER_DECORATE => {
+                let member = (*operands.first()?, *operands.get(1)?);
+                match operands.get(2) {
+                    Some(&DECORATION_OFFSET) => {
+                        module.member_offsets.insert(member, *operands.get(3)?);
+                    }
+                    Some(&DECORATION_MATRIX_STRIDE) => {
+                        module
+                            .member_matrix_strides
+                            .insert(member, *operands.get(3)?);
+                    }
+                    _ => {}
+                }
+            }
+            OP_FUNCTION => {
+                let id = *operands.get(1)?;
+                module.functions.insert(id, Function::default());
+                current_function = Some(id);
+            }
+            _ => {}
+        }
+    }
+    Some(module)
+}
+
+/// Decodes a nul terminated literal string packed into words.
+fn parse_string(words: &[u32]) -> String {
+    let bytes = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes().to_vec())
+        .take_while(|&byte| byte != 0)
+        .collect::<Vec<_>>();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+impl Module {
+    fn reachable_push_constants(&self, entry_point: u32) -> HashSet<u32> {
+        let mut push_constants = HashSet::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![entry_point];
+        while let Some(id) = stack.pop() {
+            if !visited.insert(id) {
+                continue;
+            }
+            if let Some(function) = self.functions.get(&id) {
+                push_constants.extend(function.push_constants.iter().copied());
+                stack.extend(function.callees.iter().copied());
+            }
+        }
+        push_constants
+    }
+
+    /// The size of a type laid out with its explicit offsets and strides.
+    fn size_of(&self, id: u32) -> Option<u32> {
+        match self.types.get(&id)? {
+            Type::Scalar { width } => Some(width / 8),
+            Type::Vector { component, count } => Some(self.size_of(*component)? * count),
+            Type::Matrix { column, count } => Some(self.size_of(*column)? * count),
+            Type::Array { element, length } => {
+                let stride = match self.array_strides.get(&id) {
+                    Some(&stride) => stride,
+                    None => self.size_of(*element)?,
+                };
+                Some(stride * self.constants.get(length)?)
+            }
+            Type::Struct { members } => {
+                let mut size = 0;
+                for (index, &member) in members.iter().enumerate() {
+                    let key = (id, index as u32);
+                    let offset = self.member_offsets.get(&key).copied().unwrap_or(size);
+                    let member_size = match (
+                        self.types.get(&member)?,
+                        self.member_matrix_strides.get(&key),
+                    ) {
+                        (Type::Matrix { count, .. }, Some(&stride)) => stride * count,
+                        _ => self.size_of(member)?,
+                    };
+                    size = size.max(offset + member_size);
+                }
+                Some(size)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OP_TYPE_VOID: u32 = 19;
+    const OP_LOAD: u32 = 61;
+    const VOID: u32 = 100;
+
+    fn op(opcode: u32, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode];
+        words.extend_from_slice(operands);
+        words
+    }
+
+    /// Packs a literal string, nul terminated and padded to whole words.
+    fn string(s: &str) -> Vec<u32> {
+        let mut bytes = s.as_bytes().to_vec();
+        bytes.resize(s.len() / 4 * 4 + 4, 0);
+        bytes
+            .chunks(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect()
+    }
+
+    fn entry_point(function: u32, name: &str) -> Vec<u32> {
+        let mut operands = vec![EXECUTION_MODEL_FRAGMENT, function];
+        operands.extend(string(name));
+        op(OP_ENTRY_POINT, &operands)
+    }
+
+    fn module(instructions: Vec<Vec<u32>>) -> Vec<u32> {
+        let mut words = vec![MAGIC, 0x0001_0300, 0, 1000, 0];
+        words.extend(instructions.into_iter().flatten());
+        words
+    }
+
+    /// A `main` entry point loading the push constant block `block`, a struct with one member at
+    /// offset 0, declared by `types` with ids from 1 up.
+    fn push_constant_module(types: Vec<Vec<u32>>, block: u32) -> Vec<u32> {
+        let mut instructions = vec![entry_point(10, "main"), op(OP_TYPE_VOID, &[VOID])];
+        instructions.extend(types);
+        instructions.extend(vec![
+            op(OP_MEMBER_DECORATE, &[block, 0, DECORATION_OFFSET, 0]),
+            op(OP_TYPE_POINTER, &[50, STORAGE_CLASS_PUSH_CONSTANT, block]),
+            op(OP_VARIABLE, &[50, 51, STORAGE_CLASS_PUSH_CONSTANT]),
+            op(OP_FUNCTION, &[VOID, 10, 0, 0]),
+            op(OP_LOAD, &[block, 52, 51]),
+            op(OP_FUNCTION_END, &[]),
+        ]);
+        module(instructions)
+    }
+
+    #[test]
+    fn truncated_stream() {
+        let spirv = push_constant_module(
+            vec![op(OP_TYPE_FLOAT, &[1, 32]), op(OP_TYPE_STRUCT, &[2, 1])],
+            2,
+        );
+        assert_eq!(validate(&spirv), Ok(()));
+        // cut inside the entry point instruction
+        let truncated = &spirv[..HEADER_WORDS + 2];
+        assert_eq!(
+            validate(truncated),
+            Err("malformed instruction stream".to_owned())
+        );
+        assert!(push_constant_sizes(truncated).is_empty());
+        assert!(entry_points(truncated).is_empty());
+        assert!(validate(&spirv[..HEADER_WORDS - 1]).is_err());
+    }
+
+    #[test]
+    fn push_constants_reached_through_a_callee() {
+        let spirv = module(vec![
+            entry_point(10, "main"),
+            entry_point(12, "unused"),
+            op(OP_TYPE_VOID, &[VOID]),
+            op(OP_TYPE_FLOAT, &[1, 32]),
+            op(OP_TYPE_STRUCT, &[2, 1, 1]),
+            op(OP_MEMBER_DECORATE, &[2, 0, DECORATION_OFFSET, 0]),
+            op(OP_MEMBER_DECORATE, &[2, 1, DECORATION_OFFSET, 4]),
+            op(OP_TYPE_POINTER, &[3, STORAGE_CLASS_PUSH_CONSTANT, 2]),
+            op(OP_VARIABLE, &[3, 4, STORAGE_CLASS_PUSH_CONSTANT]),
+            // main only calls the helper, which loads the block
+            op(OP_FUNCTION, &[VOID, 10, 0, 0]),
+            op(OP_FUNCTION_CALL, &[VOID, 20, 11]),
+            op(OP_FUNCTION_END, &[]),
+            op(OP_FUNCTION, &[VOID, 11, 0, 0]),
+            op(OP_LOAD, &[2, 21, 4]),
+            op(OP_FUNCTION_END, &[]),
+            op(OP_FUNCTION, &[VOID, 12, 0, 0]),
+            op(OP_FUNCTION_END, &[]),
+        ]);
+        let sizes = push_constant_sizes(&spirv);
+        assert_eq!(sizes.get("main"), Some(&8));
+        assert_eq!(sizes.get("unused"), None);
+        assert_eq!(entry_points(&spirv), vec!["main", "unused"]);
+    }
+
+    #[test]
+    fn array_stride() {
+        // struct { float; float[4] } with a stride of 16 and the array at offset 16
+        let spirv = push_constant_module(
+            vec![
+                op(OP_TYPE_FLOAT, &[1, 32]),
+                op(OP_TYPE_INT, &[2, 32, 0]),
+                op(OP_CONSTANT, &[2, 3, 4]),
+                op(OP_TYPE_ARRAY, &[4, 1, 3]),
+                op(OP_DECORATE, &[4, DECORATION_ARRAY_STRIDE, 16]),
+                op(OP_TYPE_STRUCT, &[5, 1, 4]),
+                op(OP_MEMBER_DECORATE, &[5, 1, DECORATION_OFFSET, 16]),
+            ],
+            5,
+        );
+        assert_eq!(
+            push_constant_sizes(&spirv).get("main"),
+            Some(&(16 + 4 * 16))
+        );
+    }
+
+    #[test]
+    fn matrix_stride() {
+        // struct { mat3 } with each column of three floats padded to 16 bytes
+        let spirv = push_constant_module(
+            vec![
+                op(OP_TYPE_FLOAT, &[1, 32]),
+                op(OP_TYPE_VECTOR, &[2, 1, 3]),
+                op(OP_TYPE_MATRIX, &[3, 2, 3]),
+                op(OP_TYPE_STRUCT, &[4, 3]),
+                op(OP_MEMBER_DECORATE, &[4, 0, DECORATION_MATRIX_STRIDE, 16]),
+            ],
+            4,
+        );
+        assert_eq!(push_constant_sizes(&spirv).get("main"), Some(&(3 * 16)));
+    }
+}
@@ -0,0 +1,466 @@
+//! Configuring the render pass the pipelines draw in: what happens to the swapchain image at the
+//! start and end of a frame, and additional color or depth attachments rendered alongside it.
+//!
+//! Attachments whose contents don't outlive the render pass, neither loaded nor stored, e.g. a
+//! depth buffer only tested against while drawing, are transient: their images are created with
+//! `TRANSIENT_ATTACHMENT` usage in lazily allocated memory where the device has such a memory type,
+//! so tiled GPUs can keep them in tile memory without ever backing them.  Other devices get plain
+//! device local memory.
+
+use crate::ash_runner::RenderBase;
+
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk,
+};
+
+/// Depth formats with a stencil aspect, in order of preference.  Every device supports one of them
+/// as a depth stencil attachment.
+const STENCIL_FORMATS: [vk::Format; 2] = [
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D32_SFLOAT_S8_UINT,
+];
+
+/// The render pass used for the swapchain.  The default clears the image and stores it for
+/// presenting.
+#[derive(Clone)]
+pub struct RenderPassConfig {
+    pub color_load_op: vk::AttachmentLoadOp,
+    pub color_store_op: vk::AttachmentStoreOp,
+    /// Rendered to after the swapchain image, in order.
+    pub extra_attachments: Vec<ExtraAttachment>,
+}
+
+impl Default for RenderPassConfig {
+    fn default() -> Self {
+        Self {
+            color_load_op: vk::AttachmentLoadOp::CLEAR,
+            color_store_op: vk::AttachmentStoreOp::STORE,
+            extra_attachments: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachmentKind {
+    Color,
+    /// At most one per render pass.
+    DepthStencil,
+}
+
+/// An image the size of the swapchain owned by the renderer, kept between frames.
+#[derive(Clone, Copy)]
+pub struct ExtraAttachment {
+    pub kind: AttachmentKind,
+    pub format: vk::Format,
+    pub load_op: vk::AttachmentLoadOp,
+    pub store_op: vk::AttachmentStoreOp,
+    /// Used when `load_op` is `CLEAR`.
+    pub clear_value: vk::ClearValue,
+}
+
+impl ExtraAttachment {
+    /// A depth stencil attachment cleared to `depth`, e.g. `DepthConvention::clear_depth`, and
+    /// `stencil` at the start of the frame and discarded at its end, e.g. of `stencil_format`.
+    pub fn depth_stencil(format: vk::Format, depth: f32, stencil: u32) -> Self {
+        Self {
+            kind: AttachmentKind::DepthStencil,
+            format,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            clear_value: vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth, stencil },
+            },
+        }
+    }
+
+    /// Neither loaded nor stored, so only used within the render pass.
+    pub fn is_transient(&self) -> bool {
+        self.load_op != vk::AttachmentLoadOp::LOAD
+            && self.store_op == vk::AttachmentStoreOp::DONT_CARE
+    }
+}
+
+impl AttachmentKind {
+    fn layout(self) -> vk::ImageLayout {
+        match self {
+            AttachmentKind::Color => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            AttachmentKind::DepthStencil => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        }
+    }
+}
+
+impl RenderPassConfig {
+    /// Number of color attachments, which pipelines need a blend state for each of.
+    pub fn color_attachment_count(&self) -> usize {
+        1 + self
+            .extra_attachments
+            .iter()
+            .filter(|attachment| attachment.kind == AttachmentKind::Color)
+            .count()
+    }
+
+    /// The same attachments, but cleared instead of loaded, for rendering a single frame into
+    /// fresh images.
+    pub fn cleared(&self) -> Self {
+        let mut config = self.clone();
+        config.color_load_op = vk::AttachmentLoadOp::CLEAR;
+        for attachment in config.extra_attachments.iter_mut() {
+            attachment.load_op = vk::AttachmentLoadOp::CLEAR;
+        }
+        config
+    }
+
+    /// Creates a render pass whose first attachment is a color image of `format`, left in
+    /// `final_layout`.  Loading it expects the image to be in `final_layout` already.
+    pub fn create_render_pass(
+        &self,
+        device: &ash::Device,
+        format: vk::Format,
+        final_layout: vk::ImageLayout,
+    ) -> vk::RenderPass {
+        let mut renderpass_attachments = vec![vk::AttachmentDescription {
+            format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: self.color_load_op,
+            store_op: self.color_store_op,
+            initial_layout: if self.color_load_op == vk::AttachmentLoadOp::LOAD {
+                final_layout
+            } else {
+                vk::ImageLayout::UNDEFINED
+            },
+            final_layout,
+            ..Default::default()
+        }];
+        let mut color_attachment_refs = vec![vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }];
+        let mut depth_attachment_ref = None;
+        for attachment in self.extra_attachments.iter() {
+            let layout = attachment.kind.layout();
+            let reference = vk::AttachmentReference {
+                attachment: renderpass_attachments.len() as u32,
+                layout,
+            };
+            match attachment.kind {
+                AttachmentKind::Color => color_attachment_refs.push(reference),
+                AttachmentKind::DepthStencil => {
+                    assert!(
+                        depth_attachment_ref.is_none(),
+                        "A render pass can only have one depth stencil attachment"
+                    );
+                    depth_attachment_ref = Some(reference);
+                }
+            }
+            renderpass_attachments.push(vk::AttachmentDescription {
+                format: attachment.format,
+                samples: vk::SampleCountFlags::TYPE_1,
+                load_op: attachment.load_op,
+                store_op: attachment.store_op,
+                stencil_load_op: attachment.load_op,
+                stencil_store_op: attachment.store_op,
+                initial_layout: if attachment.load_op == vk::AttachmentLoadOp::LOAD {
+                    layout
+                } else {
+                    vk::ImageLayout::UNDEFINED
+                },
+                final_layout: layout,
+                ..Default::default()
+            });
+        }
+
+        let mut stage_mask = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+        let mut access_mask =
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE;
+        if depth_attachment_ref.is_some() {
+            stage_mask |= vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+            access_mask |= vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+        }
+        let dependencies = [vk::SubpassDependency {
+            src_subpass: vk::SUBPASS_EXTERNAL,
+            src_stage_mask: stage_mask,
+            // the previous frame's writes to loaded attachments
+            src_access_mask: access_mask
+                & (vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE),
+            dst_access_mask: access_mask,
+            dst_stage_mask: stage_mask,
+            ..Default::default()
+        }];
+        let mut subpass = vk::SubpassDescription::builder()
+            .color_attachments(&color_attachment_refs)
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+        if let Some(depth_attachment_ref) = &depth_attachment_ref {
+            subpass = subpass.depth_stencil_attachment(depth_attachment_ref);
+        }
+        let subpasses = [subpass.build()];
+        let renderpass_create_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&renderpass_attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
+        unsafe {
+            device
+                .create_render_pass(&renderpass_create_info, None)
+                .unwrap()
+        }
+    }
+}
+
+/// Which end of the 0 to 1 depth range is near.  Reverse-Z maps the near plane to 1 and the far
+/// plane to 0, which spreads float depth precision evenly over distance instead of spending it all
+/// close to the camera.  Depth attachments are then cleared to 0 and tested with
+/// `GREATER_OR_EQUAL`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepthConvention {
+    Standard,
+    Reverse,
+}
+
+impl Default for DepthConvention {
+    fn default() -> Self {
+        DepthConvention::Standard
+    }
+}
+
+impl DepthConvention {
+    /// Passes fragments at least as near as what was drawn before.
+    pub fn compare_op(self) -> vk::CompareOp {
+        match self {
+            DepthConvention::Standard => vk::CompareOp::LESS_OR_EQUAL,
+            DepthConvention::Reverse => vk::CompareOp::GREATER_OR_EQUAL,
+        }
+    }
+
+    /// The farthest depth, to clear depth attachments to.
+    pub fn clear_depth(self) -> f32 {
+        match self {
+            DepthConvention::Standard => 1.0,
+            DepthConvention::Reverse => 0.0,
+        }
+    }
+
+    /// A right-handed perspective projection looking down -z, mapping `near` and `far` to the
+    /// convention's ends of the depth range.  Column major, with y up as in the runner's default
+    /// `RenderCtx::flip_y`.
+    pub fn perspective(self, fov_y: f32, aspect_ratio: f32, near: f32, far: f32) -> [[f32; 4]; 4] {
+        let focal_length = 1.0 / (fov_y / 2.0).tan();
+        let (depth_scale, depth_offset) = self.depth_terms(near, far);
+        [
+            [focal_length / aspect_ratio, 0.0, 0.0, 0.0],
+            [0.0, focal_length, 0.0, 0.0],
+            [0.0, 0.0, depth_scale, -1.0],
+            [0.0, 0.0, depth_offset, 0.0],
+        ]
+    }
+
+    /// Like `perspective`, but off center, from the tangents of the angles between the view
+    /// direction and each side of the frustum.  Left and down are negative, as in an OpenXR
+    /// field of view.
+    pub fn frustum(self, [left, right, down, up]: [f32; 4], near: f32, far: f32) -> [[f32; 4]; 4] {
+        let (depth_scale, depth_offset) = self.depth_terms(near, far);
+        let (width, height) = (right - left, up - down);
+        [
+            [2.0 / width, 0.0, 0.0, 0.0],
+            [0.0, 2.0 / height, 0.0, 0.0],
+            [
+                (right + left) / width,
+                (up + down) / height,
+                depth_scale,
+                -1.0,
+            ],
+            [0.0, 0.0, depth_offset, 0.0],
+        ]
+    }
+
+    fn depth_terms(self, near: f32, far: f32) -> (f32, f32) {
+        match self {
+            DepthConvention::Standard => (far / (near - far), near * far / (near - far)),
+            DepthConvention::Reverse => (near / (far - near), near * far / (far - near)),
+        }
+    }
+}
+
+/// The first of D24S8 and D32S8 the device can use as a depth stencil attachment, for masking with
+/// the stencil test.
+pub fn stencil_format(base: &RenderBase) -> vk::Format {
+    STENCIL_FORMATS
+        .iter()
+        .copied()
+        .find(|&format| {
+            unsafe {
+                base.instance
+                    .get_physical_device_format_properties(base.pdevice, format)
+            }
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+        })
+        .expect("The device supports neither D24S8 nor D32S8 depth stencil attachments")
+}
+
+/// The images backing a config's extra attachments.
+#[derive(Default)]
+pub struct AttachmentImages {
+    images: Vec<(vk::Image, vk::DeviceMemory, vk::ImageView)>,
+}
+
+impl AttachmentImages {
+    pub fn new(base: &RenderBase, config: &RenderPassConfig, extent: vk::Extent2D) -> Self {
+        let images = config
+            .extra_attachments
+            .iter()
+            .map(|attachment| {
+                let transient = attachment.is_transient();
+                let (usage, aspect_mask) = match attachment.kind {
+                    // transient images can't be sampled
+                    AttachmentKind::Color if transient => (
+                        vk::ImageUsageFlags::COLOR_ATTACHMENT
+                            | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+                        vk::ImageAspectFlags::COLOR,
+                    ),
+                    // read back for picking
+                    AttachmentKind::Color => (
+                        vk::ImageUsageFlags::COLOR_ATTACHMENT
+                            | vk::ImageUsageFlags::SAMPLED
+                            | vk::ImageUsageFlags::TRANSFER_SRC,
+                        vk::ImageAspectFlags::COLOR,
+                    ),
+                    AttachmentKind::DepthStencil if transient => (
+                        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                            | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+                        depth_stencil_aspect(attachment.format),
+                    ),
+                    AttachmentKind::DepthStencil => (
+                        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                        depth_stencil_aspect(attachment.format),
+                    ),
+                };
+                let image_info = vk::ImageCreateInfo::builder()
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(attachment.format)
+                    .extent(vk::Extent3D {
+                        width: extent.width,
+                        height: extent.height,
+                        depth: 1,
+                    })
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .usage(usage)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED);
+                let image = unsafe { base.device.create_image(&image_info, None).unwrap() };
+                let memory = unsafe {
+                    let requirements = base.device.get_image_memory_requirements(image);
+                    let lazily_allocated = if transient {
+                        base.find_memory_type(
+                            requirements.memory_type_bits,
+                            vk::MemoryPropertyFlags::DEVICE_LOCAL
+                                | vk::MemoryPropertyFlags::LAZILY_ALLOCATED,
+                        )
+                    } else {
+                        None
+                    };
+                    let memory_type_index = lazily_allocated
+                        .or_else(|| {
+                            base.find_memory_type(
+                                requirements.memory_type_bits,
+                                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                            )
+                        })
+                        .expect("No device local memory type for an attachment");
+                    let allocate_info = vk::MemoryAllocateInfo::builder()
+                        .allocation_size(requirements.size)
+                        .memory_type_index(memory_type_index);
+                    let memory = base.device.allocate_memory(&allocate_info, None).unwrap();
+                    base.device.bind_image_memory(image, memory, 0).unwrap();
+                    memory
+                };
+                let view_info = vk::ImageViewCreateInfo::builder()
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .format(attachment.format)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask,
+                        base_mip_level: 0,
+                        level_count: 1,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    })
+                    .image(image);
+                let view = unsafe { base.device.create_image_view(&view_info, None).unwrap() };
+                (image, memory, view)
+            })
+            .collect();
+        Self { images }
+    }
+
+    /// The image of the extra attachment at `index`.
+    pub fn image(&self, index: usize) -> vk::Image {
+        self.images[index].0
+    }
+
+    pub fn views(&self) -> Vec<vk::ImageView> {
+        self.images.iter().map(|&(_, _, view)| view).collect()
+    }
+
+    /// Barriers moving freshly created images into the layout loading them expects.
+    pub fn initial_barriers(&self, config: &RenderPassConfig) -> Vec<vk::ImageMemoryBarrier> {
+        self.images
+            .iter()
+            .zip(config.extra_attachments.iter())
+            .filter(|(_, attachment)| attachment.load_op == vk::AttachmentLoadOp::LOAD)
+            .map(|(&(image, _, _), attachment)| {
+                let aspect_mask = match attachment.kind {
+                    AttachmentKind::Color => vk::ImageAspectFlags::COLOR,
+                    AttachmentKind::DepthStencil => depth_stencil_aspect(attachment.format),
+                };
+                layout_barrier(image, aspect_mask, attachment.kind.layout())
+            })
+            .collect()
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for (image, memory, view) in self.images.drain(..) {
+            unsafe {
+                device.destroy_image_view(view, None);
+                device.destroy_image(image, None);
+                device.free_memory(memory, None);
+            }
+        }
+    }
+}
+
+/// A barrier moving an image with undefined contents into `layout`.
+pub fn layout_barrier(
+    image: vk::Image,
+    aspect_mask: vk::ImageAspectFlags,
+    layout: vk::ImageLayout,
+) -> vk::ImageMemoryBarrier {
+    vk::ImageMemoryBarrier::builder()
+        .image(image)
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        })
+        .build()
+}
+
+fn depth_stencil_aspect(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        _ => vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+    }
+}
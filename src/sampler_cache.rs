@@ -0,0 +1,110 @@
+//! Samplers created once per configuration and shared by every texture binding asking for the
+//! same one, through `RenderCtx::sampler`.  Like the layouts, they live as long as the
+//! `RenderCtx`.  The presets cover the usual cases:
+//!
+//! ```ignore
+//! let sampler = ctx.sampler(SamplerDesc::LINEAR_CLAMP);
+//! let pixel_art = ctx.sampler(SamplerDesc {
+//!     filter: vk::Filter::NEAREST,
+//!     ..SamplerDesc::LINEAR_REPEAT
+//! });
+//! ```
+
+use crate::ash_runner::RenderBase;
+
+use ash::{
+    version::{DeviceV1_0, InstanceV1_0},
+    vk,
+};
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerDesc {
+    /// For magnification and minification.
+    pub filter: vk::Filter,
+    /// For all three coordinates.
+    pub address_mode: vk::SamplerAddressMode,
+    /// The maximum anisotropy, with 1 for none.  Clamped to the device's limit, and ignored if the
+    /// device doesn't have `samplerAnisotropy`.
+    pub anisotropy: u32,
+    /// Samples every mip level, filtering between them with `filter`, instead of only the first.
+    pub mips: bool,
+}
+
+impl SamplerDesc {
+    pub const NEAREST_CLAMP: Self = Self {
+        filter: vk::Filter::NEAREST,
+        address_mode: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        anisotropy: 1,
+        mips: false,
+    };
+    pub const LINEAR_CLAMP: Self = Self {
+        filter: vk::Filter::LINEAR,
+        ..Self::NEAREST_CLAMP
+    };
+    pub const NEAREST_REPEAT: Self = Self {
+        address_mode: vk::SamplerAddressMode::REPEAT,
+        ..Self::NEAREST_CLAMP
+    };
+    pub const LINEAR_REPEAT: Self = Self {
+        filter: vk::Filter::LINEAR,
+        ..Self::NEAREST_REPEAT
+    };
+    /// For mipmapped textures seen at grazing angles, like ground planes.
+    pub const TRILINEAR_ANISOTROPIC: Self = Self {
+        anisotropy: 16,
+        mips: true,
+        ..Self::LINEAR_REPEAT
+    };
+}
+
+#[derive(Default)]
+pub struct SamplerCache {
+    samplers: HashMap<SamplerDesc, vk::Sampler>,
+}
+
+impl SamplerCache {
+    pub fn sampler(&mut self, base: &RenderBase, desc: SamplerDesc) -> vk::Sampler {
+        *self.samplers.entry(desc).or_insert_with(|| {
+            let max_anisotropy =
+                if desc.anisotropy > 1 && base.enabled_features.sampler_anisotropy == vk::TRUE {
+                    let limits = unsafe {
+                        base.instance
+                            .get_physical_device_properties(base.pdevice)
+                            .limits
+                    };
+                    (desc.anisotropy as f32).min(limits.max_sampler_anisotropy)
+                } else {
+                    1.0
+                };
+            let (mipmap_mode, max_lod) = if desc.mips {
+                let mipmap_mode = match desc.filter {
+                    vk::Filter::NEAREST => vk::SamplerMipmapMode::NEAREST,
+                    _ => vk::SamplerMipmapMode::LINEAR,
+                };
+                (mipmap_mode, vk::LOD_CLAMP_NONE)
+            } else {
+                (vk::SamplerMipmapMode::NEAREST, 0.0)
+            };
+            let sampler_info = vk::SamplerCreateInfo::builder()
+                .mag_filter(desc.filter)
+                .min_filter(desc.filter)
+                .mipmap_mode(mipmap_mode)
+                .address_mode_u(desc.address_mode)
+                .address_mode_v(desc.address_mode)
+                .address_mode_w(desc.address_mode)
+                .anisotropy_enable(max_anisotropy > 1.0)
+                .max_anisotropy(max_anisotropy)
+                .max_lod(max_lod);
+            unsafe { base.device.create_sampler(&sampler_info, None).unwrap() }
+        })
+    }
+
+    /// No descriptor set may use the samplers anymore.
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for (_, sampler) in self.samplers.drain() {
+            unsafe { device.destroy_sampler(sampler, None) };
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! A json file with the pipelines to draw and the clear color, applied again whenever it changes so
+//! a scene can be rearranged without restarting:
+//!
+//! ```json
+//! {
+//!     "clear_color": "0.1,0.1,0.1",
+//!     "pipelines": [
+//!         {
+//!             "name": "sky",
+//!             "vertex": { "module": "sky_shader", "entry_point": "main_vs" },
+//!             "fragment": { "module": "sky_shader", "entry_point": "main_fs" },
+//!             "blend": "alpha_blend"
+//!         }
+//!     ]
+//! }
+//! ```
+//!
+//! The pipelines are drawn in the listed order, before any others.  `clear_color` takes the same
+//! values as `--clear-color`; `enabled` and `blend` default to `true` and `"opaque"`.
+
+use crate::ash_runner::{ClearColor, FragmentShaderEntryPoint, RenderCtx, VertexShaderEntryPoint};
+use crate::pipeline_registry::BlendMode;
+use crate::plugin::Plugin;
+
+use ash::vk;
+use serde::{Deserialize, Deserializer};
+
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneConfig {
+    #[serde(default, deserialize_with = "deserialize_clear_color")]
+    pub clear_color: Option<ClearColor>,
+    pub pipelines: Vec<ScenePipeline>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenePipeline {
+    pub name: String,
+    pub vertex: VertexShaderEntryPoint,
+    pub fragment: FragmentShaderEntryPoint,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    pub blend: Option<BlendMode>,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+fn deserialize_clear_color<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<ClearColor>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(s) => s.parse().map(Some).map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+impl SceneConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|err| err.to_string())?;
+        serde_json::from_reader(BufReader::new(file)).map_err(|err| err.to_string())
+    }
+
+    /// Checks that the shader modules and entry points the pipelines use are loaded, as building a
+    /// pipeline without them panics.
+    fn check(&self, ctx: &RenderCtx) -> Result<(), String> {
+        for pipeline in &self.pipelines {
+            let stages = [
+                (&pipeline.vertex.module, &pipeline.vertex.entry_point),
+                (&pipeline.fragment.module, &pipeline.fragment.entry_point),
+            ];
+            for &(module, entry_point) in stages.iter() {
+                // reflected along with creating each of `ctx.shader_modules`
+                match ctx.entry_points.get(module) {
+                    Some(names) if names.contains(entry_point) => {}
+                    Some(_) => {
+                        return Err(format!(
+                            "pipeline \"{}\": no entry point \"{}\" in module \"{}\"",
+                            pipeline.name, entry_point, module
+                        ))
+                    }
+                    None => {
+                        return Err(format!(
+                            "pipeline \"{}\": no shader module \"{}\"",
+                            pipeline.name, module
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Applies a scene file on init, and again whenever its modification time changes.
+pub struct SceneWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    /// The pipelines the file added, removed again once they are taken out of it.
+    pipelines: Vec<String>,
+}
+
+impl SceneWatcher {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            modified: None,
+            pipelines: Vec::new(),
+        }
+    }
+
+    /// Returns whether the file changed and was applied.  A file that fails to load, or uses shaders
+    /// that aren't loaded, leaves the scene as it is.
+    fn reload(&mut self, ctx: &mut RenderCtx) -> bool {
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        let config = match SceneConfig::load(&self.path)
+            .and_then(|config| config.check(ctx).map(|()| config))
+        {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("{}: {}", self.path.display(), err);
+                return false;
+            }
+        };
+        println!("loaded {}", self.path.display());
+
+        for name in self.pipelines.drain(..) {
+            if config
+                .pipelines
+                .iter()
+                .all(|pipeline| pipeline.name != name)
+            {
+                if let Some(handle) = ctx.pipelines.handle(&name) {
+                    ctx.remove_pipeline(handle);
+                }
+            }
+        }
+        for (index, pipeline) in config.pipelines.into_iter().enumerate() {
+            let handle =
+                ctx.pipelines
+                    .insert(pipeline.name.clone(), pipeline.vertex, pipeline.fragment);
+            ctx.pipelines.move_to(handle, index);
+            let entry = ctx.pipelines.get_mut(handle).unwrap();
+            entry.enabled = pipeline.enabled;
+            entry.config.blend = pipeline.blend.unwrap_or(BlendMode::Opaque);
+            self.pipelines.push(pipeline.name);
+        }
+        if let Some(clear_color) = config.clear_color {
+            ctx.set_clear_color(clear_color);
+        }
+        true
+    }
+}
+
+impl Plugin for SceneWatcher {
+    fn name(&self) -> &str {
+        "scene"
+    }
+
+    fn init(&mut self, ctx: &mut RenderCtx) {
+        // the pipelines are built along with the rest after the plugins are added
+        self.reload(ctx);
+    }
+
+    fn update(&mut self, ctx: &mut RenderCtx) {
+        if self.reload(ctx) {
+            ctx.rebuild_pipelines(vk::PipelineCache::null());
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! Per-frame logic in a rhai script, reloaded whenever the file changes, so it can be iterated on
+//! without recompiling the runner.
+//!
+//! The runner calls these functions of the script, each only if it is defined:
+//! - `init()` after every (re)load,
+//! - `update(time)` before each frame, with the seconds since the script plugin was created,
+//! - `key(name, pressed)` for keys the runner doesn't use, named like `VirtualKeyCode`'s variants.
+//!
+//! They all share `this`, an object map for state that lives until the next reload.  The script can
+//! call `set_param(index, value)` to set one of the `RenderCtx::shader_params`, `set_enabled(name,
+//! enabled)` to switch a pipeline on or off, and `width()` and `height()` for the render size.
+
+use crate::ash_runner::RenderCtx;
+use crate::plugin::Plugin;
+
+use ash::vk;
+use rhai::{Dynamic, Engine, EvalAltResult, ImmutableString, Map, Scope, AST};
+use winit::event::VirtualKeyCode;
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime},
+};
+
+pub struct ScriptPlugin {
+    path: PathBuf,
+    engine: Engine,
+    /// `None` until the script first compiles.
+    ast: Option<AST>,
+    /// The script's `this`.
+    state: Dynamic,
+    /// Modification time of the file the loaded script was compiled from.
+    modified: Option<SystemTime>,
+    shared: Arc<Mutex<Shared>>,
+    started: Instant,
+}
+
+/// What the functions registered with the engine work on, as they can't borrow the `RenderCtx`.
+#[derive(Default)]
+struct Shared {
+    extent: vk::Extent2D,
+    /// Applied to the `RenderCtx` after each call into the script.
+    commands: Vec<Command>,
+}
+
+enum Command {
+    SetParam(usize, f32),
+    SetEnabled(String, bool),
+}
+
+impl ScriptPlugin {
+    pub fn new(path: PathBuf) -> Self {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let mut engine = Engine::new();
+        let state = shared.clone();
+        engine.register_fn("set_param", move |index: i64, value: f64| {
+            state
+                .lock()
+                .unwrap()
+                .commands
+                .push(Command::SetParam(index as usize, value as f32));
+        });
+        let state = shared.clone();
+        engine.register_fn(
+            "set_enabled",
+            move |pipeline: ImmutableString, enabled: bool| {
+                state
+                    .lock()
+                    .unwrap()
+                    .commands
+                    .push(Command::SetEnabled(pipeline.to_string(), enabled));
+            },
+        );
+        let state = shared.clone();
+        engine.register_fn("width", move || state.lock().unwrap().extent.width as i64);
+        let state = shared.clone();
+        engine.register_fn("height", move || state.lock().unwrap().extent.height as i64);
+        Self {
+            path,
+            engine,
+            ast: None,
+            state: Dynamic::from(Map::new()),
+            modified: None,
+            shared,
+            started: Instant::now(),
+        }
+    }
+
+    /// Recompiles the script if the file changed.  If it doesn't compile, the last version that did
+    /// keeps running.
+    fn reload(&mut self) {
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified.is_none() || modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+        match self.engine.compile_file(self.path.clone()) {
+            Ok(ast) => {
+                println!("loaded {}", self.path.display());
+                self.ast = Some(ast);
+                self.state = Dynamic::from(Map::new());
+                self.call("init", Vec::new());
+            }
+            Err(err) => eprintln!("{}: {}", self.path.display(), err),
+        }
+    }
+
+    /// Calls a function of the script if it defines one with this name.
+    fn call<A: AsMut<[Dynamic]>>(&mut self, name: &str, args: A) {
+        let ast = match &self.ast {
+            Some(ast) => ast,
+            None => return,
+        };
+        let result =
+            self.engine
+                .call_fn_dynamic(&mut Scope::new(), ast, name, Some(&mut self.state), args);
+        if let Err(err) = result {
+            match *err {
+                EvalAltResult::ErrorFunctionNotFound(ref signature, _)
+                    if signature.starts_with(name) => {}
+                _ => eprintln!("{}: {}", self.path.display(), err),
+            }
+        }
+    }
+
+    fn apply_commands(&self, ctx: &mut RenderCtx) {
+        let commands = std::mem::take(&mut self.shared.lock().unwrap().commands);
+        for command in commands {
+            match command {
+                Command::SetParam(index, value) => match ctx.shader_params.get_mut(index) {
+                    Some(param) => *param = value,
+                    None => eprintln!("set_param: no shader param {}", index),
+                },
+                Command::SetEnabled(name, enabled) => match ctx.pipelines.handle(&name) {
+                    Some(handle) => ctx.pipelines.set_enabled(handle, enabled),
+                    None => eprintln!("set_enabled: no pipeline named \"{}\"", name),
+                },
+            }
+        }
+    }
+}
+
+impl Plugin for ScriptPlugin {
+    fn name(&self) -> &str {
+        "script"
+    }
+
+    fn update(&mut self, ctx: &mut RenderCtx) {
+        self.shared.lock().unwrap().extent = ctx.render_extent();
+        self.reload();
+        let time = self.started.elapsed().as_secs_f64();
+        self.call("update", [Dynamic::from(time)]);
+        self.apply_commands(ctx);
+    }
+
+    fn key(&mut self, ctx: &mut RenderCtx, key: VirtualKeyCode, pressed: bool) {
+        let name = format!("{:?}", key);
+        self.call("key", [Dynamic::from(name), Dynamic::from(pressed)]);
+        self.apply_commands(ctx);
+    }
+}
@@ -0,0 +1,103 @@
+//! What the runner remembers between runs: the window's size, position and fullscreen state, the
+//! present mode and which pipelines were enabled.  Saved to `--settings`, `ash-runner.ron` in the
+//! working directory by default, on exit and restored at startup.  Command line options win over
+//! restored settings, and `--no-settings` neither restores nor saves them.
+
+use crate::ash_runner::{parse_present_mode, RenderCtx};
+
+use serde::{Deserialize, Serialize};
+use winit::window::Window;
+
+use std::{fs::File, io::BufReader, path::Path};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Inner size in physical pixels, from the last run that ended windowed.
+    pub size: Option<[u32; 2]>,
+    /// Outer position in physical pixels, from the last run that ended windowed.
+    pub position: Option<[i32; 2]>,
+    /// Borderless fullscreen.
+    pub fullscreen: bool,
+    /// Like `MAILBOX` or `FIFO`.
+    pub present_mode: Option<String>,
+    /// Names of the enabled pipelines.
+    pub enabled_pipelines: Option<Vec<String>>,
+}
+
+impl Settings {
+    /// The defaults if the file doesn't exist.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err.to_string()),
+        };
+        ron::de::from_reader(BufReader::new(file)).map_err(|err| err.to_string())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| err.to_string())?;
+        std::fs::write(path, text).map_err(|err| err.to_string())
+    }
+
+    /// Takes the present mode and enabled pipelines from `ctx`.
+    pub fn update_from_ctx(&mut self, ctx: &RenderCtx) {
+        self.present_mode = Some(format!("{:?}", ctx.present_mode));
+        self.enabled_pipelines = Some(
+            ctx.pipelines
+                .entries()
+                .filter(|entry| entry.enabled)
+                .map(|entry| entry.name.clone())
+                .collect(),
+        );
+    }
+
+    /// Takes the window's geometry, keeping the windowed size and position while fullscreen.
+    pub fn update_from_window(&mut self, window: &Window) {
+        self.fullscreen = window.fullscreen().is_some();
+        if self.fullscreen {
+            return;
+        }
+        let size = window.inner_size();
+        self.size = Some([size.width, size.height]);
+        if let Ok(position) = window.outer_position() {
+            self.position = Some([position.x, position.y]);
+        }
+    }
+
+    /// Restores the present mode and enabled pipelines.  Pipelines are left alone if none of the
+    /// saved ones exist, e.g. with another scene.
+    pub fn apply(&self, ctx: &mut RenderCtx) {
+        if let Some(name) = &self.present_mode {
+            match parse_present_mode(name) {
+                Ok(present_mode) => ctx.set_present_mode(present_mode),
+                Err(err) => eprintln!("settings: present mode: {}", err),
+            }
+        }
+        let enabled = match &self.enabled_pipelines {
+            Some(enabled)
+                if enabled
+                    .iter()
+                    .any(|name| ctx.pipelines.handle(name).is_some()) =>
+            {
+                enabled
+            }
+            _ => return,
+        };
+        let handles = ctx
+            .pipelines
+            .entries()
+            .map(|entry| {
+                (
+                    ctx.pipelines.handle(&entry.name).unwrap(),
+                    entry.name.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        for (handle, name) in handles {
+            ctx.pipelines.set_enabled(handle, enabled.contains(&name));
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Loading `.spv` files dropped onto the window.  A file replaces the shader module of the same
+//! name, its file stem, and the pipelines using it are rebuilt, like on a reload.  A module with a
+//! new name is loaded next to the others and its entry points listed; if it has a fragment shader,
+//! pressing Enter, or the key bound to `Action::BindDroppedShader`, draws it instead of the enabled
+//! pipelines.  Without a vertex shader of its own it is paired with the first enabled pipeline's,
+//! which suits fullscreen fragment shaders.
+//!
+//! The next build of the shader crate doesn't know about dropped modules, and replaces them if they
+//! are named like one of its own.
+//!
+//! A dropped directory with a Cargo.toml becomes the shader crate: it is built, and its modules are
+//! loaded, replacing those of the same names.  If the enabled pipelines use none of them, the first
+//! module with a fragment shader is drawn instead, as if it had been dropped and bound.
+
+use crate::ash_runner::{FragmentShaderEntryPoint, RenderCtx, SpirvShader, VertexShaderEntryPoint};
+use crate::keymap::Action;
+use crate::reflect::{self, Stage};
+
+use ash::{util::read_spv, vk};
+
+use std::{fs::File, path::Path};
+
+/// A dropped module waiting to be bound.
+pub struct ShaderOffer {
+    name: String,
+    vertex: VertexShaderEntryPoint,
+    fragment: FragmentShaderEntryPoint,
+}
+
+/// Loads the module at `path`, returning an offer to bind it if it is new.
+pub(crate) fn load(ctx: &mut RenderCtx, path: &Path) -> Option<ShaderOffer> {
+    let name = path.file_stem()?.to_string_lossy().into_owned();
+    let spirv = match File::open(path).and_then(|mut file| read_spv(&mut file)) {
+        Ok(spirv) => spirv,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", path.display(), err);
+            return None;
+        }
+    };
+    if let Err(err) = reflect::validate(&spirv) {
+        eprintln!("{}: {}", path.display(), err);
+        return None;
+    }
+    let stages = reflect::entry_point_stages(&spirv);
+    let replaced = ctx.shader_modules.contains_key(&name);
+    if ctx.insert_shader_module(name.clone(), spirv) && replaced {
+        println!("replaced shader module \"{}\"", name);
+        ctx.rebuild_pipelines(vk::PipelineCache::null());
+    }
+    if replaced {
+        return None;
+    }
+
+    println!("loaded shader module \"{}\" with entry points:", name);
+    for (entry_point, stage) in stages.iter() {
+        println!("  {} ({:?})", entry_point, stage);
+    }
+    let offer = offer(ctx, name, &stages);
+    if offer.is_none() {
+        println!("no fragment shader to draw, or no vertex shader to draw it with");
+    }
+    if let (Some(offer), Some(key)) = (&offer, ctx.keymap.key(Action::BindDroppedShader)) {
+        println!(
+            "press {:?} to draw {}::{} with {}::{} instead of the enabled pipelines",
+            key,
+            offer.fragment.module,
+            offer.fragment.entry_point,
+            offer.vertex.module,
+            offer.vertex.entry_point
+        );
+    }
+    offer
+}
+
+/// Replaces the shader modules with those of a newly switched to shader crate and rebuilds.
+pub(crate) fn replace_shader_set(ctx: &mut RenderCtx, shaders: Vec<SpirvShader>) {
+    let names = shaders
+        .iter()
+        .map(|shader| shader.name.clone())
+        .collect::<Vec<_>>();
+    let mut offers = Vec::new();
+    for SpirvShader { name, spirv } in shaders {
+        let stages = reflect::entry_point_stages(&spirv);
+        ctx.insert_shader_module(name.clone(), spirv);
+        offers.push((name, stages));
+    }
+    let in_use = ctx.pipelines.entries().any(|entry| {
+        entry.enabled
+            && (names.contains(&entry.vertex.module) || names.contains(&entry.fragment.module))
+    });
+    if in_use {
+        ctx.rebuild_pipelines(vk::PipelineCache::null());
+        return;
+    }
+    let offer = offers
+        .into_iter()
+        .find_map(|(name, stages)| offer(ctx, name, &stages));
+    match offer {
+        Some(offer) => offer.bind(ctx),
+        None => eprintln!("the shader crate has no fragment shader to draw"),
+    }
+}
+
+/// Pairs the module's first fragment shader with its first vertex shader, or the first enabled
+/// pipeline's.
+fn offer(ctx: &RenderCtx, name: String, stages: &[(String, Stage)]) -> Option<ShaderOffer> {
+    let entry_point = |wanted| {
+        stages
+            .iter()
+            .find(|(_, stage)| *stage == wanted)
+            .map(|(entry_point, _)| entry_point.clone())
+    };
+    let fragment = match entry_point(Stage::Fragment) {
+        Some(entry_point) => FragmentShaderEntryPoint {
+            module: name.clone(),
+            entry_point,
+        },
+        None => return None,
+    };
+    let vertex = match entry_point(Stage::Vertex) {
+        Some(entry_point) => VertexShaderEntryPoint {
+            module: name.clone(),
+            entry_point,
+        },
+        None => {
+            let active = ctx.pipelines.entries().find(|entry| entry.enabled)?;
+            VertexShaderEntryPoint {
+                module: active.vertex.module.clone(),
+                entry_point: active.vertex.entry_point.clone(),
+            }
+        }
+    };
+    Some(ShaderOffer {
+        name,
+        vertex,
+        fragment,
+    })
+}
+
+impl ShaderOffer {
+    /// Adds a pipeline named after the module, disables the others and rebuilds.
+    pub(crate) fn bind(self, ctx: &mut RenderCtx) {
+        let handles = ctx
+            .pipelines
+            .entries()
+            .map(|entry| ctx.pipelines.handle(&entry.name).unwrap())
+            .collect::<Vec<_>>();
+        for handle in handles {
+            ctx.pipelines.set_enabled(handle, false);
+        }
+        let handle = ctx.pipelines.insert(self.name, self.vertex, self.fragment);
+        ctx.pipelines.set_enabled(handle, true);
+        ctx.rebuild_pipelines(vk::PipelineCache::null());
+        println!("drawing the dropped shader");
+    }
+}
@@ -0,0 +1,545 @@
+//! Batches of textured quads, for HUDs and other overlays and for trying out textured pipelines end
+//! to end.  A `SpriteBatch` owns a texture atlas and a pipeline of its own, and draws the sprites
+//! of a frame over the window from a host visible vertex buffer with a single draw call.  The text
+//! renderer is a sprite batch with a font atlas.
+//!
+//! The shaders are `sprite.vert` and `sprite.frag` next to this file, compiled to SPIR-V by
+//! `build.rs`: the vertex shader passes the position through, and the fragment shader multiplies
+//! the vertex color with the atlas.
+
+use crate::ash_runner::{Pipeline, PipelineDescriptor, RenderCtx};
+use crate::pipeline_registry::BlendMode;
+use crate::sampler_cache::SamplerDesc;
+
+use ash::{util::read_spv, version::DeviceV1_0, vk};
+
+use std::{ffi::CStr, io::Cursor, mem, os::raw::c_void, ptr};
+
+/// Sprites drawn per frame by one batch; the rest are left out.
+pub const MAX_SPRITES: usize = 4096;
+/// The atlas' format.  Colors are in sRGB, alpha is straight.
+pub const FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+const VERTICES_PER_SPRITE: usize = 6;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Vertex {
+    /// In normalized device coordinates.
+    position: [f32; 2],
+    uv: [f32; 2],
+    color: [f32; 4],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sprite {
+    /// The top left corner, in physical pixels.
+    pub position: [f32; 2],
+    /// In physical pixels.
+    pub size: [f32; 2],
+    /// The part of the atlas drawn, in atlas pixels.
+    pub source: vk::Rect2D,
+    /// Multiplied with the atlas, in linear RGB with straight alpha.
+    pub color: [f32; 4],
+}
+
+/// Draws `Sprite`s from one atlas into the window's render pass.  Owned by the plugin using it,
+/// which creates it in `Plugin::init`, calls `recreate_pipeline` from `Plugin::pipelines_rebuilt`
+/// and `destroy` from `Plugin::destroy`.
+pub struct SpriteBatch {
+    vertex_module: vk::ShaderModule,
+    fragment_module: vk::ShaderModule,
+    atlas_extent: vk::Extent2D,
+    atlas: vk::Image,
+    atlas_memory: vk::DeviceMemory,
+    atlas_view: vk::ImageView,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline: Pipeline,
+    vertex_buffer: vk::Buffer,
+    vertex_memory: vk::DeviceMemory,
+    /// `vertex_memory`, mapped for as long as the batch lives.
+    vertices: *mut c_void,
+}
+
+// `vertices` is only written through `draw`, which the render thread calls
+unsafe impl Send for SpriteBatch {}
+
+impl SpriteBatch {
+    /// Uploads the atlas, `FORMAT` pixels row by row from the top, sampled with `filter`.
+    pub fn new(
+        ctx: &RenderCtx,
+        atlas_extent: vk::Extent2D,
+        pixels: &[u8],
+        filter: vk::Filter,
+    ) -> Self {
+        assert_eq!(
+            pixels.len(),
+            atlas_extent.width as usize * atlas_extent.height as usize * 4,
+            "The atlas pixels don't match its size"
+        );
+        let base = &ctx.base;
+        let device = &base.device;
+        let create_module = |bytes: &[u8]| {
+            let spirv = read_spv(&mut Cursor::new(bytes)).expect("Invalid SPIR-V");
+            let shader_info = vk::ShaderModuleCreateInfo::builder().code(&spirv);
+            unsafe {
+                device
+                    .create_shader_module(&shader_info, None)
+                    .expect("Shader module error")
+            }
+        };
+        let vertex_module =
+            create_module(include_bytes!(concat!(env!("OUT_DIR"), "/sprite.vert.spv")));
+        let fragment_module =
+            create_module(include_bytes!(concat!(env!("OUT_DIR"), "/sprite.frag.spv")));
+
+        let (atlas, atlas_memory, atlas_view) = create_atlas(ctx, atlas_extent, pixels);
+        let sampler = ctx.sampler(SamplerDesc {
+            filter,
+            ..SamplerDesc::NEAREST_CLAMP
+        });
+
+        let bindings = [vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        }];
+        let descriptor_set_layout = ctx.descriptor_set_layout(&bindings);
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(1)
+            .pool_sizes(&pool_sizes);
+        let descriptor_pool = unsafe { device.create_descriptor_pool(&pool_info, None).unwrap() };
+        let set_layouts = [descriptor_set_layout];
+        let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&set_layouts);
+        let descriptor_set = unsafe { device.allocate_descriptor_sets(&allocate_info).unwrap()[0] };
+        let image_infos = [vk::DescriptorImageInfo {
+            sampler,
+            image_view: atlas_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos)
+            .build();
+        unsafe { device.update_descriptor_sets(&[write], &[]) };
+
+        let buffer_size = MAX_SPRITES * VERTICES_PER_SPRITE * mem::size_of::<Vertex>();
+        let (vertex_buffer, vertex_memory) = create_host_buffer(
+            ctx,
+            buffer_size as vk::DeviceSize,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        );
+        let vertices = unsafe {
+            device
+                .map_memory(
+                    vertex_memory,
+                    0,
+                    vk::WHOLE_SIZE,
+                    vk::MemoryMapFlags::empty(),
+                )
+                .unwrap()
+        };
+
+        Self {
+            vertex_module,
+            fragment_module,
+            atlas_extent,
+            atlas,
+            atlas_memory,
+            atlas_view,
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_set,
+            pipeline: create_pipeline(ctx, vertex_module, fragment_module, descriptor_set_layout),
+            vertex_buffer,
+            vertex_memory,
+            vertices,
+        }
+    }
+
+    pub fn atlas_extent(&self) -> vk::Extent2D {
+        self.atlas_extent
+    }
+
+    /// Recreates the pipeline for the current render pass.  The device has to be idle.
+    pub fn recreate_pipeline(&mut self, ctx: &RenderCtx) {
+        destroy_pipeline(&ctx.base.device, &self.pipeline);
+        self.pipeline = create_pipeline(
+            ctx,
+            self.vertex_module,
+            self.fragment_module,
+            self.descriptor_set_layout,
+        );
+    }
+
+    /// Records drawing `sprites` over the whole window, in order, with one draw call.  Their
+    /// vertices go in a buffer reused every frame, so call it at most once per frame, from
+    /// `Plugin::draw`.
+    pub fn draw(&self, ctx: &RenderCtx, command_buffer: vk::CommandBuffer, sprites: &[Sprite]) {
+        let extent = ctx.extent;
+        let sprites = &sprites[..sprites.len().min(MAX_SPRITES)];
+        let mut vertices = Vec::with_capacity(sprites.len() * VERTICES_PER_SPRITE);
+        for sprite in sprites {
+            vertices.extend_from_slice(&self.vertices(sprite, extent));
+        }
+        if vertices.is_empty() {
+            return;
+        }
+        let device = &ctx.base.device;
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        let scissor = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        };
+        unsafe {
+            // the last frame reading the buffer finished before this one was recorded
+            ptr::copy_nonoverlapping(
+                vertices.as_ptr(),
+                self.vertices as *mut Vertex,
+                vertices.len(),
+            );
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline,
+            );
+            device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffer], &[0]);
+            device.cmd_draw(command_buffer, vertices.len() as u32, 1, 0, 0);
+        }
+    }
+
+    /// Two triangles covering `sprite` in a window of `extent`.
+    fn vertices(&self, sprite: &Sprite, extent: vk::Extent2D) -> [Vertex; VERTICES_PER_SPRITE] {
+        let atlas = self.atlas_extent;
+        let source = sprite.source;
+        let corner = |dx: f32, dy: f32| {
+            let x = sprite.position[0] + dx * sprite.size[0];
+            let y = sprite.position[1] + dy * sprite.size[1];
+            let u = source.offset.x as f32 + dx * source.extent.width as f32;
+            let v = source.offset.y as f32 + dy * source.extent.height as f32;
+            Vertex {
+                position: [
+                    2.0 * x / extent.width as f32 - 1.0,
+                    2.0 * y / extent.height as f32 - 1.0,
+                ],
+                uv: [u / atlas.width as f32, v / atlas.height as f32],
+                color: sprite.color,
+            }
+        };
+        let (top_left, top_right) = (corner(0.0, 0.0), corner(1.0, 0.0));
+        let (bottom_left, bottom_right) = (corner(0.0, 1.0), corner(1.0, 1.0));
+        [
+            top_left,
+            bottom_left,
+            top_right,
+            top_right,
+            bottom_left,
+            bottom_right,
+        ]
+    }
+
+    /// The device has to be idle.
+    pub fn destroy(&mut self, device: &ash::Device) {
+        destroy_pipeline(device, &self.pipeline);
+        unsafe {
+            // frees the set along with it
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_image_view(self.atlas_view, None);
+            device.destroy_image(self.atlas, None);
+            device.free_memory(self.atlas_memory, None);
+            device.unmap_memory(self.vertex_memory);
+            device.destroy_buffer(self.vertex_buffer, None);
+            device.free_memory(self.vertex_memory, None);
+            device.destroy_shader_module(self.vertex_module, None);
+            device.destroy_shader_module(self.fragment_module, None);
+        }
+    }
+}
+
+fn create_pipeline(
+    ctx: &RenderCtx,
+    vertex_module: vk::ShaderModule,
+    fragment_module: vk::ShaderModule,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+) -> Pipeline {
+    let entry_point = unsafe { CStr::from_bytes_with_nul_unchecked(b"main\0") };
+    let bindings = [vk::VertexInputBindingDescription {
+        binding: 0,
+        stride: mem::size_of::<Vertex>() as u32,
+        input_rate: vk::VertexInputRate::VERTEX,
+    }];
+    let attributes = [
+        vk::VertexInputAttributeDescription {
+            location: 0,
+            binding: 0,
+            format: vk::Format::R32G32_SFLOAT,
+            offset: 0,
+        },
+        vk::VertexInputAttributeDescription {
+            location: 1,
+            binding: 0,
+            format: vk::Format::R32G32_SFLOAT,
+            offset: 8,
+        },
+        vk::VertexInputAttributeDescription {
+            location: 2,
+            binding: 0,
+            format: vk::Format::R32G32B32A32_SFLOAT,
+            offset: 16,
+        },
+    ];
+    // the fragment shader only writes the swapchain image, leave any other attachment alone
+    let mut attachments =
+        vec![BlendMode::Opaque.attachment_state(); ctx.render_pass_config.color_attachment_count()];
+    for attachment in attachments.iter_mut() {
+        attachment.color_write_mask = vk::ColorComponentFlags::empty();
+    }
+    attachments[0] = BlendMode::AlphaBlend.attachment_state();
+    let desc = PipelineDescriptor::builder(Box::new([
+        vk::PipelineShaderStageCreateInfo {
+            module: vertex_module,
+            p_name: entry_point.as_ptr(),
+            stage: vk::ShaderStageFlags::VERTEX,
+            ..Default::default()
+        },
+        vk::PipelineShaderStageCreateInfo {
+            module: fragment_module,
+            p_name: entry_point.as_ptr(),
+            stage: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        },
+    ]))
+    .vertex_input(&bindings, &attributes)
+    .color_blend_attachments(&attachments)
+    .build();
+
+    let pipeline_layout = ctx.pipeline_layout(&[descriptor_set_layout], None);
+    Pipeline::with_layout(
+        ctx,
+        desc,
+        pipeline_layout,
+        ctx.render_pass,
+        vk::PipelineCache::null(),
+    )
+}
+
+fn destroy_pipeline(device: &ash::Device, pipeline: &Pipeline) {
+    unsafe { device.destroy_pipeline(pipeline.pipeline, None) };
+}
+
+/// A buffer in host visible, coherent memory.
+fn create_host_buffer(
+    ctx: &RenderCtx,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+) -> (vk::Buffer, vk::DeviceMemory) {
+    let device = &ctx.base.device;
+    let buffer_info = vk::BufferCreateInfo::builder()
+        .size(size)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let buffer = unsafe { device.create_buffer(&buffer_info, None).unwrap() };
+    let memory = unsafe {
+        let requirements = device.get_buffer_memory_requirements(buffer);
+        let memory_type_index = ctx
+            .base
+            .find_memory_type(
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )
+            .expect("No host visible memory type for the sprite vertices");
+        let allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type_index);
+        let memory = device.allocate_memory(&allocate_info, None).unwrap();
+        device.bind_buffer_memory(buffer, memory, 0).unwrap();
+        memory
+    };
+    (buffer, memory)
+}
+
+/// Creates the atlas image and uploads `pixels` into it, waiting for the upload to finish.
+pub(crate) fn create_atlas(
+    ctx: &RenderCtx,
+    atlas_extent: vk::Extent2D,
+    pixels: &[u8],
+) -> (vk::Image, vk::DeviceMemory, vk::ImageView) {
+    let device = &ctx.base.device;
+    let extent = vk::Extent3D {
+        width: atlas_extent.width,
+        height: atlas_extent.height,
+        depth: 1,
+    };
+    let image_info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(FORMAT)
+        .extent(extent)
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .initial_layout(vk::ImageLayout::UNDEFINED);
+    let image = unsafe { device.create_image(&image_info, None).unwrap() };
+    let memory = unsafe {
+        let requirements = device.get_image_memory_requirements(image);
+        let memory_type_index = ctx
+            .base
+            .find_memory_type(
+                requirements.memory_type_bits,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .expect("No device local memory type for the sprite atlas");
+        let allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type_index);
+        let memory = device.allocate_memory(&allocate_info, None).unwrap();
+        device.bind_image_memory(image, memory, 0).unwrap();
+        memory
+    };
+    let subresource_range = vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+    let view_info = vk::ImageViewCreateInfo::builder()
+        .view_type(vk::ImageViewType::TYPE_2D)
+        .format(FORMAT)
+        .subresource_range(subresource_range)
+        .image(image);
+    let view = unsafe { device.create_image_view(&view_info, None).unwrap() };
+
+    let (staging, staging_memory) = create_host_buffer(
+        ctx,
+        pixels.len() as vk::DeviceSize,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+    );
+    let command_buffer = ctx.commands.setup_command_buffer;
+    let fence = ctx.sync.setup_commands_reuse_fence;
+    unsafe {
+        let mapped = device
+            .map_memory(
+                staging_memory,
+                0,
+                vk::WHOLE_SIZE,
+                vk::MemoryMapFlags::empty(),
+            )
+            .unwrap();
+        ptr::copy_nonoverlapping(pixels.as_ptr(), mapped as *mut u8, pixels.len());
+        device.unmap_memory(staging_memory);
+
+        device
+            .wait_for_fences(&[fence], true, std::u64::MAX)
+            .expect("Wait for fence failed.");
+        device.reset_fences(&[fence]).expect("Reset fences failed.");
+        device
+            .begin_command_buffer(
+                command_buffer,
+                &vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )
+            .expect("Begin commandbuffer");
+        let to_transfer = vk::ImageMemoryBarrier::builder()
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource_range)
+            .build();
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_transfer],
+        );
+        let region = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D::default(),
+            image_extent: extent,
+        };
+        device.cmd_copy_buffer_to_image(
+            command_buffer,
+            staging,
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[region],
+        );
+        let to_shader_read = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource_range)
+            .build();
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_shader_read],
+        );
+        device
+            .end_command_buffer(command_buffer)
+            .expect("End commandbuffer");
+        let command_buffers = [command_buffer];
+        let submit_info = vk::SubmitInfo::builder().command_buffers(&command_buffers);
+        device
+            .queue_submit(ctx.base.present_queue, &[submit_info.build()], fence)
+            .expect("queue submit failed.");
+        device
+            .wait_for_fences(&[fence], true, std::u64::MAX)
+            .expect("Wait for fence failed.");
+        device.destroy_buffer(staging, None);
+        device.free_memory(staging_memory, None);
+    }
+    (image, memory, view)
+}
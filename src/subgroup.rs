@@ -0,0 +1,118 @@
+//! What the device offers in subgroups: their size, the stages they're available in and the
+//! operations supported.  Queried at startup, and shown by `info`.  Shader modules declaring
+//! subgroup operations the device lacks, or using them in a stage without subgroup support, get a
+//! warning when they're loaded, as the driver may otherwise accept them and draw garbage.
+
+use crate::reflect::{self, Stage, SubgroupOperation};
+
+use ash::{
+    version::{InstanceV1_0, InstanceV1_1},
+    vk,
+};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SubgroupSupport {
+    /// 0 on a Vulkan 1.0 device, which has no subgroups.
+    pub size: u32,
+    pub stages: vk::ShaderStageFlags,
+    pub operations: vk::SubgroupFeatureFlags,
+    /// Otherwise quad operations are only supported in fragment and compute shaders.
+    pub quad_operations_in_all_stages: bool,
+}
+
+impl SubgroupSupport {
+    pub fn new(instance: &ash::Instance, pdevice: vk::PhysicalDevice) -> Self {
+        let api_version = unsafe { instance.get_physical_device_properties(pdevice) }.api_version;
+        if vk::version_major(api_version) == 1 && vk::version_minor(api_version) == 0 {
+            return Self::default();
+        }
+        let mut subgroup = vk::PhysicalDeviceSubgroupProperties::default();
+        let mut properties = vk::PhysicalDeviceProperties2::builder().push_next(&mut subgroup);
+        unsafe { instance.get_physical_device_properties2(pdevice, &mut properties) };
+        Self {
+            size: subgroup.subgroup_size,
+            stages: subgroup.supported_stages,
+            operations: subgroup.supported_operations,
+            quad_operations_in_all_stages: subgroup.quad_operations_in_all_stages == vk::TRUE,
+        }
+    }
+
+    /// Why the SPIR-V module can't use the subgroup operations it declares, empty if it can.
+    pub fn check(&self, spirv: &[u32]) -> Vec<String> {
+        let required = operations(&reflect::subgroup_operations(spirv));
+        if required.is_empty() {
+            return Vec::new();
+        }
+        let mut problems = Vec::new();
+        let missing = required & !self.operations;
+        if !missing.is_empty() {
+            problems.push(format!(
+                "uses unsupported subgroup operations {:?}",
+                missing
+            ));
+        }
+        for (name, stage) in reflect::entry_point_stages(spirv) {
+            let stage = match stage {
+                Stage::Vertex => vk::ShaderStageFlags::VERTEX,
+                Stage::Fragment => vk::ShaderStageFlags::FRAGMENT,
+                Stage::Compute => vk::ShaderStageFlags::COMPUTE,
+                Stage::Other => continue,
+            };
+            if !self.stages.contains(stage) {
+                problems.push(format!(
+                    "entry point \"{}\" has no subgroup support in the {:?} stage",
+                    name, stage
+                ));
+            } else if required.contains(vk::SubgroupFeatureFlags::QUAD)
+                && stage == vk::ShaderStageFlags::VERTEX
+                && !self.quad_operations_in_all_stages
+            {
+                problems.push(format!(
+                    "entry point \"{}\" has no quad operations in the vertex stage",
+                    name
+                ));
+            }
+        }
+        problems
+    }
+}
+
+impl std::fmt::Display for SubgroupSupport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.size == 0 {
+            return write!(f, "none");
+        }
+        write!(
+            f,
+            "size {}, {:?} in {:?}",
+            self.size, self.operations, self.stages
+        )?;
+        if self.operations.contains(vk::SubgroupFeatureFlags::QUAD)
+            && !self.quad_operations_in_all_stages
+        {
+            write!(f, ", quad in fragment and compute only")?;
+        }
+        Ok(())
+    }
+}
+
+/// The Vulkan flags for operations found by reflection.
+pub fn operations(operations: &[SubgroupOperation]) -> vk::SubgroupFeatureFlags {
+    operations
+        .iter()
+        .fold(vk::SubgroupFeatureFlags::empty(), |flags, operation| {
+            flags
+                | match operation {
+                    SubgroupOperation::Basic => vk::SubgroupFeatureFlags::BASIC,
+                    SubgroupOperation::Vote => vk::SubgroupFeatureFlags::VOTE,
+                    SubgroupOperation::Arithmetic => vk::SubgroupFeatureFlags::ARITHMETIC,
+                    SubgroupOperation::Ballot => vk::SubgroupFeatureFlags::BALLOT,
+                    SubgroupOperation::Shuffle => vk::SubgroupFeatureFlags::SHUFFLE,
+                    SubgroupOperation::ShuffleRelative => {
+                        vk::SubgroupFeatureFlags::SHUFFLE_RELATIVE
+                    }
+                    SubgroupOperation::Clustered => vk::SubgroupFeatureFlags::CLUSTERED,
+                    SubgroupOperation::Quad => vk::SubgroupFeatureFlags::QUAD,
+                }
+        })
+}
@@ -0,0 +1,635 @@
+//! Submits and barriers through `VK_KHR_synchronization2` where the device has it, enabled
+//! whenever it is supported, and through `vkQueueSubmit` and `vkCmdPipelineBarrier` otherwise.
+//! Each barrier carries its own stages, and the finer stages and accesses only the extension has,
+//! like `COPY` or `SHADER_SAMPLED_READ`, can be used either way: without the extension they widen
+//! to the core ones containing them.
+//!
+//! ```ignore
+//! ctx.synchronization2.pipeline_barrier(
+//!     device,
+//!     command_buffer,
+//!     &Dependency {
+//!         memory: &[MemoryBarrier {
+//!             src_stage: PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+//!             src_access: AccessFlags2::COLOR_ATTACHMENT_WRITE,
+//!             dst_stage: PipelineStageFlags2::FRAGMENT_SHADER,
+//!             dst_access: AccessFlags2::SHADER_SAMPLED_READ,
+//!         }],
+//!         ..Dependency::default()
+//!     },
+//! );
+//! ```
+//!
+//! ash 0.31 predates the extension, so the definitions it needs are declared here.
+
+use crate::ash_runner::RenderBase;
+
+use ash::{
+    prelude::VkResult,
+    version::{DeviceV1_0, InstanceV1_0, InstanceV1_1},
+    vk,
+};
+
+use std::{ffi::CStr, mem, ops::BitOr, os::raw::c_void, ptr};
+
+const STRUCTURE_TYPE_MEMORY_BARRIER_2: vk::StructureType =
+    vk::StructureType::from_raw(1_000_314_000);
+const STRUCTURE_TYPE_BUFFER_MEMORY_BARRIER_2: vk::StructureType =
+    vk::StructureType::from_raw(1_000_314_001);
+const STRUCTURE_TYPE_IMAGE_MEMORY_BARRIER_2: vk::StructureType =
+    vk::StructureType::from_raw(1_000_314_002);
+const STRUCTURE_TYPE_DEPENDENCY_INFO: vk::StructureType =
+    vk::StructureType::from_raw(1_000_314_003);
+const STRUCTURE_TYPE_SUBMIT_INFO_2: vk::StructureType = vk::StructureType::from_raw(1_000_314_004);
+const STRUCTURE_TYPE_SEMAPHORE_SUBMIT_INFO: vk::StructureType =
+    vk::StructureType::from_raw(1_000_314_005);
+const STRUCTURE_TYPE_COMMAND_BUFFER_SUBMIT_INFO: vk::StructureType =
+    vk::StructureType::from_raw(1_000_314_006);
+const STRUCTURE_TYPE_PHYSICAL_DEVICE_SYNCHRONIZATION_2_FEATURES: vk::StructureType =
+    vk::StructureType::from_raw(1_000_314_007);
+
+pub fn extension_name() -> &'static CStr {
+    unsafe { CStr::from_bytes_with_nul_unchecked(b"VK_KHR_synchronization2\0") }
+}
+
+/// `VkPipelineStageFlags2`: the core stages at the same bits, and finer ones above them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PipelineStageFlags2(pub u64);
+
+impl PipelineStageFlags2 {
+    pub const NONE: Self = Self(0);
+    pub const DRAW_INDIRECT: Self = Self(0x2);
+    pub const VERTEX_SHADER: Self = Self(0x8);
+    pub const FRAGMENT_SHADER: Self = Self(0x80);
+    pub const EARLY_FRAGMENT_TESTS: Self = Self(0x100);
+    pub const LATE_FRAGMENT_TESTS: Self = Self(0x200);
+    pub const COLOR_ATTACHMENT_OUTPUT: Self = Self(0x400);
+    pub const COMPUTE_SHADER: Self = Self(0x800);
+    pub const ALL_TRANSFER: Self = Self(0x1000);
+    pub const HOST: Self = Self(0x4000);
+    pub const ALL_GRAPHICS: Self = Self(0x8000);
+    pub const ALL_COMMANDS: Self = Self(0x1_0000);
+    pub const COPY: Self = Self(0x1_0000_0000);
+    pub const RESOLVE: Self = Self(0x2_0000_0000);
+    pub const BLIT: Self = Self(0x4_0000_0000);
+    pub const CLEAR: Self = Self(0x8_0000_0000);
+    pub const INDEX_INPUT: Self = Self(0x10_0000_0000);
+    pub const VERTEX_ATTRIBUTE_INPUT: Self = Self(0x20_0000_0000);
+    pub const PRE_RASTERIZATION_SHADERS: Self = Self(0x40_0000_0000);
+
+    /// The core stages containing these, for `vkCmdPipelineBarrier`.
+    fn core(self) -> vk::PipelineStageFlags {
+        let mut core = vk::PipelineStageFlags::from_raw(self.0 as u32);
+        for &(stage, containing) in [
+            (Self::COPY, vk::PipelineStageFlags::TRANSFER),
+            (Self::RESOLVE, vk::PipelineStageFlags::TRANSFER),
+            (Self::BLIT, vk::PipelineStageFlags::TRANSFER),
+            (Self::CLEAR, vk::PipelineStageFlags::TRANSFER),
+            (Self::INDEX_INPUT, vk::PipelineStageFlags::VERTEX_INPUT),
+            (
+                Self::VERTEX_ATTRIBUTE_INPUT,
+                vk::PipelineStageFlags::VERTEX_INPUT,
+            ),
+            (
+                Self::PRE_RASTERIZATION_SHADERS,
+                vk::PipelineStageFlags::VERTEX_SHADER
+                    | vk::PipelineStageFlags::TESSELLATION_CONTROL_SHADER
+                    | vk::PipelineStageFlags::TESSELLATION_EVALUATION_SHADER
+                    | vk::PipelineStageFlags::GEOMETRY_SHADER,
+            ),
+        ]
+        .iter()
+        {
+            if self.0 & stage.0 != 0 {
+                core |= containing;
+            }
+        }
+        core
+    }
+}
+
+impl From<vk::PipelineStageFlags> for PipelineStageFlags2 {
+    fn from(stages: vk::PipelineStageFlags) -> Self {
+        Self(u64::from(stages.as_raw()))
+    }
+}
+
+impl BitOr for PipelineStageFlags2 {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// `VkAccessFlags2`: the core accesses at the same bits, and finer ones above them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AccessFlags2(pub u64);
+
+impl AccessFlags2 {
+    pub const NONE: Self = Self(0);
+    pub const INDIRECT_COMMAND_READ: Self = Self(0x1);
+    pub const INDEX_READ: Self = Self(0x2);
+    pub const VERTEX_ATTRIBUTE_READ: Self = Self(0x4);
+    pub const UNIFORM_READ: Self = Self(0x8);
+    pub const SHADER_READ: Self = Self(0x20);
+    pub const SHADER_WRITE: Self = Self(0x40);
+    pub const COLOR_ATTACHMENT_READ: Self = Self(0x80);
+    pub const COLOR_ATTACHMENT_WRITE: Self = Self(0x100);
+    pub const DEPTH_STENCIL_ATTACHMENT_READ: Self = Self(0x200);
+    pub const DEPTH_STENCIL_ATTACHMENT_WRITE: Self = Self(0x400);
+    pub const TRANSFER_READ: Self = Self(0x800);
+    pub const TRANSFER_WRITE: Self = Self(0x1000);
+    pub const HOST_READ: Self = Self(0x2000);
+    pub const HOST_WRITE: Self = Self(0x4000);
+    pub const MEMORY_READ: Self = Self(0x8000);
+    pub const MEMORY_WRITE: Self = Self(0x1_0000);
+    pub const SHADER_SAMPLED_READ: Self = Self(0x1_0000_0000);
+    pub const SHADER_STORAGE_READ: Self = Self(0x2_0000_0000);
+    pub const SHADER_STORAGE_WRITE: Self = Self(0x4_0000_0000);
+
+    /// The core accesses containing these, for `vkCmdPipelineBarrier`.
+    fn core(self) -> vk::AccessFlags {
+        let mut core = vk::AccessFlags::from_raw(self.0 as u32);
+        if self.0 & (Self::SHADER_SAMPLED_READ.0 | Self::SHADER_STORAGE_READ.0) != 0 {
+            core |= vk::AccessFlags::SHADER_READ;
+        }
+        if self.0 & Self::SHADER_STORAGE_WRITE.0 != 0 {
+            core |= vk::AccessFlags::SHADER_WRITE;
+        }
+        core
+    }
+}
+
+impl From<vk::AccessFlags> for AccessFlags2 {
+    fn from(accesses: vk::AccessFlags) -> Self {
+        Self(u64::from(accesses.as_raw()))
+    }
+}
+
+impl BitOr for AccessFlags2 {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryBarrier {
+    pub src_stage: PipelineStageFlags2,
+    pub src_access: AccessFlags2,
+    pub dst_stage: PipelineStageFlags2,
+    pub dst_access: AccessFlags2,
+}
+
+/// Within the queue family, over `size` bytes from `offset`.
+#[derive(Debug, Clone, Copy)]
+pub struct BufferBarrier {
+    pub src_stage: PipelineStageFlags2,
+    pub src_access: AccessFlags2,
+    pub dst_stage: PipelineStageFlags2,
+    pub dst_access: AccessFlags2,
+    pub buffer: vk::Buffer,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+}
+
+/// Within the queue family, optionally changing the layout.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageBarrier {
+    pub src_stage: PipelineStageFlags2,
+    pub src_access: AccessFlags2,
+    pub dst_stage: PipelineStageFlags2,
+    pub dst_access: AccessFlags2,
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
+    pub image: vk::Image,
+    pub subresource_range: vk::ImageSubresourceRange,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Dependency<'a> {
+    pub memory: &'a [MemoryBarrier],
+    pub buffers: &'a [BufferBarrier],
+    pub images: &'a [ImageBarrier],
+}
+
+/// A semaphore waited on before `stage`, or signaled once `stage` is done.
+#[derive(Debug, Clone, Copy)]
+pub struct SemaphoreSubmit {
+    pub semaphore: vk::Semaphore,
+    pub stage: PipelineStageFlags2,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Submit<'a> {
+    pub wait: &'a [SemaphoreSubmit],
+    pub command_buffers: &'a [vk::CommandBuffer],
+    /// Without the extension the semaphores are signaled once all commands are done.
+    pub signal: &'a [SemaphoreSubmit],
+}
+
+#[repr(C)]
+pub struct PhysicalDeviceSynchronization2Features {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    synchronization2: vk::Bool32,
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDeviceSynchronization2Features {}
+
+impl PhysicalDeviceSynchronization2Features {
+    /// The features to chain into the device create info to enable the extension.
+    pub fn enabled() -> Self {
+        Self {
+            s_type: STRUCTURE_TYPE_PHYSICAL_DEVICE_SYNCHRONIZATION_2_FEATURES,
+            p_next: ptr::null_mut(),
+            synchronization2: vk::TRUE,
+        }
+    }
+}
+
+#[repr(C)]
+struct MemoryBarrier2 {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    src_stage_mask: u64,
+    src_access_mask: u64,
+    dst_stage_mask: u64,
+    dst_access_mask: u64,
+}
+
+#[repr(C)]
+struct BufferMemoryBarrier2 {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    src_stage_mask: u64,
+    src_access_mask: u64,
+    dst_stage_mask: u64,
+    dst_access_mask: u64,
+    src_queue_family_index: u32,
+    dst_queue_family_index: u32,
+    buffer: vk::Buffer,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+}
+
+#[repr(C)]
+struct ImageMemoryBarrier2 {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    src_stage_mask: u64,
+    src_access_mask: u64,
+    dst_stage_mask: u64,
+    dst_access_mask: u64,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_queue_family_index: u32,
+    dst_queue_family_index: u32,
+    image: vk::Image,
+    subresource_range: vk::ImageSubresourceRange,
+}
+
+#[repr(C)]
+struct DependencyInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    dependency_flags: vk::DependencyFlags,
+    memory_barrier_count: u32,
+    p_memory_barriers: *const MemoryBarrier2,
+    buffer_memory_barrier_count: u32,
+    p_buffer_memory_barriers: *const BufferMemoryBarrier2,
+    image_memory_barrier_count: u32,
+    p_image_memory_barriers: *const ImageMemoryBarrier2,
+}
+
+#[repr(C)]
+struct SemaphoreSubmitInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    semaphore: vk::Semaphore,
+    value: u64,
+    stage_mask: u64,
+    device_index: u32,
+}
+
+#[repr(C)]
+struct CommandBufferSubmitInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    command_buffer: vk::CommandBuffer,
+    device_mask: u32,
+}
+
+#[repr(C)]
+struct SubmitInfo2 {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    flags: u32,
+    wait_semaphore_info_count: u32,
+    p_wait_semaphore_infos: *const SemaphoreSubmitInfo,
+    command_buffer_info_count: u32,
+    p_command_buffer_infos: *const CommandBufferSubmitInfo,
+    signal_semaphore_info_count: u32,
+    p_signal_semaphore_infos: *const SemaphoreSubmitInfo,
+}
+
+type QueueSubmit2 = unsafe extern "system" fn(
+    queue: vk::Queue,
+    submit_count: u32,
+    p_submits: *const SubmitInfo2,
+    fence: vk::Fence,
+) -> vk::Result;
+type CmdPipelineBarrier2 = unsafe extern "system" fn(
+    command_buffer: vk::CommandBuffer,
+    p_dependency_info: *const DependencyInfo,
+);
+
+/// Whether the device has the extension and its feature.
+pub fn is_supported(instance: &ash::Instance, pdevice: vk::PhysicalDevice) -> bool {
+    let has_extension = unsafe { instance.enumerate_device_extension_properties(pdevice) }
+        .unwrap_or_default()
+        .iter()
+        .any(|ext| unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) } == extension_name());
+    if !has_extension {
+        return false;
+    }
+    let mut synchronization2_features = PhysicalDeviceSynchronization2Features {
+        synchronization2: vk::FALSE,
+        ..PhysicalDeviceSynchronization2Features::enabled()
+    };
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut synchronization2_features as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+    unsafe { instance.get_physical_device_features2(pdevice, &mut features) };
+    synchronization2_features.synchronization2 == vk::TRUE
+}
+
+pub struct Synchronization2 {
+    /// `None` without the extension, falling back to the core commands.
+    fns: Option<(QueueSubmit2, CmdPipelineBarrier2)>,
+}
+
+impl Synchronization2 {
+    /// Uses the extension if the device was created with it.
+    pub fn new(base: &RenderBase) -> Self {
+        let enabled = base
+            .enabled_extensions
+            .iter()
+            .any(|name| name.as_c_str() == extension_name());
+        let load = |name: &[u8]| unsafe {
+            base.instance
+                .get_device_proc_addr(base.device.handle(), name.as_ptr() as *const _)
+        };
+        let fns = if enabled {
+            match (
+                load(b"vkQueueSubmit2KHR\0"),
+                load(b"vkCmdPipelineBarrier2KHR\0"),
+            ) {
+                (Some(queue_submit2), Some(cmd_pipeline_barrier2)) => unsafe {
+                    Some((
+                        mem::transmute::<unsafe extern "system" fn() -> c_void, QueueSubmit2>(
+                            queue_submit2,
+                        ),
+                        mem::transmute::<unsafe extern "system" fn() -> c_void, CmdPipelineBarrier2>(
+                            cmd_pipeline_barrier2,
+                        ),
+                    ))
+                },
+                _ => None,
+            }
+        } else {
+            None
+        };
+        Self { fns }
+    }
+
+    /// Whether submits and barriers go through the extension.
+    pub fn is_enabled(&self) -> bool {
+        self.fns.is_some()
+    }
+
+    pub fn submit(
+        &self,
+        device: &ash::Device,
+        queue: vk::Queue,
+        submit: &Submit,
+        fence: vk::Fence,
+    ) -> VkResult<()> {
+        let (queue_submit2, _) = match self.fns {
+            Some(fns) => fns,
+            None => {
+                let wait_semaphores = submit
+                    .wait
+                    .iter()
+                    .map(|wait| wait.semaphore)
+                    .collect::<Vec<_>>();
+                let wait_stages = submit
+                    .wait
+                    .iter()
+                    .map(|wait| match wait.stage.core() {
+                        stages if stages.is_empty() => vk::PipelineStageFlags::TOP_OF_PIPE,
+                        stages => stages,
+                    })
+                    .collect::<Vec<_>>();
+                let signal_semaphores = submit
+                    .signal
+                    .iter()
+                    .map(|signal| signal.semaphore)
+                    .collect::<Vec<_>>();
+                let submit_info = vk::SubmitInfo::builder()
+                    .wait_semaphores(&wait_semaphores)
+                    .wait_dst_stage_mask(&wait_stages)
+                    .command_buffers(submit.command_buffers)
+                    .signal_semaphores(&signal_semaphores);
+                return unsafe { device.queue_submit(queue, &[submit_info.build()], fence) };
+            }
+        };
+        let semaphore_info = |semaphore: &SemaphoreSubmit| SemaphoreSubmitInfo {
+            s_type: STRUCTURE_TYPE_SEMAPHORE_SUBMIT_INFO,
+            p_next: ptr::null(),
+            semaphore: semaphore.semaphore,
+            value: 0,
+            stage_mask: semaphore.stage.0,
+            device_index: 0,
+        };
+        let wait_infos = submit.wait.iter().map(semaphore_info).collect::<Vec<_>>();
+        let signal_infos = submit.signal.iter().map(semaphore_info).collect::<Vec<_>>();
+        let command_buffer_infos = submit
+            .command_buffers
+            .iter()
+            .map(|&command_buffer| CommandBufferSubmitInfo {
+                s_type: STRUCTURE_TYPE_COMMAND_BUFFER_SUBMIT_INFO,
+                p_next: ptr::null(),
+                command_buffer,
+                device_mask: 0,
+            })
+            .collect::<Vec<_>>();
+        let submit_info = SubmitInfo2 {
+            s_type: STRUCTURE_TYPE_SUBMIT_INFO_2,
+            p_next: ptr::null(),
+            flags: 0,
+            wait_semaphore_info_count: wait_infos.len() as u32,
+            p_wait_semaphore_infos: wait_infos.as_ptr(),
+            command_buffer_info_count: command_buffer_infos.len() as u32,
+            p_command_buffer_infos: command_buffer_infos.as_ptr(),
+            signal_semaphore_info_count: signal_infos.len() as u32,
+            p_signal_semaphore_infos: signal_infos.as_ptr(),
+        };
+        match unsafe { queue_submit2(queue, 1, &submit_info, fence) } {
+            vk::Result::SUCCESS => Ok(()),
+            err => Err(err),
+        }
+    }
+
+    /// Without the extension the barriers are recorded in one `vkCmdPipelineBarrier`, between
+    /// the union of their stages.
+    pub fn pipeline_barrier(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        dependency: &Dependency,
+    ) {
+        let (_, cmd_pipeline_barrier2) = match self.fns {
+            Some(fns) => fns,
+            None => return record_core_barrier(device, command_buffer, dependency),
+        };
+        let memory_barriers = dependency
+            .memory
+            .iter()
+            .map(|barrier| MemoryBarrier2 {
+                s_type: STRUCTURE_TYPE_MEMORY_BARRIER_2,
+                p_next: ptr::null(),
+                src_stage_mask: barrier.src_stage.0,
+                src_access_mask: barrier.src_access.0,
+                dst_stage_mask: barrier.dst_stage.0,
+                dst_access_mask: barrier.dst_access.0,
+            })
+            .collect::<Vec<_>>();
+        let buffer_barriers = dependency
+            .buffers
+            .iter()
+            .map(|barrier| BufferMemoryBarrier2 {
+                s_type: STRUCTURE_TYPE_BUFFER_MEMORY_BARRIER_2,
+                p_next: ptr::null(),
+                src_stage_mask: barrier.src_stage.0,
+                src_access_mask: barrier.src_access.0,
+                dst_stage_mask: barrier.dst_stage.0,
+                dst_access_mask: barrier.dst_access.0,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                buffer: barrier.buffer,
+                offset: barrier.offset,
+                size: barrier.size,
+            })
+            .collect::<Vec<_>>();
+        let image_barriers = dependency
+            .images
+            .iter()
+            .map(|barrier| ImageMemoryBarrier2 {
+                s_type: STRUCTURE_TYPE_IMAGE_MEMORY_BARRIER_2,
+                p_next: ptr::null(),
+                src_stage_mask: barrier.src_stage.0,
+                src_access_mask: barrier.src_access.0,
+                dst_stage_mask: barrier.dst_stage.0,
+                dst_access_mask: barrier.dst_access.0,
+                old_layout: barrier.old_layout,
+                new_layout: barrier.new_layout,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image: barrier.image,
+                subresource_range: barrier.subresource_range,
+            })
+            .collect::<Vec<_>>();
+        let dependency_info = DependencyInfo {
+            s_type: STRUCTURE_TYPE_DEPENDENCY_INFO,
+            p_next: ptr::null(),
+            dependency_flags: vk::DependencyFlags::empty(),
+            memory_barrier_count: memory_barriers.len() as u32,
+            p_memory_barriers: memory_barriers.as_ptr(),
+            buffer_memory_barrier_count: buffer_barriers.len() as u32,
+            p_buffer_memory_barriers: buffer_barriers.as_ptr(),
+            image_memory_barrier_count: image_barriers.len() as u32,
+            p_image_memory_barriers: image_barriers.as_ptr(),
+        };
+        unsafe { cmd_pipeline_barrier2(command_buffer, &dependency_info) };
+    }
+}
+
+fn record_core_barrier(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    dependency: &Dependency,
+) {
+    let mut src_stages = PipelineStageFlags2::NONE;
+    let mut dst_stages = PipelineStageFlags2::NONE;
+    let memory_barriers = dependency
+        .memory
+        .iter()
+        .map(|barrier| {
+            src_stages = src_stages | barrier.src_stage;
+            dst_stages = dst_stages | barrier.dst_stage;
+            vk::MemoryBarrier::builder()
+                .src_access_mask(barrier.src_access.core())
+                .dst_access_mask(barrier.dst_access.core())
+                .build()
+        })
+        .collect::<Vec<_>>();
+    let buffer_barriers = dependency
+        .buffers
+        .iter()
+        .map(|barrier| {
+            src_stages = src_stages | barrier.src_stage;
+            dst_stages = dst_stages | barrier.dst_stage;
+            vk::BufferMemoryBarrier::builder()
+                .src_access_mask(barrier.src_access.core())
+                .dst_access_mask(barrier.dst_access.core())
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(barrier.buffer)
+                .offset(barrier.offset)
+                .size(barrier.size)
+                .build()
+        })
+        .collect::<Vec<_>>();
+    let image_barriers = dependency
+        .images
+        .iter()
+        .map(|barrier| {
+            src_stages = src_stages | barrier.src_stage;
+            dst_stages = dst_stages | barrier.dst_stage;
+            vk::ImageMemoryBarrier::builder()
+                .src_access_mask(barrier.src_access.core())
+                .dst_access_mask(barrier.dst_access.core())
+                .old_layout(barrier.old_layout)
+                .new_layout(barrier.new_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(barrier.image)
+                .subresource_range(barrier.subresource_range)
+                .build()
+        })
+        .collect::<Vec<_>>();
+    // the core command needs stages on both sides, where NONE means none
+    let src_stages = match src_stages.core() {
+        stages if stages.is_empty() => vk::PipelineStageFlags::TOP_OF_PIPE,
+        stages => stages,
+    };
+    let dst_stages = match dst_stages.core() {
+        stages if stages.is_empty() => vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        stages => stages,
+    };
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stages,
+            dst_stages,
+            vk::DependencyFlags::empty(),
+            &memory_barriers,
+            &buffer_barriers,
+            &image_barriers,
+        );
+    }
+}
@@ -0,0 +1,271 @@
+//! A minimal text renderer for overlays like the help, drawing an 8x8 pixel ASCII font as a
+//! `SpriteBatch` with a glyph atlas, so no GUI library is needed.  Text is positioned in physical
+//! pixels from the top left of the window and scaled by whole pixels to stay sharp.
+
+use crate::ash_runner::RenderCtx;
+use crate::sprite::{Sprite, SpriteBatch, MAX_SPRITES};
+
+use ash::vk;
+
+/// Glyphs drawn per frame by one renderer, including backgrounds; the rest are left out.
+pub const MAX_GLYPHS: usize = MAX_SPRITES;
+/// The size of a glyph at scale 1, in pixels.
+pub const GLYPH_SIZE: u32 = 8;
+/// The distance between lines at scale 1, in pixels.
+pub const LINE_HEIGHT: u32 = 10;
+
+const ATLAS_COLUMNS: u32 = 16;
+const ATLAS_ROWS: u32 = 6;
+const ATLAS_WIDTH: u32 = ATLAS_COLUMNS * GLYPH_SIZE;
+const ATLAS_HEIGHT: u32 = ATLAS_ROWS * GLYPH_SIZE;
+/// The atlas cell after `~`, filled in for backgrounds.
+const SOLID: u8 = 127;
+
+/// The printable ASCII characters from the space on, eight rows each with bit 0 the leftmost
+/// pixel.  From the public domain font8x8 by Daniel Hepper.
+const FONT: [[u8; 8]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x18, 0x3c, 0x3c, 0x18, 0x18, 0x00, 0x18, 0x00], // '!'
+    [0x36, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '"'
+    [0x36, 0x36, 0x7f, 0x36, 0x7f, 0x36, 0x36, 0x00], // '#'
+    [0x0c, 0x3e, 0x03, 0x1e, 0x30, 0x1f, 0x0c, 0x00], // '$'
+    [0x00, 0x63, 0x33, 0x18, 0x0c, 0x66, 0x63, 0x00], // '%'
+    [0x1c, 0x36, 0x1c, 0x6e, 0x3b, 0x33, 0x6e, 0x00], // '&'
+    [0x06, 0x06, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00], // "'"
+    [0x18, 0x0c, 0x06, 0x06, 0x06, 0x0c, 0x18, 0x00], // '('
+    [0x06, 0x0c, 0x18, 0x18, 0x18, 0x0c, 0x06, 0x00], // ')'
+    [0x00, 0x66, 0x3c, 0xff, 0x3c, 0x66, 0x00, 0x00], // '*'
+    [0x00, 0x0c, 0x0c, 0x3f, 0x0c, 0x0c, 0x00, 0x00], // '+'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c, 0x06], // ','
+    [0x00, 0x00, 0x00, 0x3f, 0x00, 0x00, 0x00, 0x00], // '-'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c, 0x00], // '.'
+    [0x60, 0x30, 0x18, 0x0c, 0x06, 0x03, 0x01, 0x00], // '/'
+    [0x3e, 0x63, 0x73, 0x7b, 0x6f, 0x67, 0x3e, 0x00], // '0'
+    [0x0c, 0x0e, 0x0c, 0x0c, 0x0c, 0x0c, 0x3f, 0x00], // '1'
+    [0x1e, 0x33, 0x30, 0x1c, 0x06, 0x33, 0x3f, 0x00], // '2'
+    [0x1e, 0x33, 0x30, 0x1c, 0x30, 0x33, 0x1e, 0x00], // '3'
+    [0x38, 0x3c, 0x36, 0x33, 0x7f, 0x30, 0x78, 0x00], // '4'
+    [0x3f, 0x03, 0x1f, 0x30, 0x30, 0x33, 0x1e, 0x00], // '5'
+    [0x1c, 0x06, 0x03, 0x1f, 0x33, 0x33, 0x1e, 0x00], // '6'
+    [0x3f, 0x33, 0x30, 0x18, 0x0c, 0x0c, 0x0c, 0x00], // '7'
+    [0x1e, 0x33, 0x33, 0x1e, 0x33, 0x33, 0x1e, 0x00], // '8'
+    [0x1e, 0x33, 0x33, 0x3e, 0x30, 0x18, 0x0e, 0x00], // '9'
+    [0x00, 0x0c, 0x0c, 0x00, 0x00, 0x0c, 0x0c, 0x00], // ':'
+    [0x00, 0x0c, 0x0c, 0x00, 0x00, 0x0c, 0x0c, 0x06], // ';'
+    [0x18, 0x0c, 0x06, 0x03, 0x06, 0x0c, 0x18, 0x00], // '<'
+    [0x00, 0x00, 0x3f, 0x00, 0x00, 0x3f, 0x00, 0x00], // '='
+    [0x06, 0x0c, 0x18, 0x30, 0x18, 0x0c, 0x06, 0x00], // '>'
+    [0x1e, 0x33, 0x30, 0x18, 0x0c, 0x00, 0x0c, 0x00], // '?'
+    [0x3e, 0x63, 0x7b, 0x7b, 0x7b, 0x03, 0x1e, 0x00], // '@'
+    [0x0c, 0x1e, 0x33, 0x33, 0x3f, 0x33, 0x33, 0x00], // 'A'
+    [0x3f, 0x66, 0x66, 0x3e, 0x66, 0x66, 0x3f, 0x00], // 'B'
+    [0x3c, 0x66, 0x03, 0x03, 0x03, 0x66, 0x3c, 0x00], // 'C'
+    [0x1f, 0x36, 0x66, 0x66, 0x66, 0x36, 0x1f, 0x00], // 'D'
+    [0x7f, 0x46, 0x16, 0x1e, 0x16, 0x46, 0x7f, 0x00], // 'E'
+    [0x7f, 0x46, 0x16, 0x1e, 0x16, 0x06, 0x0f, 0x00], // 'F'
+    [0x3c, 0x66, 0x03, 0x03, 0x73, 0x66, 0x7c, 0x00], // 'G'
+    [0x33, 0x33, 0x33, 0x3f, 0x33, 0x33, 0x33, 0x00], // 'H'
+    [0x1e, 0x0c, 0x0c, 0x0c, 0x0c, 0x0c, 0x1e, 0x00], // 'I'
+    [0x78, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1e, 0x00], // 'J'
+    [0x67, 0x66, 0x36, 0x1e, 0x36, 0x66, 0x67, 0x00], // 'K'
+    [0x0f, 0x06, 0x06, 0x06, 0x46, 0x66, 0x7f, 0x00], // 'L'
+    [0x63, 0x77, 0x7f, 0x7f, 0x6b, 0x63, 0x63, 0x00], // 'M'
+    [0x63, 0x67, 0x6f, 0x7b, 0x73, 0x63, 0x63, 0x00], // 'N'
+    [0x1c, 0x36, 0x63, 0x63, 0x63, 0x36, 0x1c, 0x00], // 'O'
+    [0x3f, 0x66, 0x66, 0x3e, 0x06, 0x06, 0x0f, 0x00], // 'P'
+    [0x1e, 0x33, 0x33, 0x33, 0x3b, 0x1e, 0x38, 0x00], // 'Q'
+    [0x3f, 0x66, 0x66, 0x3e, 0x36, 0x66, 0x67, 0x00], // 'R'
+    [0x1e, 0x33, 0x07, 0x0e, 0x38, 0x33, 0x1e, 0x00], // 'S'
+    [0x3f, 0x2d, 0x0c, 0x0c, 0x0c, 0x0c, 0x1e, 0x00], // 'T'
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x3f, 0x00], // 'U'
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x1e, 0x0c, 0x00], // 'V'
+    [0x63, 0x63, 0x63, 0x6b, 0x7f, 0x77, 0x63, 0x00], // 'W'
+    [0x63, 0x63, 0x36, 0x1c, 0x1c, 0x36, 0x63, 0x00], // 'X'
+    [0x33, 0x33, 0x33, 0x1e, 0x0c, 0x0c, 0x1e, 0x00], // 'Y'
+    [0x7f, 0x63, 0x31, 0x18, 0x4c, 0x66, 0x7f, 0x00], // 'Z'
+    [0x1e, 0x06, 0x06, 0x06, 0x06, 0x06, 0x1e, 0x00], // '['
+    [0x03, 0x06, 0x0c, 0x18, 0x30, 0x60, 0x40, 0x00], // '\\'
+    [0x1e, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1e, 0x00], // ']'
+    [0x08, 0x1c, 0x36, 0x63, 0x00, 0x00, 0x00, 0x00], // '^'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff], // '_'
+    [0x0c, 0x0c, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00], // '`'
+    [0x00, 0x00, 0x1e, 0x30, 0x3e, 0x33, 0x6e, 0x00], // 'a'
+    [0x07, 0x06, 0x06, 0x3e, 0x66, 0x66, 0x3b, 0x00], // 'b'
+    [0x00, 0x00, 0x1e, 0x33, 0x03, 0x33, 0x1e, 0x00], // 'c'
+    [0x38, 0x30, 0x30, 0x3e, 0x33, 0x33, 0x6e, 0x00], // 'd'
+    [0x00, 0x00, 0x1e, 0x33, 0x3f, 0x03, 0x1e, 0x00], // 'e'
+    [0x1c, 0x36, 0x06, 0x0f, 0x06, 0x06, 0x0f, 0x00], // 'f'
+    [0x00, 0x00, 0x6e, 0x33, 0x33, 0x3e, 0x30, 0x1f], // 'g'
+    [0x07, 0x06, 0x36, 0x6e, 0x66, 0x66, 0x67, 0x00], // 'h'
+    [0x0c, 0x00, 0x0e, 0x0c, 0x0c, 0x0c, 0x1e, 0x00], // 'i'
+    [0x30, 0x00, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1e], // 'j'
+    [0x07, 0x06, 0x66, 0x36, 0x1e, 0x36, 0x67, 0x00], // 'k'
+    [0x0e, 0x0c, 0x0c, 0x0c, 0x0c, 0x0c, 0x1e, 0x00], // 'l'
+    [0x00, 0x00, 0x33, 0x7f, 0x7f, 0x6b, 0x63, 0x00], // 'm'
+    [0x00, 0x00, 0x1f, 0x33, 0x33, 0x33, 0x33, 0x00], // 'n'
+    [0x00, 0x00, 0x1e, 0x33, 0x33, 0x33, 0x1e, 0x00], // 'o'
+    [0x00, 0x00, 0x3b, 0x66, 0x66, 0x3e, 0x06, 0x0f], // 'p'
+    [0x00, 0x00, 0x6e, 0x33, 0x33, 0x3e, 0x30, 0x78], // 'q'
+    [0x00, 0x00, 0x3b, 0x6e, 0x66, 0x06, 0x0f, 0x00], // 'r'
+    [0x00, 0x00, 0x3e, 0x03, 0x1e, 0x30, 0x1f, 0x00], // 's'
+    [0x08, 0x0c, 0x3e, 0x0c, 0x0c, 0x2c, 0x18, 0x00], // 't'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x33, 0x6e, 0x00], // 'u'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x1e, 0x0c, 0x00], // 'v'
+    [0x00, 0x00, 0x63, 0x6b, 0x7f, 0x7f, 0x36, 0x00], // 'w'
+    [0x00, 0x00, 0x63, 0x36, 0x1c, 0x36, 0x63, 0x00], // 'x'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x3e, 0x30, 0x1f], // 'y'
+    [0x00, 0x00, 0x3f, 0x19, 0x0c, 0x26, 0x3f, 0x00], // 'z'
+    [0x38, 0x0c, 0x0c, 0x07, 0x0c, 0x0c, 0x38, 0x00], // '{'
+    [0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x00], // '|'
+    [0x07, 0x0c, 0x0c, 0x38, 0x0c, 0x0c, 0x07, 0x00], // '}'
+    [0x6e, 0x3b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '~'
+];
+
+/// A block of text to draw.  Lines are separated by `\n`, and characters outside printable ASCII
+/// are drawn as `?`.
+pub struct Text<'a> {
+    pub text: &'a str,
+    /// The top left corner, in physical pixels.
+    pub position: [f32; 2],
+    /// Whole multiples of the 8 pixel font.
+    pub scale: u32,
+    pub color: [f32; 4],
+    /// Drawn behind the text's bounding box, with a glyph-wide margin.
+    pub background: Option<[f32; 4]>,
+}
+
+impl Text<'_> {
+    /// The width and height of the text without background, in physical pixels.
+    pub fn size(&self) -> [f32; 2] {
+        let lines = self.text.lines();
+        let columns = lines.clone().map(|line| line.chars().count()).max();
+        let scale = self.scale.max(1) as f32;
+        let height = match lines.count() as u32 {
+            0 => 0,
+            rows => (rows - 1) * LINE_HEIGHT + GLYPH_SIZE,
+        };
+        [
+            (columns.unwrap_or(0) as u32 * GLYPH_SIZE) as f32 * scale,
+            height as f32 * scale,
+        ]
+    }
+}
+
+/// Draws `Text` into the window's render pass.  Owned by the plugin using it, which creates it in
+/// `Plugin::init`, calls `recreate_pipeline` from `Plugin::pipelines_rebuilt` and `destroy` from
+/// `Plugin::destroy`.
+pub struct TextRenderer {
+    glyphs: SpriteBatch,
+}
+
+impl TextRenderer {
+    pub fn new(ctx: &RenderCtx) -> Self {
+        let extent = vk::Extent2D {
+            width: ATLAS_WIDTH,
+            height: ATLAS_HEIGHT,
+        };
+        Self {
+            glyphs: SpriteBatch::new(ctx, extent, &atlas_pixels(), vk::Filter::NEAREST),
+        }
+    }
+
+    /// Recreates the pipeline for the current render pass.  The device has to be idle.
+    pub fn recreate_pipeline(&mut self, ctx: &RenderCtx) {
+        self.glyphs.recreate_pipeline(ctx);
+    }
+
+    /// Records drawing `texts` over the whole window, in order.  Call it at most once per frame,
+    /// from `Plugin::draw`.
+    pub fn draw(&self, ctx: &RenderCtx, command_buffer: vk::CommandBuffer, texts: &[Text]) {
+        let mut sprites = Vec::new();
+        for text in texts {
+            push_text(&mut sprites, text);
+        }
+        self.glyphs.draw(ctx, command_buffer, &sprites);
+    }
+
+    /// The device has to be idle.
+    pub fn destroy(&mut self, device: &ash::Device) {
+        self.glyphs.destroy(device);
+    }
+}
+
+/// Appends a sprite per glyph of `text`, after its background.
+fn push_text(sprites: &mut Vec<Sprite>, text: &Text) {
+    let scale = text.scale.max(1) as f32;
+    let glyph = GLYPH_SIZE as f32 * scale;
+    // whole pixels, so the glyphs' pixels line up with the window's
+    let [left, top] = [text.position[0].round(), text.position[1].round()];
+    let mut push = |x: f32, y: f32, size: [f32; 2], character: u8, color: [f32; 4]| {
+        let index = u32::from(character - b' ');
+        sprites.push(Sprite {
+            position: [x, y],
+            size,
+            source: vk::Rect2D {
+                offset: vk::Offset2D {
+                    x: (index % ATLAS_COLUMNS * GLYPH_SIZE) as i32,
+                    y: (index / ATLAS_COLUMNS * GLYPH_SIZE) as i32,
+                },
+                extent: vk::Extent2D {
+                    width: GLYPH_SIZE,
+                    height: GLYPH_SIZE,
+                },
+            },
+            color,
+        });
+    };
+
+    if let Some(background) = text.background {
+        let [width, height] = text.size();
+        push(
+            left - glyph,
+            top - glyph,
+            [width + 2.0 * glyph, height + 2.0 * glyph],
+            SOLID,
+            background,
+        );
+    }
+    for (row, line) in text.text.lines().enumerate() {
+        let y = top + (row as u32 * LINE_HEIGHT) as f32 * scale;
+        for (column, character) in line.chars().enumerate() {
+            if character == ' ' {
+                continue;
+            }
+            let character = match character {
+                ' '..='~' => character as u8,
+                _ => b'?',
+            };
+            let x = left + column as f32 * glyph;
+            push(x, y, [glyph, glyph], character, text.color);
+        }
+    }
+}
+
+/// The rows of a character's glyph, bit 0 the leftmost pixel, with `?` for those outside
+/// printable ASCII.  For drawing text into images on the CPU.
+pub(crate) fn glyph_rows(character: char) -> [u8; 8] {
+    match character {
+        ' '..='~' => FONT[character as usize - ' ' as usize],
+        _ => FONT[usize::from(b'?' - b' ')],
+    }
+}
+
+/// The atlas pixels, white with the glyphs' coverage as alpha, with the glyphs in rows of
+/// `ATLAS_COLUMNS`.
+fn atlas_pixels() -> Vec<u8> {
+    let mut pixels = [0xff, 0xff, 0xff, 0].repeat((ATLAS_WIDTH * ATLAS_HEIGHT) as usize);
+    let solid = [0xff; 8];
+    for (index, rows) in FONT.iter().chain(std::iter::once(&solid)).enumerate() {
+        let left = index as u32 % ATLAS_COLUMNS * GLYPH_SIZE;
+        let top = index as u32 / ATLAS_COLUMNS * GLYPH_SIZE;
+        for (y, row) in rows.iter().enumerate() {
+            for x in 0..GLYPH_SIZE {
+                if row & (1 << x) != 0 {
+                    let offset = (top + y as u32) * ATLAS_WIDTH + left + x;
+                    pixels[offset as usize * 4 + 3] = 0xff;
+                }
+            }
+        }
+    }
+    pixels
+}
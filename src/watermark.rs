@@ -0,0 +1,152 @@
+//! Text burned into the bottom left corner of captures with `--watermark`, so a capture kept for a
+//! comparison still says what it shows: when it was taken, the shaders drawn, as the fragment
+//! shaders' `module::entry_point`, and the git revision of the shader crate, marked dirty if it
+//! has uncommitted changes.
+
+use crate::ash_runner::RenderCtx;
+use crate::text::{self, GLYPH_SIZE, LINE_HEIGHT};
+
+use ash::vk;
+
+use std::{
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Scale of the font per 720 pixels of image height, at least 1.
+const SCALE_PER_720: u32 = 2;
+const COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+/// Blended over the image behind the text, so it stays legible on any background.
+const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.75];
+
+pub struct Watermark {
+    lines: Vec<String>,
+}
+
+impl Watermark {
+    /// Describes the frame `ctx` draws now.  `crate_dir` is the shader crate's, for its revision.
+    pub fn new(ctx: &RenderCtx, crate_dir: Option<&Path>) -> Self {
+        let shaders = ctx
+            .pipelines
+            .entries()
+            .filter(|entry| entry.enabled)
+            .map(|entry| format!("{}::{}", entry.fragment.module, entry.fragment.entry_point))
+            .collect::<Vec<_>>();
+        let revision = crate_dir.and_then(git_revision);
+        Self {
+            lines: vec![
+                utc_timestamp(SystemTime::now()),
+                if shaders.is_empty() {
+                    "no pipelines".to_owned()
+                } else {
+                    shaders.join(", ")
+                },
+                revision.unwrap_or_else(|| "no git revision".to_owned()),
+            ],
+        }
+    }
+
+    /// Draws the text into RGBA float pixels, top row first.
+    pub fn burn(&self, extent: vk::Extent2D, pixels: &mut [f32]) {
+        let scale = (SCALE_PER_720 * extent.height / 720).max(1);
+        let glyph = GLYPH_SIZE * scale;
+        let line_height = LINE_HEIGHT * scale;
+        let columns = self
+            .lines
+            .iter()
+            .map(|line| line.chars().count() as u32)
+            .max()
+            .unwrap_or(0);
+        let rows = self.lines.len() as u32;
+        let width = columns * glyph + 2 * glyph;
+        let height = rows.saturating_sub(1) * line_height + glyph + 2 * glyph;
+        let left = glyph;
+        let top = extent.height.saturating_sub(height + glyph);
+        let mut blend = |x: u32, y: u32, color: [f32; 4]| {
+            if x >= extent.width || y >= extent.height {
+                return;
+            }
+            let offset = (y * extent.width + x) as usize * 4;
+            for (channel, &value) in pixels[offset..offset + 3].iter_mut().zip(color.iter()) {
+                *channel += (value - *channel) * color[3];
+            }
+        };
+        for y in top..top + height {
+            for x in left..left + width {
+                blend(x, y, BACKGROUND);
+            }
+        }
+        for (row, line) in self.lines.iter().enumerate() {
+            let line_top = top + glyph + row as u32 * line_height;
+            for (column, character) in line.chars().enumerate() {
+                let glyph_left = left + glyph + column as u32 * glyph;
+                for (glyph_y, bits) in text::glyph_rows(character).iter().enumerate() {
+                    for glyph_x in 0..GLYPH_SIZE {
+                        if bits & (1 << glyph_x) == 0 {
+                            continue;
+                        }
+                        for dy in 0..scale {
+                            for dx in 0..scale {
+                                blend(
+                                    glyph_left + glyph_x * scale + dx,
+                                    line_top + glyph_y as u32 * scale + dy,
+                                    COLOR,
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Like `1a2b3c4` or `1a2b3c4-dirty`, `None` outside a git repository or without git.
+fn git_revision(dir: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(&["describe", "--always", "--dirty"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let revision = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    Some(revision).filter(|revision| !revision.is_empty())
+}
+
+/// Like `2021-03-04 05:06:07 UTC`.
+fn utc_timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, seconds_of_day) = (seconds / 86_400, seconds % 86_400);
+    // the proleptic Gregorian calendar from days since 1970-01-01, after Howard Hinnant's
+    // civil_from_days
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    )
+}
@@ -0,0 +1,555 @@
+//! Stereo rendering to an OpenXR headset, enabled with the `openxr` feature and `--xr`.  The
+//! desktop window keeps rendering as usual, and every frame the pipelines are additionally drawn
+//! once per eye into a two layer XR swapchain, with `ShaderConstants::view_projection` taken from
+//! that eye's pose and field of view.
+
+use crate::ash_runner::{ExternalDevice, Options, RenderBase, RenderCtx};
+use crate::camera;
+use crate::render_pass::{AttachmentImages, AttachmentKind, RenderPassConfig};
+
+use ash::{
+    version::{DeviceV1_0, EntryV1_0, InstanceV1_0},
+    vk::{self, Handle},
+};
+
+use openxr as xr;
+
+use std::{ffi::CString, sync::Arc};
+
+use winit::window::Window;
+
+const VIEW_TYPE: xr::ViewConfigurationType = xr::ViewConfigurationType::PRIMARY_STEREO;
+
+/// Creates the Vulkan instance and device through the OpenXR runtime, which gets to pick the GPU
+/// the headset is connected to and add the extensions it needs.  They belong to the runner like
+/// its own, and are destroyed with the `RenderBase`.
+pub fn create_base(
+    window: Arc<Window>,
+    options: &Options,
+) -> (xr::Instance, xr::SystemId, RenderBase) {
+    let xr_entry = xr::Entry::load().expect("Unable to load the OpenXR loader");
+    let mut xr_extensions = xr::ExtensionSet::default();
+    xr_extensions.khr_vulkan_enable2 = true;
+    let xr_instance = xr_entry
+        .create_instance(
+            &xr::ApplicationInfo {
+                application_name: "Rust GPU - ash",
+                application_version: 0,
+                engine_name: "Rust GPU - ash",
+                engine_version: 0,
+            },
+            &xr_extensions,
+            &[],
+        )
+        .expect("Unable to create an OpenXR instance, is a runtime installed?");
+    let system = xr_instance
+        .system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)
+        .expect("No headset found");
+    // the runtime requires querying the requirements before creating the instance
+    xr_instance
+        .graphics_requirements::<xr::Vulkan>(system)
+        .unwrap();
+
+    let entry = ash::Entry::new().unwrap();
+    let get_instance_proc_addr =
+        unsafe { std::mem::transmute(entry.static_fn().get_instance_proc_addr) };
+
+    let instance = {
+        let app_name = CString::new("VulkanTriangle").unwrap();
+        let extension_names_raw = ash_window::enumerate_required_extensions(&*window)
+            .unwrap()
+            .iter()
+            .map(|ext| ext.as_ptr())
+            .collect::<Vec<_>>();
+        let appinfo = vk::ApplicationInfo::builder()
+            .application_name(&app_name)
+            .application_version(0)
+            .engine_name(&app_name)
+            .engine_version(0)
+            .api_version(vk::make_version(1, 1, 0));
+        let instance_create_info = vk::InstanceCreateInfo::builder()
+            .application_info(&appinfo)
+            .enabled_extension_names(&extension_names_raw);
+        unsafe {
+            let instance = xr_instance
+                .create_vulkan_instance(
+                    system,
+                    get_instance_proc_addr,
+                    &*instance_create_info as *const _ as *const _,
+                )
+                .unwrap()
+                .map_err(vk::Result::from_raw)
+                .expect("Instance creation error");
+            ash::Instance::load(entry.static_fn(), vk::Instance::from_raw(instance as _))
+        }
+    };
+
+    let pdevice = vk::PhysicalDevice::from_raw(
+        xr_instance
+            .vulkan_graphics_device(system, instance.handle().as_raw() as _)
+            .unwrap() as _,
+    );
+    let queue_family_index = unsafe {
+        instance
+            .get_physical_device_queue_family_properties(pdevice)
+            .iter()
+            .position(|info| info.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+            .expect("The headset's device has no graphics queue") as u32
+    };
+
+    let device = {
+        let device_extension_names_raw = [ash::extensions::khr::Swapchain::name().as_ptr()];
+        let priorities = [1.0];
+        let queue_info = [vk::DeviceQueueCreateInfo::builder()
+            .queue_family_index(queue_family_index)
+            .queue_priorities(&priorities)
+            .build()];
+        let device_create_info = vk::DeviceCreateInfo::builder()
+            .queue_create_infos(&queue_info)
+            .enabled_extension_names(&device_extension_names_raw);
+        unsafe {
+            let device = xr_instance
+                .create_vulkan_device(
+                    system,
+                    get_instance_proc_addr,
+                    pdevice.as_raw() as _,
+                    &*device_create_info as *const _ as *const _,
+                )
+                .unwrap()
+                .map_err(vk::Result::from_raw)
+                .expect("Device creation error");
+            ash::Device::load(instance.fp_v1_0(), vk::Device::from_raw(device as _))
+        }
+    };
+    let queue = unsafe { device.get_device_queue(queue_family_index, 0) };
+
+    let base = RenderBase::from_handed_over(
+        window,
+        ExternalDevice {
+            entry,
+            instance,
+            pdevice,
+            device,
+            queue_family_index,
+            queue,
+        },
+        options,
+    );
+    (xr_instance, system, base)
+}
+
+pub struct XrSession {
+    instance: xr::Instance,
+    session: xr::Session<xr::Vulkan>,
+    frame_waiter: xr::FrameWaiter,
+    frame_stream: xr::FrameStream<xr::Vulkan>,
+    stage: xr::Space,
+    blend_mode: xr::EnvironmentBlendMode,
+    event_storage: xr::EventDataBuffer,
+    running: bool,
+
+    swapchain: xr::Swapchain<xr::Vulkan>,
+    extent: vk::Extent2D,
+    /// Per swapchain image, one view for each eye's array layer.
+    image_views: Vec<[vk::ImageView; 2]>,
+    /// Compatible with the window's render pass, so the pipelines can draw in it.  Rebuilt along
+    /// with the extra attachments' images and the framebuffers when its attachments change.
+    render_pass: vk::RenderPass,
+    attachments: Vec<(AttachmentKind, vk::Format)>,
+    /// Shared by both eyes, which are rendered one after the other.
+    attachment_images: AttachmentImages,
+    /// Per swapchain image, one framebuffer for each eye.
+    framebuffers: Vec<[vk::Framebuffer; 2]>,
+
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+}
+
+impl XrSession {
+    pub fn new(instance: xr::Instance, system: xr::SystemId, ctx: &RenderCtx) -> Self {
+        let base = &ctx.base;
+        let blend_mode = instance
+            .enumerate_environment_blend_modes(system, VIEW_TYPE)
+            .unwrap()[0];
+        let (session, frame_waiter, frame_stream) = unsafe {
+            instance
+                .create_session::<xr::Vulkan>(
+                    system,
+                    &xr::vulkan::SessionCreateInfo {
+                        instance: base.instance.handle().as_raw() as _,
+                        physical_device: base.pdevice.as_raw() as _,
+                        device: base.device.handle().as_raw() as _,
+                        queue_family_index: base.queue_family_index,
+                        queue_index: 0,
+                    },
+                )
+                .unwrap()
+        };
+        let stage = session
+            .create_reference_space(xr::ReferenceSpaceType::STAGE, xr::Posef::IDENTITY)
+            .unwrap();
+
+        let views = instance
+            .enumerate_view_configuration_views(system, VIEW_TYPE)
+            .unwrap();
+        assert_eq!(views.len(), 2, "Expected a stereo view configuration");
+        let extent = vk::Extent2D {
+            width: views[0].recommended_image_rect_width,
+            height: views[0].recommended_image_rect_height,
+        };
+        // the pipelines are built against the window's render pass, so the eyes need the same
+        // format for the render passes to be compatible
+        let format = base.view_format;
+        assert!(
+            session
+                .enumerate_swapchain_formats()
+                .unwrap()
+                .contains(&(format.as_raw() as _)),
+            "The OpenXR runtime doesn't support the window's format {:?}",
+            format
+        );
+        let swapchain = session
+            .create_swapchain(&xr::SwapchainCreateInfo {
+                create_flags: xr::SwapchainCreateFlags::EMPTY,
+                usage_flags: xr::SwapchainUsageFlags::COLOR_ATTACHMENT
+                    | xr::SwapchainUsageFlags::SAMPLED,
+                format: format.as_raw() as _,
+                sample_count: 1,
+                width: extent.width,
+                height: extent.height,
+                face_count: 1,
+                array_size: 2,
+                mip_count: 1,
+            })
+            .unwrap();
+
+        let image_views = swapchain
+            .enumerate_images()
+            .unwrap()
+            .into_iter()
+            .map(|image| {
+                let image = vk::Image::from_raw(image);
+                let eye_view = |layer| {
+                    let create_view_info = vk::ImageViewCreateInfo::builder()
+                        .view_type(vk::ImageViewType::TYPE_2D)
+                        .format(format)
+                        .subresource_range(vk::ImageSubresourceRange {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            base_mip_level: 0,
+                            level_count: 1,
+                            base_array_layer: layer,
+                            layer_count: 1,
+                        })
+                        .image(image);
+                    unsafe {
+                        base.device
+                            .create_image_view(&create_view_info, None)
+                            .unwrap()
+                    }
+                };
+                [eye_view(0), eye_view(1)]
+            })
+            .collect();
+
+        let command_pool = {
+            let pool_create_info = vk::CommandPoolCreateInfo::builder()
+                .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                .queue_family_index(base.queue_family_index);
+            unsafe {
+                base.device
+                    .create_command_pool(&pool_create_info, None)
+                    .unwrap()
+            }
+        };
+        let command_buffer = {
+            let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::builder()
+                .command_buffer_count(1)
+                .command_pool(command_pool)
+                .level(vk::CommandBufferLevel::PRIMARY);
+            unsafe {
+                base.device
+                    .allocate_command_buffers(&command_buffer_allocate_info)
+                    .unwrap()[0]
+            }
+        };
+        let fence = unsafe {
+            base.device
+                .create_fence(
+                    &vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED),
+                    None,
+                )
+                .unwrap()
+        };
+
+        let mut xr_session = Self {
+            instance,
+            session,
+            frame_waiter,
+            frame_stream,
+            stage,
+            blend_mode,
+            event_storage: xr::EventDataBuffer::new(),
+            running: false,
+            swapchain,
+            extent,
+            image_views,
+            render_pass: vk::RenderPass::null(),
+            attachments: Vec::new(),
+            attachment_images: AttachmentImages::default(),
+            framebuffers: Vec::new(),
+            command_pool,
+            command_buffer,
+            fence,
+        };
+        xr_session.create_render_targets(ctx);
+        xr_session
+    }
+
+    /// The formats render pass compatibility depends on.
+    fn attachments(config: &RenderPassConfig) -> Vec<(AttachmentKind, vk::Format)> {
+        config
+            .extra_attachments
+            .iter()
+            .map(|attachment| (attachment.kind, attachment.format))
+            .collect()
+    }
+
+    /// Creates the render pass from the window's config, cleared for every eye and left in the
+    /// layout the runtime expects, with images for its extra attachments.
+    fn create_render_targets(&mut self, ctx: &RenderCtx) {
+        let base = &ctx.base;
+        let config = ctx.render_pass_config.cleared();
+        self.render_pass = config.create_render_pass(
+            &base.device,
+            base.view_format,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        );
+        self.attachments = Self::attachments(&config);
+        self.attachment_images = AttachmentImages::new(base, &config, self.extent);
+        let extra_views = self.attachment_images.views();
+        let render_pass = self.render_pass;
+        let extent = self.extent;
+        self.framebuffers = self
+            .image_views
+            .iter()
+            .map(|views| {
+                let framebuffers =
+                    base.create_framebuffers(views, &extra_views, render_pass, extent);
+                [framebuffers[0], framebuffers[1]]
+            })
+            .collect();
+    }
+
+    fn destroy_render_targets(&mut self, device: &ash::Device) {
+        unsafe {
+            for framebuffer in self.framebuffers.drain(..).flat_map(|eyes| eyes.to_vec()) {
+                device.destroy_framebuffer(framebuffer, None);
+            }
+            device.destroy_render_pass(self.render_pass, None);
+        }
+        self.attachment_images.destroy(device);
+    }
+
+    /// Handles OpenXR session events and renders both eyes while the session is running, which
+    /// blocks until the runtime wants the next frame.  Returns false once the runtime wants the
+    /// application to exit.
+    pub fn frame(&mut self, ctx: &RenderCtx) -> bool {
+        while let Some(event) = self.instance.poll_event(&mut self.event_storage).unwrap() {
+            match event {
+                xr::Event::SessionStateChanged(event) => match event.state() {
+                    xr::SessionState::READY => {
+                        self.session.begin(VIEW_TYPE).unwrap();
+                        self.running = true;
+                    }
+                    xr::SessionState::STOPPING => {
+                        self.session.end().unwrap();
+                        self.running = false;
+                    }
+                    xr::SessionState::EXITING | xr::SessionState::LOSS_PENDING => return false,
+                    _ => {}
+                },
+                xr::Event::InstanceLossPending(_) => return false,
+                _ => {}
+            }
+        }
+        if !self.running {
+            return true;
+        }
+
+        let frame_state = self.frame_waiter.wait().unwrap();
+        self.frame_stream.begin().unwrap();
+        if !frame_state.should_render {
+            self.frame_stream
+                .end(frame_state.predicted_display_time, self.blend_mode, &[])
+                .unwrap();
+            return true;
+        }
+
+        let (_, views) = self
+            .session
+            .locate_views(VIEW_TYPE, frame_state.predicted_display_time, &self.stage)
+            .unwrap();
+        if self.attachments != Self::attachments(&ctx.render_pass_config) {
+            unsafe {
+                ctx.base
+                    .device
+                    .wait_for_fences(&[self.fence], true, std::u64::MAX)
+                    .expect("Wait for fence failed.");
+            }
+            self.destroy_render_targets(&ctx.base.device);
+            self.create_render_targets(ctx);
+        }
+        let image_index = self.swapchain.acquire_image().unwrap() as usize;
+        self.swapchain.wait_image(xr::Duration::INFINITE).unwrap();
+        self.record_submit(ctx, image_index, &views);
+        self.swapchain.release_image().unwrap();
+
+        let rect = xr::Rect2Di {
+            offset: xr::Offset2Di { x: 0, y: 0 },
+            extent: xr::Extent2Di {
+                width: self.extent.width as _,
+                height: self.extent.height as _,
+            },
+        };
+        let projection_view = |eye: usize| {
+            xr::CompositionLayerProjectionView::new()
+                .pose(views[eye].pose)
+                .fov(views[eye].fov)
+                .sub_image(
+                    xr::SwapchainSubImage::new()
+                        .swapchain(&self.swapchain)
+                        .image_array_index(eye as u32)
+                        .image_rect(rect),
+                )
+        };
+        self.frame_stream
+            .end(
+                frame_state.predicted_display_time,
+                self.blend_mode,
+                &[&xr::CompositionLayerProjection::new()
+                    .space(&self.stage)
+                    .views(&[projection_view(0), projection_view(1)])],
+            )
+            .unwrap();
+        true
+    }
+
+    fn record_submit(&self, ctx: &RenderCtx, image_index: usize, views: &[xr::View]) {
+        let device = &ctx.base.device;
+        let (viewports, scissors) = RenderCtx::create_viewports_scissors(self.extent, ctx.flip_y);
+        let clear_values = ctx.clear_values();
+        unsafe {
+            device
+                .wait_for_fences(&[self.fence], true, std::u64::MAX)
+                .expect("Wait for fence failed.");
+            device
+                .reset_fences(&[self.fence])
+                .expect("Reset fences failed.");
+            device
+                .begin_command_buffer(
+                    self.command_buffer,
+                    &vk::CommandBufferBeginInfo::builder()
+                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                )
+                .expect("Begin commandbuffer");
+            for (&framebuffer, view) in self.framebuffers[image_index].iter().zip(views) {
+                let mut push_constants = ctx.shader_constants(self.extent);
+                push_constants.view_projection = ctx.flipped(eye_view_projection(ctx, view));
+                let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+                    .render_pass(self.render_pass)
+                    .framebuffer(framebuffer)
+                    .render_area(vk::Rect2D {
+                        offset: vk::Offset2D { x: 0, y: 0 },
+                        extent: self.extent,
+                    })
+                    .clear_values(&clear_values);
+                device.cmd_begin_render_pass(
+                    self.command_buffer,
+                    &render_pass_begin_info,
+                    vk::SubpassContents::INLINE,
+                );
+                for pipeline in ctx.pipelines.enabled() {
+                    ctx.draw_with_constants(
+                        pipeline,
+                        self.command_buffer,
+                        &push_constants,
+                        &viewports,
+                        &scissors,
+                    );
+                }
+                device.cmd_end_render_pass(self.command_buffer);
+            }
+            device
+                .end_command_buffer(self.command_buffer)
+                .expect("End commandbuffer");
+            let command_buffers = [self.command_buffer];
+            let submit_info = vk::SubmitInfo::builder().command_buffers(&command_buffers);
+            device
+                .queue_submit(ctx.base.present_queue, &[submit_info.build()], self.fence)
+                .expect("queue submit failed.");
+        }
+    }
+
+    /// Destroys the Vulkan objects owned by the session.  Must be called before the device goes
+    /// away.
+    pub fn destroy(&mut self, device: &ash::Device) {
+        unsafe {
+            device.device_wait_idle().unwrap();
+            self.destroy_render_targets(device);
+            for image_view in self.image_views.drain(..).flat_map(|eyes| eyes.to_vec()) {
+                device.destroy_image_view(image_view, None);
+            }
+            device.destroy_command_pool(self.command_pool, None);
+            device.destroy_fence(self.fence, None);
+        }
+    }
+}
+
+/// From the stage space to the eye's clip space, with y up, clipped at the camera's near and far
+/// planes.
+fn eye_view_projection(ctx: &RenderCtx, view: &xr::View) -> [[f32; 4]; 4] {
+    let xr::Quaternionf { x, y, z, w } = view.pose.orientation;
+    // the columns of the eye's rotation, its axes in stage space
+    let right = [
+        1.0 - 2.0 * (y * y + z * z),
+        2.0 * (x * y + w * z),
+        2.0 * (x * z - w * y),
+    ];
+    let up = [
+        2.0 * (x * y - w * z),
+        1.0 - 2.0 * (x * x + z * z),
+        2.0 * (y * z + w * x),
+    ];
+    let back = [
+        2.0 * (x * z + w * y),
+        2.0 * (y * z - w * x),
+        1.0 - 2.0 * (x * x + y * y),
+    ];
+    let position = view.pose.position;
+    let eye = [position.x, position.y, position.z];
+    // the inverse of the pose: the transposed rotation after moving the eye to the origin
+    let view_matrix = [
+        [right[0], up[0], back[0], 0.0],
+        [right[1], up[1], back[1], 0.0],
+        [right[2], up[2], back[2], 0.0],
+        [
+            -camera::dot(right, eye),
+            -camera::dot(up, eye),
+            -camera::dot(back, eye),
+            1.0,
+        ],
+    ];
+    let fov = view.fov;
+    let projection = ctx.depth_convention.frustum(
+        [
+            fov.angle_left.tan(),
+            fov.angle_right.tan(),
+            fov.angle_down.tan(),
+            fov.angle_up.tan(),
+        ],
+        ctx.camera.near,
+        ctx.camera.far,
+    );
+    camera::mul(&projection, &view_matrix)
+}